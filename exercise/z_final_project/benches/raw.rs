@@ -0,0 +1,38 @@
+// The raw subpixel fast paths in `mirage::raw` against the generic `image` code they replace, on a
+// 16 megapixel RGBA image. Run with `cargo bench --bench raw`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use image::{DynamicImage, RgbaImage};
+
+fn photo() -> RgbaImage {
+    RgbaImage::from_fn(4096, 4096, |x, y| {
+        image::Rgba([(x % 256) as u8, (y % 256) as u8, ((x ^ y) % 256) as u8, 255])
+    })
+}
+
+fn invert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("invert");
+    group.sample_size(10);
+    let mut img = DynamicImage::ImageRgba8(photo());
+    group.bench_function("generic", |b| b.iter(|| black_box(&mut img).invert()));
+    let mut buf = photo();
+    group.bench_function("raw", |b| {
+        b.iter(|| mirage::raw::invert_bytes(black_box(&mut buf), 4, true))
+    });
+    group.finish();
+}
+
+fn brighten(c: &mut Criterion) {
+    let mut group = c.benchmark_group("brighten");
+    group.sample_size(10);
+    let img = DynamicImage::ImageRgba8(photo());
+    group.bench_function("generic", |b| b.iter(|| black_box(&img).brighten(20)));
+    let mut buf = photo();
+    group.bench_function("raw", |b| {
+        b.iter(|| mirage::raw::brighten_bytes(black_box(&mut buf), 4, true, 20))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, invert, brighten);
+criterion_main!(benches);
@@ -0,0 +1,43 @@
+/*
+ * Blur and rotate an image through the C interface, e.g.
+ *
+ *     cargo build --release --lib --features ffi
+ *     cc -Iinclude examples/ffi/blur.c -Ltarget/release -lmirage -o blur
+ *     LD_LIBRARY_PATH=target/release ./blur dyson.png blurred.png
+ */
+
+#include <stdio.h>
+
+#include "mirage.h"
+
+static int fail(const char *what) {
+    fprintf(stderr, "%s failed: %s\n", what, it_last_error_message());
+    return 1;
+}
+
+int main(int argc, char **argv) {
+    if (argc != 3) {
+        fprintf(stderr, "usage: %s INFILE OUTFILE\n", argv[0]);
+        return 2;
+    }
+
+    ItImage *img = it_load(argv[1]);
+    if (img == NULL) {
+        return fail("it_load");
+    }
+
+    int status = 0;
+    if (it_blur(img, 2.5f) != 0) {
+        status = fail("it_blur");
+    } else if (it_rotate(img, 45) == 0) {
+        fprintf(stderr, "it_rotate accepted an invalid rotation\n");
+        status = 1;
+    } else if (it_rotate(img, 90) != 0) {
+        status = fail("it_rotate");
+    } else if (it_save(img, argv[2]) != 0) {
+        status = fail("it_save");
+    }
+
+    it_free(img);
+    return status;
+}
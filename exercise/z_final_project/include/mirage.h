@@ -0,0 +1,85 @@
+/*
+ * C interface to the mirage image library. Build the shared library with
+ *
+ *     cargo build --release --lib --features ffi
+ *
+ * and link against target/release/libmirage.so (libmirage.dylib on macOS, mirage.dll on Windows).
+ *
+ * Functions returning int return 0 on success and -1 on failure; it_load returns NULL on failure.
+ * After a failure, it_last_error_message() describes what went wrong.
+ */
+
+#ifndef MIRAGE_H
+#define MIRAGE_H
+
+/* Generated by cbindgen from src/ffi.rs. Don't edit it by hand, see cbindgen.toml. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef struct ItImage ItImage;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ Load an image from `path`. Returns NULL on failure.
+
+ # Safety
+
+ `path` must be NULL or a NUL-terminated string.
+ */
+struct ItImage *it_load(const char *path);
+
+/*
+ Gaussian blur `img` in place. Returns 0 on success and -1 on failure.
+
+ # Safety
+
+ `img` must be NULL or a live pointer returned by `it_load`.
+ */
+int it_blur(struct ItImage *img, float sigma);
+
+/*
+ Rotate `img` clockwise by 90, 180 or 270 degrees. Returns 0 on success and -1 on failure.
+
+ # Safety
+
+ `img` must be NULL or a live pointer returned by `it_load`.
+ */
+int it_rotate(struct ItImage *img, unsigned int degrees);
+
+/*
+ Save `img` to `path`, in the format named by its extension. Returns 0 on success and -1 on
+ failure.
+
+ # Safety
+
+ `img` must be NULL or a live pointer returned by `it_load`, and `path` must be NULL or a
+ NUL-terminated string.
+ */
+int it_save(const struct ItImage *img, const char *path);
+
+/*
+ Release an image returned by `it_load`. Passing NULL does nothing.
+
+ # Safety
+
+ `img` must be NULL or a live pointer returned by `it_load`, and must not be used afterwards.
+ */
+void it_free(struct ItImage *img);
+
+/*
+ The message for the most recent failure on this thread, or NULL if nothing has failed. The
+ string stays valid until the next failing call on the same thread.
+ */
+const char *it_last_error_message(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* MIRAGE_H */
@@ -0,0 +1,84 @@
+// A BK-tree (Burkhard and Keller, 1973) of 64-bit hashes under Hamming distance, for finding every
+// hash within a small distance of another without comparing against all of them.
+//
+// Each node's children are keyed by their distance to it. By the triangle inequality, a hash within
+// `max` of the query can only be below a child whose key is within `max` of the query's distance
+// to the node, so whole subtrees are skipped.
+
+pub struct BkTree {
+    nodes: Vec<Node>,
+}
+
+struct Node {
+    hash: u64,
+    // Everything inserted with exactly this hash.
+    items: Vec<usize>,
+    children: Vec<(u32, usize)>,
+}
+
+impl BkTree {
+    pub fn new() -> Self {
+        BkTree { nodes: Vec::new() }
+    }
+
+    pub fn insert(&mut self, hash: u64, item: usize) {
+        if self.nodes.is_empty() {
+            self.nodes.push(Node {
+                hash,
+                items: vec![item],
+                children: Vec::new(),
+            });
+            return;
+        }
+        let mut current = 0;
+        loop {
+            let distance = (self.nodes[current].hash ^ hash).count_ones();
+            if distance == 0 {
+                self.nodes[current].items.push(item);
+                return;
+            }
+            match self.nodes[current]
+                .children
+                .iter()
+                .find(|&&(key, _)| key == distance)
+            {
+                Some(&(_, child)) => current = child,
+                None => {
+                    let child = self.nodes.len();
+                    self.nodes.push(Node {
+                        hash,
+                        items: vec![item],
+                        children: Vec::new(),
+                    });
+                    self.nodes[current].children.push((distance, child));
+                    return;
+                }
+            }
+        }
+    }
+
+    // Every item whose hash is within `max_distance` of `hash`, including those equal to it.
+    pub fn find(&self, hash: u64, max_distance: u32) -> Vec<usize> {
+        let mut found = Vec::new();
+        let mut pending = if self.nodes.is_empty() {
+            vec![]
+        } else {
+            vec![0]
+        };
+        while let Some(current) = pending.pop() {
+            let node = &self.nodes[current];
+            let distance = (node.hash ^ hash).count_ones();
+            if distance <= max_distance {
+                found.extend(&node.items);
+            }
+            let range = distance.saturating_sub(max_distance)..=distance + max_distance;
+            pending.extend(
+                node.children
+                    .iter()
+                    .filter(|(key, _)| range.contains(key))
+                    .map(|&(_, child)| child),
+            );
+        }
+        found
+    }
+}
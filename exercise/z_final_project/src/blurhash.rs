@@ -0,0 +1,226 @@
+// BlurHash (https://blurha.sh): a few DCT components of an image packed into a short base 83
+// string, which front ends decode into a blurry placeholder while the real image loads.
+//
+// This follows the blurhash crate (github.com/whisperfish/blurhash-rs) step for step: the same f32
+// arithmetic in the same order and the same rounding, down to its 8192-step table for converting
+// back to sRGB, so hashes and renderings match the ones it produces exactly.
+
+use image::{Rgb, RgbImage};
+use std::f32::consts::PI;
+
+const DIGITS: &[u8; 83] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+pub fn encode(img: &RgbImage, x_components: u32, y_components: u32) -> Result<String, String> {
+    if !(1..=9).contains(&x_components) || !(1..=9).contains(&y_components) {
+        return Err("BlurHash component counts must be between 1 and 9!".to_string());
+    }
+    let (width, height) = img.dimensions();
+    let linear = img
+        .pixels()
+        .map(|p| [0, 1, 2].map(|c| srgb_to_linear(p[c])))
+        .collect::<Vec<_>>();
+
+    let mut factors = Vec::new();
+    for j in 0..y_components {
+        for i in 0..x_components {
+            let normalisation = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+            let pi_i_over_width = PI * i as f32 / width as f32;
+            let pi_j_over_height = PI * j as f32 / height as f32;
+            let cos_x = (0..width)
+                .map(|x| (pi_i_over_width * x as f32).cos())
+                .collect::<Vec<_>>();
+            let mut factor = [0.0f32; 3];
+            for y in 0..height {
+                let cos_y = (pi_j_over_height * y as f32).cos();
+                for x in 0..width {
+                    let basis = cos_x[x as usize] * cos_y;
+                    let pixel = linear[(y * width + x) as usize];
+                    for c in 0..3 {
+                        factor[c] += basis * pixel[c];
+                    }
+                }
+            }
+            let scale = normalisation / (width * height) as f32;
+            factors.push(factor.map(|v| v * scale));
+        }
+    }
+
+    let mut hash = String::new();
+    let size_flag = (x_components - 1) + (y_components - 1) * 9;
+    encode83(size_flag, 1, &mut hash);
+
+    let (dc, ac) = factors.split_first().unwrap();
+    let maximum_value = if ac.is_empty() {
+        encode83(0, 1, &mut hash);
+        1.0
+    } else {
+        let actual_maximum = ac
+            .iter()
+            .flat_map(|factor| factor.iter())
+            .map(|v| v.abs())
+            .fold(0.0f32, f32::max);
+        let quantised = (actual_maximum * 166.0 - 0.5).floor().clamp(0.0, 82.0) as u32;
+        encode83(quantised, 1, &mut hash);
+        (quantised + 1) as f32 / 166.0
+    };
+
+    let dc_value = ((linear_to_srgb(dc[0]) as u32) << 16)
+        + ((linear_to_srgb(dc[1]) as u32) << 8)
+        + linear_to_srgb(dc[2]) as u32;
+    encode83(dc_value, 4, &mut hash);
+    for factor in ac {
+        let quantise = |v: f32| {
+            ((sign_pow(v / maximum_value, 0.5) * 9.0 + 9.5).floor() as i32).clamp(0, 18) as u32
+        };
+        let value = quantise(factor[0]) * 19 * 19 + quantise(factor[1]) * 19 + quantise(factor[2]);
+        encode83(value, 2, &mut hash);
+    }
+    Ok(hash)
+}
+
+// Render `hash` at the given size. `punch` scales the contrast, 1.0 being the original.
+pub fn decode(hash: &str, width: u32, height: u32, punch: f32) -> Result<RgbImage, String> {
+    let invalid = || format!("{} is not a valid BlurHash!", hash);
+    if hash.len() < 6 || !hash.is_ascii() {
+        return Err(invalid());
+    }
+    let size_flag = decode83(&hash[0..1]).ok_or_else(invalid)?;
+    let y_components = size_flag / 9 + 1;
+    let x_components = size_flag % 9 + 1;
+    if hash.len() != 4 + 2 * (x_components * y_components) as usize {
+        return Err(invalid());
+    }
+    let quantised_maximum = decode83(&hash[1..2]).ok_or_else(invalid)?;
+    let maximum_value = (quantised_maximum + 1) as f32 / 166.0 * punch;
+
+    let dc = decode83(&hash[2..6]).ok_or_else(invalid)?;
+    let mut colors = vec![[
+        srgb_to_linear((dc >> 16) as u8),
+        srgb_to_linear(((dc >> 8) & 255) as u8),
+        srgb_to_linear((dc & 255) as u8),
+    ]];
+    for i in 1..(x_components * y_components) as usize {
+        let value = decode83(&hash[4 + i * 2..6 + i * 2]).ok_or_else(invalid)?;
+        let unquantise = |q: u32| sign_pow((q as f32 - 9.0) / 9.0, 2.0) * maximum_value;
+        colors.push([
+            unquantise(value / (19 * 19)),
+            unquantise((value / 19) % 19),
+            unquantise(value % 19),
+        ]);
+    }
+
+    let (pi_over_width, pi_over_height) = (PI / width as f32, PI / height as f32);
+    Ok(RgbImage::from_fn(width, height, |x, y| {
+        let mut pixel = [0.0f32; 3];
+        for j in 0..y_components {
+            let cos_y = (j as f32 * (y as f32 * pi_over_height)).cos();
+            for i in 0..x_components {
+                let basis = (x as f32 * pi_over_width * i as f32).cos() * cos_y;
+                let color = colors[(i + j * x_components) as usize];
+                for c in 0..3 {
+                    pixel[c] += color[c] * basis;
+                }
+            }
+        }
+        Rgb(pixel.map(linear_to_srgb))
+    }))
+}
+
+fn encode83(value: u32, length: u32, hash: &mut String) {
+    for i in 1..=length {
+        let digit = (value / 83u32.pow(length - i)) % 83;
+        hash.push(DIGITS[digit as usize] as char);
+    }
+}
+
+fn decode83(digits: &str) -> Option<u32> {
+    digits.bytes().try_fold(0, |value, byte| {
+        let digit = DIGITS.iter().position(|&d| d == byte)?;
+        Some(value * 83 + digit as u32)
+    })
+}
+
+// The reference implementation's own sRGB conversions, which round differently from the ones in
+// the colorspace module.
+fn srgb_to_linear(value: u8) -> f32 {
+    let v = value as f32 / 255.0;
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// How many evenly spaced linear values the reference converts ahead of time. A value takes the
+// result of the step at or below it.
+const LINEAR_TO_SRGB_STEPS: usize = 8192;
+
+fn linear_to_srgb(value: f32) -> u8 {
+    let v = value.clamp(0.0, 1.0);
+    let step = ((LINEAR_TO_SRGB_STEPS as f32 * v) as usize).min(LINEAR_TO_SRGB_STEPS - 1);
+    let v = step as f32 / (LINEAR_TO_SRGB_STEPS - 1) as f32;
+    if v <= 0.003_130_8 {
+        (v * 12.92 * 255.0 + 0.5).round() as u8
+    } else {
+        ((1.055 * 255.0) * v.powf(1.0 / 2.4) - (0.055 * 255.0 - 0.5)).round() as u8
+    }
+}
+
+fn sign_pow(value: f32, exponent: f32) -> f32 {
+    value.abs().powf(exponent).copysign(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> image::DynamicImage {
+        let path = format!(
+            "{}/tests/fixtures/blurhash/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        image::open(path).unwrap()
+    }
+
+    #[test]
+    fn base83_matches_the_reference_alphabet() {
+        let mut hash = String::new();
+        encode83(6869, 2, &mut hash);
+        assert_eq!(hash, "~$");
+        assert_eq!(decode83("~$"), Some(6869));
+        assert_eq!(decode83("\\"), None);
+    }
+
+    // Hashes the blurhash crate gives for the fixtures.
+    #[test]
+    fn hashes_match_the_reference_implementation() {
+        for (name, x_components, y_components, expected) in [
+            ("octocat", 4, 3, "LNAdAqj[00aymkj[TKay9}ay-Sj["),
+            ("octocat", 1, 1, "00AdAq"),
+            ("octocat", 3, 5, "cNAdAqj[00mkj[TK9}ay-S4:fk?Hs8j[R-"),
+            ("wikipedia_logo", 4, 3, "LLO:@T~q%Mj]t7ofIUof?bM{?bM{"),
+            ("wikipedia_logo", 3, 5, "cLO:@T~q%Mt7ofIU?bM{?b%MxuRj?bofWB"),
+        ] {
+            let img = fixture(&format!("{}.png", name)).to_rgb8();
+            let hash = encode(&img, x_components, y_components).unwrap();
+            assert_eq!(hash, expected, "{} {}x{}", name, x_components, y_components);
+        }
+    }
+
+    // The blurhash crate's rendering of each image's 4x3 hash, pixel for pixel.
+    #[test]
+    fn decoding_matches_the_reference_implementation() {
+        for name in ["wikipedia_logo", "octocat"] {
+            let img = fixture(&format!("{}.png", name)).to_rgb8();
+            let hash = encode(&img, 4, 3).unwrap();
+            let decoded = decode(&hash, img.width(), img.height(), 1.0).unwrap();
+            assert!(
+                decoded == fixture(&format!("{}_blurred.png", name)).to_rgb8(),
+                "{}",
+                name
+            );
+        }
+    }
+}
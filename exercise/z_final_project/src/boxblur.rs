@@ -0,0 +1,207 @@
+// A fast approximation of a gaussian blur.
+//
+// Blurring three times with box filters of carefully chosen widths comes very close to a gaussian
+// (the central limit theorem at work), and a box filter can be computed with a running sum, so the
+// cost per pixel doesn't depend on the radius at all. The widths follow Peter Kovesi, "Fast
+// Almost-Gaussian Filtering" (2010).
+
+use image::Rgba32FImage;
+
+const PASSES: usize = 3;
+
+pub fn gaussian_approx(img: &Rgba32FImage, sigma: f32) -> Rgba32FImage {
+    let mut blurred = img.clone();
+    let mut scratch = img.clone();
+    for width in box_widths(sigma) {
+        let radius = (width / 2) as i64;
+        box_blur_rows(&blurred, &mut scratch, radius);
+        box_blur_columns(&scratch, &mut blurred, radius);
+    }
+    blurred
+}
+
+// Odd box widths whose combined variance is as close to `sigma` squared as possible: `m` boxes of
+// the largest odd width below the ideal one, the rest two pixels wider.
+fn box_widths(sigma: f32) -> Vec<usize> {
+    let n = PASSES as f32;
+    let ideal = (12.0 * sigma * sigma / n + 1.0).sqrt();
+    let mut lower = ideal.floor() as i64;
+    if lower % 2 == 0 {
+        lower -= 1;
+    }
+    let lower = lower.max(1) as f32;
+    let m = ((12.0 * sigma * sigma - n * lower * lower - 4.0 * n * lower - 3.0 * n)
+        / (-4.0 * lower - 4.0))
+        .round() as usize;
+    (0..PASSES)
+        .map(|i| {
+            if i < m {
+                lower as usize
+            } else {
+                lower as usize + 2
+            }
+        })
+        .collect()
+}
+
+// Average each pixel with the `radius` pixels on either side of it, repeating the edge pixels past
+// the borders.
+fn box_blur_rows(src: &Rgba32FImage, dst: &mut Rgba32FImage, radius: i64) {
+    let (width, height) = src.dimensions();
+    for y in 0..height {
+        let at = |x: i64| src.get_pixel(x.clamp(0, width as i64 - 1) as u32, y);
+        let scale = 1.0 / (2 * radius + 1) as f32;
+        let mut sum = [0.0f32; 4];
+        for x in -radius..=radius {
+            for (c, total) in sum.iter_mut().enumerate() {
+                *total += at(x)[c];
+            }
+        }
+        for x in 0..width as i64 {
+            let out = dst.get_pixel_mut(x as u32, y);
+            for c in 0..4 {
+                out[c] = sum[c] * scale;
+            }
+            let (entering, leaving) = (at(x + radius + 1), at(x - radius));
+            for c in 0..4 {
+                sum[c] += entering[c] - leaving[c];
+            }
+        }
+    }
+}
+
+fn box_blur_columns(src: &Rgba32FImage, dst: &mut Rgba32FImage, radius: i64) {
+    let (width, height) = src.dimensions();
+    for x in 0..width {
+        let at = |y: i64| src.get_pixel(x, y.clamp(0, height as i64 - 1) as u32);
+        let scale = 1.0 / (2 * radius + 1) as f32;
+        let mut sum = [0.0f32; 4];
+        for y in -radius..=radius {
+            for (c, total) in sum.iter_mut().enumerate() {
+                *total += at(y)[c];
+            }
+        }
+        for y in 0..height as i64 {
+            let out = dst.get_pixel_mut(x, y as u32);
+            for c in 0..4 {
+                out[c] = sum[c] * scale;
+            }
+            let (entering, leaving) = (at(y + radius + 1), at(y - radius));
+            for c in 0..4 {
+                sum[c] += entering[c] - leaving[c];
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A true gaussian blur, one row and one column pass with a kernel 4 sigma wide on either side,
+    // repeating the edge pixels past the borders like the box blurs do.
+    fn gaussian(img: &Rgba32FImage, sigma: f32) -> Rgba32FImage {
+        let radius = (4.0 * sigma).ceil() as i64;
+        let kernel = (-radius..=radius)
+            .map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp())
+            .collect::<Vec<_>>();
+        let total: f32 = kernel.iter().sum();
+        let (width, height) = img.dimensions();
+        let pass = |src: &Rgba32FImage, horizontal: bool| {
+            Rgba32FImage::from_fn(width, height, |x, y| {
+                let mut sum = [0.0f32; 4];
+                for (i, weight) in (-radius..=radius).zip(&kernel) {
+                    let p = if horizontal {
+                        src.get_pixel((x as i64 + i).clamp(0, width as i64 - 1) as u32, y)
+                    } else {
+                        src.get_pixel(x, (y as i64 + i).clamp(0, height as i64 - 1) as u32)
+                    };
+                    for c in 0..4 {
+                        sum[c] += p[c] * weight / total;
+                    }
+                }
+                image::Rgba(sum)
+            })
+        };
+        pass(&pass(img, true), false)
+    }
+
+    // The mean structural similarity of the red channels of `a` and `b` over 8x8 windows, with the
+    // usual constants for values in 0..=1.
+    fn ssim(a: &Rgba32FImage, b: &Rgba32FImage) -> f32 {
+        let (c1, c2) = (0.01f32.powi(2), 0.03f32.powi(2));
+        let mut total = 0.0;
+        let mut windows = 0;
+        for wy in (0..a.height() - 7).step_by(8) {
+            for wx in (0..a.width() - 7).step_by(8) {
+                let values = |img: &Rgba32FImage| {
+                    (0..64)
+                        .map(|i| img.get_pixel(wx + i % 8, wy + i / 8)[0])
+                        .collect::<Vec<_>>()
+                };
+                let (va, vb) = (values(a), values(b));
+                let mean = |v: &[f32]| v.iter().sum::<f32>() / 64.0;
+                let (ma, mb) = (mean(&va), mean(&vb));
+                let (mut var_a, mut var_b, mut covariance) = (0.0, 0.0, 0.0);
+                for (x, y) in va.iter().zip(&vb) {
+                    var_a += (x - ma) * (x - ma) / 64.0;
+                    var_b += (y - mb) * (y - mb) / 64.0;
+                    covariance += (x - ma) * (y - mb) / 64.0;
+                }
+                total += (2.0 * ma * mb + c1) * (2.0 * covariance + c2)
+                    / ((ma * ma + mb * mb + c1) * (var_a + var_b + c2));
+                windows += 1;
+            }
+        }
+        total / windows as f32
+    }
+
+    #[test]
+    fn three_box_blurs_are_indistinguishable_from_a_gaussian() {
+        // Hard edges and fine texture, where a poor approximation would show most.
+        let img = Rgba32FImage::from_fn(96, 96, |x, y| {
+            let block = if (x / 24 + y / 24) % 2 == 0 { 0.8 } else { 0.2 };
+            let texture = ((x * 7 + y * 13) % 11) as f32 / 55.0;
+            image::Rgba([block + texture - 0.1, 0.5, 0.5, 1.0])
+        });
+        for sigma in [2.0, 5.0, 12.0] {
+            let approx = gaussian_approx(&img, sigma);
+            let exact = gaussian(&img, sigma);
+            let similarity = ssim(&approx, &exact);
+            assert!(similarity > 0.99, "sigma {}: SSIM {}", sigma, similarity);
+        }
+    }
+
+    #[test]
+    fn three_box_blurs_stay_close_to_a_gaussian() {
+        for sigma in [1.0f32, 3.0, 8.0, 20.0] {
+            let variance = box_widths(sigma)
+                .iter()
+                .map(|&width| (width * width - 1) as f32 / 12.0)
+                .sum::<f32>();
+            assert!(
+                (variance.sqrt() - sigma).abs() < 0.2 * sigma,
+                "sigma {}: {}",
+                sigma,
+                variance.sqrt()
+            );
+        }
+
+        // A white square on black, whose edges show the differences most.
+        let img = Rgba32FImage::from_fn(80, 80, |x, y| {
+            let inside = (20..60).contains(&x) && (20..60).contains(&y);
+            let value = if inside { 1.0 } else { 0.0 };
+            image::Rgba([value, value, value, 1.0])
+        });
+        for sigma in [3.0, 8.0, 20.0] {
+            let approx = gaussian_approx(&img, sigma);
+            let exact = gaussian(&img, sigma);
+            let error = approx
+                .pixels()
+                .zip(exact.pixels())
+                .map(|(a, b)| (a[0] - b[0]).abs())
+                .fold(0.0, f32::max);
+            assert!(error < 0.04, "sigma {}: error {}", sigma, error);
+        }
+    }
+}
@@ -0,0 +1,54 @@
+// Ctrl-C handling.
+//
+// The first Ctrl-C only sets a flag. Long-running loops are handed a `Cancel` token and poll it at
+// points where stopping is safe, so they can skip writing a half-finished OUTFILE and report how far
+// they got before exiting with `EXIT_CODE`. A second Ctrl-C exits immediately.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// The conventional exit code of a process stopped by SIGINT.
+pub const EXIT_CODE: i32 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Copy, Clone)]
+pub struct Cancel {
+    flag: &'static AtomicBool,
+}
+
+impl Cancel {
+    pub fn requested(self) -> bool {
+        self.flag.load(Ordering::Relaxed)
+    }
+}
+
+// Install the SIGINT handler and return the token to pass to a long-running command. Only commands
+// that poll the token install it, so Ctrl-C keeps its default behaviour everywhere else.
+pub fn install() -> Cancel {
+    #[cfg(unix)]
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            handle_sigint as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+    Cancel { flag: &INTERRUPTED }
+}
+
+#[cfg(unix)]
+extern "C" fn handle_sigint(_signal: libc::c_int) {
+    if INTERRUPTED.swap(true, Ordering::Relaxed) {
+        // Only async-signal-safe calls are allowed here, which rules out `std::process::exit`.
+        unsafe { libc::_exit(EXIT_CODE) };
+    }
+}
+
+// Report an interrupted command and exit. `done` and `total` count whatever unit of work the
+// command polls between, e.g. rows or operations.
+pub fn exit(done: usize, total: usize, unit: &str) -> ! {
+    eprintln!(
+        "Interrupted after {} of {} {}, OUTFILE was not written.",
+        done, total, unit
+    );
+    std::process::exit(EXIT_CODE);
+}
@@ -0,0 +1,134 @@
+// Content-aware resizing by seam carving (Avidan and Shamir, 2007).
+//
+// A seam is a connected path of pixels from one edge of the image to the opposite one, with one
+// pixel per row (or column). Removing the seam whose pixels have the lowest total energy, i.e. the
+// one running through the flattest areas, shrinks the image by one pixel while leaving edges and
+// detail, where the energy is high, mostly intact.
+
+use image::{GrayImage, Luma, Rgba, RgbaImage};
+
+pub struct Carver {
+    width: u32,
+    height: u32,
+    pixels: Vec<Rgba<u8>>,
+    // Where every remaining pixel was in the original image.
+    origins: Vec<(u32, u32)>,
+    transposed: bool,
+}
+
+impl Carver {
+    pub fn new(img: &RgbaImage) -> Self {
+        Carver {
+            width: img.width(),
+            height: img.height(),
+            pixels: img.pixels().copied().collect(),
+            origins: img.enumerate_pixels().map(|(x, y, _)| (x, y)).collect(),
+            transposed: false,
+        }
+    }
+
+    // Remove the lowest-energy seam running from top to bottom, making the image one pixel
+    // narrower. Returns the original positions of the removed pixels.
+    pub fn remove_vertical_seam(&mut self) -> Vec<(u32, u32)> {
+        self.set_transposed(false);
+        self.remove_seam()
+    }
+
+    // Remove the lowest-energy seam running from left to right, making the image one pixel lower.
+    pub fn remove_horizontal_seam(&mut self) -> Vec<(u32, u32)> {
+        self.set_transposed(true);
+        self.remove_seam()
+    }
+
+    pub fn into_image(mut self) -> RgbaImage {
+        self.set_transposed(false);
+        let (width, height) = (self.width, self.height);
+        RgbaImage::from_fn(width, height, |x, y| self.pixels[(y * width + x) as usize])
+    }
+
+    // Horizontal seams are removed as vertical seams of the transposed image.
+    fn set_transposed(&mut self, transposed: bool) {
+        if self.transposed == transposed {
+            return;
+        }
+        let (width, height) = (self.width as usize, self.height as usize);
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+        let mut origins = Vec::with_capacity(self.origins.len());
+        for x in 0..width {
+            for y in 0..height {
+                pixels.push(self.pixels[y * width + x]);
+                origins.push(self.origins[y * width + x]);
+            }
+        }
+        self.pixels = pixels;
+        self.origins = origins;
+        self.width = height as u32;
+        self.height = width as u32;
+        self.transposed = transposed;
+    }
+
+    fn remove_seam(&mut self) -> Vec<(u32, u32)> {
+        let seam = self.find_seam();
+        let width = self.width as usize;
+        let mut pixels = Vec::with_capacity(self.pixels.len() - seam.len());
+        let mut origins = Vec::with_capacity(self.origins.len() - seam.len());
+        let mut removed = Vec::with_capacity(seam.len());
+        for (y, &x) in seam.iter().enumerate() {
+            let row = y * width;
+            pixels.extend_from_slice(&self.pixels[row..row + x]);
+            pixels.extend_from_slice(&self.pixels[row + x + 1..row + width]);
+            origins.extend_from_slice(&self.origins[row..row + x]);
+            origins.extend_from_slice(&self.origins[row + x + 1..row + width]);
+            removed.push(self.origins[row + x]);
+        }
+        self.pixels = pixels;
+        self.origins = origins;
+        self.width -= 1;
+        removed
+    }
+
+    // The x position of the lowest-energy seam in every row, found by dynamic programming: the
+    // cheapest way to reach a pixel is its energy plus the cheapest way to reach one of the three
+    // pixels above it.
+    fn find_seam(&self) -> Vec<usize> {
+        let energy = self.energy();
+        let (width, height) = (self.width as usize, self.height as usize);
+        let mut cost = energy;
+        for y in 1..height {
+            for x in 0..width {
+                let above = (x.saturating_sub(1)..=(x + 1).min(width - 1))
+                    .map(|ax| cost[(y - 1) * width + ax])
+                    .min()
+                    .unwrap();
+                cost[y * width + x] += above;
+            }
+        }
+
+        let last_row = &cost[(height - 1) * width..];
+        let mut x = (0..width).min_by_key(|&x| last_row[x]).unwrap();
+        let mut seam = vec![0; height];
+        seam[height - 1] = x;
+        for y in (0..height - 1).rev() {
+            x = (x.saturating_sub(1)..=(x + 1).min(width - 1))
+                .min_by_key(|&ax| cost[y * width + ax])
+                .unwrap();
+            seam[y] = x;
+        }
+        seam
+    }
+
+    // The Sobel gradient magnitude of the luma at every pixel, in row-major order.
+    fn energy(&self) -> Vec<u32> {
+        let (width, height) = (self.width, self.height);
+        let luma = GrayImage::from_fn(width, height, |x, y| {
+            let p = self.pixels[(y * width + x) as usize];
+            Luma([((2126 * p[0] as u32 + 7152 * p[1] as u32 + 722 * p[2] as u32) / 10000) as u8])
+        });
+        luma.enumerate_pixels()
+            .map(|(x, y, _)| {
+                let (gx, gy) = crate::sobel_gradient(&luma, x, y);
+                (gx * gx + gy * gy).sqrt() as u32
+            })
+            .collect()
+    }
+}
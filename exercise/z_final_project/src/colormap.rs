@@ -0,0 +1,100 @@
+// Colormaps for turning single-channel data (heightmaps, sensor readings) into false-color images.
+//
+// Each map is stored as a handful of evenly spaced sRGB samples (the nine-step palettes of R's
+// viridisLite package) and colors in between are interpolated linearly.
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum Colormap {
+    Viridis,
+    Magma,
+    Inferno,
+    Plasma,
+    Turbo,
+    Grayscale,
+}
+
+const VIRIDIS: &[[u8; 3]] = &[
+    [0x44, 0x01, 0x54],
+    [0x47, 0x2d, 0x7b],
+    [0x3b, 0x52, 0x8b],
+    [0x2c, 0x72, 0x8e],
+    [0x21, 0x90, 0x8c],
+    [0x27, 0xad, 0x81],
+    [0x5d, 0xc8, 0x63],
+    [0xaa, 0xdc, 0x32],
+    [0xfd, 0xe7, 0x25],
+];
+
+const MAGMA: &[[u8; 3]] = &[
+    [0x00, 0x00, 0x04],
+    [0x1d, 0x11, 0x47],
+    [0x51, 0x12, 0x7c],
+    [0x82, 0x26, 0x81],
+    [0xb6, 0x36, 0x79],
+    [0xe6, 0x51, 0x64],
+    [0xfb, 0x88, 0x61],
+    [0xfe, 0xc2, 0x87],
+    [0xfc, 0xfd, 0xbf],
+];
+
+const INFERNO: &[[u8; 3]] = &[
+    [0x00, 0x00, 0x04],
+    [0x1f, 0x0c, 0x48],
+    [0x55, 0x0f, 0x6d],
+    [0x88, 0x22, 0x6a],
+    [0xba, 0x36, 0x55],
+    [0xe3, 0x59, 0x32],
+    [0xf9, 0x8c, 0x0a],
+    [0xf9, 0xc9, 0x32],
+    [0xfc, 0xff, 0xa4],
+];
+
+const PLASMA: &[[u8; 3]] = &[
+    [0x0d, 0x08, 0x87],
+    [0x4c, 0x02, 0xa1],
+    [0x7e, 0x03, 0xa8],
+    [0xa9, 0x23, 0x95],
+    [0xcc, 0x46, 0x78],
+    [0xe5, 0x6b, 0x5d],
+    [0xf8, 0x94, 0x41],
+    [0xfd, 0xc3, 0x28],
+    [0xf0, 0xf9, 0x21],
+];
+
+const TURBO: &[[u8; 3]] = &[
+    [0x30, 0x12, 0x3b],
+    [0x46, 0x62, 0xd7],
+    [0x35, 0xab, 0xf8],
+    [0x1a, 0xe4, 0xb6],
+    [0x72, 0xfe, 0x5e],
+    [0xc8, 0xef, 0x34],
+    [0xfa, 0xba, 0x39],
+    [0xe4, 0x46, 0x0a],
+    [0x7a, 0x04, 0x03],
+];
+
+const GRAYSCALE: &[[u8; 3]] = &[[0, 0, 0], [255, 255, 255]];
+
+impl Colormap {
+    fn table(self) -> &'static [[u8; 3]] {
+        match self {
+            Colormap::Viridis => VIRIDIS,
+            Colormap::Magma => MAGMA,
+            Colormap::Inferno => INFERNO,
+            Colormap::Plasma => PLASMA,
+            Colormap::Turbo => TURBO,
+            Colormap::Grayscale => GRAYSCALE,
+        }
+    }
+
+    // The color at `t`, from 0.0 (the low end of the map) to 1.0 (the high end).
+    pub fn sample(self, t: f32) -> [u8; 3] {
+        let table = self.table();
+        let position = t.clamp(0.0, 1.0) * (table.len() - 1) as f32;
+        let index = (position.floor() as usize).min(table.len() - 2);
+        let fraction = position - index as f32;
+        let (low, high) = (table[index], table[index + 1]);
+        [0, 1, 2]
+            .map(|c| (low[c] as f32 + (high[c] as f32 - low[c] as f32) * fraction).round() as u8)
+    }
+}
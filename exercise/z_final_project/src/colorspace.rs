@@ -0,0 +1,115 @@
+// Conversions between color spaces.
+
+use image::{DynamicImage, Rgba32FImage};
+
+// The sRGB transfer functions, using the exact piecewise curve from the sRGB specification rather
+// than a plain 2.2 gamma. Values are normalized to `0.0..=1.0`.
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// CIE L*a*b* (D65 white point) for an sRGB color with channels in `0.0..=1.0`. Euclidean distance in
+// Lab tracks perceived color difference far better than distance in RGB.
+pub fn srgb_to_lab(rgb: [f32; 3]) -> [f32; 3] {
+    let [r, g, b] = rgb.map(srgb_to_linear);
+    let x = (0.412_456_4 * r + 0.357_576_1 * g + 0.180_437_5 * b) / 0.950_47;
+    let y = 0.212_672_9 * r + 0.715_152_2 * g + 0.072_175 * b;
+    let z = (0.019_333_9 * r + 0.119_192 * g + 0.950_304_1 * b) / 1.088_83;
+    let f = |t: f32| {
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            (24389.0 / 27.0 * t + 16.0) / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+// HSV for a color with channels in `0.0..=1.0`: hue in degrees from `0.0` up to `360.0`, with `0.0`
+// for grays, and saturation and value in `0.0..=1.0`. Hue and saturation don't depend on the scale
+// of the channels, so they come out the same for channels in `0.0..=255.0`.
+pub fn rgb_to_hsv([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let max = r.max(g).max(b);
+    let chroma = max - r.min(g).min(b);
+    let hue = if chroma == 0.0 {
+        0.0
+    } else if max == r {
+        (60.0 * (g - b) / chroma).rem_euclid(360.0)
+    } else if max == g {
+        60.0 * (b - r) / chroma + 120.0
+    } else {
+        60.0 * (r - g) / chroma + 240.0
+    };
+    let saturation = if max == 0.0 { 0.0 } else { chroma / max };
+    [hue, saturation, max]
+}
+
+pub fn hsv_to_rgb([hue, saturation, value]: [f32; 3]) -> [f32; 3] {
+    let chroma = value * saturation;
+    let sector = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (sector.rem_euclid(2.0) - 1.0).abs());
+    let [r, g, b] = match sector as u32 {
+        0 => [chroma, x, 0.0],
+        1 => [x, chroma, 0.0],
+        2 => [0.0, chroma, x],
+        3 => [0.0, x, chroma],
+        4 => [x, 0.0, chroma],
+        _ => [chroma, 0.0, x],
+    };
+    let min = value - chroma;
+    [r + min, g + min, b + min]
+}
+
+// Decode `img` into linear-light floating point RGBA. Alpha is already linear and is kept as is.
+pub fn to_linear(img: &DynamicImage) -> Rgba32FImage {
+    let mut linear = img.to_rgba32f();
+    for pixel in linear.pixels_mut() {
+        for c in 0..3 {
+            pixel[c] = srgb_to_linear(pixel[c]);
+        }
+    }
+    linear
+}
+
+// Re-encode linear-light RGBA as sRGB, at the same bit depth and with the same alpha-ness as
+// `original`.
+pub fn from_linear(mut linear: Rgba32FImage, original: &DynamicImage) -> DynamicImage {
+    for pixel in linear.pixels_mut() {
+        for c in 0..3 {
+            pixel[c] = linear_to_srgb(pixel[c].clamp(0.0, 1.0));
+        }
+    }
+    crate::like_original(linear, original)
+}
+
+// For commands with a `--linear` switch: the pixels as floating point RGBA, in linear light when
+// `linear` is set and as sRGB-encoded values otherwise.
+pub fn to_working(img: &DynamicImage, linear: bool) -> Rgba32FImage {
+    if linear {
+        to_linear(img)
+    } else {
+        img.to_rgba32f()
+    }
+}
+
+// The inverse of `to_working`, at the same bit depth and with the same alpha-ness as `original`.
+pub fn from_working(working: Rgba32FImage, original: &DynamicImage, linear: bool) -> DynamicImage {
+    if linear {
+        from_linear(working, original)
+    } else {
+        crate::like_original(working, original)
+    }
+}
@@ -0,0 +1,223 @@
+// Just enough of an EXIF reader to find where and when a photo was taken and which way up it is.
+//
+// EXIF metadata is a little TIFF file of its own: a header giving the byte order, then directories
+// (IFDs) of 12-byte entries. JPEG files carry it in an APP1 segment starting with `Exif\0\0`, and
+// TIFF files are EXIF containers already. The GPS position lives in a separate directory that
+// IFD0 points to with tag 0x8825, and the capture date in the EXIF directory behind tag 0x8769.
+
+const ORIENTATION: u16 = 0x0112;
+const DATE_TIME: u16 = 0x0132;
+const EXIF_IFD_POINTER: u16 = 0x8769;
+const DATE_TIME_ORIGINAL: u16 = 0x9003;
+const GPS_IFD_POINTER: u16 = 0x8825;
+const GPS_LATITUDE_REF: u16 = 1;
+const GPS_LATITUDE: u16 = 2;
+const GPS_LONGITUDE_REF: u16 = 3;
+const GPS_LONGITUDE: u16 = 4;
+
+// The latitude and longitude in decimal degrees, negative for south and west, or None if the file
+// has no GPS position.
+pub fn gps_position(bytes: &[u8]) -> Result<Option<(f64, f64)>, String> {
+    let tiff = match find_tiff(bytes)? {
+        Some(tiff) => tiff,
+        None => return Ok(None),
+    };
+    let tiff = Tiff::new(tiff)?;
+    let ifd0 = tiff.u32(4)? as usize;
+    let gps_ifd = match tiff.find_entry(ifd0, GPS_IFD_POINTER)? {
+        Some(entry) => tiff.u32(entry + 8)? as usize,
+        None => return Ok(None),
+    };
+
+    let coordinate = |value_tag: u16, ref_tag: u16, negative: u8| -> Result<Option<f64>, String> {
+        let (value, reference) = match (
+            tiff.find_entry(gps_ifd, value_tag)?,
+            tiff.find_entry(gps_ifd, ref_tag)?,
+        ) {
+            (Some(value), Some(reference)) => (value, reference),
+            _ => return Ok(None),
+        };
+        // Degrees, minutes and seconds as three RATIONALs, stored outside the entry.
+        let offset = tiff.u32(value + 8)? as usize;
+        let mut degrees = 0.0;
+        for (i, unit) in [1.0, 60.0, 3600.0].iter().enumerate() {
+            let numerator = tiff.u32(offset + i * 8)? as f64;
+            let denominator = tiff.u32(offset + i * 8 + 4)? as f64;
+            if denominator != 0.0 {
+                degrees += numerator / denominator / unit;
+            }
+        }
+        // The reference is a one-letter ASCII string, stored in the entry itself.
+        let sign = if tiff.byte(reference + 8)? == negative {
+            -1.0
+        } else {
+            1.0
+        };
+        Ok(Some(sign * degrees))
+    };
+
+    match (
+        coordinate(GPS_LATITUDE, GPS_LATITUDE_REF, b'S')?,
+        coordinate(GPS_LONGITUDE, GPS_LONGITUDE_REF, b'W')?,
+    ) {
+        (Some(latitude), Some(longitude)) => Ok(Some((latitude, longitude))),
+        _ => Ok(None),
+    }
+}
+
+// The orientation tag from IFD0: 1 for pixels stored the way up they are meant to be seen, up to 8
+// for the combinations of rotating by quarter turns and mirroring. None if the file has no tag,
+// which includes every format but JPEG and TIFF.
+pub fn orientation(bytes: &[u8]) -> Result<Option<u16>, String> {
+    let tiff = match find_tiff_in_any(bytes)? {
+        Some(tiff) => tiff,
+        None => return Ok(None),
+    };
+    let tiff = Tiff::new(tiff)?;
+    let ifd0 = tiff.u32(4)? as usize;
+    match tiff.find_entry(ifd0, ORIENTATION)? {
+        // A single SHORT, stored in the entry itself.
+        Some(entry) => Ok(Some(tiff.u16(entry + 8)?)),
+        None => Ok(None),
+    }
+}
+
+// When the photo was taken, as `YYYY:MM:DD HH:MM:SS` in the camera's local time, which sorts
+// chronologically as a string. The original capture date is preferred over the date the file was
+// last changed. None if the file has neither, or only the all-zero placeholder some cameras write.
+pub fn capture_date(bytes: &[u8]) -> Result<Option<String>, String> {
+    let tiff = match find_tiff_in_any(bytes)? {
+        Some(tiff) => tiff,
+        None => return Ok(None),
+    };
+    let tiff = Tiff::new(tiff)?;
+    let ifd0 = tiff.u32(4)? as usize;
+
+    let mut entries = Vec::new();
+    if let Some(pointer) = tiff.find_entry(ifd0, EXIF_IFD_POINTER)? {
+        let exif_ifd = tiff.u32(pointer + 8)? as usize;
+        entries.push(tiff.find_entry(exif_ifd, DATE_TIME_ORIGINAL)?);
+    }
+    entries.push(tiff.find_entry(ifd0, DATE_TIME)?);
+
+    for entry in entries.into_iter().flatten() {
+        // An ASCII string of 20 bytes including the terminating NUL, too long to fit in the entry.
+        let count = tiff.u32(entry + 4)? as usize;
+        if count < 20 {
+            continue;
+        }
+        let offset = tiff.u32(entry + 8)? as usize;
+        let date = String::from_utf8_lossy(tiff.bytes(offset, 19)?).into_owned();
+        let well_formed = date.char_indices().all(|(i, c)| match i {
+            4 | 7 | 13 | 16 => c == ':',
+            10 => c == ' ',
+            _ => c.is_ascii_digit(),
+        });
+        if well_formed && !date.starts_with("0000") {
+            return Ok(Some(date));
+        }
+    }
+    Ok(None)
+}
+
+// Like `find_tiff`, but formats that can't carry EXIF data here just have none.
+fn find_tiff_in_any(bytes: &[u8]) -> Result<Option<&[u8]>, String> {
+    match find_tiff(bytes) {
+        Err(_) if !bytes.starts_with(&[0xFF, 0xD8]) => Ok(None),
+        result => result,
+    }
+}
+
+// The embedded TIFF structure of a JPEG file, or the whole file if it is a TIFF itself.
+fn find_tiff(bytes: &[u8]) -> Result<Option<&[u8]>, String> {
+    if bytes.starts_with(b"II*\0") || bytes.starts_with(b"MM\0*") {
+        return Ok(Some(bytes));
+    }
+    if !bytes.starts_with(&[0xFF, 0xD8]) {
+        return Err("Only JPEG and TIFF files carry EXIF data!".to_string());
+    }
+    let mut pos = 2;
+    while pos + 4 <= bytes.len() && bytes[pos] == 0xFF {
+        let marker = bytes[pos + 1];
+        // Start of scan: the compressed image data follows, and no more metadata.
+        if marker == 0xDA {
+            break;
+        }
+        let length = u16::from_be_bytes([bytes[pos + 2], bytes[pos + 3]]) as usize;
+        let segment = bytes
+            .get(pos + 4..pos + 2 + length)
+            .ok_or_else(|| "The JPEG file is truncated!".to_string())?;
+        if marker == 0xE1 && segment.starts_with(b"Exif\0\0") {
+            return Ok(Some(&segment[6..]));
+        }
+        pos += 2 + length;
+    }
+    Ok(None)
+}
+
+struct Tiff<'a> {
+    bytes: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(bytes: &'a [u8]) -> Result<Self, String> {
+        let big_endian = match bytes.get(0..2) {
+            Some(b"II") => false,
+            Some(b"MM") => true,
+            _ => return Err("The EXIF data has an unknown byte order!".to_string()),
+        };
+        Ok(Tiff { bytes, big_endian })
+    }
+
+    fn slice<const N: usize>(&self, offset: usize) -> Result<[u8; N], String> {
+        self.bytes
+            .get(offset..offset + N)
+            .map(|slice| {
+                let mut array = [0; N];
+                array.copy_from_slice(slice);
+                array
+            })
+            .ok_or_else(|| "The EXIF data is truncated!".to_string())
+    }
+
+    fn bytes(&self, offset: usize, len: usize) -> Result<&'a [u8], String> {
+        self.bytes
+            .get(offset..offset + len)
+            .ok_or_else(|| "The EXIF data is truncated!".to_string())
+    }
+
+    fn byte(&self, offset: usize) -> Result<u8, String> {
+        Ok(self.slice::<1>(offset)?[0])
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16, String> {
+        let bytes = self.slice(offset)?;
+        Ok(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32, String> {
+        let bytes = self.slice(offset)?;
+        Ok(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    // The offset of the entry for `tag` in the directory at `ifd`.
+    fn find_entry(&self, ifd: usize, tag: u16) -> Result<Option<usize>, String> {
+        let count = self.u16(ifd)? as usize;
+        for i in 0..count {
+            let entry = ifd + 2 + i * 12;
+            if self.u16(entry)? == tag {
+                return Ok(Some(entry));
+            }
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,175 @@
+// Images as C or Rust source code, for firmware that has no file system or image decoder and wants
+// its bitmaps compiled in.
+
+use image::RgbImage;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum Layout {
+    // Two bytes per pixel, 5 bits red, 6 green and 5 blue, high byte first as most SPI displays
+    // expect.
+    Rgb565,
+    // Three bytes per pixel, red, green, blue.
+    Rgb888,
+    // One bit per pixel, set for light pixels, the leftmost pixel in the most significant bit. Every
+    // row starts on a fresh byte, so the last byte of a row is padded with zeros when the width
+    // isn't a multiple of 8. This is the layout of SSD1306-style OLED frame buffers in horizontal
+    // addressing mode.
+    Mono,
+}
+
+impl Layout {
+    fn describe(self) -> &'static str {
+        match self {
+            Layout::Rgb565 => "RGB565, 2 bytes per pixel, high byte first",
+            Layout::Rgb888 => "RGB888, 3 bytes per pixel",
+            Layout::Mono => "1 bit per pixel, MSB first, rows padded to whole bytes",
+        }
+    }
+}
+
+// `threshold` is the luma at or above which a pixel counts as light in the `Mono` layout.
+pub fn encode(img: &RgbImage, layout: Layout, threshold: u8) -> Vec<u8> {
+    match layout {
+        Layout::Rgb565 => img
+            .pixels()
+            .flat_map(|p| {
+                let value =
+                    ((p[0] as u16 >> 3) << 11) | ((p[1] as u16 >> 2) << 5) | (p[2] as u16 >> 3);
+                value.to_be_bytes()
+            })
+            .collect(),
+        Layout::Rgb888 => img.as_raw().clone(),
+        Layout::Mono => {
+            let (width, height) = img.dimensions();
+            let bytes_per_row = width.div_ceil(8) as usize;
+            let mut bytes = vec![0u8; bytes_per_row * height as usize];
+            for (x, y, p) in img.enumerate_pixels() {
+                let luma = (2126 * p[0] as u32 + 7152 * p[1] as u32 + 722 * p[2] as u32) / 10000;
+                if luma >= threshold as u32 {
+                    bytes[y as usize * bytes_per_row + x as usize / 8] |= 0x80 >> (x % 8);
+                }
+            }
+            bytes
+        }
+    }
+}
+
+// The source for a C header (`rust == false`) or a Rust module defining `name`, `name_WIDTH` and
+// `name_HEIGHT`.
+pub fn source(
+    name: &str,
+    rust: bool,
+    width: u32,
+    height: u32,
+    layout: Layout,
+    bytes: &[u8],
+) -> String {
+    let mut source = format!(
+        "// Generated by mirage: {}x{} pixels, {}.\n",
+        width,
+        height,
+        layout.describe()
+    );
+    if rust {
+        source.push_str(&format!(
+            "\npub const {0}_WIDTH: u32 = {1};\npub const {0}_HEIGHT: u32 = {2};\n\npub const {0}: [u8; {3}] = [\n",
+            name,
+            width,
+            height,
+            bytes.len()
+        ));
+    } else {
+        source.push_str(&format!(
+            "\n#include <stdint.h>\n\n#define {0}_WIDTH {1}\n#define {0}_HEIGHT {2}\n\nconst uint8_t {0}[{3}] = {{\n",
+            name,
+            width,
+            height,
+            bytes.len()
+        ));
+    }
+    for line in bytes.chunks(12) {
+        let line = line
+            .iter()
+            .map(|byte| format!("0x{:02x},", byte))
+            .collect::<Vec<_>>();
+        source.push_str(&format!("    {}\n", line.join(" ")));
+    }
+    source.push_str(if rust { "];\n" } else { "};\n" });
+    source
+}
+
+// An identifier for the array, taken from the output file's name: `logo-small.h` becomes
+// `LOGO_SMALL`.
+pub fn default_name(outfile: &str) -> String {
+    let stem = std::path::Path::new(outfile)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut name = stem
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        name.insert(0, '_');
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Row 0 holds one pixel of each interesting color, the other rows are black with a white pixel on
+    // the diagonal.
+    fn fixture() -> RgbImage {
+        let row = [
+            [255, 0, 0],
+            [0, 255, 0],
+            [0, 0, 255],
+            [255, 255, 255],
+            [0, 0, 0],
+            [128, 128, 128],
+            [8, 4, 8],
+            [255, 255, 0],
+        ];
+        RgbImage::from_fn(8, 8, |x, y| match y {
+            0 => image::Rgb(row[x as usize]),
+            _ if x == y => image::Rgb([255; 3]),
+            _ => image::Rgb([0; 3]),
+        })
+    }
+
+    #[test]
+    fn rgb565_packs_the_top_bits_high_byte_first() {
+        let bytes = encode(&fixture(), Layout::Rgb565, 128);
+        assert_eq!(bytes.len(), 8 * 8 * 2);
+        assert_eq!(
+            bytes[..16],
+            [
+                0xf8, 0x00, 0x07, 0xe0, 0x00, 0x1f, 0xff, 0xff, 0x00, 0x00, 0x84, 0x10, 0x08, 0x21,
+                0xff, 0xe0
+            ]
+        );
+        assert_eq!(bytes[2 * (8 + 1)..2 * (8 + 2)], [0xff, 0xff]);
+    }
+
+    #[test]
+    fn mono_packs_light_pixels_msb_first_with_padded_rows() {
+        assert_eq!(
+            encode(&fixture(), Layout::Mono, 128),
+            [0x55, 0x40, 0x20, 0x10, 0x08, 0x04, 0x02, 0x01]
+        );
+        let white = RgbImage::from_pixel(10, 2, image::Rgb([255; 3]));
+        assert_eq!(encode(&white, Layout::Mono, 128), [0xff, 0xc0, 0xff, 0xc0]);
+        assert_eq!(
+            encode(&fixture(), Layout::Rgb888, 128),
+            fixture().into_raw()
+        );
+    }
+}
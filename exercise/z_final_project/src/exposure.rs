@@ -0,0 +1,215 @@
+// Exposure fusion (Mertens, Kautz and Van Reeth, 2007).
+//
+// Every input pixel gets a weight from its local contrast, its saturation and how close it is to a
+// mid-tone. Blending the inputs with those weights directly leaves visible seams, so the blend
+// happens per level of a Laplacian pyramid, with the weights smoothed by a gaussian pyramid.
+
+use image::imageops::{self, FilterType};
+use image::{ImageBuffer, Luma, Pixel, Rgb, Rgb32FImage};
+
+type WeightMap = ImageBuffer<Luma<f32>, Vec<f32>>;
+
+// Fuse same-sized exposures of one scene, with channel values in 0..=1, into a single image.
+pub fn merge(exposures: &[Rgb32FImage]) -> Rgb32FImage {
+    let (width, height) = exposures[0].dimensions();
+    let levels = pyramid_levels(width, height);
+
+    let mut weights = exposures.iter().map(weight_map).collect::<Vec<_>>();
+    normalize_weights(&mut weights);
+
+    let mut blended: Option<Vec<Rgb32FImage>> = None;
+    for (exposure, weight) in exposures.iter().zip(weights) {
+        let laplacian = laplacian_pyramid(exposure.clone(), levels);
+        let weight_pyramid = gaussian_pyramid(weight, levels);
+        let blended = blended.get_or_insert_with(|| {
+            laplacian
+                .iter()
+                .map(|level| Rgb32FImage::new(level.width(), level.height()))
+                .collect()
+        });
+        for ((out, detail), weight) in blended.iter_mut().zip(&laplacian).zip(&weight_pyramid) {
+            for (x, y, pixel) in out.enumerate_pixels_mut() {
+                let w = weight.get_pixel(x, y)[0];
+                let d = detail.get_pixel(x, y);
+                for c in 0..3 {
+                    pixel[c] += w * d[c];
+                }
+            }
+        }
+    }
+
+    let mut merged = collapse(blended.unwrap());
+    for pixel in merged.pixels_mut() {
+        for c in 0..3 {
+            pixel[c] = pixel[c].clamp(0.0, 1.0);
+        }
+    }
+    merged
+}
+
+// Stop halving once the smallest level would be only a few pixels across.
+fn pyramid_levels(width: u32, height: u32) -> usize {
+    let mut levels = 1;
+    let mut size = width.min(height);
+    while size > 8 {
+        size /= 2;
+        levels += 1;
+    }
+    levels
+}
+
+fn weight_map(img: &Rgb32FImage) -> WeightMap {
+    let (width, height) = img.dimensions();
+    let gray = ImageBuffer::from_fn(width, height, |x, y| {
+        let p = img.get_pixel(x, y);
+        Luma([0.2126 * p[0] + 0.7152 * p[1] + 0.0722 * p[2]])
+    });
+    let at = |x: i64, y: i64| {
+        let x = x.clamp(0, width as i64 - 1) as u32;
+        let y = y.clamp(0, height as i64 - 1) as u32;
+        gray.get_pixel(x, y)[0]
+    };
+
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let (xi, yi) = (x as i64, y as i64);
+        let contrast = (at(xi - 1, yi) + at(xi + 1, yi) + at(xi, yi - 1) + at(xi, yi + 1)
+            - 4.0 * at(xi, yi))
+        .abs();
+
+        let p = img.get_pixel(x, y);
+        let mean = (p[0] + p[1] + p[2]) / 3.0;
+        let saturation =
+            (((p[0] - mean).powi(2) + (p[1] - mean).powi(2) + (p[2] - mean).powi(2)) / 3.0).sqrt();
+
+        let well_exposed = p
+            .channels()
+            .iter()
+            .map(|v| (-(v - 0.5).powi(2) / (2.0 * 0.2 * 0.2)).exp())
+            .product::<f32>();
+
+        Luma([contrast * saturation * well_exposed + 1e-12])
+    })
+}
+
+// Scale the weights so that they sum to one at every pixel.
+fn normalize_weights(weights: &mut [WeightMap]) {
+    let (width, height) = weights[0].dimensions();
+    for y in 0..height {
+        for x in 0..width {
+            let total: f32 = weights.iter().map(|w| w.get_pixel(x, y)[0]).sum();
+            for weight in weights.iter_mut() {
+                weight.get_pixel_mut(x, y)[0] /= total;
+            }
+        }
+    }
+}
+
+fn downsample<P>(img: &ImageBuffer<P, Vec<f32>>) -> ImageBuffer<P, Vec<f32>>
+where
+    P: Pixel<Subpixel = f32> + 'static,
+{
+    let width = img.width().div_ceil(2);
+    let height = img.height().div_ceil(2);
+    imageops::resize(img, width, height, FilterType::Triangle)
+}
+
+fn upsample<P>(img: &ImageBuffer<P, Vec<f32>>, width: u32, height: u32) -> ImageBuffer<P, Vec<f32>>
+where
+    P: Pixel<Subpixel = f32> + 'static,
+{
+    imageops::resize(img, width, height, FilterType::Triangle)
+}
+
+fn gaussian_pyramid<P>(
+    img: ImageBuffer<P, Vec<f32>>,
+    levels: usize,
+) -> Vec<ImageBuffer<P, Vec<f32>>>
+where
+    P: Pixel<Subpixel = f32> + 'static,
+{
+    let mut pyramid = vec![img];
+    while pyramid.len() < levels {
+        let next = downsample(pyramid.last().unwrap());
+        pyramid.push(next);
+    }
+    pyramid
+}
+
+// Each level holds the detail lost by downsampling the level above, except the last, which holds
+// the remaining low frequencies.
+fn laplacian_pyramid(img: Rgb32FImage, levels: usize) -> Vec<Rgb32FImage> {
+    let gaussian = gaussian_pyramid(img, levels);
+    let mut pyramid = Vec::with_capacity(levels);
+    for i in 0..levels - 1 {
+        let (width, height) = gaussian[i].dimensions();
+        let expanded = upsample(&gaussian[i + 1], width, height);
+        let mut detail = gaussian[i].clone();
+        for (pixel, low) in detail.pixels_mut().zip(expanded.pixels()) {
+            for c in 0..3 {
+                pixel[c] -= low[c];
+            }
+        }
+        pyramid.push(detail);
+    }
+    pyramid.push(gaussian[levels - 1].clone());
+    pyramid
+}
+
+fn collapse(mut pyramid: Vec<Rgb32FImage>) -> Rgb32FImage {
+    let mut img = pyramid.pop().unwrap();
+    while let Some(detail) = pyramid.pop() {
+        let (width, height) = detail.dimensions();
+        let mut expanded: ImageBuffer<Rgb<f32>, Vec<f32>> = upsample(&img, width, height);
+        for (pixel, d) in expanded.pixels_mut().zip(detail.pixels()) {
+            for c in 0..3 {
+                pixel[c] += d[c];
+            }
+        }
+        img = expanded;
+    }
+    img
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mean(img: &Rgb32FImage) -> f32 {
+        img.pixels()
+            .map(|p| (p[0] + p[1] + p[2]) / 3.0)
+            .sum::<f32>()
+            / (img.width() * img.height()) as f32
+    }
+
+    fn clipped(img: &Rgb32FImage) -> usize {
+        img.pixels()
+            .filter(|p| p.0.iter().any(|&v| v >= 1.0 || v <= 0.0))
+            .count()
+    }
+
+    #[test]
+    fn fusing_an_under_and_over_exposure_reduces_clipping() {
+        let scene = Rgb32FImage::from_fn(64, 64, |x, y| {
+            let v = (x as f32 + 0.5) / 64.0;
+            Rgb([v, v * (0.5 + y as f32 / 128.0), 1.0 - v])
+        });
+        let expose = |gain: f32| {
+            let mut img = scene.clone();
+            for pixel in img.pixels_mut() {
+                for c in 0..3 {
+                    pixel[c] = (pixel[c] * gain).clamp(0.0, 1.0);
+                }
+            }
+            img
+        };
+        let (under, over) = (expose(0.25), expose(4.0));
+        let merged = merge(&[under.clone(), over.clone()]);
+
+        assert!(mean(&under) < mean(&merged) && mean(&merged) < mean(&over));
+        assert!(
+            clipped(&merged) < clipped(&over) / 4,
+            "{}",
+            clipped(&merged)
+        );
+    }
+}
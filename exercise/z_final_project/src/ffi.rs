@@ -0,0 +1,148 @@
+// A C interface for embedding the library in non-Rust applications. Enable it with the `ffi`
+// feature and link against the cdylib; the matching declarations are in include/mirage.h.
+//
+// No panic may unwind into C, so every entry point runs inside `catch_unwind`. Failures are
+// reported with a null pointer or -1, and `it_last_error_message` describes the most recent one.
+
+use image::DynamicImage;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_float, c_int, c_uint};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+pub struct ItImage {
+    image: DynamicImage,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', ""))
+        .unwrap_or_else(|_| CString::new("unknown error").unwrap());
+    LAST_ERROR.with(|cell| *cell.borrow_mut() = Some(message));
+}
+
+// Run `f`, turning both errors and panics into `fallback` plus a stored error message.
+fn guard<T>(fallback: T, f: impl FnOnce() -> Result<T, String>) -> T {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => value,
+        Ok(Err(message)) => {
+            set_last_error(message);
+            fallback
+        }
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            set_last_error(format!("internal error: {}", message));
+            fallback
+        }
+    }
+}
+
+unsafe fn path_arg<'a>(path: *const c_char) -> Result<&'a str, String> {
+    if path.is_null() {
+        return Err("path is NULL".to_string());
+    }
+    CStr::from_ptr(path)
+        .to_str()
+        .map_err(|_| "path is not valid UTF-8".to_string())
+}
+
+unsafe fn image_arg<'a>(img: *mut ItImage) -> Result<&'a mut ItImage, String> {
+    img.as_mut().ok_or_else(|| "image is NULL".to_string())
+}
+
+/// Load an image from `path`. Returns NULL on failure.
+///
+/// # Safety
+///
+/// `path` must be NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn it_load(path: *const c_char) -> *mut ItImage {
+    guard(ptr::null_mut(), || {
+        let path = path_arg(path)?;
+        let image = image::open(path).map_err(|err| format!("Failed to open {}: {}", path, err))?;
+        Ok(Box::into_raw(Box::new(ItImage { image })))
+    })
+}
+
+/// Gaussian blur `img` in place. Returns 0 on success and -1 on failure.
+///
+/// # Safety
+///
+/// `img` must be NULL or a live pointer returned by `it_load`.
+#[no_mangle]
+pub unsafe extern "C" fn it_blur(img: *mut ItImage, sigma: c_float) -> c_int {
+    guard(-1, || {
+        let img = image_arg(img)?;
+        img.image = img.image.blur(sigma);
+        Ok(0)
+    })
+}
+
+/// Rotate `img` clockwise by 90, 180 or 270 degrees. Returns 0 on success and -1 on failure.
+///
+/// # Safety
+///
+/// `img` must be NULL or a live pointer returned by `it_load`.
+#[no_mangle]
+pub unsafe extern "C" fn it_rotate(img: *mut ItImage, degrees: c_uint) -> c_int {
+    guard(-1, || {
+        let img = image_arg(img)?;
+        img.image = match degrees {
+            90 => img.image.rotate90(),
+            180 => img.image.rotate180(),
+            270 => img.image.rotate270(),
+            _ => return Err(format!("{} is not a valid rotation amount", degrees)),
+        };
+        Ok(0)
+    })
+}
+
+/// Save `img` to `path`, in the format named by its extension. Returns 0 on success and -1 on
+/// failure.
+///
+/// # Safety
+///
+/// `img` must be NULL or a live pointer returned by `it_load`, and `path` must be NULL or a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn it_save(img: *const ItImage, path: *const c_char) -> c_int {
+    guard(-1, || {
+        let img = img.as_ref().ok_or("image is NULL")?;
+        let path = path_arg(path)?;
+        img.image
+            .save(path)
+            .map_err(|err| format!("Failed writing {}: {}", path, err))?;
+        Ok(0)
+    })
+}
+
+/// Release an image returned by `it_load`. Passing NULL does nothing.
+///
+/// # Safety
+///
+/// `img` must be NULL or a live pointer returned by `it_load`, and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn it_free(img: *mut ItImage) {
+    if !img.is_null() {
+        let _ = catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(img))));
+    }
+}
+
+/// The message for the most recent failure on this thread, or NULL if nothing has failed. The
+/// string stays valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn it_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|cell| {
+        cell.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
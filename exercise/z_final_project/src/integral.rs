@@ -0,0 +1,63 @@
+// Summed-area tables (integral images) of a grayscale image and of its squares.
+//
+// Once the tables are built, the sum of any rectangle takes four lookups, so the mean and variance
+// of a pixel's neighborhood cost the same whatever the size of the neighborhood.
+
+use image::GrayImage;
+
+pub struct Integral {
+    width: u32,
+    height: u32,
+    // sums[y * (width + 1) + x] holds the sum of all pixels above and to the left of (x, y), and
+    // squares the sum of their squares.
+    sums: Vec<u64>,
+    squares: Vec<u64>,
+}
+
+impl Integral {
+    pub fn new(img: &GrayImage) -> Self {
+        let (width, height) = img.dimensions();
+        let stride = width as usize + 1;
+        let mut sums = vec![0u64; stride * (height as usize + 1)];
+        let mut squares = sums.clone();
+        for y in 0..height as usize {
+            let mut row_sum = 0;
+            let mut row_squares = 0;
+            for x in 0..width as usize {
+                let value = img.get_pixel(x as u32, y as u32)[0] as u64;
+                row_sum += value;
+                row_squares += value * value;
+                sums[(y + 1) * stride + x + 1] = sums[y * stride + x + 1] + row_sum;
+                squares[(y + 1) * stride + x + 1] = squares[y * stride + x + 1] + row_squares;
+            }
+        }
+        Integral {
+            width,
+            height,
+            sums,
+            squares,
+        }
+    }
+
+    // The mean and variance of the `size` x `size` square around (x, y). Near the edges only the
+    // part of the square inside the image counts. When `size` is even the square reaches one pixel
+    // further right and down than left and up.
+    pub fn window(&self, x: u32, y: u32, size: u32) -> (f64, f64) {
+        let before = (size / 2) as i64;
+        let after = (size - 1 - size / 2) as i64;
+        let x0 = (x as i64 - before).max(0) as usize;
+        let y0 = (y as i64 - before).max(0) as usize;
+        let x1 = (x as i64 + after + 1).min(self.width as i64) as usize;
+        let y1 = (y as i64 + after + 1).min(self.height as i64) as usize;
+        let stride = self.width as usize + 1;
+        let area = |table: &[u64]| {
+            table[y1 * stride + x1] + table[y0 * stride + x0]
+                - table[y0 * stride + x1]
+                - table[y1 * stride + x0]
+        };
+        let count = ((x1 - x0) * (y1 - y0)) as f64;
+        let mean = area(&self.sums) as f64 / count;
+        let variance = area(&self.squares) as f64 / count - mean * mean;
+        (mean, variance.max(0.0))
+    }
+}
@@ -0,0 +1,865 @@
+// Lossless rotation and mirroring of JPEGs, the way jpegtran does it.
+//
+// A JPEG stores each 8x8 block of each component as quantized DCT coefficients. Mirroring a block
+// only negates its odd horizontal (or vertical) frequencies, and transposing it swaps the two
+// frequency axes, so moving whole blocks around and adjusting their coefficients turns the image
+// without going back to pixels. Nothing is requantized, so turning a photo any number of times
+// loses nothing.
+//
+// Only sequential Huffman-coded files (SOF0 and SOF1) are handled, which covers what cameras and
+// most encoders write. A block can only be moved whole, so mirroring needs the mirrored edge to
+// fall on an MCU boundary, or the partial blocks on the far edge would end up on the near one.
+// Everything else is reported as an error for the caller to fall back on decoding. APPn and COM
+// segments, where EXIF and ICC data live, are copied through as they are.
+
+use std::convert::TryFrom;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Transform {
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    FlipHorizontal,
+    FlipVertical,
+}
+
+// The position in an 8x8 block, counted row by row, of each coefficient in the order they are
+// coded in.
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+type Block = [i16; 64];
+
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    quant_table: u8,
+    dc_table: u8,
+    ac_table: u8,
+    // The size of the block grid, padded out to whole MCUs.
+    blocks_wide: usize,
+    blocks_high: usize,
+    blocks: Vec<Block>,
+}
+
+struct Frame {
+    // SOF0 or SOF1.
+    marker: u8,
+    precision: u8,
+    width: usize,
+    height: usize,
+    components: Vec<Component>,
+    // Each table's precision (0 for 8-bit, 1 for 16-bit) and values, row by row.
+    quant_tables: [Option<(u8, [u16; 64])>; 4],
+    // APPn and COM segments, marker included.
+    metadata: Vec<Vec<u8>>,
+}
+
+impl Frame {
+    fn max_h(&self) -> usize {
+        self.components.iter().map(|c| c.h).max().unwrap()
+    }
+
+    fn max_v(&self) -> usize {
+        self.components.iter().map(|c| c.v).max().unwrap()
+    }
+
+    fn mcus_wide(&self) -> usize {
+        self.width.div_ceil(8 * self.max_h())
+    }
+
+    fn mcus_high(&self) -> usize {
+        self.height.div_ceil(8 * self.max_v())
+    }
+}
+
+// Apply `transform` to the JPEG in `bytes`, returning the new file.
+pub fn transform(bytes: &[u8], transform: Transform) -> Result<Vec<u8>, String> {
+    let mut frame = decode(bytes)?;
+
+    // Which edges end up mirrored, in terms of the original image.
+    let (mirrors_x, mirrors_y) = match transform {
+        Transform::Rotate90 => (false, true),
+        Transform::Rotate180 => (true, true),
+        Transform::Rotate270 => (true, false),
+        Transform::FlipHorizontal => (true, false),
+        Transform::FlipVertical => (false, true),
+    };
+    let (mcu_width, mcu_height) = (8 * frame.max_h(), 8 * frame.max_v());
+    if mirrors_x && !frame.width.is_multiple_of(mcu_width) {
+        return Err(format!(
+            "the width, {}, is not a multiple of the {}-pixel MCU width",
+            frame.width, mcu_width
+        ));
+    }
+    if mirrors_y && !frame.height.is_multiple_of(mcu_height) {
+        return Err(format!(
+            "the height, {}, is not a multiple of the {}-pixel MCU height",
+            frame.height, mcu_height
+        ));
+    }
+
+    match transform {
+        Transform::Rotate90 => {
+            transpose(&mut frame);
+            flip_horizontal(&mut frame);
+        }
+        Transform::Rotate180 => {
+            flip_horizontal(&mut frame);
+            flip_vertical(&mut frame);
+        }
+        Transform::Rotate270 => {
+            transpose(&mut frame);
+            flip_vertical(&mut frame);
+        }
+        Transform::FlipHorizontal => flip_horizontal(&mut frame),
+        Transform::FlipVertical => flip_vertical(&mut frame),
+    }
+    encode(&frame)
+}
+
+fn transpose(frame: &mut Frame) {
+    std::mem::swap(&mut frame.width, &mut frame.height);
+    for component in &mut frame.components {
+        let (wide, high) = (component.blocks_wide, component.blocks_high);
+        let mut blocks = vec![[0; 64]; wide * high];
+        for y in 0..high {
+            for x in 0..wide {
+                let old = &component.blocks[y * wide + x];
+                let new = &mut blocks[x * high + y];
+                for row in 0..8 {
+                    for col in 0..8 {
+                        new[col * 8 + row] = old[row * 8 + col];
+                    }
+                }
+            }
+        }
+        component.blocks = blocks;
+        std::mem::swap(&mut component.blocks_wide, &mut component.blocks_high);
+        std::mem::swap(&mut component.h, &mut component.v);
+    }
+    // Coefficients keep their quantization step, so the steps move with them.
+    for (_, table) in frame.quant_tables.iter_mut().flatten() {
+        let old = *table;
+        for row in 0..8 {
+            for col in 0..8 {
+                table[col * 8 + row] = old[row * 8 + col];
+            }
+        }
+    }
+}
+
+// Mirror the block grid and every block in it. Only exact when the width is a whole number of MCUs.
+fn flip_horizontal(frame: &mut Frame) {
+    for component in &mut frame.components {
+        let wide = component.blocks_wide;
+        for row in component.blocks.chunks_mut(wide) {
+            row.reverse();
+            for block in row {
+                for (i, coefficient) in block.iter_mut().enumerate() {
+                    if i % 2 == 1 {
+                        *coefficient = -*coefficient;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn flip_vertical(frame: &mut Frame) {
+    for component in &mut frame.components {
+        let wide = component.blocks_wide;
+        let mut rows = component
+            .blocks
+            .chunks(wide)
+            .rev()
+            .collect::<Vec<_>>()
+            .concat();
+        for block in &mut rows {
+            for (i, coefficient) in block.iter_mut().enumerate() {
+                if (i / 8) % 2 == 1 {
+                    *coefficient = -*coefficient;
+                }
+            }
+        }
+        component.blocks = rows;
+    }
+}
+
+// Huffman decoding tables, as in section F.2.2.3 of the JPEG standard.
+struct HuffmanTable {
+    max_code: [i32; 18],
+    offset: [i32; 17],
+    values: Vec<u8>,
+}
+
+impl HuffmanTable {
+    fn new(counts: &[u8], values: Vec<u8>) -> Self {
+        let mut max_code = [-1; 18];
+        let mut offset = [0; 17];
+        let mut code = 0;
+        let mut index = 0;
+        for length in 1..=16 {
+            let count = counts[length - 1] as i32;
+            offset[length] = index - code;
+            code += count;
+            index += count;
+            if count > 0 {
+                max_code[length] = code - 1;
+            }
+            code <<= 1;
+        }
+        // Makes the decoding loop stop at 17 bits.
+        max_code[17] = i32::MAX;
+        HuffmanTable {
+            max_code,
+            offset,
+            values,
+        }
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u32,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn bit(&mut self) -> Result<u32, String> {
+        if self.count == 0 {
+            let byte = match self.data.get(self.pos) {
+                Some(&0xff) => match self.data.get(self.pos + 1) {
+                    Some(0x00) => {
+                        self.pos += 2;
+                        0xff
+                    }
+                    // A marker: the data ran out, and decoders pad with zeros.
+                    _ => 0,
+                },
+                Some(&byte) => {
+                    self.pos += 1;
+                    byte
+                }
+                None => return Err("the file ends in the middle of the image data".to_string()),
+            };
+            self.bits = byte as u32;
+            self.count = 8;
+        }
+        self.count -= 1;
+        Ok((self.bits >> self.count) & 1)
+    }
+
+    fn bits(&mut self, n: u32) -> Result<u32, String> {
+        let mut value = 0;
+        for _ in 0..n {
+            value = (value << 1) | self.bit()?;
+        }
+        Ok(value)
+    }
+
+    fn decode(&mut self, table: &HuffmanTable) -> Result<u8, String> {
+        let mut code = self.bit()? as i32;
+        let mut length = 1;
+        while code > table.max_code[length] {
+            code = (code << 1) | self.bit()? as i32;
+            length += 1;
+        }
+        if length > 16 {
+            return Err("the image data has an invalid Huffman code".to_string());
+        }
+        table
+            .values
+            .get((table.offset[length] + code) as usize)
+            .copied()
+            .ok_or_else(|| "the image data has an invalid Huffman code".to_string())
+    }
+
+    // A coefficient coded as `size` extra bits, with the high bit clear for negative values.
+    fn signed(&mut self, size: u8) -> Result<i32, String> {
+        if size == 0 {
+            return Ok(0);
+        }
+        if size > 15 {
+            return Err("the image data has an invalid coefficient size".to_string());
+        }
+        let value = self.bits(size as u32)? as i32;
+        Ok(if value < 1 << (size - 1) {
+            value - (1 << size) + 1
+        } else {
+            value
+        })
+    }
+
+    // Skip to the restart marker after the current interval.
+    fn restart(&mut self) -> Result<(), String> {
+        self.count = 0;
+        while self.data.get(self.pos) == Some(&0xff) && self.data.get(self.pos + 1) == Some(&0xff) {
+            self.pos += 1;
+        }
+        match self.data.get(self.pos..self.pos + 2) {
+            Some([0xff, 0xd0..=0xd7]) => {
+                self.pos += 2;
+                Ok(())
+            }
+            _ => Err("a restart marker is missing from the image data".to_string()),
+        }
+    }
+}
+
+fn segment(bytes: &[u8], pos: usize) -> Result<&[u8], String> {
+    let length = match bytes.get(pos..pos + 2) {
+        Some(length) => u16::from_be_bytes([length[0], length[1]]) as usize,
+        None => return Err("the file ends in the middle of a segment".to_string()),
+    };
+    if length < 2 {
+        return Err("a segment has an invalid length".to_string());
+    }
+    bytes
+        .get(pos + 2..pos + length)
+        .ok_or_else(|| "the file ends in the middle of a segment".to_string())
+}
+
+fn decode(bytes: &[u8]) -> Result<Frame, String> {
+    if !bytes.starts_with(&[0xff, 0xd8]) {
+        return Err("the file is not a JPEG".to_string());
+    }
+    let mut frame: Option<Frame> = None;
+    let mut metadata = Vec::new();
+    let mut quant_tables = [None; 4];
+    let mut huffman_tables: [[Option<HuffmanTable>; 4]; 2] = Default::default();
+    let mut restart_interval = 0;
+    let mut pos = 2;
+    loop {
+        if bytes.get(pos) != Some(&0xff) {
+            return Err("a marker is missing".to_string());
+        }
+        while bytes.get(pos + 1) == Some(&0xff) {
+            pos += 1;
+        }
+        let marker = *bytes
+            .get(pos + 1)
+            .ok_or("the file ends before the end-of-image marker")?;
+        pos += 2;
+        if marker == 0xd9 {
+            break;
+        }
+        let data = segment(bytes, pos)?;
+        let next = pos + 2 + data.len();
+        match marker {
+            0xe0..=0xef | 0xfe => metadata.push(bytes[pos - 2..next].to_vec()),
+            0xdb => {
+                let mut rest = data;
+                while !rest.is_empty() {
+                    let precision = rest[0] >> 4;
+                    let id = (rest[0] & 15) as usize;
+                    let size = if precision == 0 { 64 } else { 128 };
+                    if precision > 1 || id > 3 || rest.len() < 1 + size {
+                        return Err("a quantization table is invalid".to_string());
+                    }
+                    let mut table = [0; 64];
+                    for (k, &position) in ZIGZAG.iter().enumerate() {
+                        table[position] = if precision == 0 {
+                            rest[1 + k] as u16
+                        } else {
+                            u16::from_be_bytes([rest[1 + 2 * k], rest[2 + 2 * k]])
+                        };
+                    }
+                    quant_tables[id] = Some((precision, table));
+                    rest = &rest[1 + size..];
+                }
+            }
+            0xc4 => {
+                let mut rest = data;
+                while !rest.is_empty() {
+                    if rest.len() < 17 || rest[0] >> 4 > 1 || rest[0] & 15 > 3 {
+                        return Err("a Huffman table is invalid".to_string());
+                    }
+                    let count = rest[1..17].iter().map(|&n| n as usize).sum::<usize>();
+                    let values = rest
+                        .get(17..17 + count)
+                        .ok_or("a Huffman table is invalid")?
+                        .to_vec();
+                    huffman_tables[(rest[0] >> 4) as usize][(rest[0] & 15) as usize] =
+                        Some(HuffmanTable::new(&rest[1..17], values));
+                    rest = &rest[17 + count..];
+                }
+            }
+            0xc0 | 0xc1 => {
+                if frame.is_some() || data.len() < 6 {
+                    return Err("the frame header is invalid".to_string());
+                }
+                frame = Some(frame_header(marker, data)?);
+            }
+            0xc2 | 0xc3 | 0xc5..=0xc7 | 0xc9..=0xcb | 0xcd..=0xcf => {
+                return Err(
+                    "progressive, lossless and arithmetic-coded JPEGs aren't supported".to_string(),
+                );
+            }
+            0xdd => {
+                if data.len() < 2 {
+                    return Err("the restart interval is invalid".to_string());
+                }
+                restart_interval = u16::from_be_bytes([data[0], data[1]]) as usize;
+            }
+            0xda => {
+                let frame = frame
+                    .as_mut()
+                    .ok_or("the image data comes before the frame header")?;
+                pos = scan(frame, data, bytes, next, &huffman_tables, restart_interval)?;
+                continue;
+            }
+            _ => {
+                return Err(format!(
+                    "the file has an unsupported marker 0x{:02x}",
+                    marker
+                ))
+            }
+        }
+        pos = next;
+    }
+
+    let mut frame = frame.ok_or("the file has no frame header")?;
+    frame.quant_tables = quant_tables;
+    frame.metadata = metadata;
+    for component in &frame.components {
+        if frame.quant_tables[component.quant_table as usize].is_none() {
+            return Err("a component's quantization table is missing".to_string());
+        }
+    }
+    Ok(frame)
+}
+
+fn frame_header(marker: u8, data: &[u8]) -> Result<Frame, String> {
+    let invalid = || "the frame header is invalid".to_string();
+    let precision = data[0];
+    let height = u16::from_be_bytes([data[1], data[2]]) as usize;
+    let width = u16::from_be_bytes([data[3], data[4]]) as usize;
+    let count = data[5] as usize;
+    if height == 0 {
+        return Err("images whose height comes after the image data aren't supported".to_string());
+    }
+    if width == 0 || count == 0 || count > 4 || data.len() < 6 + 3 * count {
+        return Err(invalid());
+    }
+    let mut components = data[6..6 + 3 * count]
+        .chunks(3)
+        .map(|c| {
+            let (h, v) = ((c[1] >> 4) as usize, (c[1] & 15) as usize);
+            if !(1..=4).contains(&h) || !(1..=4).contains(&v) || c[2] > 3 {
+                return Err(invalid());
+            }
+            Ok(Component {
+                id: c[0],
+                h,
+                v,
+                quant_table: c[2],
+                dc_table: 0,
+                ac_table: 0,
+                blocks_wide: 0,
+                blocks_high: 0,
+                blocks: Vec::new(),
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    // A lone component is always coded block by block, whatever its sampling factors say.
+    if count == 1 {
+        components[0].h = 1;
+        components[0].v = 1;
+    }
+    let mut frame = Frame {
+        marker,
+        precision,
+        width,
+        height,
+        components,
+        quant_tables: [None; 4],
+        metadata: Vec::new(),
+    };
+    let (mcus_wide, mcus_high) = (frame.mcus_wide(), frame.mcus_high());
+    for component in &mut frame.components {
+        component.blocks_wide = mcus_wide * component.h;
+        component.blocks_high = mcus_high * component.v;
+        component.blocks = vec![[0; 64]; component.blocks_wide * component.blocks_high];
+    }
+    Ok(frame)
+}
+
+// The blocks of one MCU of a scan over `components` (indices into the frame's components), as
+// (component, block index) pairs. A scan over a single component goes block by block over just
+// the blocks that cover the image, without padding them out to whole MCUs.
+fn mcu_blocks(frame: &Frame, components: &[usize], mcu: usize) -> Vec<(usize, usize)> {
+    if let [c] = *components {
+        let component = &frame.components[c];
+        let wide = (frame.width * component.h).div_ceil(8 * frame.max_h());
+        let (x, y) = (mcu % wide, mcu / wide);
+        return vec![(c, y * component.blocks_wide + x)];
+    }
+    let (mcu_x, mcu_y) = (mcu % frame.mcus_wide(), mcu / frame.mcus_wide());
+    let mut blocks = Vec::new();
+    for &c in components {
+        let component = &frame.components[c];
+        for v in 0..component.v {
+            for h in 0..component.h {
+                let x = mcu_x * component.h + h;
+                let y = mcu_y * component.v + v;
+                blocks.push((c, y * component.blocks_wide + x));
+            }
+        }
+    }
+    blocks
+}
+
+fn mcu_count(frame: &Frame, components: &[usize]) -> usize {
+    if let [c] = *components {
+        let component = &frame.components[c];
+        let wide = (frame.width * component.h).div_ceil(8 * frame.max_h());
+        let high = (frame.height * component.v).div_ceil(8 * frame.max_v());
+        return wide * high;
+    }
+    frame.mcus_wide() * frame.mcus_high()
+}
+
+// Decode the scan whose header is `header` and whose data starts at `start`, returning the
+// position of the marker after it.
+fn scan(
+    frame: &mut Frame,
+    header: &[u8],
+    bytes: &[u8],
+    start: usize,
+    huffman_tables: &[[Option<HuffmanTable>; 4]; 2],
+    restart_interval: usize,
+) -> Result<usize, String> {
+    let invalid = || "a scan header is invalid".to_string();
+    let count = *header.first().ok_or_else(invalid)? as usize;
+    if header.len() < 1 + 2 * count + 3 {
+        return Err(invalid());
+    }
+    let mut components = Vec::new();
+    for c in header[1..1 + 2 * count].chunks(2) {
+        let index = frame
+            .components
+            .iter()
+            .position(|component| component.id == c[0])
+            .ok_or_else(invalid)?;
+        let component = &mut frame.components[index];
+        component.dc_table = c[1] >> 4;
+        component.ac_table = c[1] & 15;
+        if component.dc_table > 3 || component.ac_table > 3 {
+            return Err(invalid());
+        }
+        components.push(index);
+    }
+    let spectral = &header[1 + 2 * count..];
+    if spectral != [0, 63, 0] {
+        return Err(invalid());
+    }
+
+    let table = |class: usize, id: u8| {
+        huffman_tables[class][id as usize]
+            .as_ref()
+            .ok_or_else(|| "a Huffman table is missing".to_string())
+    };
+    let mut reader = BitReader {
+        data: bytes,
+        pos: start,
+        bits: 0,
+        count: 0,
+    };
+    let mut predictions = vec![0i32; frame.components.len()];
+    let total = mcu_count(frame, &components);
+    for mcu in 0..total {
+        if restart_interval > 0 && mcu > 0 && mcu.is_multiple_of(restart_interval) {
+            reader.restart()?;
+            predictions.iter_mut().for_each(|p| *p = 0);
+        }
+        for (c, index) in mcu_blocks(frame, &components, mcu) {
+            let dc = table(0, frame.components[c].dc_table)?;
+            let ac = table(1, frame.components[c].ac_table)?;
+            let block = &mut frame.components[c].blocks[index];
+
+            let size = reader.decode(dc)?;
+            predictions[c] += reader.signed(size)?;
+            block[0] = i16::try_from(predictions[c]).map_err(|_| invalid())?;
+            let mut k = 1;
+            while k < 64 {
+                let symbol = reader.decode(ac)?;
+                let (run, size) = ((symbol >> 4) as usize, symbol & 15);
+                if size == 0 {
+                    if run == 15 {
+                        k += 16;
+                        continue;
+                    }
+                    break;
+                }
+                k += run;
+                if k > 63 {
+                    return Err("the image data has too many coefficients in a block".to_string());
+                }
+                block[ZIGZAG[k]] = reader.signed(size)? as i16;
+                k += 1;
+            }
+        }
+    }
+
+    // Skip any padding to the next marker that isn't a restart.
+    let mut pos = reader.pos;
+    loop {
+        match bytes.get(pos..pos + 2) {
+            Some([0xff, 0x00]) | Some([0xff, 0xd0..=0xd7]) => pos += 2,
+            Some([0xff, _]) => return Ok(pos),
+            Some(_) => pos += 1,
+            None => return Err("the file ends before the end-of-image marker".to_string()),
+        }
+    }
+}
+
+// A Huffman table with code lengths chosen for the given symbol frequencies, as the number of codes
+// of each length from 1 to 16 followed by the symbols in order of length. This is the procedure
+// from section K.2 of the standard, which limits codes to 16 bits and keeps the all-ones code
+// unused.
+fn optimal_table(frequencies: &[u32; 256]) -> ([u8; 16], Vec<u8>) {
+    let mut frequencies = frequencies.map(|f| f as u64).to_vec();
+    // A reserved symbol, so no real symbol gets the all-ones code.
+    frequencies.push(1);
+    let mut code_sizes = [0usize; 257];
+    let mut others = [usize::MAX; 257];
+    loop {
+        // The two least frequent symbols still in play, preferring later ones on ties.
+        let mut least = None;
+        let mut second = None;
+        for (i, &f) in frequencies.iter().enumerate() {
+            if f == 0 {
+                continue;
+            }
+            if least.is_none_or(|l: usize| f <= frequencies[l]) {
+                second = least;
+                least = Some(i);
+            } else if second.is_none_or(|s: usize| f <= frequencies[s]) {
+                second = Some(i);
+            }
+        }
+        let (mut a, mut b) = match (least, second) {
+            (Some(a), Some(b)) => (a, b),
+            _ => break,
+        };
+        frequencies[a] += frequencies[b];
+        frequencies[b] = 0;
+        code_sizes[a] += 1;
+        while others[a] != usize::MAX {
+            a = others[a];
+            code_sizes[a] += 1;
+        }
+        others[a] = b;
+        code_sizes[b] += 1;
+        while others[b] != usize::MAX {
+            b = others[b];
+            code_sizes[b] += 1;
+        }
+    }
+
+    let mut counts = [0u32; 33];
+    for &size in code_sizes.iter().filter(|&&size| size > 0) {
+        counts[size] += 1;
+    }
+    // Shorten codes longer than 16 bits by moving pairs of them up the tree.
+    for i in (17..=32).rev() {
+        while counts[i] > 0 {
+            let mut j = i - 2;
+            while counts[j] == 0 {
+                j -= 1;
+            }
+            counts[i] -= 2;
+            counts[i - 1] += 1;
+            counts[j + 1] += 2;
+            counts[j] -= 1;
+        }
+    }
+    // Drop the reserved symbol, which has the longest code.
+    let longest = (1..=16).rev().find(|&i| counts[i] > 0).unwrap();
+    counts[longest] -= 1;
+
+    let mut symbols = (0..256).filter(|&s| code_sizes[s] > 0).collect::<Vec<_>>();
+    symbols.sort_by_key(|&s| code_sizes[s]);
+    let mut lengths = [0u8; 16];
+    for (length, &count) in lengths.iter_mut().zip(&counts[1..=16]) {
+        *length = count as u8;
+    }
+    (lengths, symbols.into_iter().map(|s| s as u8).collect())
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, length: u32) {
+        for i in (0..length).rev() {
+            self.bits = (self.bits << 1) | ((value >> i) & 1);
+            self.count += 1;
+            if self.count == 8 {
+                self.bytes.push(self.bits as u8);
+                if self.bits == 0xff {
+                    self.bytes.push(0);
+                }
+                self.bits = 0;
+                self.count = 0;
+            }
+        }
+    }
+
+    // Pad the last byte with ones.
+    fn flush(&mut self) {
+        if self.count > 0 {
+            self.write(0xff, 8 - self.count);
+        }
+    }
+}
+
+// The size category of a coefficient and the extra bits that code it.
+fn magnitude(value: i32) -> (u8, u32) {
+    if value == 0 {
+        return (0, 0);
+    }
+    let size = 32 - value.unsigned_abs().leading_zeros();
+    let bits = if value < 0 { value - 1 } else { value };
+    (size as u8, bits as u32 & ((1 << size) - 1))
+}
+
+// Feed every symbol of the entropy-coded data to `emit`, as (class, table, symbol, extra bits,
+// number of extra bits), with class 0 for DC and 1 for AC.
+fn walk(frame: &Frame, components: &[usize], emit: &mut dyn FnMut(usize, u8, u8, u32, u8)) {
+    let mut predictions = vec![0i32; frame.components.len()];
+    for mcu in 0..mcu_count(frame, components) {
+        for (c, index) in mcu_blocks(frame, components, mcu) {
+            let component = &frame.components[c];
+            let block = &component.blocks[index];
+            let (size, bits) = magnitude(block[0] as i32 - predictions[c]);
+            emit(0, component.dc_table, size, bits, size);
+            predictions[c] = block[0] as i32;
+
+            let mut run = 0;
+            for &position in &ZIGZAG[1..] {
+                let coefficient = block[position] as i32;
+                if coefficient == 0 {
+                    run += 1;
+                    continue;
+                }
+                while run > 15 {
+                    emit(1, component.ac_table, 0xf0, 0, 0);
+                    run -= 16;
+                }
+                let (size, bits) = magnitude(coefficient);
+                emit(1, component.ac_table, (run << 4) | size, bits, size);
+                run = 0;
+            }
+            if run > 0 {
+                emit(1, component.ac_table, 0x00, 0, 0);
+            }
+        }
+    }
+}
+
+fn push_segment(out: &mut Vec<u8>, marker: u8, data: &[u8]) {
+    out.extend([0xff, marker]);
+    out.extend(((data.len() + 2) as u16).to_be_bytes());
+    out.extend(data);
+}
+
+fn encode(frame: &Frame) -> Result<Vec<u8>, String> {
+    let components = (0..frame.components.len()).collect::<Vec<_>>();
+    if components.len() > 1 && frame.components.iter().map(|c| c.h * c.v).sum::<usize>() > 10 {
+        return Err("the sampling factors need more than 10 blocks per MCU".to_string());
+    }
+
+    let mut frequencies = [[[0u32; 256]; 4]; 2];
+    walk(frame, &components, &mut |class, table, symbol, _, _| {
+        frequencies[class][table as usize][symbol as usize] += 1;
+    });
+    let mut codes = [[[(0u32, 0u32); 256]; 4]; 2];
+    let mut huffman_segment = Vec::new();
+    for class in 0..2 {
+        for table in 0..4 {
+            if frequencies[class][table].iter().all(|&f| f == 0) {
+                continue;
+            }
+            let (lengths, symbols) = optimal_table(&frequencies[class][table]);
+            huffman_segment.push(((class << 4) | table) as u8);
+            huffman_segment.extend(lengths);
+            huffman_segment.extend(&symbols);
+            let mut code = 0;
+            let mut symbols = symbols.iter();
+            for (length, &count) in (1..=16).zip(&lengths) {
+                for &symbol in symbols.by_ref().take(count as usize) {
+                    codes[class][table][symbol as usize] = (code, length);
+                    code += 1;
+                }
+                code <<= 1;
+            }
+        }
+    }
+
+    let mut out = vec![0xff, 0xd8];
+    for segment in &frame.metadata {
+        out.extend(segment);
+    }
+    for (id, table) in frame.quant_tables.iter().enumerate() {
+        let Some((precision, table)) = table else {
+            continue;
+        };
+        let mut data = vec![(precision << 4) | id as u8];
+        for &position in &ZIGZAG {
+            if *precision == 0 {
+                data.push(table[position] as u8);
+            } else {
+                data.extend(table[position].to_be_bytes());
+            }
+        }
+        push_segment(&mut out, 0xdb, &data);
+    }
+    let mut header = vec![frame.precision];
+    header.extend((frame.height as u16).to_be_bytes());
+    header.extend((frame.width as u16).to_be_bytes());
+    header.push(frame.components.len() as u8);
+    for component in &frame.components {
+        header.extend([
+            component.id,
+            ((component.h << 4) | component.v) as u8,
+            component.quant_table,
+        ]);
+    }
+    push_segment(&mut out, frame.marker, &header);
+    push_segment(&mut out, 0xc4, &huffman_segment);
+    let mut header = vec![frame.components.len() as u8];
+    for component in &frame.components {
+        header.extend([component.id, (component.dc_table << 4) | component.ac_table]);
+    }
+    header.extend([0, 63, 0]);
+    push_segment(&mut out, 0xda, &header);
+
+    let mut writer = BitWriter {
+        bytes: out,
+        bits: 0,
+        count: 0,
+    };
+    walk(
+        frame,
+        &components,
+        &mut |class, table, symbol, bits, size| {
+            let (code, length) = codes[class][table as usize][symbol as usize];
+            writer.write(code, length);
+            writer.write(bits, size as u32);
+        },
+    );
+    writer.flush();
+    let mut out = writer.bytes;
+    out.extend([0xff, 0xd9]);
+    Ok(out)
+}
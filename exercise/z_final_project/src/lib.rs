@@ -0,0 +1,177 @@
+// The core image operations behind the `mirage` command line tool, for programs that want to run
+// them in-process on encoded image bytes instead of files.
+//
+// Everything here works on memory only, so the library also builds for the browser:
+//
+//     cargo build --release --lib --target wasm32-unknown-unknown --no-default-features
+//
+// File access is behind the `std-fs` feature, which is on by default. The `ffi` feature adds a C
+// interface, declared in include/mirage.h.
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod json;
+pub mod pipeline;
+pub mod raw;
+
+#[cfg(target_arch = "wasm32")]
+mod wasm;
+
+use image::{ColorType, DynamicImage, ImageOutputFormat};
+
+// Decode `bytes` (any format the `image` crate can detect), run the operations described by
+// `ops_json` and encode the result in the same format and, where that format can store it, the same
+// color type. Formats that `image` can only decode come back as PNG.
+//
+// `ops_json` is a JSON array of operations written the same way as for the `chain` subcommand,
+// e.g. `["resize 400 300", "blur 2.5", "grayscale"]`.
+pub fn process(bytes: &[u8], ops_json: &str) -> Result<Vec<u8>, String> {
+    let operations = parse_ops_json(ops_json)?;
+    let format =
+        image::guess_format(bytes).map_err(|err| format!("Failed to decode image: {}", err))?;
+    let img = image::load_from_memory_with_format(bytes, format)
+        .map_err(|err| format!("Failed to decode image: {}", err))?;
+    let output = match ImageOutputFormat::from(format) {
+        ImageOutputFormat::Unsupported(_) => ImageOutputFormat::Png,
+        output => output,
+    };
+    let result = pipeline::apply_all(img.to_rgba8(), &operations);
+    let result = convert(
+        DynamicImage::ImageRgba8(result),
+        encodable(img.color(), &output),
+    );
+
+    let mut encoded = std::io::Cursor::new(Vec::new());
+    result
+        .write_to(&mut encoded, output)
+        .map_err(|err| format!("Failed to encode image: {}", err))?;
+    Ok(encoded.into_inner())
+}
+
+// The file based counterpart of `process`, writing in whatever format `outfile`'s extension names.
+#[cfg(feature = "std-fs")]
+pub fn process_file(infile: &str, outfile: &str, ops_json: &str) -> Result<(), String> {
+    let operations = parse_ops_json(ops_json)?;
+    let img = image::open(infile).map_err(|err| format!("Failed to open {}: {}", infile, err))?;
+    let output = image::ImageFormat::from_path(outfile)
+        .map(ImageOutputFormat::from)
+        .map_err(|err| format!("Failed writing {}: {}", outfile, err))?;
+    let result = pipeline::apply_all(img.to_rgba8(), &operations);
+    convert(
+        DynamicImage::ImageRgba8(result),
+        encodable(img.color(), &output),
+    )
+    .save(outfile)
+    .map_err(|err| format!("Failed writing {}: {}", outfile, err))
+}
+
+// The color type closest to `color` that the encoder for `output` accepts. The operations work on
+// 8-bit data, so deeper inputs only keep their depth in name, but their channels are kept.
+fn encodable(color: ColorType, output: &ImageOutputFormat) -> ColorType {
+    let (eight, sixteen) = match color.channel_count() {
+        1 => (ColorType::L8, ColorType::L16),
+        2 => (ColorType::La8, ColorType::La16),
+        3 => (ColorType::Rgb8, ColorType::Rgb16),
+        _ => (ColorType::Rgba8, ColorType::Rgba16),
+    };
+    let is_float = matches!(color, ColorType::Rgb32F | ColorType::Rgba32F);
+    let is_deep = color.bytes_per_pixel() > color.channel_count();
+    match output {
+        ImageOutputFormat::OpenExr => color,
+        ImageOutputFormat::Png | ImageOutputFormat::Tiff if is_float => sixteen,
+        ImageOutputFormat::Png | ImageOutputFormat::Tiff => color,
+        ImageOutputFormat::Farbfeld => ColorType::Rgba16,
+        ImageOutputFormat::Pnm(_) if color == ColorType::L16 => color,
+        _ if is_deep => eight,
+        _ => color,
+    }
+}
+
+// `img` with the color type `color`, or 8-bit RGBA for color types that `image` has no buffer for.
+pub fn convert(img: DynamicImage, color: ColorType) -> DynamicImage {
+    match color {
+        ColorType::L8 => DynamicImage::ImageLuma8(img.to_luma8()),
+        ColorType::La8 => DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+        ColorType::Rgb8 => DynamicImage::ImageRgb8(img.to_rgb8()),
+        ColorType::L16 => DynamicImage::ImageLuma16(img.to_luma16()),
+        ColorType::La16 => DynamicImage::ImageLumaA16(img.to_luma_alpha16()),
+        ColorType::Rgb16 => DynamicImage::ImageRgb16(img.to_rgb16()),
+        ColorType::Rgba16 => DynamicImage::ImageRgba16(img.to_rgba16()),
+        ColorType::Rgb32F => DynamicImage::ImageRgb32F(img.to_rgb32f()),
+        ColorType::Rgba32F => DynamicImage::ImageRgba32F(img.to_rgba32f()),
+        _ => DynamicImage::ImageRgba8(img.to_rgba8()),
+    }
+}
+
+fn parse_ops_json(ops_json: &str) -> Result<Vec<Box<dyn pipeline::Operation>>, String> {
+    let value = json::parse(ops_json)?;
+    let entries = value
+        .as_array()
+        .ok_or("The operations must be a JSON array of strings.")?;
+    let mut words = Vec::new();
+    for entry in entries {
+        let operation = entry
+            .as_str()
+            .ok_or("The operations must be a JSON array of strings.")?;
+        words.extend(operation.split_whitespace().map(str::to_string));
+    }
+    pipeline::parse_operations(&words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(img: &DynamicImage, format: ImageOutputFormat) -> Vec<u8> {
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        img.write_to(&mut bytes, format).unwrap();
+        bytes.into_inner()
+    }
+
+    fn gradient() -> DynamicImage {
+        DynamicImage::ImageRgb8(image::RgbImage::from_fn(16, 8, |x, y| {
+            image::Rgb([(x * 16) as u8, (y * 32) as u8, 128])
+        }))
+    }
+
+    #[test]
+    fn process_keeps_the_format_and_color_type() {
+        let gray = DynamicImage::ImageLuma8(gradient().to_luma8());
+        let output = process(&encode(&gray, ImageOutputFormat::Png), r#"["invert"]"#).unwrap();
+        assert_eq!(
+            image::guess_format(&output).unwrap(),
+            image::ImageFormat::Png
+        );
+        let result = image::load_from_memory(&output).unwrap();
+        assert_eq!(result.color(), ColorType::L8);
+        assert_eq!(
+            result.to_luma8().get_pixel(3, 2)[0],
+            255 - gray.to_luma8().get_pixel(3, 2)[0]
+        );
+
+        let deep = DynamicImage::ImageRgb16(gradient().to_rgb16());
+        let output = process(&encode(&deep, ImageOutputFormat::Tiff), r#"["rotate 90"]"#).unwrap();
+        assert_eq!(
+            image::guess_format(&output).unwrap(),
+            image::ImageFormat::Tiff
+        );
+        let result = image::load_from_memory(&output).unwrap();
+        assert_eq!(result.color(), ColorType::Rgb16);
+        assert_eq!((result.width(), result.height()), (8, 16));
+
+        let jpeg = encode(&gradient(), ImageOutputFormat::Jpeg(90));
+        let output = process(&jpeg, r#"["resize 8 4"]"#).unwrap();
+        assert_eq!(
+            image::guess_format(&output).unwrap(),
+            image::ImageFormat::Jpeg
+        );
+    }
+
+    #[test]
+    fn process_reports_bad_input() {
+        assert!(process(b"not an image", r#"["invert"]"#).is_err());
+        let png = encode(&gradient(), ImageOutputFormat::Png);
+        assert!(process(&png, r#"{"invert": 1}"#).is_err());
+        assert!(process(&png, r#"["no-such-operation"]"#).is_err());
+    }
+}
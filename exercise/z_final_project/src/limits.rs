@@ -0,0 +1,269 @@
+// A guard against decompression bombs.
+//
+// A tiny, highly compressed file can declare enormous dimensions, and decoding it would exhaust
+// memory. Before decoding, the dimensions are read from the header and compared with the
+// `--max-pixels` limit. Every frame of an animated GIF counts, since they are all decoded.
+
+use image::{ColorType, DynamicImage, ImageDecoder, ImageFormat, ImageResult};
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::sync::OnceLock;
+
+pub const DEFAULT_MAX_PIXELS: u64 = 250_000_000;
+
+// Set once from the command line before any command runs.
+static MAX_PIXELS: OnceLock<u64> = OnceLock::new();
+
+pub fn init(max_pixels: u64) {
+    MAX_PIXELS.set(max_pixels).ok();
+}
+
+// The limit in pixels, or 0 for no limit.
+pub fn max_pixels() -> u64 {
+    MAX_PIXELS.get().copied().unwrap_or(DEFAULT_MAX_PIXELS)
+}
+
+// What the header of an image file says about the pixels decoding it would produce.
+pub struct Header {
+    pub width: u32,
+    pub height: u32,
+    // Every frame of an animated GIF, or 1.
+    pub frames: u64,
+    pub color: ColorType,
+}
+
+impl Header {
+    pub fn pixels(&self) -> u64 {
+        self.width as u64 * self.height as u64 * self.frames
+    }
+}
+
+// Read the header of the image at `path` without decoding any pixels.
+pub fn header_info(path: &Path) -> ImageResult<Header> {
+    let reader = image::io::Reader::open(path)?.with_guessed_format()?;
+    let format = reader.format();
+    let ((width, height), color) = match format.and_then(|format| describe(path, format)) {
+        Some(described) => described?,
+        // A format this build has no decoder for; let `image` report it.
+        None => (reader.into_dimensions()?, ColorType::Rgba8),
+    };
+    let frames = if format == Some(ImageFormat::Gif) {
+        gif_frame_count(path)?.max(1)
+    } else {
+        1
+    };
+    Ok(Header {
+        width,
+        height,
+        frames,
+        color,
+    })
+}
+
+// The dimensions and color type from the decoder for `format`, which reads only the header when it
+// is created. None for formats without a decoder in this build.
+fn describe(path: &Path, format: ImageFormat) -> Option<ImageResult<((u32, u32), ColorType)>> {
+    use image::codecs::*;
+
+    fn of<'a, D: ImageDecoder<'a>>(
+        decoder: ImageResult<D>,
+    ) -> ImageResult<((u32, u32), ColorType)> {
+        decoder.map(|decoder| (decoder.dimensions(), decoder.color_type()))
+    }
+
+    let reader = match std::fs::File::open(path) {
+        Ok(file) => BufReader::new(file),
+        Err(err) => return Some(Err(err.into())),
+    };
+    Some(match format {
+        ImageFormat::Png => of(png::PngDecoder::new(reader)),
+        ImageFormat::Jpeg => of(jpeg::JpegDecoder::new(reader)),
+        ImageFormat::Gif => of(gif::GifDecoder::new(reader)),
+        ImageFormat::WebP => of(webp::WebPDecoder::new(reader)),
+        ImageFormat::Pnm => of(pnm::PnmDecoder::new(reader)),
+        ImageFormat::Tiff => of(tiff::TiffDecoder::new(reader)),
+        ImageFormat::Tga => of(tga::TgaDecoder::new(reader)),
+        ImageFormat::Dds => of(dds::DdsDecoder::new(reader)),
+        ImageFormat::Bmp => of(bmp::BmpDecoder::new(reader)),
+        ImageFormat::Ico => of(ico::IcoDecoder::new(reader)),
+        ImageFormat::Hdr => of(hdr::HdrAdapter::new(reader)),
+        ImageFormat::OpenExr => of(openexr::OpenExrDecoder::new(reader)),
+        ImageFormat::Farbfeld => of(farbfeld::FarbfeldDecoder::new(reader)),
+        ImageFormat::Qoi => of(qoi::QoiDecoder::new(reader)),
+        _ => return None,
+    })
+}
+
+// Refuse `path`, with an explanation, if decoding it would produce more pixels than `--max-pixels`
+// allows. Files whose header can't be read are left for the decoder to report.
+pub fn check(path: &Path) -> Result<(), String> {
+    check_against(path, max_pixels())
+}
+
+fn check_against(path: &Path, max_pixels: u64) -> Result<(), String> {
+    if max_pixels == 0 {
+        return Ok(());
+    }
+    match header_info(path) {
+        Ok(header) if header.pixels() > max_pixels => {
+            let size = if header.frames > 1 {
+                format!(
+                    "{} frames of {}x{} pixels",
+                    header.frames, header.width, header.height
+                )
+            } else {
+                format!("{}x{} pixels", header.width, header.height)
+            };
+            Err(format!(
+                "{} is {}, more than the --max-pixels limit of {}. Decoding it would take at least \
+                 {} MB of memory. Pass a larger --max-pixels, or --max-pixels 0 for no limit.",
+                path.display(),
+                size,
+                max_pixels,
+                estimated_megabytes(&header)
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+// `check`, for commands that stop at the first problem.
+pub fn enforce(path: &Path) {
+    if let Err(message) = check(path) {
+        panic!("{}", message);
+    }
+}
+
+// The memory the decoded pixels take, in megabytes rounded up, at the size of the color type the
+// decoder reports. Commands that convert to floating point need several times this.
+fn estimated_megabytes(header: &Header) -> u64 {
+    (header.pixels() * header.color.bytes_per_pixel() as u64).div_ceil(1_000_000)
+}
+
+// The color type the first image opened decoded to, which --keep-color-type writes output with.
+static INPUT_COLOR_TYPE: OnceLock<ColorType> = OnceLock::new();
+
+// `image::open`, after checking the file against `--max-pixels`.
+pub fn open<P: AsRef<Path>>(path: P) -> ImageResult<DynamicImage> {
+    let _load = crate::trace::span!("load", path = path.as_ref().display());
+    enforce(path.as_ref());
+    let decode = crate::trace::span!("decode");
+    let img = image::open(path)?;
+    decode.finish();
+    INPUT_COLOR_TYPE.set(img.color()).ok();
+    Ok(img)
+}
+
+pub fn input_color_type() -> Option<ColorType> {
+    INPUT_COLOR_TYPE.get().copied()
+}
+
+// Count the image descriptors in a GIF by walking its blocks, skipping over the compressed data.
+fn gif_frame_count(path: &Path) -> std::io::Result<u64> {
+    let mut reader = BufReader::new(std::fs::File::open(path)?);
+    let mut byte = || -> std::io::Result<u8> {
+        let mut buf = [0u8; 1];
+        reader.read_exact(&mut buf)?;
+        Ok(buf[0])
+    };
+    // A color table follows when the top bit of `flags` is set, sized by its lowest three bits.
+    let color_table_size = |flags: u8| {
+        if flags & 0x80 != 0 {
+            3 * (2usize << (flags & 0x07))
+        } else {
+            0
+        }
+    };
+
+    // Header (6 bytes) and logical screen descriptor (7 bytes).
+    let mut header = [0u8; 13];
+    for b in header.iter_mut() {
+        *b = byte()?;
+    }
+    for _ in 0..color_table_size(header[10]) {
+        byte()?;
+    }
+
+    let mut frames = 0;
+    loop {
+        match byte()? {
+            // Extension: a label followed by data sub-blocks.
+            0x21 => {
+                byte()?;
+                skip_sub_blocks(&mut byte)?;
+            }
+            // Image descriptor, optional local color table, LZW code size, then the image data.
+            0x2C => {
+                frames += 1;
+                let mut descriptor = [0u8; 9];
+                for b in descriptor.iter_mut() {
+                    *b = byte()?;
+                }
+                for _ in 0..color_table_size(descriptor[8]) {
+                    byte()?;
+                }
+                byte()?;
+                skip_sub_blocks(&mut byte)?;
+            }
+            // The trailer (0x3B), or anything unexpected.
+            _ => return Ok(frames),
+        }
+    }
+}
+
+fn skip_sub_blocks(byte: &mut impl FnMut() -> std::io::Result<u8>) -> std::io::Result<()> {
+    loop {
+        let length = byte()?;
+        if length == 0 {
+            return Ok(());
+        }
+        for _ in 0..length {
+            byte()?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A PGM header with no pixel data after it, so only a header read can succeed.
+    fn header_only_pgm(name: &str, width: u32, height: u32, max_value: u32) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("mirage-{}-{}.pgm", std::process::id(), name));
+        let header = format!("P5\n{} {}\n{}\n", width, height, max_value);
+        std::fs::write(&path, header).unwrap();
+        path
+    }
+
+    #[test]
+    fn rejects_an_image_over_the_limit_before_decoding() {
+        let path = header_only_pgm("over", 100_000, 100_000, 255);
+        let refusal = check_against(&path, 1_000_000).unwrap_err();
+        assert!(refusal.contains("100000x100000 pixels"), "{}", refusal);
+        assert!(refusal.contains("10000 MB"), "{}", refusal);
+        // The file has no pixel data, so decoding it fails; the refusal came from the header alone.
+        assert!(image::open(&path).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn estimates_memory_from_the_decoded_color_type() {
+        let path = header_only_pgm("deep", 2_000, 1_000, 65535);
+        let header = header_info(&path).unwrap();
+        assert_eq!(header.color, ColorType::L16);
+        assert_eq!(estimated_megabytes(&header), 4);
+        assert!(check_against(&path, 1_000_000)
+            .unwrap_err()
+            .contains("4 MB"));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn accepts_images_within_the_limit_or_without_one() {
+        let path = header_only_pgm("within", 1_000, 1_000, 255);
+        assert!(check_against(&path, 1_000_000).is_ok());
+        assert!(check_against(&path, 999_999).is_err());
+        assert!(check_against(&path, 0).is_ok());
+        std::fs::remove_file(path).unwrap();
+    }
+}
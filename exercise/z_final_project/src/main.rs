@@ -26,20 +26,130 @@
 //     let positive_number: u32 = some_string.parse().expect("Failed to parse a number");
 
 use clap::{Parser, Subcommand};
+use std::sync::OnceLock;
+
+mod bktree;
+mod blurhash;
+mod boxblur;
+mod cancel;
+mod carve;
+mod colormap;
+mod colorspace;
+mod exif;
+mod export;
+mod exposure;
+mod font;
+mod integral;
+mod jpegtran;
+mod limits;
+mod noise;
+mod palette;
+mod qr;
+mod random;
+#[cfg(feature = "serve")]
+mod serve;
+mod stego;
+mod trace;
+
+use mirage::json;
+use mirage::pipeline;
+use mirage::raw::{brighten_bytes, brighten_words, invert_bytes, invert_words};
+use std::time::{Duration, Instant};
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 struct Cli {
     #[clap(subcommand)]
     command: Commands,
+    /// Write output with the color type the input decoded to (the first one, for commands with
+    /// several), instead of the narrowest one that represents the result exactly
+    #[clap(long, global = true, conflicts_with = "force-rgba")]
+    keep_color_type: bool,
+    /// Always write RGBA output
+    #[clap(long, global = true)]
+    force_rgba: bool,
+    /// Log each step to stderr (-v), or each step's start and end (-vv)
+    #[cfg(feature = "tracing")]
+    #[clap(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    #[cfg(feature = "tracing")]
+    #[clap(long, global = true, arg_enum, value_parser, default_value = "human")]
+    log_format: trace::LogFormat,
+    /// Refuse to decode images with more pixels than this, counting every frame of an animated
+    /// GIF (0 for no limit)
+    #[clap(long, global = true, default_value_t = limits::DEFAULT_MAX_PIXELS)]
+    max_pixels: u64,
+    /// Write OUTFILE in this format instead of the one its extension names. `raw` writes bare
+    /// RGB8 or RGBA8 bytes, row by row, with no header
+    #[clap(long, global = true, arg_enum, value_parser)]
+    format: Option<OutputFormat>,
+}
+
+// How `save_image` picks the color type it encodes with.
+#[derive(Copy, Clone, Default)]
+enum ColorTypeMode {
+    // Drop alpha when every pixel is opaque and color when every pixel is gray.
+    #[default]
+    Narrowest,
+    Keep,
+    ForceRgba,
+}
+
+// Set once from the command line before any command runs.
+static COLOR_TYPE_MODE: OnceLock<ColorTypeMode> = OnceLock::new();
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+enum OutputFormat {
+    Png,
+    Jpeg,
+    Bmp,
+    Tiff,
+    Tga,
+    Pnm,
+    Qoi,
+    Raw,
 }
 
+impl OutputFormat {
+    fn image_format(self) -> Option<image::ImageFormat> {
+        use image::ImageFormat;
+        match self {
+            OutputFormat::Png => Some(ImageFormat::Png),
+            OutputFormat::Jpeg => Some(ImageFormat::Jpeg),
+            OutputFormat::Bmp => Some(ImageFormat::Bmp),
+            OutputFormat::Tiff => Some(ImageFormat::Tiff),
+            OutputFormat::Tga => Some(ImageFormat::Tga),
+            OutputFormat::Pnm => Some(ImageFormat::Pnm),
+            OutputFormat::Qoi => Some(ImageFormat::Qoi),
+            OutputFormat::Raw => None,
+        }
+    }
+}
+
+// Set once from the command line before any command runs. `None` means "from the extension".
+static OUTPUT_FORMAT: OnceLock<Option<OutputFormat>> = OnceLock::new();
+
 #[derive(Subcommand)]
 enum Commands {
     Blur {
         infile: String,
         outfile: String,
         blur_amount: Option<f32>,
+        /// Blur in linear light instead of gamma-encoded sRGB, which avoids dark fringes
+        #[clap(long)]
+        linear: bool,
+        /// `approx` uses three box blurs, which is much faster for large amounts. `auto` picks it
+        /// for amounts above 10
+        #[clap(long, arg_enum, value_parser, default_value = "auto")]
+        algorithm: BlurAlgorithm,
+    },
+    /// Blur with three box blurs approximating a gaussian, which takes the same time for any radius.
+    /// The same as `blur --algorithm approx`
+    FastBlur {
+        infile: String,
+        outfile: String,
+        /// The standard deviation of the gaussian to approximate, like `blur`'s amount
+        radius: f32,
     },
     Brighten {
         infile: String,
@@ -50,22 +160,66 @@ enum Commands {
         infile: String,
         outfile: String,
         rotation_amount: u32,
+        /// For a JPEG INFILE and OUTFILE, rearrange the compressed data instead of decoding and
+        /// re-encoding, so no quality is lost. Falls back to re-encoding, with a warning, when the
+        /// image can't be turned that way
+        #[clap(long)]
+        lossless: bool,
+        /// With --lossless, fail instead of falling back to re-encoding
+        #[clap(long, requires = "lossless")]
+        strict: bool,
     },
+    /// Mirror an image left to right (horizontal) or top to bottom (vertical)
+    Flip {
+        infile: String,
+        outfile: String,
+        #[clap(arg_enum, value_parser)]
+        direction: FlipDirection,
+        /// The same as rotate's --lossless
+        #[clap(long)]
+        lossless: bool,
+        /// With --lossless, fail instead of falling back to re-encoding
+        #[clap(long, requires = "lossless")]
+        strict: bool,
+    },
+    /// Invert the colors of an image. Alpha is left as it is
     Invert {
         infile: String,
         outfile: String,
     },
+    /// Crop to a rectangle given in pixels, or in percent of the image size with --percent or
+    /// --percent-rect
     Crop {
         infile: String,
         outfile: String,
-        x: u32,
-        y: u32,
-        width: u32,
-        height: u32,
+        #[clap(required_unless_present_any = &["percent", "percent-rect"])]
+        x: Option<u32>,
+        #[clap(required_unless_present_any = &["percent", "percent-rect"])]
+        y: Option<u32>,
+        #[clap(required_unless_present_any = &["percent", "percent-rect"])]
+        width: Option<u32>,
+        #[clap(required_unless_present_any = &["percent", "percent-rect"])]
+        height: Option<u32>,
+        /// Keep this percentage of the width and height, centered
+        #[clap(long, conflicts_with_all = &["x", "percent-rect"])]
+        percent: Option<f32>,
+        /// Keep the rectangle at X, Y of size WIDTH x HEIGHT, all in percent of the image size
+        #[clap(
+            long,
+            number_of_values = 4,
+            value_names = &["X", "Y", "WIDTH", "HEIGHT"],
+            conflicts_with = "x"
+        )]
+        percent_rect: Option<Vec<f32>>,
     },
+    /// Convert an image to grayscale, keeping its transparency
     Grayscale {
         infile: String,
         outfile: String,
+        /// Discard the alpha channel, making every pixel opaque. Transparent areas show whatever
+        /// color they happen to hold, which is often black
+        #[clap(long)]
+        drop_alpha: bool,
     },
     Generate {
         outfile: String,
@@ -78,224 +232,9672 @@ enum Commands {
     Fractal {
         outfile: String,
     },
-}
+    /// Lay out every frame of an animated GIF in a grid COLS frames wide, as one still image
+    Filmstrip {
+        infile: String,
+        outfile: String,
+        cols: u32,
+    },
+    /// Sharpen by adding back AMOUNT times the difference from a blur of RADIUS, wherever it is
+    /// more than THRESHOLD
+    UnsharpMask {
+        infile: String,
+        outfile: String,
+        radius: f32,
+        amount: f32,
+        threshold: u8,
+    },
+    /// Report the percentage of clipped shadows and highlights per channel
+    ClippingCheck {
+        infile: String,
+        /// Also write a copy of the image with clipped areas marked by zebra stripes
+        #[clap(long)]
+        outfile: Option<String>,
+    },
+    /// Generate a grayscale cloud texture from several octaves of Perlin noise
+    Clouds {
+        outfile: String,
+        width: u32,
+        height: u32,
+        octaves: u32,
+        seed: u64,
+    },
+    /// Shift each pixel by an amount taken from a grayscale displacement map (mid-gray = no shift)
+    Displace {
+        infile: String,
+        map: String,
+        outfile: String,
+        strength: f32,
+    },
+    /// Turn a grayscale heightmap into a tangent-space normal map
+    NormalMap {
+        infile: String,
+        outfile: String,
+        strength: f32,
+    },
+    /// Compress a wide dynamic range into displayable 8-bit with a tone-mapping curve
+    ToneMap {
+        infile: String,
+        outfile: String,
+        #[clap(arg_enum, value_parser)]
+        operator: ToneMapOperator,
+        /// Exposure adjustment in stops, applied before the curve
+        #[clap(long, default_value_t = 0.0, allow_hyphen_values = true)]
+        exposure: f32,
+    },
+    /// Fuse differently exposed shots of the same scene into one well-exposed image
+    MergeExposures {
+        #[clap(required = true, min_values = 2)]
+        infiles: Vec<String>,
+        outfile: String,
+    },
+    /// Brighten the corners to compensate for a lens's vignetting
+    DevignetteCorrect {
+        infile: String,
+        outfile: String,
+        /// How much to brighten the corners, e.g. 0.5 for 50% more light at the very corners
+        #[clap(allow_hyphen_values = true)]
+        strength: f32,
+    },
+    /// Wrap an image around its center ("tiny planet") or unwrap such an image back into a strip
+    PolarTransform {
+        infile: String,
+        outfile: String,
+        #[clap(arg_enum, value_parser)]
+        direction: PolarDirection,
+    },
+    /// Composite transparent layers bottom to top with gamma-correct alpha blending
+    StackLayers {
+        /// The layers, bottom first. All must be the same size
+        #[clap(required = true, min_values = 2)]
+        infiles: Vec<String>,
+        outfile: String,
+    },
+    /// Print an image's width and height, read from its header without decoding the pixels
+    Dimensions {
+        infile: String,
+    },
+    /// Fully decode an image to check that it isn't truncated or corrupt. Exits with 1 if it is
+    Verify {
+        infile: String,
+        /// Print nothing, only set the exit code
+        #[clap(long)]
+        quiet: bool,
+    },
+    /// Shrink an image by removing low-energy seams, keeping the important content intact
+    Carve {
+        infile: String,
+        outfile: String,
+        /// Change in width, e.g. -400 to remove 400 columns
+        #[clap(long, allow_hyphen_values = true)]
+        width: Option<i32>,
+        /// Change in height, e.g. -100 to remove 100 rows
+        #[clap(long, allow_hyphen_values = true)]
+        height: Option<i32>,
+        /// Also write a copy of INFILE with the removed seams painted red
+        #[clap(long)]
+        visualize: Option<String>,
+    },
+    /// Turn an image black and white at the threshold Otsu's method picks from its histogram
+    OtsuThreshold {
+        infile: String,
+        outfile: String,
+    },
+    /// Print a 64-bit perceptual hash of an image, as hex. Similar images get similar hashes
+    Phash {
+        infile: String,
+    },
+    /// Print how many bits differ between two hashes from `phash` (0 to 64, lower is more similar)
+    PhashDistance {
+        hash_a: String,
+        hash_b: String,
+    },
+    /// Turn an image black and white by comparing every pixel with the mean of its neighborhood
+    AdaptiveThreshold {
+        infile: String,
+        outfile: String,
+        /// Width and height of the neighborhood, in pixels
+        block_size: u32,
+        /// How far below the local mean a pixel must be to turn black
+        #[clap(allow_hyphen_values = true)]
+        offset: f32,
+    },
+    /// Print the BlurHash of an image, a short string that front ends render as a placeholder
+    Blurhash {
+        infile: String,
+        /// Number of horizontal components, 1 to 9
+        #[clap(long, default_value_t = 4)]
+        x_components: u32,
+        /// Number of vertical components, 1 to 9
+        #[clap(long, default_value_t = 3)]
+        y_components: u32,
+    },
+    /// Render a BlurHash back into an image
+    BlurhashDecode {
+        hash: String,
+        outfile: String,
+        #[clap(long, default_value_t = 32)]
+        width: u32,
+        #[clap(long, default_value_t = 32)]
+        height: u32,
+        /// Contrast of the result, 1.0 being the original
+        #[clap(long, default_value_t = 1.0)]
+        punch: f32,
+    },
+    /// Count the objects in an image after binarizing it, printing each one's area and bounds
+    CountObjects {
+        infile: String,
+        /// Ignore objects with fewer pixels than this
+        min_area: u32,
+        /// Count dark objects on a light background instead of light objects on a dark one
+        #[clap(long)]
+        dark: bool,
+    },
+    /// Hide a file in the lowest bits of an image's pixels
+    #[clap(alias = "stego-encode")]
+    Embed {
+        infile: String,
+        outfile: String,
+        /// The file to hide. Read from standard input when neither it nor --message is given
+        #[clap(long)]
+        payload: Option<String>,
+        /// Hide this text instead of a file
+        #[clap(long, conflicts_with = "payload")]
+        message: Option<String>,
+        /// How many bits of every color channel to use: 2 doubles the capacity but is less subtle
+        #[clap(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=2))]
+        bits: u8,
+    },
+    /// Recover a file hidden with `embed`
+    #[clap(alias = "stego-decode")]
+    Extract {
+        infile: String,
+        /// Where to write the payload. Written to standard output when not given
+        #[clap(long)]
+        output: Option<String>,
+        /// The --bits value the payload was embedded with
+        #[clap(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=2))]
+        bits: u8,
+    },
+    /// Report the average run length of each channel along the scanlines, a rough guide to how well
+    /// the image will compress losslessly
+    RlePreview {
+        infile: String,
+    },
+    /// Write an image's pixels as raw bytes, without a header, the counterpart of from-raw
+    RawDump {
+        infile: String,
+        outfile: String,
+        /// How the bytes of every pixel are laid out
+        #[clap(arg_enum, value_parser)]
+        pixel_format: RawPixelFormat,
+    },
+    /// Build an image from a headerless dump of raw pixel bytes
+    #[clap(alias = "fromraw", alias = "raw-load")]
+    FromRaw {
+        infile: String,
+        outfile: String,
+        #[clap(long)]
+        width: u32,
+        #[clap(long)]
+        height: u32,
+        /// How the bytes of every pixel are laid out
+        #[clap(long, arg_enum, value_parser)]
+        pixel_format: RawPixelFormat,
+    },
+    /// Generate a checkerboard of diamonds (squares rotated by 45 degrees)
+    Diamond {
+        outfile: String,
+        width: u32,
+        height: u32,
+        /// A color written as red:green:blue, e.g. 255:128:0
+        color_a: String,
+        color_b: String,
+        /// Width and height of every diamond, in pixels
+        cell_size: u32,
+    },
+    /// Write an image as a byte array in a C header (.h) or Rust source file (.rs)
+    ExportArray {
+        infile: String,
+        outfile: String,
+        #[clap(long, arg_enum, value_parser, default_value = "rgb565")]
+        layout: export::Layout,
+        /// Name of the array. Derived from OUTFILE's name when not given
+        #[clap(long)]
+        name: Option<String>,
+        /// Luma at or above which a pixel is lit in the `mono` layout
+        #[clap(long, default_value_t = 128)]
+        threshold: u8,
+    },
+    /// Sort runs of bright pixels by brightness along every row or column, a glitch art effect
+    #[clap(alias = "pixelsort")]
+    PixelSort {
+        infile: String,
+        outfile: String,
+        #[clap(arg_enum, value_parser)]
+        direction: PixelSortDirection,
+        /// Only pixels brighter than this (0 to 255) are sorted
+        threshold: u8,
+        /// Only pixels no brighter than this are sorted either, so dark and very bright areas both
+        /// stay put
+        #[clap(long, default_value_t = 255)]
+        threshold_high: u8,
+        /// What to sort the runs by
+        #[clap(long, arg_enum, value_parser, default_value = "luminance")]
+        key: PixelSortKey,
+        /// Only sort a random half of the runs, picked with this seed
+        #[clap(long)]
+        seed: Option<u64>,
+    },
+    /// Color a grayscale image, such as a heightmap or sensor data, with a colormap
+    Colormap {
+        infile: String,
+        outfile: String,
+        #[clap(arg_enum, value_parser)]
+        colormap: colormap::Colormap,
+        /// Run the colormap from high to low values instead
+        #[clap(long)]
+        invert: bool,
+        /// The input value mapped to the low end of the colormap, in the input's own units (0 to
+        /// 255 for 8-bit images, 0 to 65535 for 16-bit ones). Defaults to the lowest possible value
+        #[clap(long)]
+        min: Option<f32>,
+        /// The input value mapped to the high end of the colormap. Defaults to the highest
+        /// possible value
+        #[clap(long)]
+        max: Option<f32>,
+    },
+    /// Corrupt an image on purpose with shifted bands and misaligned color channels
+    Glitch {
+        infile: String,
+        outfile: String,
+        /// How strong the corruption is, from 0.0 (none) to 1.0
+        intensity: f32,
+        seed: u64,
+    },
+    /// Build an RGB image with each channel taken from any image's channel, e.g. a near-infrared
+    /// false-color composite or a channel swap
+    Falsecolor {
+        outfile: String,
+        /// Where the red channel comes from: a grayscale file, a file with a channel selector like
+        /// `photo.png:g`, or just `r`, `g` or `b` for a channel of --input
+        #[clap(long)]
+        r: String,
+        /// Where the green channel comes from
+        #[clap(long)]
+        g: String,
+        /// Where the blue channel comes from
+        #[clap(long)]
+        b: String,
+        /// The image bare channel selectors refer to
+        #[clap(long)]
+        input: Option<String>,
+    },
+    /// Render a Julia, Mandelbrot or Burning Ship fractal, colored by escape time with a colormap
+    FractalArt {
+        outfile: String,
+        width: u32,
+        height: u32,
+        #[clap(arg_enum, value_parser)]
+        fractal_type: FractalType,
+        #[clap(arg_enum, value_parser)]
+        palette: colormap::Colormap,
+        /// Give up on a point after this many iterations and color it black
+        max_iter: u32,
+    },
+    /// Write INFILE's colors to OUTFILE as labeled swatches and list them on stdout
+    Palette {
+        infile: String,
+        outfile: String,
+        /// Refuse images with more distinct colors than this, unless --quantize is given
+        #[clap(long, default_value_t = 256)]
+        max: usize,
+        /// Reduce the image to this many representative colors first
+        #[clap(long)]
+        quantize: Option<usize>,
+        #[clap(long, arg_enum, value_parser, default_value = "frequency")]
+        sort: PaletteSort,
+        /// List the colors as JSON, with pixel counts, instead of one hex code per line
+        #[clap(long)]
+        json: bool,
+    },
+    /// Reduce an image to the colors of a fixed palette
+    #[clap(alias = "applypalette")]
+    ApplyPalette {
+        infile: String,
+        outfile: String,
+        /// A file of hex colors (plain or JSON, like the palette command prints), or an image whose
+        /// colors make up the palette
+        palette: String,
+        /// Diffuse the rounding error to neighboring pixels (Floyd-Steinberg) to fake the missing
+        /// shades
+        #[clap(long)]
+        dither: bool,
+        /// How to decide which palette color is nearest
+        #[clap(long, arg_enum, value_parser, default_value = "rgb")]
+        distance: palette::Distance,
+    },
+    /// Magnify (bulge) or shrink (pinch) a round area of an image, like a lens held over it
+    Bulge {
+        infile: String,
+        outfile: String,
+        /// Center of the distortion, in pixels from the left
+        cx: f32,
+        /// Center of the distortion, in pixels from the top
+        cy: f32,
+        /// Pixels further than this from the center are left alone
+        radius: f32,
+        /// From -1.0 (strongest pinch) through 0.0 (no change) to 1.0 (strongest bulge)
+        #[clap(allow_hyphen_values = true)]
+        strength: f32,
+    },
+    /// Write the GPS position from a photo's EXIF data onto a corner of the photo
+    GeoStamp {
+        infile: String,
+        outfile: String,
+        #[clap(arg_enum, value_parser)]
+        position: Corner,
+        /// A color written as red:green:blue, e.g. 255:128:0
+        color: String,
+        /// Copy photos without a GPS position unchanged instead of failing
+        #[clap(long)]
+        skip_missing: bool,
+    },
+    /// Pack images into one sprite sheet and print where each one went as JSON
+    #[clap(alias = "spritesheet")]
+    SpriteSheet {
+        #[clap(required = true, min_values = 1)]
+        infiles: Vec<String>,
+        outfile: String,
+        /// Transparent pixels to leave around every sprite
+        #[clap(long, default_value_t = 0)]
+        padding: u32,
+        /// The largest width and height the sheet may have
+        #[clap(long, default_value_t = 4096)]
+        max_size: u32,
+    },
+    /// Print an image as ASCII art, optionally in color
+    Ascii {
+        infile: String,
+        /// How many characters wide the output is
+        #[clap(long, default_value_t = 80)]
+        width: u32,
+        /// Tint every character with the average color of its part of the image, using 24-bit ANSI
+        /// escape codes. Ignored unless stdout is a terminal
+        #[clap(long)]
+        color: bool,
+        /// Use colors even when stdout isn't a terminal, e.g. when piping into `less -R`
+        #[clap(long)]
+        force_color: bool,
+    },
+    /// Add a mirrored copy below the image that fades out, like a product shot on a glossy floor
+    #[clap(alias = "reflection")]
+    Reflect {
+        infile: String,
+        outfile: String,
+        /// How tall the reflection is, in pixels
+        #[clap(long, conflicts_with = "fraction")]
+        height: Option<u32>,
+        /// How tall the reflection is, as a fraction of the image's height
+        #[clap(long, default_value_t = 0.5)]
+        fraction: f32,
+        /// How opaque the reflection is where it meets the image, from 0.0 to 1.0. It fades to
+        /// nothing at the bottom
+        #[clap(long, default_value_t = 0.4)]
+        opacity: f32,
+        /// How the opacity falls off towards the bottom
+        #[clap(long, arg_enum, value_parser, default_value = "linear")]
+        fade: FadeCurve,
+        /// Put everything on a solid color written as red:green:blue instead of transparency
+        #[clap(long)]
+        background: Option<String>,
+    },
+    /// Generate a honeycomb of hexagon outlines
+    HexGrid {
+        outfile: String,
+        width: u32,
+        height: u32,
+        /// A color written as red:green:blue, e.g. 255:128:0
+        color: String,
+        background: String,
+        /// Distance from the center of a hexagon to its corners, in pixels
+        hex_size: f32,
+        /// Width of the outlines, in pixels
+        thickness: f32,
+    },
+    /// Pad an image to an aspect ratio or size with a blurred, enlarged copy of itself instead of
+    /// bars
+    #[clap(alias = "blurpad")]
+    BlurPad {
+        infile: String,
+        outfile: String,
+        /// An aspect ratio like 4:5, which keeps all of INFILE's pixels, or an exact size like
+        /// 1080x1350
+        target: String,
+        #[clap(long, default_value_t = 20.0)]
+        blur_sigma: f32,
+        /// How much to darken the padding, from 0.0 (not at all) to 1.0 (black)
+        #[clap(long, default_value_t = 0.0)]
+        darken: f32,
+    },
+    /// Fade an image out to transparency towards one or more of its edges
+    Fade {
+        infile: String,
+        outfile: String,
+        /// The edge to fade towards. Give it more than once to fade several edges
+        #[clap(long = "edge", arg_enum, value_parser, required = true)]
+        edges: Vec<Edge>,
+        /// How far from the edge, in pixels, the fade starts
+        #[clap(long)]
+        distance: u32,
+        #[clap(long, arg_enum, value_parser, default_value = "linear")]
+        curve: FadeCurve,
+    },
+    /// Draw the Barnsley fern by playing the chaos game
+    Fern {
+        outfile: String,
+        width: u32,
+        height: u32,
+        /// How many points to plot. A few hundred thousand give a solid fern
+        iterations: u64,
+        seed: u64,
+    },
+    /// Add a bar of wrapped text below (or above) an image, leaving the image itself untouched
+    Caption {
+        infile: String,
+        outfile: String,
+        text: String,
+        /// Put the bar above the image instead
+        #[clap(long)]
+        top: bool,
+        /// Height of a capital letter, in pixels. Rounded down to a multiple of 5, the height of
+        /// the built-in font
+        #[clap(long, default_value_t = 20)]
+        font_size: u32,
+        /// A color written as red:green:blue, e.g. 255:128:0
+        #[clap(long, default_value = "255:255:255")]
+        background: String,
+        #[clap(long, default_value = "0:0:0")]
+        color: String,
+    },
+    /// Render an image as black dots on white, bigger where it is darker, like newspaper print
+    Halftone {
+        infile: String,
+        outfile: String,
+        /// Distance between the centers of neighboring dots, in pixels
+        dot_spacing: f32,
+        /// Rotation of the dot grid in degrees; print traditionally uses 45
+        #[clap(allow_hyphen_values = true)]
+        angle: f32,
+    },
+    /// Encode DATA as a QR code
+    Qr {
+        outfile: String,
+        /// The text to encode, or - to read it from standard input. Every byte is encoded as is,
+        /// so use `echo -n` or `printf` to leave out a trailing newline
+        data: String,
+        /// Width and height of the image in pixels, rounded down so every module is a whole
+        /// number of pixels. Without it, modules are 8 pixels wide
+        #[clap(long)]
+        size: Option<u32>,
+        /// Width of the light border around the code, in modules
+        #[clap(long, default_value_t = 4)]
+        margin: u32,
+        #[clap(long, arg_enum, value_parser, ignore_case = true, default_value = "m")]
+        ec_level: qr::EcLevel,
+        /// A color written as red:green:blue, e.g. 255:128:0
+        #[clap(long, default_value = "0:0:0")]
+        dark: String,
+        #[clap(long, default_value = "255:255:255")]
+        light: String,
+    },
+    /// Remove a color cast by assuming the scene averages out to gray
+    AutoWhiteBalance {
+        infile: String,
+        outfile: String,
+    },
+    /// Report whether INFILE's EXIF orientation tag makes viewers rotate or mirror it
+    CheckOrientation {
+        infile: String,
+        /// Write a copy with the rotation applied to the pixels, which displays the same way
+        /// everywhere, whether or not the viewer reads the tag
+        #[clap(long, value_name = "OUTFILE")]
+        fix: Option<String>,
+    },
+    /// Draw a grid of numbers, one row per line of INFILE, as an image with one pixel per number
+    FromMatrix {
+        /// Numbers separated by commas or whitespace, like a CSV file without a header
+        infile: String,
+        outfile: String,
+        /// The lowest number becomes the low end of the colormap, and the highest the high end
+        #[clap(arg_enum, value_parser, default_value = "grayscale")]
+        palette: colormap::Colormap,
+    },
+    /// Resize to exactly WIDTH x HEIGHT, or with --max-edge keeping the aspect ratio
+    Resize {
+        infile: String,
+        outfile: String,
+        #[clap(required_unless_present = "max-edge")]
+        width: Option<u32>,
+        #[clap(required_unless_present = "max-edge")]
+        height: Option<u32>,
+        /// Shrink so the longer side is this many pixels. Images that already fit are left alone,
+        /// and copied byte for byte when OUTFILE has INFILE's format
+        #[clap(long, conflicts_with = "width")]
+        max_edge: Option<u32>,
+        /// Let --max-edge enlarge smaller images too
+        #[clap(long, requires = "max-edge")]
+        upscale: bool,
+        /// Resample in linear light instead of gamma-encoded sRGB, so fine detail keeps its
+        /// brightness when shrunk
+        #[clap(long)]
+        linear: bool,
+    },
+    /// Lay out the images in INDIR in a grid, oldest first by their EXIF capture dates
+    Timeline {
+        indir: String,
+        outfile: String,
+        cols: u32,
+        /// Width and height of the square each image is shrunk to fit in
+        #[clap(long, default_value_t = 256)]
+        thumb_size: u32,
+    },
+    /// Sharpen only along edges, leaving flat areas and their noise alone
+    EdgeSharpen {
+        infile: String,
+        outfile: String,
+        /// How strongly to sharpen, as in unsharp-mask
+        amount: f32,
+        /// How strong an edge must be to get sharpened: roughly the step in brightness across it,
+        /// from 0 to 255
+        edge_threshold: f32,
+    },
+    /// Print the minimum, maximum, mean and standard deviation of every channel, and how many
+    /// pixels are black, white or fully transparent
+    Stats {
+        infile: String,
+        #[clap(long)]
+        json: bool,
+    },
+    /// Render TEXT filled with a vertical gradient, on a canvas just big enough for it
+    GradientText {
+        outfile: String,
+        text: String,
+        /// Height of a capital letter, in pixels. Rounded down to a multiple of 5, the height of
+        /// the built-in font
+        size: u32,
+        /// Color at the top of the letters, written as red:green:blue, e.g. 255:128:0
+        start_color: String,
+        /// Color at the bottom of the letters
+        end_color: String,
+        background: String,
+    },
+    /// Find groups of near-duplicate images in DIR by comparing their perceptual hashes
+    Dedupe {
+        dir: String,
+        /// Also look in subdirectories
+        #[clap(long)]
+        recursive: bool,
+        /// Largest number of differing hash bits for two images to count as duplicates
+        #[clap(long, default_value_t = 5)]
+        threshold: u32,
+        #[clap(long)]
+        json: bool,
+        /// Delete all but the largest image of every group, after asking for confirmation
+        #[clap(long)]
+        delete_smaller: bool,
+        /// With --delete-smaller, only list the files that would be deleted
+        #[clap(long, requires = "delete-smaller")]
+        dry_run: bool,
+    },
+    /// Report whether INFILE has a color cast, which way it leans and how strong it is
+    ColorCast {
+        infile: String,
+    },
+    /// Give a photo a cartoon look: smoothed, flat areas of color with dark outlines
+    Cartoon {
+        infile: String,
+        outfile: String,
+    },
+    /// Fake a miniature: keep a horizontal band sharp, blur more and more away from it and boost
+    /// the saturation, like a photo taken with a tilt-shift lens
+    TiltShift {
+        infile: String,
+        outfile: String,
+        /// The row at the middle of the sharp band
+        focus_y: u32,
+        /// How tall the sharp band is, in pixels
+        focus_height: u32,
+        /// The strength of the blur at the top and bottom edges, like `blur`'s amount
+        blur: f32,
+        /// How much to multiply the saturation by, with 1.0 for no change
+        #[clap(long, default_value_t = 1.3)]
+        saturation: f32,
+    },
+    /// Write a grayscale map of how much each part of the image stands out, brightest where the
+    /// eye is drawn first
+    Saliency {
+        infile: String,
+        outfile: String,
+    },
+    /// Copy the images in INDIR into dated folders under OUTDIR, named by their EXIF capture dates
+    Organize {
+        indir: String,
+        outdir: String,
+        /// Where each image goes, relative to OUTDIR. {year}, {month}, {day}, {date} (YYYY-MM-DD)
+        /// and {time} (HHMMSS) come from the capture date, {seq} counts images taken on the same
+        /// day, and {name} and {ext} are the file's own name and extension. Naming a different
+        /// format than the original's converts it. Images without a date go to `undated/` under
+        /// their own names
+        #[clap(default_value = "{year}/{month}/{date}_{seq}.{ext}")]
+        pattern: String,
+        /// Also look in subdirectories of INDIR
+        #[clap(long)]
+        recursive: bool,
+    },
+    /// Fake partial transparency with a pattern of fully opaque and fully transparent pixels, for
+    /// renderers that only support alpha testing
+    ScreenDoor {
+        infile: String,
+        outfile: String,
+        /// The share of pixels to keep, from 0.0 to 1.0
+        opacity: f32,
+    },
+    /// Reduce each channel to LEVELS values with a fixed crosshatch pattern (Bayer ordered
+    /// dithering) rather than diffusing the error, for a retro look
+    OrderedDither {
+        infile: String,
+        outfile: String,
+        /// How many evenly spaced values each channel keeps, from black to full
+        levels: u32,
+        /// The width of the repeating pattern: 2, 4 or 8. Bigger ones fake more shades
+        #[clap(long, default_value_t = 8)]
+        matrix_size: u32,
+    },
+    /// Play Conway's Game of Life from a random grid and write every generation to an animated GIF
+    GameOfLife {
+        outfile: String,
+        /// Grid size in cells, one pixel each
+        width: u32,
+        height: u32,
+        /// How many times to step the grid after the starting one
+        generations: u32,
+        seed: u64,
+        /// How long each generation is shown, in milliseconds
+        #[clap(long, default_value_t = 100)]
+        delay_ms: u32,
+    },
+    /// Blur each pixel by how far its depth, read from a grayscale depth map, is from the depth in
+    /// focus, like a camera with a shallow depth of field
+    DepthBlur {
+        infile: String,
+        /// Brightest where the scene is nearest, unless --far-is-bright is given. A map that differs
+        /// in size from INFILE is stretched to fit
+        depth_map: String,
+        outfile: String,
+        /// The depth that stays sharp, from 0.0 (nearest) to 1.0 (farthest)
+        focus_depth: f32,
+        /// The strength of the blur at the nearest and farthest depths, like `blur`'s amount
+        max_blur: f32,
+        /// Read DEPTH_MAP as brightest where the scene is farthest
+        #[clap(long)]
+        far_is_bright: bool,
+    },
+    /// Draw a spectrogram from a grid of magnitudes, one row of INFILE per moment in time and one
+    /// column per frequency, lowest first
+    Spectrum {
+        /// Numbers separated by commas or whitespace, like a CSV file without a header
+        infile: String,
+        outfile: String,
+        /// The quietest magnitude becomes the low end of the colormap, and the loudest the high end
+        #[clap(arg_enum, value_parser, default_value = "inferno")]
+        palette: colormap::Colormap,
+    },
+    /// Remove the purple and green fringes that lenses leave along high-contrast edges
+    DefringeCorrect {
+        infile: String,
+        outfile: String,
+        /// How far to take fringe colors toward gray, from 0.0 (not at all) to 1.0 (all the way)
+        amount: f32,
+    },
+    /// Find a document photographed at an angle, straighten it out into a rectangle and crop to it
+    ScanCrop {
+        infile: String,
+        outfile: String,
+    },
+    /// Stretch the quadrilateral between four points of an image into a rectangle, undoing (or
+    /// adding) perspective
+    Perspective {
+        infile: String,
+        outfile: String,
+        /// The points, as X,Y in pixels, that become the top left, top right, bottom right and
+        /// bottom left corners of OUTFILE. The image's own corners are 0,0 WIDTH,0 WIDTH,HEIGHT
+        /// and 0,HEIGHT; points outside it repeat its edge pixels. Put `--` before the points if
+        /// any of them is negative
+        #[clap(required = true, min_values = 4, max_values = 4)]
+        corners: Vec<String>,
+        /// Width of OUTFILE; defaults to the longer of the top and bottom sides
+        #[clap(long)]
+        out_width: Option<u32>,
+        /// Height of OUTFILE; defaults to the longer of the left and right sides
+        #[clap(long)]
+        out_height: Option<u32>,
+    },
+    /// Draw a random maze with exactly one path between any two of its cells, entered at the top
+    /// left and left at the bottom right
+    Maze {
+        outfile: String,
+        /// Size of the maze in cells
+        width: u32,
+        height: u32,
+        /// How wide each cell's path, and each wall, is in pixels
+        #[clap(long, default_value_t = 8)]
+        cell_size: u32,
+        seed: u64,
+    },
+    /// Write a grayscale map of the mean, variance or standard deviation of the brightness around
+    /// each pixel
+    LocalStats {
+        infile: String,
+        outfile: String,
+        /// Width and height of the neighborhood, in pixels
+        window: u32,
+        #[clap(arg_enum, value_parser)]
+        stat: LocalStat,
+    },
+    /// Write a black and white mask of where two frames of the same scene differ, white for
+    /// motion, and print how much of the frame moved
+    MotionMask {
+        frame_a: String,
+        frame_b: String,
+        outfile: String,
+        /// How much a pixel's color must change to count as motion, from 0 to 255 in its most
+        /// changed channel
+        threshold: u8,
+        /// Remove specks of motion and fill holes in it up to this many pixels across, which
+        /// filters out sensor noise
+        #[clap(long, default_value_t = 0)]
+        cleanup: u32,
+    },
+    /// Generate a standard pattern for testing displays and processing
+    TestPattern {
+        outfile: String,
+        width: u32,
+        height: u32,
+        #[clap(arg_enum, value_parser)]
+        pattern: TestPatternType,
+    },
+    /// Change the amount of cyan, magenta, yellow and black ink in one family of colors only, like
+    /// Photoshop's Selective Color
+    SelectiveColor {
+        infile: String,
+        outfile: String,
+        #[clap(arg_enum, value_parser)]
+        target: ColorFamily,
+        /// Percent to add to (or, if negative, take from) the cyan, -100 to 100
+        #[clap(long, default_value_t = 0.0, allow_hyphen_values = true)]
+        cyan: f32,
+        #[clap(long, default_value_t = 0.0, allow_hyphen_values = true)]
+        magenta: f32,
+        #[clap(long, default_value_t = 0.0, allow_hyphen_values = true)]
+        yellow: f32,
+        /// Percent to add to (or take from) all three inks at once, darkening (or lightening)
+        #[clap(long, default_value_t = 0.0, allow_hyphen_values = true)]
+        black: f32,
+    },
+    /// Remove noise by averaging each pixel with the pixels nearby whose surroundings look alike
+    /// (non-local means), which keeps texture that other denoisers smear
+    NlMeans {
+        infile: String,
+        outfile: String,
+        /// Roughly the standard deviation of the noise, from 0 to 255. Higher removes more noise
+        /// but starts to flatten fine detail
+        strength: f32,
+        /// Width of the patches compared to judge how alike two pixels' surroundings are; odd
+        #[clap(long, default_value_t = 7)]
+        patch_size: u32,
+        /// Width of the area searched for similar patches around each pixel; odd
+        #[clap(long, default_value_t = 21)]
+        window: u32,
+    },
+    /// Generate a background to match an image: a diagonal gradient between its two most used
+    /// colors
+    AutoBackground {
+        infile: String,
+        outfile: String,
+        width: u32,
+        height: u32,
+    },
+    /// Draw a star rating, a progress bar or a short text on a dark plate in a corner of an image
+    Badge {
+        infile: String,
+        outfile: String,
+        #[clap(arg_enum, value_parser)]
+        kind: BadgeKind,
+        /// How many of five stars are filled, how full the bar is in percent, or the text
+        value: String,
+        #[clap(arg_enum, value_parser)]
+        position: Corner,
+    },
+    /// Stretch each channel on its own so that its darkest and lightest values, ignoring the
+    /// outermost CLIP_PERCENT at either end, become 0 and 255
+    AutoLevels {
+        infile: String,
+        outfile: String,
+        clip_percent: f32,
+    },
+    /// Split an image into a blurred layer of its colors and tones and a mid-gray layer of its
+    /// detail, so each can be retouched without touching the other
+    FrequencySeparation {
+        infile: String,
+        low_out: String,
+        high_out: String,
+        /// Standard deviation of the Gaussian blur; detail finer than this goes to HIGH_OUT
+        radius: f32,
+    },
+    /// Add the two layers written by frequency-separation back into one image
+    Recombine {
+        low: String,
+        high: String,
+        outfile: String,
+    },
+    /// Generate stripes like `generate`, but with each band as wide as its share of the weights
+    WeightedStripes {
+        outfile: String,
+        width: u32,
+        height: u32,
+        #[clap(arg_enum, value_parser)]
+        orientation: StripeOrientation,
+        /// Bands written as `red:green:blue:weight`, e.g. `255:0:0:3 0:0:255:1`
+        #[clap(required = true)]
+        stripes: Vec<String>,
+    },
+    /// Give a photo the look of a film stock: its contrast, color and grain
+    FilmEmulate {
+        infile: String,
+        outfile: String,
+        #[clap(arg_enum, value_parser)]
+        stock: FilmStock,
+    },
+    /// Generate an HSV color wheel: hue around the circle with red at the top, and saturation
+    /// from white in the center to full at the rim
+    ColorWheel {
+        outfile: String,
+        /// Width and height of the image, which the wheel fills
+        size: u32,
+    },
+    /// Average images of the same size pixel by pixel, e.g. to stack several shots of a scene and
+    /// reduce their noise
+    Average {
+        #[clap(required = true, min_values = 2)]
+        infiles: Vec<String>,
+        outfile: String,
+        /// Average in linear light instead of gamma-encoded sRGB, which comes out too dark
+        #[clap(long)]
+        linear: bool,
+    },
+    /// Place OVERLAY over BASE with its top left corner at X,Y, keeping BASE's size
+    Compose {
+        base: String,
+        overlay: String,
+        outfile: String,
+        #[clap(allow_hyphen_values = true)]
+        x: i64,
+        #[clap(allow_hyphen_values = true)]
+        y: i64,
+        /// How opaque OVERLAY is, from 0 (invisible) to 1 (as its own alpha says)
+        #[clap(long, default_value_t = 1.0)]
+        opacity: f32,
+        /// Blend in linear light instead of gamma-encoded sRGB, which makes soft edges too dark
+        #[clap(long)]
+        linear: bool,
+    },
+    /// Serve images from ROOT over HTTP, processed according to query parameters
+    #[cfg(feature = "serve")]
+    Serve {
+        root: String,
+        #[clap(long, default_value = "127.0.0.1:8080")]
+        addr: String,
+        /// How many processed images to keep in memory
+        #[clap(long, default_value_t = 64)]
+        cache_entries: usize,
+    },
+    /// Apply several operations in a row, e.g. `chain in.png out.png blur 2.5 invert rotate 180`
+    Chain {
+        infile: String,
+        outfile: String,
+        /// Print how long each step took and how many image buffers were allocated
+        #[clap(long)]
+        timing: bool,
+        /// Also write an animated GIF showing the image after every step, labeled with the step
+        #[clap(long, value_name = "GIF")]
+        explain: Option<String>,
+        /// Shrink the frames of the --explain GIF to fit in a square this many pixels wide
+        #[clap(long, default_value_t = 256)]
+        explain_size: u32,
+        /// How long each frame of the --explain GIF is shown, in milliseconds
+        #[clap(long, default_value_t = 1000)]
+        explain_delay: u32,
+        #[clap(required = true, allow_hyphen_values = true)]
+        operations: Vec<String>,
+    },
+}
+
+fn main() {
+    let args = Cli::parse();
+
+    let color_type_mode = if args.force_rgba {
+        ColorTypeMode::ForceRgba
+    } else if args.keep_color_type {
+        ColorTypeMode::Keep
+    } else {
+        ColorTypeMode::Narrowest
+    };
+    COLOR_TYPE_MODE.set(color_type_mode).ok();
+    OUTPUT_FORMAT.set(args.format).ok();
+    #[cfg(feature = "tracing")]
+    trace::init(args.verbose, args.log_format);
+    limits::init(args.max_pixels);
+
+    match args.command {
+        Commands::Blur {
+            infile,
+            outfile,
+            blur_amount,
+            linear,
+            algorithm,
+        } => {
+            let blur_amount = blur_amount.unwrap_or(2.0);
+            blur(infile, outfile, blur_amount, linear, algorithm);
+        }
+
+        Commands::FastBlur {
+            infile,
+            outfile,
+            radius,
+        } => {
+            blur(infile, outfile, radius, false, BlurAlgorithm::Approx);
+        }
+
+        Commands::Brighten {
+            infile,
+            outfile,
+            brightness_amount,
+        } => {
+            let brightness_amount = brightness_amount.unwrap_or(10);
+            brighten(infile, outfile, brightness_amount);
+        }
+
+        Commands::Rotate {
+            infile,
+            outfile,
+            rotation_amount,
+            lossless,
+            strict,
+        } => {
+            rotate(infile, outfile, rotation_amount, lossless, strict);
+        }
+
+        Commands::Flip {
+            infile,
+            outfile,
+            direction,
+            lossless,
+            strict,
+        } => {
+            flip(infile, outfile, direction, lossless, strict);
+        }
+
+        Commands::Grayscale {
+            infile,
+            outfile,
+            drop_alpha,
+        } => {
+            grayscale(infile, outfile, drop_alpha);
+        }
+
+        Commands::Crop {
+            infile,
+            outfile,
+            x,
+            y,
+            width,
+            height,
+            percent,
+            percent_rect,
+        } => {
+            let rect = match (percent, percent_rect) {
+                (Some(percent), _) => CropRect::Percent([
+                    (100.0 - percent) / 2.0,
+                    (100.0 - percent) / 2.0,
+                    percent,
+                    percent,
+                ]),
+                (None, Some(rect)) => CropRect::Percent([rect[0], rect[1], rect[2], rect[3]]),
+                (None, None) => {
+                    CropRect::Pixels(x.unwrap(), y.unwrap(), width.unwrap(), height.unwrap())
+                }
+            };
+            crop(infile, outfile, rect);
+        }
+
+        Commands::Generate {
+            outfile,
+            width,
+            height,
+            stripe_orientation,
+            colors,
+        } => {
+            let colors = colors
+                .iter()
+                .map(|color_string| parse_color(color_string))
+                .collect::<Vec<Color>>();
+
+            generate(outfile, width, height, colors, stripe_orientation);
+        }
+
+        Commands::Invert { infile, outfile } => {
+            invert(infile, outfile);
+        }
+
+        Commands::Fractal { outfile } => {
+            fractal(outfile, cancel::install());
+        }
+
+        Commands::Filmstrip {
+            infile,
+            outfile,
+            cols,
+        } => {
+            filmstrip(infile, outfile, cols);
+        }
+
+        Commands::UnsharpMask {
+            infile,
+            outfile,
+            radius,
+            amount,
+            threshold,
+        } => {
+            unsharp_mask(infile, outfile, radius, amount, threshold);
+        }
+
+        Commands::ClippingCheck { infile, outfile } => {
+            clipping_check(infile, outfile);
+        }
+
+        Commands::Clouds {
+            outfile,
+            width,
+            height,
+            octaves,
+            seed,
+        } => {
+            clouds(outfile, width, height, octaves, seed, cancel::install());
+        }
+
+        Commands::Displace {
+            infile,
+            map,
+            outfile,
+            strength,
+        } => {
+            displace(infile, map, outfile, strength);
+        }
+
+        Commands::NormalMap {
+            infile,
+            outfile,
+            strength,
+        } => {
+            normal_map(infile, outfile, strength);
+        }
+
+        Commands::ToneMap {
+            infile,
+            outfile,
+            operator,
+            exposure,
+        } => {
+            tone_map(infile, outfile, operator, exposure);
+        }
+
+        Commands::MergeExposures { infiles, outfile } => {
+            merge_exposures(infiles, outfile);
+        }
+
+        Commands::DevignetteCorrect {
+            infile,
+            outfile,
+            strength,
+        } => {
+            devignette_correct(infile, outfile, strength);
+        }
+
+        Commands::PolarTransform {
+            infile,
+            outfile,
+            direction,
+        } => {
+            polar_transform(infile, outfile, direction);
+        }
+
+        Commands::StackLayers { infiles, outfile } => {
+            stack_layers(infiles, outfile);
+        }
+
+        Commands::Dimensions { infile } => {
+            dimensions(infile);
+        }
+
+        Commands::Verify { infile, quiet } => {
+            verify(infile, quiet);
+        }
+
+        Commands::Carve {
+            infile,
+            outfile,
+            width,
+            height,
+            visualize,
+        } => {
+            carve(
+                infile,
+                outfile,
+                width.unwrap_or(0),
+                height.unwrap_or(0),
+                visualize,
+                cancel::install(),
+            );
+        }
+
+        Commands::OtsuThreshold { infile, outfile } => {
+            otsu_threshold(infile, outfile);
+        }
+
+        Commands::Phash { infile } => {
+            phash(infile);
+        }
+
+        Commands::PhashDistance { hash_a, hash_b } => {
+            phash_distance(hash_a, hash_b);
+        }
+
+        Commands::AdaptiveThreshold {
+            infile,
+            outfile,
+            block_size,
+            offset,
+        } => {
+            adaptive_threshold(infile, outfile, block_size, offset);
+        }
+
+        Commands::Blurhash {
+            infile,
+            x_components,
+            y_components,
+        } => {
+            let img = limits::open(infile).expect("Failed to open INFILE.");
+            warn_if_downconverting(&img, "INFILE", "blurhash");
+            let img = img.to_rgb8();
+            let hash = blurhash::encode(&img, x_components, y_components)
+                .unwrap_or_else(|err| panic!("{}", err));
+            println!("{}", hash);
+        }
+
+        Commands::BlurhashDecode {
+            hash,
+            outfile,
+            width,
+            height,
+            punch,
+        } => {
+            let img = blurhash::decode(&hash, width, height, punch)
+                .unwrap_or_else(|err| panic!("{}", err));
+            save_image(&image::DynamicImage::ImageRgb8(img), &outfile);
+        }
+
+        Commands::CountObjects {
+            infile,
+            min_area,
+            dark,
+        } => {
+            count_objects(infile, min_area, dark);
+        }
+
+        Commands::Embed {
+            infile,
+            outfile,
+            payload,
+            message,
+            bits,
+        } => {
+            embed(infile, outfile, payload, message, bits);
+        }
+
+        Commands::Extract {
+            infile,
+            output,
+            bits,
+        } => {
+            extract(infile, output, bits);
+        }
+
+        Commands::RlePreview { infile } => {
+            rle_preview(infile);
+        }
+
+        Commands::RawDump {
+            infile,
+            outfile,
+            pixel_format,
+        } => {
+            raw_dump(infile, outfile, pixel_format);
+        }
+
+        Commands::FromRaw {
+            infile,
+            outfile,
+            width,
+            height,
+            pixel_format,
+        } => {
+            from_raw(infile, outfile, width, height, pixel_format);
+        }
+
+        Commands::Diamond {
+            outfile,
+            width,
+            height,
+            color_a,
+            color_b,
+            cell_size,
+        } => {
+            let color_a = parse_color(&color_a);
+            let color_b = parse_color(&color_b);
+            diamond(outfile, width, height, color_a, color_b, cell_size);
+        }
+
+        Commands::ExportArray {
+            infile,
+            outfile,
+            layout,
+            name,
+            threshold,
+        } => {
+            export_array(infile, outfile, layout, name, threshold);
+        }
+
+        Commands::PixelSort {
+            infile,
+            outfile,
+            direction,
+            threshold,
+            threshold_high,
+            key,
+            seed,
+        } => {
+            pixel_sort(
+                infile,
+                outfile,
+                direction,
+                threshold..=threshold_high,
+                key,
+                seed,
+            );
+        }
+
+        Commands::Colormap {
+            infile,
+            outfile,
+            colormap,
+            invert,
+            min,
+            max,
+        } => {
+            apply_colormap(infile, outfile, colormap, invert, min, max);
+        }
+
+        Commands::Glitch {
+            infile,
+            outfile,
+            intensity,
+            seed,
+        } => {
+            glitch(infile, outfile, intensity, seed);
+        }
+
+        Commands::Falsecolor {
+            outfile,
+            r,
+            g,
+            b,
+            input,
+        } => {
+            falsecolor(outfile, [r, g, b], input);
+        }
+
+        Commands::FractalArt {
+            outfile,
+            width,
+            height,
+            fractal_type,
+            palette,
+            max_iter,
+        } => {
+            fractal_art(
+                outfile,
+                width,
+                height,
+                fractal_type,
+                palette,
+                max_iter,
+                cancel::install(),
+            );
+        }
+
+        Commands::Palette {
+            infile,
+            outfile,
+            max,
+            quantize,
+            sort,
+            json,
+        } => {
+            extract_palette(infile, outfile, max, quantize, sort, json);
+        }
+
+        Commands::ApplyPalette {
+            infile,
+            outfile,
+            palette,
+            dither,
+            distance,
+        } => {
+            apply_palette(infile, outfile, palette, dither, distance);
+        }
+
+        Commands::Bulge {
+            infile,
+            outfile,
+            cx,
+            cy,
+            radius,
+            strength,
+        } => {
+            bulge(infile, outfile, cx, cy, radius, strength);
+        }
+
+        Commands::GeoStamp {
+            infile,
+            outfile,
+            position,
+            color,
+            skip_missing,
+        } => {
+            geo_stamp(infile, outfile, position, parse_color(&color), skip_missing);
+        }
+
+        Commands::SpriteSheet {
+            infiles,
+            outfile,
+            padding,
+            max_size,
+        } => {
+            sprite_sheet(infiles, outfile, padding, max_size);
+        }
+
+        Commands::Ascii {
+            infile,
+            width,
+            color,
+            force_color,
+        } => {
+            use std::io::IsTerminal;
+            let color = ascii_color(color, force_color, std::io::stdout().is_terminal());
+            ascii(infile, width, color, &mut std::io::stdout().lock());
+        }
+
+        Commands::Reflect {
+            infile,
+            outfile,
+            height,
+            fraction,
+            opacity,
+            fade,
+            background,
+        } => {
+            let background = background.map(|color| parse_color(&color));
+            reflect(infile, outfile, height, fraction, opacity, fade, background);
+        }
+
+        Commands::HexGrid {
+            outfile,
+            width,
+            height,
+            color,
+            background,
+            hex_size,
+            thickness,
+        } => {
+            let color = parse_color(&color);
+            let background = parse_color(&background);
+            hex_grid(
+                outfile, width, height, color, background, hex_size, thickness,
+            );
+        }
+
+        Commands::BlurPad {
+            infile,
+            outfile,
+            target,
+            blur_sigma,
+            darken,
+        } => {
+            blur_pad(infile, outfile, target, blur_sigma, darken);
+        }
+
+        Commands::Fade {
+            infile,
+            outfile,
+            edges,
+            distance,
+            curve,
+        } => {
+            fade(infile, outfile, edges, distance, curve);
+        }
+
+        Commands::Fern {
+            outfile,
+            width,
+            height,
+            iterations,
+            seed,
+        } => {
+            fern(outfile, width, height, iterations, seed, cancel::install());
+        }
+
+        Commands::Caption {
+            infile,
+            outfile,
+            text,
+            top,
+            font_size,
+            background,
+            color,
+        } => {
+            let background = parse_color(&background);
+            let color = parse_color(&color);
+            caption(infile, outfile, text, top, font_size, background, color);
+        }
+
+        Commands::Halftone {
+            infile,
+            outfile,
+            dot_spacing,
+            angle,
+        } => {
+            halftone(infile, outfile, dot_spacing, angle);
+        }
+
+        Commands::Qr {
+            outfile,
+            data,
+            size,
+            margin,
+            ec_level,
+            dark,
+            light,
+        } => {
+            let dark = parse_color(&dark);
+            let light = parse_color(&light);
+            qr_code(outfile, data, size, margin, ec_level, dark, light);
+        }
+
+        Commands::AutoWhiteBalance { infile, outfile } => {
+            auto_white_balance(infile, outfile);
+        }
+
+        Commands::CheckOrientation { infile, fix } => {
+            check_orientation(infile, fix);
+        }
+
+        Commands::FromMatrix {
+            infile,
+            outfile,
+            palette,
+        } => {
+            from_matrix(infile, outfile, palette);
+        }
+
+        Commands::Resize {
+            infile,
+            outfile,
+            width,
+            height,
+            max_edge,
+            upscale,
+            linear,
+        } => match max_edge {
+            Some(max_edge) => resize_max_edge(infile, outfile, max_edge, upscale, linear),
+            None => resize(infile, outfile, width.unwrap(), height.unwrap(), linear),
+        },
+
+        Commands::Timeline {
+            indir,
+            outfile,
+            cols,
+            thumb_size,
+        } => {
+            timeline(indir, outfile, cols, thumb_size);
+        }
+
+        Commands::EdgeSharpen {
+            infile,
+            outfile,
+            amount,
+            edge_threshold,
+        } => {
+            edge_sharpen(infile, outfile, amount, edge_threshold);
+        }
+
+        Commands::Stats { infile, json } => {
+            stats(infile, json);
+        }
+
+        Commands::GradientText {
+            outfile,
+            text,
+            size,
+            start_color,
+            end_color,
+            background,
+        } => {
+            let start_color = parse_color(&start_color);
+            let end_color = parse_color(&end_color);
+            let background = parse_color(&background);
+            gradient_text(outfile, text, size, start_color, end_color, background);
+        }
+
+        Commands::Dedupe {
+            dir,
+            recursive,
+            threshold,
+            json,
+            delete_smaller,
+            dry_run,
+        } => {
+            dedupe(
+                dir,
+                recursive,
+                threshold,
+                json,
+                delete_smaller,
+                dry_run,
+                cancel::install(),
+            );
+        }
+
+        Commands::ColorCast { infile } => {
+            color_cast(infile);
+        }
+
+        Commands::Cartoon { infile, outfile } => {
+            cartoon(infile, outfile);
+        }
+
+        Commands::TiltShift {
+            infile,
+            outfile,
+            focus_y,
+            focus_height,
+            blur,
+            saturation,
+        } => {
+            tilt_shift(infile, outfile, focus_y, focus_height, blur, saturation);
+        }
+
+        Commands::Saliency { infile, outfile } => {
+            saliency(infile, outfile);
+        }
+
+        Commands::Organize {
+            indir,
+            outdir,
+            pattern,
+            recursive,
+        } => {
+            organize(indir, outdir, pattern, recursive);
+        }
+
+        Commands::ScreenDoor {
+            infile,
+            outfile,
+            opacity,
+        } => {
+            screen_door(infile, outfile, opacity);
+        }
+
+        Commands::OrderedDither {
+            infile,
+            outfile,
+            levels,
+            matrix_size,
+        } => {
+            ordered_dither(infile, outfile, levels, matrix_size);
+        }
+
+        Commands::GameOfLife {
+            outfile,
+            width,
+            height,
+            generations,
+            seed,
+            delay_ms,
+        } => {
+            game_of_life(
+                outfile,
+                width,
+                height,
+                generations,
+                seed,
+                delay_ms,
+                cancel::install(),
+            );
+        }
+
+        Commands::DepthBlur {
+            infile,
+            depth_map,
+            outfile,
+            focus_depth,
+            max_blur,
+            far_is_bright,
+        } => {
+            depth_blur(
+                infile,
+                depth_map,
+                outfile,
+                focus_depth,
+                max_blur,
+                far_is_bright,
+            );
+        }
+
+        Commands::Spectrum {
+            infile,
+            outfile,
+            palette,
+        } => {
+            spectrum(infile, outfile, palette);
+        }
+
+        Commands::DefringeCorrect {
+            infile,
+            outfile,
+            amount,
+        } => {
+            defringe_correct(infile, outfile, amount);
+        }
+
+        Commands::ScanCrop { infile, outfile } => {
+            scan_crop(infile, outfile);
+        }
+
+        Commands::Perspective {
+            infile,
+            outfile,
+            corners,
+            out_width,
+            out_height,
+        } => {
+            perspective(infile, outfile, corners, out_width, out_height);
+        }
+
+        Commands::Maze {
+            outfile,
+            width,
+            height,
+            cell_size,
+            seed,
+        } => {
+            maze(outfile, width, height, cell_size, seed);
+        }
+
+        Commands::LocalStats {
+            infile,
+            outfile,
+            window,
+            stat,
+        } => {
+            local_stats(infile, outfile, window, stat);
+        }
+
+        Commands::MotionMask {
+            frame_a,
+            frame_b,
+            outfile,
+            threshold,
+            cleanup,
+        } => {
+            motion_mask(frame_a, frame_b, outfile, threshold, cleanup);
+        }
+
+        Commands::TestPattern {
+            outfile,
+            width,
+            height,
+            pattern,
+        } => {
+            test_pattern(outfile, width, height, pattern);
+        }
+
+        Commands::SelectiveColor {
+            infile,
+            outfile,
+            target,
+            cyan,
+            magenta,
+            yellow,
+            black,
+        } => {
+            selective_color(infile, outfile, target, [cyan, magenta, yellow], black);
+        }
+
+        Commands::NlMeans {
+            infile,
+            outfile,
+            strength,
+            patch_size,
+            window,
+        } => {
+            nl_means(
+                infile,
+                outfile,
+                strength,
+                patch_size,
+                window,
+                cancel::install(),
+            );
+        }
+
+        Commands::AutoBackground {
+            infile,
+            outfile,
+            width,
+            height,
+        } => {
+            auto_background(infile, outfile, width, height);
+        }
+
+        Commands::Badge {
+            infile,
+            outfile,
+            kind,
+            value,
+            position,
+        } => {
+            badge(infile, outfile, kind, value, position);
+        }
+
+        Commands::AutoLevels {
+            infile,
+            outfile,
+            clip_percent,
+        } => {
+            auto_levels(infile, outfile, clip_percent);
+        }
+
+        Commands::FrequencySeparation {
+            infile,
+            low_out,
+            high_out,
+            radius,
+        } => {
+            frequency_separation(infile, low_out, high_out, radius);
+        }
+
+        Commands::Recombine { low, high, outfile } => {
+            recombine(low, high, outfile);
+        }
+
+        Commands::WeightedStripes {
+            outfile,
+            width,
+            height,
+            orientation,
+            stripes,
+        } => {
+            weighted_stripes(outfile, width, height, orientation, stripes);
+        }
+
+        Commands::FilmEmulate {
+            infile,
+            outfile,
+            stock,
+        } => {
+            film_emulate(infile, outfile, stock);
+        }
+
+        Commands::ColorWheel { outfile, size } => {
+            color_wheel(outfile, size);
+        }
+
+        Commands::Average {
+            infiles,
+            outfile,
+            linear,
+        } => {
+            average(infiles, outfile, linear);
+        }
+
+        Commands::Compose {
+            base,
+            overlay,
+            outfile,
+            x,
+            y,
+            opacity,
+            linear,
+        } => {
+            compose(base, overlay, outfile, x, y, opacity, linear);
+        }
+
+        #[cfg(feature = "serve")]
+        Commands::Serve {
+            root,
+            addr,
+            cache_entries,
+        } => {
+            serve::serve(root, addr, cache_entries, limits::max_pixels());
+        }
+
+        Commands::Chain {
+            infile,
+            outfile,
+            timing,
+            explain,
+            explain_size,
+            explain_delay,
+            operations,
+        } => {
+            let explain = explain.map(|outfile| Explain {
+                outfile,
+                size: explain_size,
+                delay: explain_delay,
+            });
+            chain(
+                infile,
+                outfile,
+                operations,
+                timing,
+                explain,
+                cancel::install(),
+            );
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+enum BlurAlgorithm {
+    Auto,
+    Gaussian,
+    Approx,
+}
+
+// Above this blur amount, `BlurAlgorithm::Auto` switches to the box blur approximation.
+const APPROX_BLUR_THRESHOLD: f32 = 10.0;
+
+fn blur(infile: String, outfile: String, blur_amount: f32, linear: bool, algorithm: BlurAlgorithm) {
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    let approx = match algorithm {
+        BlurAlgorithm::Auto => blur_amount > APPROX_BLUR_THRESHOLD,
+        BlurAlgorithm::Gaussian => false,
+        BlurAlgorithm::Approx => true,
+    };
+    let blurred = match (linear, approx) {
+        (true, true) => colorspace::from_linear(
+            boxblur::gaussian_approx(&colorspace::to_linear(&img), blur_amount),
+            &img,
+        ),
+        (true, false) => colorspace::from_linear(
+            image::imageops::blur(&colorspace::to_linear(&img), blur_amount),
+            &img,
+        ),
+        (false, true) => like_original(
+            boxblur::gaussian_approx(&img.to_rgba32f(), blur_amount),
+            &img,
+        ),
+        (false, false) => img.blur(blur_amount),
+    };
+    save_image(&blurred, &outfile);
+}
+
+fn brighten(infile: String, outfile: String, brightness_amount: i32) {
+    let mut img = limits::open(infile).expect("Failed to open INFILE.");
+    brighten_in_place(&mut img, brightness_amount);
+    save_image(&img, &outfile);
+}
+
+// Brighten the common 8-bit and 16-bit color types by working directly on the raw subpixel
+// buffer, which avoids the per-pixel get/put overhead of the generic path. Alpha is left untouched.
+fn brighten_in_place(img: &mut image::DynamicImage, brightness_amount: i32) {
+    use image::DynamicImage::*;
+    match img {
+        ImageLuma8(buf) => brighten_bytes(buf, 1, false, brightness_amount),
+        ImageLumaA8(buf) => brighten_bytes(buf, 2, true, brightness_amount),
+        ImageRgb8(buf) => brighten_bytes(buf, 3, false, brightness_amount),
+        ImageRgba8(buf) => brighten_bytes(buf, 4, true, brightness_amount),
+        ImageLuma16(buf) => brighten_words(buf, 1, false, brightness_amount),
+        ImageLumaA16(buf) => brighten_words(buf, 2, true, brightness_amount),
+        ImageRgb16(buf) => brighten_words(buf, 3, false, brightness_amount),
+        ImageRgba16(buf) => brighten_words(buf, 4, true, brightness_amount),
+        _ => *img = img.brighten(brightness_amount),
+    }
+}
+
+enum CropRect {
+    Pixels(u32, u32, u32, u32),
+    // x, y, width and height in percent of the image's size.
+    Percent([f32; 4]),
+}
+
+fn crop(infile: String, outfile: String, rect: CropRect) {
+    let mut img = limits::open(infile).expect("Failed to open INFILE.");
+    let (x, y, width, height) = match rect {
+        CropRect::Pixels(x, y, width, height) => (x, y, width, height),
+        CropRect::Percent([x, y, width, height]) => {
+            for (value, name) in [(x, "x"), (y, "y"), (width, "width"), (height, "height")] {
+                if !(0.0..=100.0).contains(&value) {
+                    panic!("{} is not a valid {} percentage!", value, name);
+                }
+            }
+            if x + width > 100.0 || y + height > 100.0 {
+                panic!("The crop rectangle extends past the edge of the image!");
+            }
+            let (x, width) = percent_span(img.width(), x, width);
+            let (y, height) = percent_span(img.height(), y, height);
+            (x, y, width, height)
+        }
+    };
+    save_image(&img.crop(x, y, width, height), &outfile);
+}
+
+// The pixel offset and length of the span from `start` to `start + length` percent of `total`
+// pixels. Both edges are rounded to the nearest pixel, then kept inside the image with at least one
+// pixel between them, so tiny images and tiny percentages still give a valid crop.
+fn percent_span(total: u32, start: f32, length: f32) -> (u32, u32) {
+    let to_pixels = |percent: f32| (total as f32 * percent / 100.0).round() as u32;
+    let first = to_pixels(start).min(total - 1);
+    let end = to_pixels(start + length).clamp(first + 1, total);
+    (first, end - first)
+}
+
+fn rotate(infile: String, outfile: String, rotation_amount: u32, lossless: bool, strict: bool) {
+    let transform = match rotation_amount {
+        90 => jpegtran::Transform::Rotate90,
+        180 => jpegtran::Transform::Rotate180,
+        270 => jpegtran::Transform::Rotate270,
+        _ => panic!("{} is not a valid rotation amount!", rotation_amount),
+    };
+    if lossless && transform_losslessly(&infile, &outfile, transform, strict) {
+        return;
+    }
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+
+    let rotated = match rotation_amount {
+        90 => img.rotate90(),
+        180 => img.rotate180(),
+        _ => img.rotate270(),
+    };
+    save_image(&rotated, &outfile);
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+enum FlipDirection {
+    Horizontal,
+    Vertical,
+}
+
+fn flip(infile: String, outfile: String, direction: FlipDirection, lossless: bool, strict: bool) {
+    let transform = match direction {
+        FlipDirection::Horizontal => jpegtran::Transform::FlipHorizontal,
+        FlipDirection::Vertical => jpegtran::Transform::FlipVertical,
+    };
+    if lossless && transform_losslessly(&infile, &outfile, transform, strict) {
+        return;
+    }
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+
+    let flipped = match direction {
+        FlipDirection::Horizontal => img.fliph(),
+        FlipDirection::Vertical => img.flipv(),
+    };
+    save_image(&flipped, &outfile);
+}
+
+// For --lossless: apply `transform` to the compressed blocks of a JPEG INFILE and write them to a
+// JPEG OUTFILE, EXIF and ICC data included. Returns false, after a warning, when the caller should
+// transform the decoded pixels instead, or panics under --strict.
+fn transform_losslessly(
+    infile: &str,
+    outfile: &str,
+    transform: jpegtran::Transform,
+    strict: bool,
+) -> bool {
+    limits::enforce(std::path::Path::new(infile));
+    let bytes = std::fs::read(infile).expect("Failed to open INFILE.");
+    let result = if image::guess_format(&bytes).ok() != Some(image::ImageFormat::Jpeg) {
+        Err("INFILE is not a JPEG".to_string())
+    } else if output_format(outfile) != Some(image::ImageFormat::Jpeg) {
+        Err("OUTFILE is not a JPEG".to_string())
+    } else {
+        jpegtran::transform(&bytes, transform)
+    };
+
+    match result {
+        Ok(transformed) => {
+            write_atomically(outfile, |path| {
+                std::fs::write(path, &transformed).map_err(|e| e.to_string())
+            });
+            true
+        }
+        Err(reason) if strict => panic!("Failed to transform INFILE losslessly: {}.", reason),
+        Err(reason) => {
+            eprintln!(
+                "Warning: can't transform INFILE losslessly ({}), re-encoding it instead.",
+                reason
+            );
+            false
+        }
+    }
+}
+
+fn invert(infile: String, outfile: String) {
+    let mut img = limits::open(infile).expect("Failed to open INFILE.");
+    invert_in_place(&mut img);
+    save_image(&img, &outfile);
+}
+
+// Invert the common 8-bit and 16-bit color types by working directly on the raw subpixel buffer.
+// Alpha is left untouched, and anything else falls back to the generic per-pixel path.
+fn invert_in_place(img: &mut image::DynamicImage) {
+    use image::DynamicImage::*;
+    match img {
+        ImageLuma8(buf) => invert_bytes(buf, 1, false),
+        ImageLumaA8(buf) => invert_bytes(buf, 2, true),
+        ImageRgb8(buf) => invert_bytes(buf, 3, false),
+        ImageRgba8(buf) => invert_bytes(buf, 4, true),
+        ImageLuma16(buf) => invert_words(buf, 1, false),
+        ImageLumaA16(buf) => invert_words(buf, 2, true),
+        ImageRgb16(buf) => invert_words(buf, 3, false),
+        ImageRgba16(buf) => invert_words(buf, 4, true),
+        _ => img.invert(),
+    }
+}
+
+// Images with alpha become LumaA (or gray RGBA for floating point images) with the alpha bytes
+// unchanged, unless `drop_alpha` asks for it to be thrown away.
+fn grayscale(infile: String, outfile: String, drop_alpha: bool) {
+    use image::DynamicImage::*;
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    let gray = img.grayscale();
+    let gray = match gray {
+        ImageLumaA8(_) if drop_alpha => ImageLuma8(gray.to_luma8()),
+        ImageLumaA16(_) if drop_alpha => ImageLuma16(gray.to_luma16()),
+        ImageRgba32F(_) if drop_alpha => ImageRgb32F(gray.to_rgb32f()),
+        _ => gray,
+    };
+    save_image(&gray, &outfile);
+}
+
+// True for images with more than 8 bits per channel, e.g. 16-bit PNG and TIFF files.
+fn is_high_bit_depth(img: &image::DynamicImage) -> bool {
+    let color = img.color();
+    color.bytes_per_pixel() > color.channel_count()
+}
+
+// Convert a floating point result back to 8 or 16 bits per channel, with or without alpha, to match
+// the image it was computed from.
+fn like_original(img: image::Rgba32FImage, original: &image::DynamicImage) -> image::DynamicImage {
+    let img = image::DynamicImage::ImageRgba32F(img);
+    match (is_high_bit_depth(original), original.color().has_alpha()) {
+        (true, true) => image::DynamicImage::ImageRgba16(img.to_rgba16()),
+        (true, false) => image::DynamicImage::ImageRgb16(img.to_rgb16()),
+        (false, true) => image::DynamicImage::ImageRgba8(img.to_rgba8()),
+        (false, false) => image::DynamicImage::ImageRgb8(img.to_rgb8()),
+    }
+}
+
+// Operations that only work on 8-bit data call this so that deep images aren't squashed silently.
+fn warn_if_downconverting(img: &image::DynamicImage, input: &str, operation: &str) {
+    if is_high_bit_depth(img) {
+        eprintln!(
+            "Warning: {} works on 8-bit data, so {} will be reduced to 8 bits per channel.",
+            operation, input
+        );
+    }
+}
+
+// Save `img` to `outfile` using the color type picked on the command line (the narrowest exact one
+// by default), keeping 16 bits per channel for formats that can store them. Other formats get an
+// 8-bit copy, with a warning so the loss of precision isn't silent.
+// The format `save_image` writes `outfile` in: --format when it was given, otherwise the one the
+// extension names. None for --format raw and for extensions `image` doesn't know.
+fn output_format(outfile: &str) -> Option<image::ImageFormat> {
+    match OUTPUT_FORMAT.get().copied().flatten() {
+        Some(format) => format.image_format(),
+        None => image::ImageFormat::from_path(outfile).ok(),
+    }
+}
+
+fn save_image(img: &image::DynamicImage, outfile: &str) {
+    use image::ImageFormat;
+
+    let _span = trace::span!("save", path = outfile);
+
+    let img = with_output_color_type(
+        img,
+        COLOR_TYPE_MODE.get().copied().unwrap_or_default(),
+        limits::input_color_type(),
+    );
+
+    let format = match OUTPUT_FORMAT.get().copied().flatten() {
+        Some(OutputFormat::Raw) => {
+            let bytes = if img.color().has_alpha() {
+                img.to_rgba8().into_raw()
+            } else {
+                img.to_rgb8().into_raw()
+            };
+            write_atomically(outfile, |path| {
+                std::fs::write(path, &bytes).map_err(|e| e.to_string())
+            });
+            return;
+        }
+        Some(format) => format.image_format().unwrap(),
+        None => ImageFormat::from_path(outfile).expect("Failed writing OUTFILE."),
+    };
+    // The temporary file has no meaningful extension, so the encoder is chosen here rather than by
+    // `image` from the path.
+    let encode = |img: &image::DynamicImage, output: image::ImageOutputFormat| {
+        write_atomically(outfile, |path| {
+            let file = std::fs::File::create(path).map_err(|e| e.to_string())?;
+            let mut writer = std::io::BufWriter::new(file);
+            img.write_to(&mut writer, output)
+                .map_err(|e| e.to_string())?;
+            std::io::Write::flush(&mut writer).map_err(|e| e.to_string())
+        })
+    };
+
+    if format == ImageFormat::Pnm && has_extension(outfile, "pbm") {
+        let bytes = pbm_bytes(&img.to_luma8());
+        write_atomically(outfile, |path| {
+            std::fs::write(path, &bytes).map_err(|e| e.to_string())
+        });
+        return;
+    }
+    if format == ImageFormat::Pnm {
+        let (converted, output) = pnm_output(&img, outfile);
+        if is_high_bit_depth(&img) && !is_high_bit_depth(&converted) {
+            eprintln!(
+                "Warning: OUTFILE's format can't store 16-bit channels, writing 8 bits per channel."
+            );
+        }
+        encode(&converted, output);
+        return;
+    }
+
+    let keeps_depth = matches!(format, ImageFormat::Png | ImageFormat::Tiff);
+    if !is_high_bit_depth(&img) || keeps_depth {
+        encode(&img, format.into());
+        return;
+    }
+
+    eprintln!("Warning: OUTFILE's format can't store 16-bit channels, writing 8 bits per channel.");
+    let reduced = match img.color() {
+        image::ColorType::L16 => image::DynamicImage::ImageLuma8(img.to_luma8()),
+        image::ColorType::La16 => image::DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+        color if color.has_alpha() => image::DynamicImage::ImageRgba8(img.to_rgba8()),
+        _ => image::DynamicImage::ImageRgb8(img.to_rgb8()),
+    };
+    encode(&reduced, format.into());
+}
+
+// PNM has several subtypes, picked by OUTFILE's extension: PGM for gray, PPM for RGB and PAM for
+// anything, alpha included. With `--format pnm` and another extension the subtype follows the image's
+// color type. The PGM and PPM encoders only take 8-bit data, and the PAM one only takes 16 bits for
+// gray, so the image is converted to what the subtype can hold. PBM is written by `pbm_bytes`.
+fn pnm_output(
+    img: &image::DynamicImage,
+    outfile: &str,
+) -> (image::DynamicImage, image::ImageOutputFormat) {
+    use image::codecs::pnm::{PnmSubtype, SampleEncoding};
+    use image::{ColorType, DynamicImage};
+
+    let gray = matches!(
+        img.color(),
+        ColorType::L8 | ColorType::La8 | ColorType::L16 | ColorType::La16
+    );
+    let subtype = if has_extension(outfile, "pgm") {
+        PnmSubtype::Graymap(SampleEncoding::Binary)
+    } else if has_extension(outfile, "ppm") {
+        PnmSubtype::Pixmap(SampleEncoding::Binary)
+    } else if has_extension(outfile, "pam") || img.color().has_alpha() {
+        PnmSubtype::ArbitraryMap
+    } else if gray {
+        PnmSubtype::Graymap(SampleEncoding::Binary)
+    } else {
+        PnmSubtype::Pixmap(SampleEncoding::Binary)
+    };
+
+    let converted = match subtype {
+        PnmSubtype::Graymap(_) => DynamicImage::ImageLuma8(img.to_luma8()),
+        PnmSubtype::Pixmap(_) => DynamicImage::ImageRgb8(img.to_rgb8()),
+        _ => match img.color() {
+            ColorType::L8
+            | ColorType::La8
+            | ColorType::Rgb8
+            | ColorType::Rgba8
+            | ColorType::L16 => img.clone(),
+            ColorType::La16 => DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+            ColorType::Rgb16 => DynamicImage::ImageRgb8(img.to_rgb8()),
+            _ => DynamicImage::ImageRgba8(img.to_rgba8()),
+        },
+    };
+    (converted, image::ImageOutputFormat::Pnm(subtype))
+}
+
+// A binary PBM file: one bit per pixel, 1 for black, each row padded to whole bytes. Pixels darker
+// than mid-gray turn black. The `image` encoder for PBM rejects any sample but 0, so it can't be used.
+fn pbm_bytes(img: &image::GrayImage) -> Vec<u8> {
+    let mut bytes = format!("P4\n{} {}\n", img.width(), img.height()).into_bytes();
+    for row in img.rows() {
+        let bits: Vec<bool> = row.map(|pixel| pixel[0] < 128).collect();
+        for chunk in bits.chunks(8) {
+            let byte = chunk
+                .iter()
+                .enumerate()
+                .fold(0u8, |byte, (i, &black)| byte | ((black as u8) << (7 - i)));
+            bytes.push(byte);
+        }
+    }
+    bytes
+}
+
+fn has_extension(path: &str, extension: &str) -> bool {
+    std::path::Path::new(path)
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case(extension))
+}
+
+// Encode into a temporary file next to `outfile` and rename it into place once it is complete, so
+// that an interrupted or failed write never leaves a truncated OUTFILE behind.
+fn write_atomically(outfile: &str, write: impl FnOnce(&std::path::Path) -> Result<(), String>) {
+    let path = std::path::Path::new(outfile);
+    let file_name = path
+        .file_name()
+        .expect("Failed writing OUTFILE.")
+        .to_string_lossy();
+    let partial = path.with_file_name(format!(".{}.partial", file_name));
+
+    if let Err(err) = write(&partial) {
+        std::fs::remove_file(&partial).ok();
+        panic!("Failed writing OUTFILE: {}", err);
+    }
+    std::fs::rename(&partial, path).expect("Failed writing OUTFILE.");
+}
+
+// `img` in the color type `mode` asks for. `input` is the color type the input decoded to, if the
+// command had one; without it, --keep-color-type keeps the result's own.
+fn with_output_color_type(
+    img: &image::DynamicImage,
+    mode: ColorTypeMode,
+    input: Option<image::ColorType>,
+) -> image::DynamicImage {
+    match (mode, input) {
+        (ColorTypeMode::Narrowest, _) => narrowest_color_type(img),
+        (ColorTypeMode::Keep, Some(color)) => mirage::convert(img.clone(), color),
+        (ColorTypeMode::Keep, None) => img.clone(),
+        (ColorTypeMode::ForceRgba, _) if is_high_bit_depth(img) => {
+            image::DynamicImage::ImageRgba16(img.to_rgba16())
+        }
+        (ColorTypeMode::ForceRgba, _) => image::DynamicImage::ImageRgba8(img.to_rgba8()),
+    }
+}
+
+// The narrowest color type that still represents `img` exactly: alpha is dropped when every pixel
+// is fully opaque, and color is dropped when every pixel is gray.
+fn narrowest_color_type(img: &image::DynamicImage) -> image::DynamicImage {
+    use image::DynamicImage::*;
+    let (gray, opaque) = match img {
+        ImageLumaA8(buf) => (true, is_opaque(buf, 2, u8::MAX)),
+        ImageRgb8(buf) => (is_gray(buf, 3), false),
+        ImageRgba8(buf) => (is_gray(buf, 4), is_opaque(buf, 4, u8::MAX)),
+        ImageLumaA16(buf) => (true, is_opaque(buf, 2, u16::MAX)),
+        ImageRgb16(buf) => (is_gray(buf, 3), false),
+        ImageRgba16(buf) => (is_gray(buf, 4), is_opaque(buf, 4, u16::MAX)),
+        _ => return img.clone(),
+    };
+    let has_alpha = img.color().has_alpha() && !opaque;
+    match (is_high_bit_depth(img), gray, has_alpha) {
+        (false, true, false) => ImageLuma8(img.to_luma8()),
+        (false, true, true) => ImageLumaA8(img.to_luma_alpha8()),
+        (false, false, false) => ImageRgb8(img.to_rgb8()),
+        (false, false, true) => ImageRgba8(img.to_rgba8()),
+        (true, true, false) => ImageLuma16(img.to_luma16()),
+        (true, true, true) => ImageLumaA16(img.to_luma_alpha16()),
+        (true, false, false) => ImageRgb16(img.to_rgb16()),
+        (true, false, true) => ImageRgba16(img.to_rgba16()),
+    }
+}
+
+// True when the first three channels of every pixel are equal.
+fn is_gray<T: PartialEq>(samples: &[T], channels: usize) -> bool {
+    samples
+        .chunks_exact(channels)
+        .all(|pixel| pixel[0] == pixel[1] && pixel[1] == pixel[2])
+}
+
+// True when the last channel of every pixel is fully opaque.
+fn is_opaque<T: PartialEq>(samples: &[T], channels: usize, opaque: T) -> bool {
+    samples
+        .chunks_exact(channels)
+        .all(|pixel| pixel[channels - 1] == opaque)
+}
+
+struct Color {
+    red: u8,
+    green: u8,
+    blue: u8,
+}
+
+// Parse a color written as `red:green:blue`, e.g. `255:128:0`.
+fn parse_color(color_string: &str) -> Color {
+    let split_vals = color_string.split(':');
+    let vec_vals = split_vals.collect::<Vec<&str>>();
+
+    Color {
+        red: vec_vals[0].parse::<u8>().unwrap(),
+        green: vec_vals[1].parse::<u8>().unwrap(),
+        blue: vec_vals[2].parse::<u8>().unwrap(),
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+enum StripeOrientation {
+    Vertical,
+    Horizontal,
+}
+
+fn generate(
+    outfile: String,
+    width: u32,
+    height: u32,
+    colors: Vec<Color>,
+    stripe_orientation: StripeOrientation,
+) {
+    let mut imgbuf = image::RgbImage::new(width, height);
+    for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+        let color_index = match stripe_orientation {
+            StripeOrientation::Vertical => (x as usize) / ((width as usize) / colors.len()),
+            StripeOrientation::Horizontal => (y as usize) / ((height as usize) / colors.len()),
+        };
+        let curr_color = &colors[color_index];
+        *pixel = image::Rgb([curr_color.red, curr_color.green, curr_color.blue]);
+    }
+
+    save_image(&image::DynamicImage::ImageRgb8(imgbuf), &outfile);
+}
+
+// This code was adapted from https://github.com/PistonDevelopers/image
+fn fractal(outfile: String, cancel: cancel::Cancel) {
+    let width = 800;
+    let height = 800;
+
+    let mut imgbuf = image::ImageBuffer::new(width, height);
+
+    let scale_x = 3.0 / width as f32;
+    let scale_y = 3.0 / height as f32;
+
+    // Iterate over the coordinates and pixels of the image, a row at a time so that Ctrl-C can stop
+    // the render between rows
+    for (y, row) in imgbuf.enumerate_rows_mut() {
+        if cancel.requested() {
+            cancel::exit(y as usize, height as usize, "rows");
+        }
+        for (x, _, pixel) in row {
+            // Use red and blue to be a pretty gradient background
+            let red = (0.3 * x as f32) as u8;
+            let blue = (0.3 * y as f32) as u8;
+
+            // Use green as the fractal foreground (here is the fractal math part)
+            let cx = y as f32 * scale_x - 1.5;
+            let cy = x as f32 * scale_y - 1.5;
+
+            let c = num_complex::Complex::new(-0.4, 0.6);
+            let mut z = num_complex::Complex::new(cx, cy);
+
+            let mut green = 0;
+            while green < 255 && z.norm() <= 2.0 {
+                z = z * z + c;
+                green += 1;
+            }
+
+            // Actually set the pixel. red, green, and blue are u8 values!
+            *pixel = image::Rgb([red, green, blue]);
+        }
+    }
+
+    save_image(&image::DynamicImage::ImageRgb8(imgbuf), &outfile);
+}
+
+// Lay out every frame of an animated GIF in a grid `cols` cells wide, producing a single static
+// overview image.
+fn filmstrip(infile: String, outfile: String, cols: u32) {
+    use image::AnimationDecoder;
+
+    if cols == 0 {
+        panic!("{} is not a valid number of columns!", cols);
+    }
+
+    limits::enforce(std::path::Path::new(&infile));
+    let file = std::fs::File::open(infile).expect("Failed to open INFILE.");
+    let decoder =
+        image::codecs::gif::GifDecoder::new(file).expect("Failed to decode INFILE as a GIF.");
+    let frames = decoder
+        .into_frames()
+        .collect_frames()
+        .expect("Failed to decode GIF frames.");
+    if frames.is_empty() {
+        panic!("INFILE does not contain any frames!");
+    }
+
+    // Frames may be smaller than the GIF canvas and offset within it, so every cell is sized to
+    // fit the largest extent of any frame.
+    let cell_width = frames
+        .iter()
+        .map(|frame| frame.left() + frame.buffer().width())
+        .max()
+        .unwrap();
+    let cell_height = frames
+        .iter()
+        .map(|frame| frame.top() + frame.buffer().height())
+        .max()
+        .unwrap();
+
+    let cols = cols.min(frames.len() as u32);
+    let rows = (frames.len() as u32).div_ceil(cols);
+
+    let mut imgbuf = image::RgbaImage::new(cols * cell_width, rows * cell_height);
+    for (i, frame) in frames.iter().enumerate() {
+        let x = (i as u32 % cols) * cell_width + frame.left();
+        let y = (i as u32 / cols) * cell_height + frame.top();
+        image::imageops::overlay(&mut imgbuf, frame.buffer(), x as i64, y as i64);
+    }
+
+    save_image(&image::DynamicImage::ImageRgba8(imgbuf), &outfile);
+}
+
+// Classic unsharp mask: blur by `radius`, then add `amount` times the difference between the
+// original and the blur back in, but only where that difference exceeds `threshold` so that
+// low-level noise isn't amplified.
+fn unsharp_mask(infile: String, outfile: String, radius: f32, amount: f32, threshold: u8) {
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "unsharp-mask");
+    let img = img.to_rgba8();
+    let blurred = image::imageops::blur(&img, radius);
+
+    let mut imgbuf = img.clone();
+    for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+        let blurred_pixel = blurred.get_pixel(x, y);
+        for c in 0..3 {
+            let original = pixel[c] as f32;
+            let diff = original - blurred_pixel[c] as f32;
+            if diff.abs() > threshold as f32 {
+                pixel[c] = (original + amount * diff).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+
+    save_image(&image::DynamicImage::ImageRgba8(imgbuf), &outfile);
+}
+
+// Report how much of each channel is crushed to 0 or blown out to 255, a standard exposure
+// diagnostic. If `outfile` is given, clipped pixels are also marked with diagonal "zebra" stripes:
+// red where a channel is blown out and blue where a channel is crushed.
+fn clipping_check(infile: String, outfile: Option<String>) {
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "clipping-check");
+    let img = img.to_rgb8();
+
+    let (shadows, highlights) = clipped_percentages(&img);
+    println!("{:<8} {:>10} {:>12}", "channel", "shadows", "highlights");
+    for (c, name) in ["red", "green", "blue"].iter().enumerate() {
+        println!("{:<8} {:>9.2}% {:>11.2}%", name, shadows[c], highlights[c]);
+    }
+
+    if let Some(outfile) = outfile {
+        let mut imgbuf = img.clone();
+        for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+            if (x + y) / 4 % 2 != 0 {
+                continue;
+            }
+            if pixel.0.contains(&255) {
+                *pixel = image::Rgb([255, 0, 0]);
+            } else if pixel.0.contains(&0) {
+                *pixel = image::Rgb([0, 0, 255]);
+            }
+        }
+        save_image(&image::DynamicImage::ImageRgb8(imgbuf), &outfile);
+    }
+}
+
+// The percentage of pixels crushed to 0 and blown out to 255, per channel.
+fn clipped_percentages(img: &image::RgbImage) -> ([f64; 3], [f64; 3]) {
+    let mut shadows = [0u64; 3];
+    let mut highlights = [0u64; 3];
+    for pixel in img.pixels() {
+        for c in 0..3 {
+            match pixel[c] {
+                0 => shadows[c] += 1,
+                255 => highlights[c] += 1,
+                _ => {}
+            }
+        }
+    }
+    let total = (img.width() as u64 * img.height() as u64).max(1) as f64;
+    (
+        shadows.map(|count| count as f64 * 100.0 / total),
+        highlights.map(|count| count as f64 * 100.0 / total),
+    )
+}
+
+fn clouds(
+    outfile: String,
+    width: u32,
+    height: u32,
+    octaves: u32,
+    seed: u64,
+    cancel: cancel::Cancel,
+) {
+    if octaves == 0 {
+        panic!("{} is not a valid number of octaves!", octaves);
+    }
+
+    let perlin = noise::Perlin::new(seed);
+    // The first octave spans about four lattice cells across the longer side of the image, and
+    // every further octave adds finer detail on top.
+    let scale = 4.0 / width.max(height) as f32;
+
+    let mut imgbuf = image::GrayImage::new(width, height);
+    for (y, row) in imgbuf.enumerate_rows_mut() {
+        if cancel.requested() {
+            cancel::exit(y as usize, height as usize, "rows");
+        }
+        for (x, _, pixel) in row {
+            let value = perlin.fbm(x as f32 * scale, y as f32 * scale, octaves);
+            *pixel = image::Luma([((value + 1.0) * 127.5).clamp(0.0, 255.0) as u8]);
+        }
+    }
+
+    save_image(&image::DynamicImage::ImageLuma8(imgbuf), &outfile);
+}
+
+// Each pixel of the output is sampled from `infile` at an offset proportional to how far the
+// matching pixel of `map` is from mid-gray: black shifts by `-strength` pixels along both axes and
+// white by `+strength`. A map that differs in size from `infile` is stretched to fit.
+fn displace(infile: String, map: String, outfile: String, strength: f32) {
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "displace");
+    let img = img.to_rgba8();
+    let map = limits::open(map).expect("Failed to open MAP.");
+    warn_if_downconverting(&map, "MAP", "displace");
+    let map = map.to_luma8();
+    let (width, height) = img.dimensions();
+    let map = if map.dimensions() == (width, height) {
+        map
+    } else {
+        image::imageops::resize(&map, width, height, image::imageops::FilterType::Triangle)
+    };
+
+    let mut imgbuf = image::RgbaImage::new(width, height);
+    for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+        let offset = (map.get_pixel(x, y)[0] as f32 - 128.0) / 128.0 * strength;
+        *pixel = sample_bilinear(&img, x as f32 + offset, y as f32 + offset);
+    }
+
+    save_image(&image::DynamicImage::ImageRgba8(imgbuf), &outfile);
+}
+
+// Sample `img` at a fractional position by blending the four surrounding pixels. Positions outside
+// the image are clamped to the nearest edge.
+fn sample_bilinear(img: &image::RgbaImage, x: f32, y: f32) -> image::Rgba<u8> {
+    let (width, height) = img.dimensions();
+    let x = x.clamp(0.0, (width - 1) as f32);
+    let y = y.clamp(0.0, (height - 1) as f32);
+    let x0 = x.floor() as u32;
+    let y0 = y.floor() as u32;
+    let x1 = (x0 + 1).min(width - 1);
+    let y1 = (y0 + 1).min(height - 1);
+    let fx = x - x0 as f32;
+    let fy = y - y0 as f32;
+
+    let p00 = img.get_pixel(x0, y0);
+    let p10 = img.get_pixel(x1, y0);
+    let p01 = img.get_pixel(x0, y1);
+    let p11 = img.get_pixel(x1, y1);
+    let mut out = [0u8; 4];
+    for c in 0..4 {
+        let top = p00[c] as f32 * (1.0 - fx) + p10[c] as f32 * fx;
+        let bottom = p01[c] as f32 * (1.0 - fx) + p11[c] as f32 * fx;
+        out[c] = (top * (1.0 - fy) + bottom * fy).round() as u8;
+    }
+    image::Rgba(out)
+}
+
+// Treat the brightness of `infile` as height and encode the surface normal at every pixel as RGB,
+// the way 3D engines expect: X in red, Y in green and Z in blue, each mapped from -1..1 to 0..255.
+// A flat heightmap gives the neutral normal color (128, 128, 255).
+fn normal_map(infile: String, outfile: String, strength: f32) {
+    let heightmap = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&heightmap, "INFILE", "normal-map");
+    let heightmap = heightmap.to_luma8();
+
+    let mut imgbuf = image::RgbImage::new(heightmap.width(), heightmap.height());
+    for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+        let (dx, dy) = sobel_gradient(&heightmap, x, y);
+        let normal = [-dx / 255.0 * strength, -dy / 255.0 * strength, 1.0];
+        let length = (normal[0] * normal[0] + normal[1] * normal[1] + normal[2] * normal[2]).sqrt();
+        let encode = |n: f32| ((n / length * 0.5 + 0.5) * 255.0).round() as u8;
+        *pixel = image::Rgb([encode(normal[0]), encode(normal[1]), encode(normal[2])]);
+    }
+
+    save_image(&image::DynamicImage::ImageRgb8(imgbuf), &outfile);
+}
+
+// The horizontal and vertical Sobel derivatives of `img` at (x, y). Pixels outside the image are
+// clamped to the nearest edge.
+fn sobel_gradient(img: &image::GrayImage, x: u32, y: u32) -> (f32, f32) {
+    let (width, height) = img.dimensions();
+    let at = |dx: i64, dy: i64| {
+        let sx = (x as i64 + dx).clamp(0, width as i64 - 1) as u32;
+        let sy = (y as i64 + dy).clamp(0, height as i64 - 1) as u32;
+        img.get_pixel(sx, sy)[0] as f32
+    };
+    let gx = (at(1, -1) + 2.0 * at(1, 0) + at(1, 1)) - (at(-1, -1) + 2.0 * at(-1, 0) + at(-1, 1));
+    let gy = (at(-1, 1) + 2.0 * at(0, 1) + at(1, 1)) - (at(-1, -1) + 2.0 * at(0, -1) + at(1, -1));
+    (gx, gy)
+}
+
+// Where and how to write the GIF of a chain's steps.
+struct Explain {
+    outfile: String,
+    size: u32,
+    // Milliseconds per frame.
+    delay: u32,
+}
+
+fn chain(
+    infile: String,
+    outfile: String,
+    operations: Vec<String>,
+    timing: bool,
+    explain: Option<Explain>,
+    cancel: cancel::Cancel,
+) {
+    let operations =
+        pipeline::parse_operations(&operations).unwrap_or_else(|err| panic!("{}", err));
+    if explain.as_ref().is_some_and(|explain| explain.size == 0) {
+        panic!("0 is not a valid --explain-size!");
+    }
+    // Downscaled copies of the image before the first step and after every step, with a label.
+    let mut steps: Vec<(String, image::RgbaImage)> = Vec::new();
+    let mut capture = |label: String, img: &image::RgbaImage| {
+        if let Some(explain) = &explain {
+            let scale = (explain.size as f64 / img.width().max(img.height()) as f64).min(1.0);
+            let small = image::imageops::thumbnail(
+                img,
+                ((img.width() as f64 * scale).round() as u32).max(1),
+                ((img.height() as f64 * scale).round() as u32).max(1),
+            );
+            steps.push((label, small));
+        }
+    };
+    let mut timings: Vec<(String, Duration)> = Vec::new();
+
+    let start = Instant::now();
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "chain");
+    let img = img.to_rgba8();
+    timings.push(("load".to_string(), start.elapsed()));
+
+    capture("original".to_string(), &img);
+
+    let mut buffers = pipeline::Buffers::new(img);
+    for (done, operation) in operations.iter().enumerate() {
+        if cancel.requested() {
+            cancel::exit(done, operations.len(), "operations");
+        }
+        let name = operation.name();
+        let parameters = operation
+            .parameters()
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let span = trace::span!(
+            "operation",
+            name = name.split_whitespace().next().unwrap_or_default(),
+            parameters = parameters,
+        );
+        operation.apply(&mut buffers);
+        timings.push((name.clone(), span.finish()));
+        capture(format!("{}. {}", done + 1, name), buffers.image());
+    }
+    let allocations = buffers.allocations();
+
+    let start = Instant::now();
+    save_image(
+        &image::DynamicImage::ImageRgba8(buffers.into_image()),
+        &outfile,
+    );
+    timings.push(("save".to_string(), start.elapsed()));
+
+    if let Some(explain) = explain {
+        write_explain_gif(&explain, steps);
+    }
+
+    if timing {
+        for (name, duration) in timings {
+            println!("{:<24} {:>10.2} ms", name, duration.as_secs_f64() * 1000.0);
+        }
+        println!("{:<24} {:>10}", "image allocations", allocations);
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+enum ToneMapOperator {
+    Reinhard,
+    Aces,
+    Filmic,
+}
+
+// Map linear light, which may go well above 1.0 in HDR inputs, into 0..=1. Bright values are
+// compressed much more than dark ones, so highlights keep some detail instead of clipping.
+fn tone_map_value(x: f32, operator: ToneMapOperator) -> f32 {
+    match operator {
+        ToneMapOperator::Reinhard => x / (1.0 + x),
+        // Krzysztof Narkowicz's fit of the ACES filmic curve.
+        ToneMapOperator::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+        // John Hable's Uncharted 2 curve, normalized so that a linear value of 11.2 maps to white.
+        ToneMapOperator::Filmic => {
+            let hable = |x: f32| {
+                let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+                ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+            };
+            hable(x * 2.0) / hable(11.2)
+        }
+    }
+    .clamp(0.0, 1.0)
+}
+
+fn tone_map(infile: String, outfile: String, operator: ToneMapOperator, exposure: f32) {
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    // Floating point inputs (e.g. Radiance HDR) are already linear, integer ones are sRGB encoded.
+    let is_float = matches!(
+        img.color(),
+        image::ColorType::Rgb32F | image::ColorType::Rgba32F
+    );
+    let scale = 2f32.powf(exposure);
+
+    let mut imgbuf = img.to_rgb32f();
+    for pixel in imgbuf.pixels_mut() {
+        for c in 0..3 {
+            let linear = if is_float {
+                pixel[c].max(0.0)
+            } else {
+                colorspace::srgb_to_linear(pixel[c])
+            };
+            pixel[c] = colorspace::linear_to_srgb(tone_map_value(linear * scale, operator));
+        }
+    }
+
+    save_image(
+        &image::DynamicImage::ImageRgb8(image::DynamicImage::ImageRgb32F(imgbuf).to_rgb8()),
+        &outfile,
+    );
+}
+
+fn merge_exposures(infiles: Vec<String>, outfile: String) {
+    let exposures = infiles
+        .iter()
+        .map(|infile| {
+            limits::open(infile)
+                .unwrap_or_else(|_| panic!("Failed to open {}.", infile))
+                .to_rgb32f()
+        })
+        .collect::<Vec<_>>();
+    let dimensions = exposures[0].dimensions();
+    for (infile, exposure) in infiles.iter().zip(&exposures) {
+        if exposure.dimensions() != dimensions {
+            panic!("{} is not the same size as {}!", infile, infiles[0]);
+        }
+    }
+
+    let merged = exposure::merge(&exposures);
+    save_image(
+        &image::DynamicImage::ImageRgb8(image::DynamicImage::ImageRgb32F(merged).to_rgb8()),
+        &outfile,
+    );
+}
+
+// Optical vignetting darkens the image roughly with the square of the distance from the center, so
+// undo it by multiplying the light at every pixel by `1 + strength * r^2`, where `r` runs from 0 at
+// the center to 1 at the corners. The gain is applied in linear light, like the falloff itself.
+fn devignette_correct(infile: String, outfile: String, strength: f32) {
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    let mut imgbuf = colorspace::to_linear(&img);
+    let (width, height) = imgbuf.dimensions();
+    let center_x = (width as f32 - 1.0) / 2.0;
+    let center_y = (height as f32 - 1.0) / 2.0;
+    let corner_distance_squared = (center_x * center_x + center_y * center_y).max(1.0);
+
+    for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+        let dx = x as f32 - center_x;
+        let dy = y as f32 - center_y;
+        let gain = (1.0 + strength * (dx * dx + dy * dy) / corner_distance_squared).max(0.0);
+        for c in 0..3 {
+            pixel[c] = (pixel[c] * gain).min(1.0);
+        }
+    }
+
+    save_image(&colorspace::from_linear(imgbuf, &img), &outfile);
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+enum PolarDirection {
+    ToPolar,
+    FromPolar,
+}
+
+// `ToPolar` bends the rows of `infile` into circles around the center of the output: the bottom row
+// ends up at the center, the top row on the largest circle that fits, and the columns run
+// clockwise from the right. `FromPolar` is the exact inverse, so a panorama can be turned into a
+// tiny planet and back. The output keeps the input's dimensions.
+fn polar_transform(infile: String, outfile: String, direction: PolarDirection) {
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "polar-transform");
+    let img = img.to_rgba8();
+    let (width, height) = img.dimensions();
+    let center_x = (width as f32 - 1.0) / 2.0;
+    let center_y = (height as f32 - 1.0) / 2.0;
+    let max_radius = center_x.min(center_y).max(1.0);
+    let tau = std::f32::consts::TAU;
+
+    let mut imgbuf = image::RgbaImage::new(width, height);
+    for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+        let (source_x, source_y) = match direction {
+            PolarDirection::ToPolar => {
+                let dx = x as f32 - center_x;
+                let dy = y as f32 - center_y;
+                let radius = (dx * dx + dy * dy).sqrt() / max_radius;
+                let angle = dy.atan2(dx).rem_euclid(tau);
+                (
+                    angle / tau * width as f32,
+                    (1.0 - radius) * (height as f32 - 1.0),
+                )
+            }
+            PolarDirection::FromPolar => {
+                let angle = x as f32 / width as f32 * tau;
+                let radius = (1.0 - y as f32 / (height as f32 - 1.0).max(1.0)) * max_radius;
+                (
+                    center_x + radius * angle.cos(),
+                    center_y + radius * angle.sin(),
+                )
+            }
+        };
+        *pixel = sample_bilinear(&img, source_x, source_y);
+    }
+
+    save_image(&image::DynamicImage::ImageRgba8(imgbuf), &outfile);
+}
+
+// Composite each layer over everything below it with the Porter-Duff "over" operator. Blending
+// happens in linear light, since averaging gamma-encoded values makes semi-transparent edges too
+// dark.
+fn stack_layers(infiles: Vec<String>, outfile: String) {
+    let layers = infiles
+        .iter()
+        .map(|infile| limits::open(infile).unwrap_or_else(|_| panic!("Failed to open {}.", infile)))
+        .collect::<Vec<_>>();
+    let dimensions = (layers[0].width(), layers[0].height());
+    for (infile, layer) in infiles.iter().zip(&layers) {
+        if (layer.width(), layer.height()) != dimensions {
+            panic!("{} is not the same size as {}!", infile, infiles[0]);
+        }
+    }
+
+    let mut stacked = colorspace::to_linear(&layers[0]);
+    for layer in &layers[1..] {
+        let layer = colorspace::to_linear(layer);
+        for (below, above) in stacked.pixels_mut().zip(layer.pixels()) {
+            blend_over(below, above, 1.0);
+        }
+    }
+
+    // Keep 16 bits per channel if any layer had them.
+    let template = if layers.iter().any(is_high_bit_depth) {
+        image::DynamicImage::new_rgba16(0, 0)
+    } else {
+        image::DynamicImage::new_rgba8(0, 0)
+    };
+    save_image(&colorspace::from_linear(stacked, &template), &outfile);
+}
+
+// The Porter-Duff "over" operator: `above`, with its alpha scaled by `opacity`, composited onto
+// `below` in place. Colors are not premultiplied.
+fn blend_over(below: &mut image::Rgba<f32>, above: &image::Rgba<f32>, opacity: f32) {
+    let above_alpha = above[3] * opacity;
+    let alpha = above_alpha + below[3] * (1.0 - above_alpha);
+    for c in 0..3 {
+        below[c] = if alpha > 0.0 {
+            (above[c] * above_alpha + below[c] * below[3] * (1.0 - above_alpha)) / alpha
+        } else {
+            0.0
+        };
+    }
+    below[3] = alpha;
+}
+
+fn dimensions(infile: String) {
+    let (width, height) =
+        header_dimensions(&infile).expect("Failed to read the dimensions of INFILE.");
+    println!("{}x{}", width, height);
+}
+
+// Read only as much of `infile` as it takes to find its dimensions.
+fn header_dimensions(infile: &str) -> image::ImageResult<(u32, u32)> {
+    image::io::Reader::open(infile)?
+        .with_guessed_format()?
+        .into_dimensions()
+}
+
+fn verify(infile: String, quiet: bool) {
+    match verify_file(&infile) {
+        Ok((width, height)) => {
+            if !quiet {
+                println!("{}: OK ({}x{})", infile, width, height);
+            }
+        }
+        Err(err) => {
+            if !quiet {
+                println!("{}: FAILED ({})", infile, err);
+            }
+            std::process::exit(1);
+        }
+    }
+}
+
+// Fully decode `infile`, returning its dimensions or why it can't be used, including a refusal
+// under --max-pixels.
+fn verify_file(infile: &str) -> Result<(u32, u32), String> {
+    limits::check(std::path::Path::new(infile))?;
+    let img = image::open(infile).map_err(|err| err.to_string())?;
+    Ok((img.width(), img.height()))
+}
+
+fn carve(
+    infile: String,
+    outfile: String,
+    width_change: i32,
+    height_change: i32,
+    visualize: Option<String>,
+    cancel: cancel::Cancel,
+) {
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "carve");
+    let img = img.to_rgba8();
+    for (change, size, what) in [
+        (width_change, img.width(), "columns"),
+        (height_change, img.height(), "rows"),
+    ] {
+        if change > 0 {
+            panic!("carve can only remove {}, not add them!", what);
+        }
+        if change.unsigned_abs() >= size {
+            panic!(
+                "Can't remove {} {} from an image with only {}!",
+                -change, what, size
+            );
+        }
+    }
+
+    let columns = width_change.unsigned_abs() as usize;
+    let rows = height_change.unsigned_abs() as usize;
+    let total = columns + rows;
+    let mut carver = carve::Carver::new(&img);
+    let mut removed = Vec::new();
+    for seam in 0..total {
+        if cancel.requested() {
+            eprintln!();
+            cancel::exit(seam, total, "seams");
+        }
+        eprint!("\rRemoving seam {} of {}", seam + 1, total);
+        if seam < columns {
+            removed.extend(carver.remove_vertical_seam());
+        } else {
+            removed.extend(carver.remove_horizontal_seam());
+        }
+    }
+    if total > 0 {
+        eprintln!();
+    }
+
+    if let Some(visualize) = visualize {
+        let mut seams = img.clone();
+        for (x, y) in removed {
+            seams.put_pixel(x, y, image::Rgba([255, 0, 0, 255]));
+        }
+        save_image(&image::DynamicImage::ImageRgba8(seams), &visualize);
+    }
+    save_image(
+        &image::DynamicImage::ImageRgba8(carver.into_image()),
+        &outfile,
+    );
+}
+
+fn otsu_threshold(infile: String, outfile: String) {
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "otsu-threshold");
+    let mut img = img.to_luma8();
+
+    let level = otsu_level(&luma_histogram(&img));
+    println!("Threshold: {}", level);
+
+    for pixel in img.pixels_mut() {
+        pixel[0] = if pixel[0] > level { 255 } else { 0 };
+    }
+    save_image(&image::DynamicImage::ImageLuma8(img), &outfile);
+}
+
+fn luma_histogram(img: &image::GrayImage) -> [u64; 256] {
+    let mut histogram = [0u64; 256];
+    for pixel in img.pixels() {
+        histogram[pixel[0] as usize] += 1;
+    }
+    histogram
+}
+
+// Otsu's method: the level that splits the histogram into a dark and a light class with the
+// largest variance between the two class means. Values up to and including the level are dark.
+// When a range of levels ties, e.g. because no pixels fall between two peaks, the middle of the range
+// is used.
+fn otsu_level(histogram: &[u64; 256]) -> u8 {
+    let total: u64 = histogram.iter().sum();
+    let total_sum: f64 = histogram
+        .iter()
+        .enumerate()
+        .map(|(value, &count)| value as f64 * count as f64)
+        .sum();
+
+    let mut best_variance = 0.0;
+    let mut best_levels = (0, 0);
+    let mut dark_count = 0u64;
+    let mut dark_sum = 0.0;
+    for (level, &count) in histogram.iter().enumerate() {
+        dark_count += count;
+        dark_sum += level as f64 * count as f64;
+        let light_count = total - dark_count;
+        if dark_count == 0 || light_count == 0 {
+            continue;
+        }
+        let dark_mean = dark_sum / dark_count as f64;
+        let light_mean = (total_sum - dark_sum) / light_count as f64;
+        let variance = dark_count as f64 * light_count as f64 * (dark_mean - light_mean).powi(2);
+        if variance > best_variance * (1.0 + 1e-9) {
+            best_variance = variance;
+            best_levels = (level, level);
+        } else if variance >= best_variance * (1.0 - 1e-9) {
+            best_levels.1 = level;
+        }
+    }
+    ((best_levels.0 + best_levels.1) / 2) as u8
+}
+
+// The DCT-based pHash: shrink to 32x32 grayscale, take the 8x8 lowest frequencies of its discrete
+// cosine transform and set one bit per coefficient that is above their median, most significant bit
+// first in row-major order. Recompressing, rescaling or slightly recoloring an image only changes a
+// few bits.
+//
+// Scripts compare these hashes across runs, so the steps here must not change.
+fn phash(infile: String) {
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    println!("{:016x}", perceptual_hash(&img));
+}
+
+fn perceptual_hash(img: &image::DynamicImage) -> u64 {
+    let img = img.to_luma32f();
+    let small = image::imageops::resize(&img, 32, 32, image::imageops::FilterType::Triangle);
+
+    let n = 32;
+    let basis =
+        |k: usize, i: usize| (std::f64::consts::PI / n as f64 * (i as f64 + 0.5) * k as f64).cos();
+    let mut coefficients = Vec::with_capacity(64);
+    for v in 0..8 {
+        for u in 0..8 {
+            let mut sum = 0.0;
+            for (x, y, pixel) in small.enumerate_pixels() {
+                sum += pixel[0] as f64 * basis(u, x as usize) * basis(v, y as usize);
+            }
+            coefficients.push(sum);
+        }
+    }
+
+    let mut sorted = coefficients.clone();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let median = (sorted[31] + sorted[32]) / 2.0;
+    coefficients
+        .iter()
+        .fold(0u64, |hash, &c| (hash << 1) | (c > median) as u64)
+}
+
+fn phash_distance(hash_a: String, hash_b: String) {
+    let parse = |hash: &str| {
+        u64::from_str_radix(hash, 16)
+            .unwrap_or_else(|_| panic!("{} is not a valid perceptual hash!", hash))
+    };
+    println!("{}", (parse(&hash_a) ^ parse(&hash_b)).count_ones());
+}
+
+// Unlike a single global threshold, comparing against the local mean keeps working when the
+// lighting changes across the image, as it does in photos of documents. The means come from a
+// summed-area table, so the block size doesn't affect the running time.
+fn adaptive_threshold(infile: String, outfile: String, block_size: u32, offset: f32) {
+    if block_size == 0 {
+        panic!("{} is not a valid block size!", block_size);
+    }
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "adaptive-threshold");
+    let mut img = img.to_luma8();
+    let integral = integral::Integral::new(&img);
+    for (x, y, pixel) in img.enumerate_pixels_mut() {
+        let (mean, _) = integral.window(x, y, block_size);
+        pixel[0] = if pixel[0] as f32 > mean as f32 - offset {
+            255
+        } else {
+            0
+        };
+    }
+
+    save_image(&image::DynamicImage::ImageLuma8(img), &outfile);
+}
+
+// Binarize with Otsu's method and label the 8-connected groups of foreground pixels.
+fn count_objects(infile: String, min_area: u32, dark: bool) {
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "count-objects");
+    let (level, objects) = find_objects(&img.to_luma8(), min_area, dark);
+
+    println!("{} objects (threshold {})", objects.len(), level);
+    for (i, (area, x, y, w, h)) in objects.iter().enumerate() {
+        println!(
+            "{:>5}: area {:>8} px, bounds {}x{} at ({}, {})",
+            i + 1,
+            area,
+            w,
+            h,
+            x,
+            y
+        );
+    }
+}
+
+// An object's area and bounding box: (area, x, y, width, height).
+type ObjectBounds = (u32, u32, u32, u32, u32);
+
+// The Otsu level and, for every object of at least `min_area` pixels, its area and bounding box, in
+// the order of their first pixel.
+fn find_objects(img: &image::GrayImage, min_area: u32, dark: bool) -> (u8, Vec<ObjectBounds>) {
+    let (width, height) = img.dimensions();
+    let level = otsu_level(&luma_histogram(img));
+    let foreground = img
+        .pixels()
+        .map(|pixel| (pixel[0] > level) != dark)
+        .collect::<Vec<bool>>();
+
+    let mut visited = vec![false; foreground.len()];
+    let mut objects = Vec::new();
+    for start in 0..foreground.len() {
+        if !foreground[start] || visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        let mut stack = vec![start];
+        let mut area = 0u32;
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (width, height, 0, 0);
+        while let Some(index) = stack.pop() {
+            let (x, y) = (
+                (index % width as usize) as u32,
+                (index / width as usize) as u32,
+            );
+            area += 1;
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+            for dy in -1i64..=1 {
+                for dx in -1i64..=1 {
+                    let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                    if nx < 0 || ny < 0 || nx >= width as i64 || ny >= height as i64 {
+                        continue;
+                    }
+                    let neighbor = ny as usize * width as usize + nx as usize;
+                    if foreground[neighbor] && !visited[neighbor] {
+                        visited[neighbor] = true;
+                        stack.push(neighbor);
+                    }
+                }
+            }
+        }
+        if area >= min_area {
+            objects.push((area, min_x, min_y, max_x - min_x + 1, max_y - min_y + 1));
+        }
+    }
+    (level, objects)
+}
+
+fn embed(
+    infile: String,
+    outfile: String,
+    payload: Option<String>,
+    message: Option<String>,
+    bits: u8,
+) {
+    use image::ImageFormat;
+    use std::io::Read;
+
+    // Lossy compression would scramble the low bits the payload lives in, and extract can't read
+    // raw pixels back.
+    match output_format(&outfile) {
+        Some(ImageFormat::Png)
+        | Some(ImageFormat::Bmp)
+        | Some(ImageFormat::Tiff)
+        | Some(ImageFormat::Pnm)
+        | Some(ImageFormat::Tga)
+        | Some(ImageFormat::Qoi) => {}
+        _ => panic!(
+            "OUTFILE must be written in a lossless format such as PNG, or the payload would be lost!"
+        ),
+    }
+
+    let payload = match (payload, message) {
+        (Some(path), _) => std::fs::read(path).expect("Failed to read PAYLOAD."),
+        (None, Some(message)) => message.into_bytes(),
+        (None, None) => {
+            let mut payload = Vec::new();
+            std::io::stdin()
+                .read_to_end(&mut payload)
+                .expect("Failed to read the payload from standard input.");
+            payload
+        }
+    };
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "embed");
+    let mut img = img.to_rgba8();
+    stego::embed(&mut img, &payload, bits).unwrap_or_else(|err| panic!("{}", err));
+    save_image(&image::DynamicImage::ImageRgba8(img), &outfile);
+}
+
+fn extract(infile: String, output: Option<String>, bits: u8) {
+    use std::io::Write;
+
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+
+    warn_if_downconverting(&img, "INFILE", "extract");
+
+    let img = img.to_rgba8();
+    let payload = stego::extract(&img, bits).unwrap_or_else(|err| panic!("{}", err));
+    match output {
+        Some(path) => write_atomically(&path, |temp| {
+            std::fs::write(temp, &payload).map_err(|e| e.to_string())
+        }),
+        None => std::io::stdout()
+            .write_all(&payload)
+            .expect("Failed writing the payload to standard output."),
+    }
+}
+
+fn rle_preview(infile: String) {
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "rle-preview");
+    let (names, samples): (&[&str], Vec<u8>) = match img.color().channel_count() {
+        1 => (&["luma"], img.to_luma8().into_raw()),
+        2 => (&["luma", "alpha"], img.to_luma_alpha8().into_raw()),
+        3 => (&["red", "green", "blue"], img.to_rgb8().into_raw()),
+        _ => (
+            &["red", "green", "blue", "alpha"],
+            img.to_rgba8().into_raw(),
+        ),
+    };
+    let channels = names.len();
+    let runs = scanline_runs(&samples, img.width() as usize, channels);
+
+    let pixels = img.width() as f64 * img.height() as f64;
+    for (name, channel_runs) in names.iter().zip(&runs) {
+        println!(
+            "{:<6} average run {:>8.2} px",
+            name,
+            pixels / *channel_runs as f64
+        );
+    }
+    let average = pixels * channels as f64 / runs.iter().sum::<usize>() as f64;
+    let verdict = if average >= 8.0 {
+        "long runs, lossless formats will compress this well"
+    } else if average >= 2.0 {
+        "some runs, expect moderate lossless compression"
+    } else {
+        "short runs, lossless formats will struggle with this (photographic or noisy content)"
+    };
+    println!("{:<6} average run {:>8.2} px: {}", "all", average, verdict);
+}
+
+// The number of runs in each channel of interleaved `samples`, `width` pixels to a row. A run ends
+// wherever a sample differs from the one before it in the same row, and at the end of every row.
+fn scanline_runs(samples: &[u8], width: usize, channels: usize) -> Vec<usize> {
+    let mut runs = vec![0usize; channels];
+    for row in samples.chunks(width * channels) {
+        for (c, channel_runs) in runs.iter_mut().enumerate() {
+            let mut previous = None;
+            for &sample in row.iter().skip(c).step_by(channels) {
+                if previous != Some(sample) {
+                    *channel_runs += 1;
+                    previous = Some(sample);
+                }
+            }
+        }
+    }
+    runs
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+enum RawPixelFormat {
+    Rgb8,
+    Rgba8,
+    Luma8,
+    Bgr8,
+}
+
+// Rows are written back to back, without padding, starting with the top row, which is what
+// from-raw reads. The width and height aren't stored, so they are printed instead.
+fn raw_dump(infile: String, outfile: String, pixel_format: RawPixelFormat) {
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "raw-dump");
+    let bytes = match pixel_format {
+        RawPixelFormat::Rgb8 => img.to_rgb8().into_raw(),
+        RawPixelFormat::Bgr8 => {
+            let mut bytes = img.to_rgb8().into_raw();
+            for pixel in bytes.chunks_exact_mut(3) {
+                pixel.swap(0, 2);
+            }
+            bytes
+        }
+        RawPixelFormat::Rgba8 => img.to_rgba8().into_raw(),
+        RawPixelFormat::Luma8 => img.to_luma8().into_raw(),
+    };
+    write_atomically(&outfile, |path| {
+        std::fs::write(path, &bytes).map_err(|e| e.to_string())
+    });
+    println!("{}x{}", img.width(), img.height());
+}
+
+// Rows are expected back to back, without padding, starting with the top row.
+fn from_raw(
+    infile: String,
+    outfile: String,
+    width: u32,
+    height: u32,
+    pixel_format: RawPixelFormat,
+) {
+    let mut bytes = std::fs::read(infile).expect("Failed to open INFILE.");
+    let channels = match pixel_format {
+        RawPixelFormat::Rgb8 | RawPixelFormat::Bgr8 => 3,
+        RawPixelFormat::Rgba8 => 4,
+        RawPixelFormat::Luma8 => 1,
+    };
+    let expected = width as u64 * height as u64 * channels;
+    if bytes.len() as u64 != expected {
+        panic!(
+            "INFILE is {} bytes, but {}x{} pixels at {} bytes per pixel needs {} bytes!",
+            bytes.len(),
+            width,
+            height,
+            channels,
+            expected
+        );
+    }
+
+    let img = match pixel_format {
+        RawPixelFormat::Rgb8 => {
+            image::DynamicImage::ImageRgb8(image::RgbImage::from_raw(width, height, bytes).unwrap())
+        }
+        RawPixelFormat::Bgr8 => {
+            for pixel in bytes.chunks_exact_mut(3) {
+                pixel.swap(0, 2);
+            }
+            image::DynamicImage::ImageRgb8(image::RgbImage::from_raw(width, height, bytes).unwrap())
+        }
+        RawPixelFormat::Rgba8 => image::DynamicImage::ImageRgba8(
+            image::RgbaImage::from_raw(width, height, bytes).unwrap(),
+        ),
+        RawPixelFormat::Luma8 => image::DynamicImage::ImageLuma8(
+            image::GrayImage::from_raw(width, height, bytes).unwrap(),
+        ),
+    };
+    save_image(&img, &outfile);
+}
+
+// A checkerboard in a coordinate system rotated by 45 degrees: `u` and `v` count diamonds along the
+// two diagonals, and neighboring diamonds differ in exactly one of them. The top left corner of
+// the image is a point where four diamonds meet.
+fn diamond(
+    outfile: String,
+    width: u32,
+    height: u32,
+    color_a: Color,
+    color_b: Color,
+    cell_size: u32,
+) {
+    if cell_size == 0 {
+        panic!("{} is not a valid cell size!", cell_size);
+    }
+
+    let mut imgbuf = image::RgbImage::new(width, height);
+    for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+        let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+        let u = ((px + py) / cell_size as f32).floor() as i64;
+        let v = ((px - py) / cell_size as f32).floor() as i64;
+        let color = if (u + v).rem_euclid(2) == 0 {
+            &color_a
+        } else {
+            &color_b
+        };
+        *pixel = image::Rgb([color.red, color.green, color.blue]);
+    }
+
+    save_image(&image::DynamicImage::ImageRgb8(imgbuf), &outfile);
+}
+
+fn export_array(
+    infile: String,
+    outfile: String,
+    layout: export::Layout,
+    name: Option<String>,
+    threshold: u8,
+) {
+    let rust = match std::path::Path::new(&outfile)
+        .extension()
+        .and_then(|ext| ext.to_str())
+    {
+        Some("rs") => true,
+        Some("h") | Some("c") => false,
+        _ => panic!("OUTFILE must end in .h, .c or .rs!"),
+    };
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "export-array");
+    let img = img.to_rgb8();
+    let name = name.unwrap_or_else(|| export::default_name(&outfile));
+    let bytes = export::encode(&img, layout, threshold);
+    let source = export::source(&name, rust, img.width(), img.height(), layout, &bytes);
+    write_atomically(&outfile, |path| {
+        std::fs::write(path, &source).map_err(|e| e.to_string())
+    });
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+enum PixelSortDirection {
+    Rows,
+    Columns,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+enum PixelSortKey {
+    Luminance,
+    Hue,
+    Saturation,
+}
+
+impl PixelSortKey {
+    fn value(self, p: &image::Rgba<u8>) -> f32 {
+        let (r, g, b) = (p[0] as f32, p[1] as f32, p[2] as f32);
+        match self {
+            PixelSortKey::Luminance => 0.2126 * r + 0.7152 * g + 0.0722 * b,
+            // HSV hue in degrees, 0 for grays.
+            PixelSortKey::Hue => colorspace::rgb_to_hsv([r, g, b])[0],
+            PixelSortKey::Saturation => colorspace::rgb_to_hsv([r, g, b])[1],
+        }
+    }
+}
+
+// Within every row (or column), each unbroken run of pixels whose luma is above the low end of
+// `band` and at most its high end is sorted by `key`, in ascending order. Pixels outside the band
+// stay where they are and split the runs.
+fn pixel_sort(
+    infile: String,
+    outfile: String,
+    direction: PixelSortDirection,
+    band: std::ops::RangeInclusive<u8>,
+    key: PixelSortKey,
+    seed: Option<u64>,
+) {
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "pixel-sort");
+    let mut img = img.to_rgba8();
+    let (width, height) = img.dimensions();
+    let (lines, length) = match direction {
+        PixelSortDirection::Rows => (height, width),
+        PixelSortDirection::Columns => (width, height),
+    };
+    let position = |line: u32, i: u32| match direction {
+        PixelSortDirection::Rows => (i, line),
+        PixelSortDirection::Columns => (line, i),
+    };
+    let in_band = |p: &image::Rgba<u8>| {
+        let luma = (2126 * p[0] as u32 + 7152 * p[1] as u32 + 722 * p[2] as u32) / 10000;
+        luma > *band.start() as u32 && luma <= *band.end() as u32
+    };
+    let mut rng = seed.map(random::Rng::new);
+
+    for line in 0..lines {
+        let mut pixels = (0..length)
+            .map(|i| {
+                let (x, y) = position(line, i);
+                *img.get_pixel(x, y)
+            })
+            .collect::<Vec<_>>();
+        let mut start = 0;
+        while start < pixels.len() {
+            if !in_band(&pixels[start]) {
+                start += 1;
+                continue;
+            }
+            let end = pixels[start..]
+                .iter()
+                .position(|p| !in_band(p))
+                .map_or(pixels.len(), |run| start + run);
+            if rng.as_mut().is_none_or(|rng| rng.below(2) == 0) {
+                pixels[start..end].sort_by(|a, b| key.value(a).total_cmp(&key.value(b)));
+            }
+            start = end;
+        }
+        for (i, pixel) in pixels.into_iter().enumerate() {
+            let (x, y) = position(line, i as u32);
+            img.put_pixel(x, y, pixel);
+        }
+    }
+
+    save_image(&image::DynamicImage::ImageRgba8(img), &outfile);
+}
+
+// Inputs that aren't grayscale are converted to luma first. Values outside `min..max` get the
+// colormap's end colors.
+fn apply_colormap(
+    infile: String,
+    outfile: String,
+    colormap: colormap::Colormap,
+    invert: bool,
+    min: Option<f32>,
+    max: Option<f32>,
+) {
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    let full_scale = if is_high_bit_depth(&img) {
+        u16::MAX as f32
+    } else {
+        u8::MAX as f32
+    };
+    let min = min.unwrap_or(0.0);
+    let max = max.unwrap_or(full_scale);
+    if max <= min {
+        panic!("--max must be greater than --min!");
+    }
+
+    let luma = img.to_luma32f();
+    let colored = image::RgbImage::from_fn(luma.width(), luma.height(), |x, y| {
+        let value = luma.get_pixel(x, y)[0] * full_scale;
+        let t = ((value - min) / (max - min)).clamp(0.0, 1.0);
+        image::Rgb(colormap.sample(if invert { 1.0 - t } else { t }))
+    });
+    save_image(&image::DynamicImage::ImageRgb8(colored), &outfile);
+}
+
+// The databending look: horizontal bands of rows slid sideways as if bytes had been dropped from
+// the file, and the red and blue channels pulled apart. Both scale with `intensity`, so 0.0 leaves
+// the image untouched, and the same seed always produces the same glitches.
+fn glitch(infile: String, outfile: String, intensity: f32, seed: u64) {
+    if !(0.0..=1.0).contains(&intensity) {
+        panic!("{} is not a valid intensity!", intensity);
+    }
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "glitch");
+    let img = img.to_rgba8();
+    let (width, height) = img.dimensions();
+    let mut rng = random::Rng::new(seed);
+    let mut glitched = img.clone();
+
+    let bands = (intensity * 24.0).round() as u32;
+    let max_band_height = ((height as f32 * intensity / 8.0).round() as u64).max(1);
+    let max_shift = (width as f32 * intensity / 3.0).round() as u64;
+    for _ in 0..bands {
+        let top = rng.below(height as u64) as u32;
+        let band_height = rng.below(max_band_height) as u32 + 1;
+        let shift = rng.below(2 * max_shift + 1) as i64 - max_shift as i64;
+        for y in top..(top + band_height).min(height) {
+            let row = (0..width)
+                .map(|x| *glitched.get_pixel(x, y))
+                .collect::<Vec<_>>();
+            for x in 0..width {
+                let source = (x as i64 - shift).rem_euclid(width as i64) as usize;
+                glitched.put_pixel(x, y, row[source]);
+            }
+        }
+    }
+
+    let max_offset = (width as f32 * intensity / 40.0).round() as u64;
+    let offset = rng.below(max_offset + 1) as i64;
+    if offset > 0 {
+        let banded = glitched.clone();
+        for (x, y, pixel) in glitched.enumerate_pixels_mut() {
+            let at =
+                |dx: i64| banded.get_pixel((x as i64 + dx).clamp(0, width as i64 - 1) as u32, y);
+            pixel[0] = at(offset)[0];
+            pixel[2] = at(-offset)[2];
+        }
+    }
+
+    save_image(&image::DynamicImage::ImageRgba8(glitched), &outfile);
+}
+
+// Resolve every channel source to a file and a channel (None for grayscale files), load each file
+// once, and copy the chosen channels over. The result keeps 16 bits per channel if any of the
+// sources had them.
+fn falsecolor(outfile: String, sources: [String; 3], input: Option<String>) {
+    let sources = sources.map(|source| {
+        let selector = |s: &str| ["r", "g", "b"].iter().position(|&c| c == s);
+        if let Some(channel) = selector(&source) {
+            let input = input.clone().unwrap_or_else(|| {
+                panic!(
+                    "{} selects a channel, but there is no --input to take it from!",
+                    source
+                )
+            });
+            return (input, Some(channel));
+        }
+        match source.rsplit_once(':') {
+            Some((path, suffix)) if selector(suffix).is_some() => {
+                (path.to_string(), selector(suffix))
+            }
+            _ => (source, None),
+        }
+    });
+
+    let mut images: Vec<(&str, image::DynamicImage)> = Vec::new();
+    for (path, _) in &sources {
+        if images.iter().all(|(loaded, _)| loaded != path) {
+            let img = limits::open(path).unwrap_or_else(|_| panic!("Failed to open {}.", path));
+            images.push((path, img));
+        }
+    }
+    let dimensions = (images[0].1.width(), images[0].1.height());
+    for (path, img) in &images {
+        if (img.width(), img.height()) != dimensions {
+            panic!("{} is not the same size as {}!", path, images[0].0);
+        }
+    }
+
+    let channels = sources
+        .iter()
+        .map(|(path, channel)| {
+            let img = &images.iter().find(|(loaded, _)| loaded == path).unwrap().1;
+            match channel {
+                Some(channel) => img
+                    .to_rgb16()
+                    .pixels()
+                    .map(|p| p[*channel])
+                    .collect::<Vec<u16>>(),
+                None if img.color().has_color() => panic!(
+                    "{} is a color image, so pick a channel with {}:r, {}:g or {}:b!",
+                    path, path, path, path
+                ),
+                None => img.to_luma16().into_raw(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let (width, height) = dimensions;
+    let composite = image::ImageBuffer::from_fn(width, height, |x, y| {
+        let i = (y * width + x) as usize;
+        image::Rgb([channels[0][i], channels[1][i], channels[2][i]])
+    });
+    let composite = image::DynamicImage::ImageRgb16(composite);
+    if images.iter().any(|(_, img)| is_high_bit_depth(img)) {
+        save_image(&composite, &outfile);
+    } else {
+        save_image(
+            &image::DynamicImage::ImageRgb8(composite.to_rgb8()),
+            &outfile,
+        );
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+enum FractalType {
+    Julia,
+    Mandelbrot,
+    BurningShip,
+}
+
+impl FractalType {
+    // The center and the height of the part of the complex plane that shows the whole fractal.
+    fn view(self) -> (num_complex::Complex<f64>, f64) {
+        match self {
+            FractalType::Julia => (num_complex::Complex::new(0.0, 0.0), 3.0),
+            FractalType::Mandelbrot => (num_complex::Complex::new(-0.5, 0.0), 2.6),
+            FractalType::BurningShip => (num_complex::Complex::new(-0.45, -0.5), 2.6),
+        }
+    }
+
+    // How many iterations it takes the point `p` to escape, with a fractional part that smooths out
+    // the bands between whole iteration counts, or None if it hasn't escaped after `max_iter`.
+    fn escape_time(self, p: num_complex::Complex<f64>, max_iter: u32) -> Option<f64> {
+        let (mut z, c) = match self {
+            // The same constant as the `fractal` command.
+            FractalType::Julia => (p, num_complex::Complex::new(-0.4, 0.6)),
+            FractalType::Mandelbrot | FractalType::BurningShip => (p * 0.0, p),
+        };
+        for i in 0..max_iter {
+            if self == FractalType::BurningShip {
+                z = num_complex::Complex::new(z.re.abs(), z.im.abs());
+            }
+            z = z * z + c;
+            // Escaping past a large radius rather than 2 makes the smoothing accurate.
+            if z.norm_sqr() > 256.0 {
+                return Some(i as f64 + 1.0 - z.norm().ln().ln() / std::f64::consts::LN_2);
+            }
+        }
+        None
+    }
+}
+
+// Points that escape are colored by how quickly they do so, from the low end of the palette for
+// the first iteration to the high end for `max_iter`, on a square root scale so the detail near the
+// set isn't squeezed into the top of the palette. Points that never escape are black.
+fn fractal_art(
+    outfile: String,
+    width: u32,
+    height: u32,
+    fractal_type: FractalType,
+    palette: colormap::Colormap,
+    max_iter: u32,
+    cancel: cancel::Cancel,
+) {
+    if max_iter == 0 {
+        panic!("{} is not a valid iteration limit!", max_iter);
+    }
+    let (center, span) = fractal_type.view();
+    let scale = span / width.min(height).max(1) as f64;
+
+    let mut imgbuf = image::RgbImage::new(width, height);
+    for (y, row) in imgbuf.enumerate_rows_mut() {
+        if cancel.requested() {
+            cancel::exit(y as usize, height as usize, "rows");
+        }
+        for (x, _, pixel) in row {
+            let p = center
+                + num_complex::Complex::new(
+                    (x as f64 - width as f64 / 2.0) * scale,
+                    (y as f64 - height as f64 / 2.0) * scale,
+                );
+            *pixel = match fractal_type.escape_time(p, max_iter) {
+                Some(time) => {
+                    let t = (time.max(0.0) / max_iter as f64).sqrt();
+                    image::Rgb(palette.sample(t as f32))
+                }
+                None => image::Rgb([0, 0, 0]),
+            };
+        }
+    }
+
+    save_image(&image::DynamicImage::ImageRgb8(imgbuf), &outfile);
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+enum PaletteSort {
+    /// Most used colors first
+    Frequency,
+    /// Darkest colors first
+    Luminance,
+}
+
+fn extract_palette(
+    infile: String,
+    outfile: String,
+    max: usize,
+    quantize: Option<usize>,
+    sort: PaletteSort,
+    json: bool,
+) {
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "palette");
+    let img = img.to_rgba8();
+    let mut colors = palette::distinct_colors(&img);
+    match quantize {
+        Some(0) => panic!("0 is not a valid number of colors!"),
+        Some(n) => colors = palette::median_cut(&colors, n),
+        None if colors.len() > max => panic!(
+            "INFILE has {} colors, more than --max {}! Use --quantize to reduce them.",
+            colors.len(),
+            max
+        ),
+        None => {}
+    }
+    match sort {
+        PaletteSort::Frequency => {
+            colors.sort_by_key(|e| (std::cmp::Reverse(e.count), palette::luma(e.color), e.color))
+        }
+        PaletteSort::Luminance => colors.sort_by_key(|e| (palette::luma(e.color), e.color)),
+    }
+
+    if json {
+        let entries = colors
+            .iter()
+            .map(|e| {
+                format!(
+                    "  {{\"color\": \"{}\", \"pixels\": {}}}",
+                    palette::to_hex(e.color),
+                    e.count
+                )
+            })
+            .collect::<Vec<_>>();
+        println!("[\n{}\n]", entries.join(",\n"));
+    } else {
+        for e in &colors {
+            println!("{}", palette::to_hex(e.color));
+        }
+    }
+    save_image(
+        &image::DynamicImage::ImageRgb8(palette::swatches(&colors)),
+        &outfile,
+    );
+}
+
+// A palette image may not have more distinct colors than this; anything bigger is almost certainly
+// a photo passed by mistake, and would make the nearest color search crawl.
+const MAX_PALETTE_IMAGE_COLORS: usize = 256;
+
+// Alpha is kept as it is; only the color channels are mapped.
+fn apply_palette(
+    infile: String,
+    outfile: String,
+    palette_file: String,
+    dither: bool,
+    distance: palette::Distance,
+) {
+    let colors = if image::ImageFormat::from_path(&palette_file).is_ok() {
+        let reference = limits::open(&palette_file).expect("Failed to open PALETTE.");
+        warn_if_downconverting(&reference, "PALETTE", "apply-palette");
+        let reference = reference.to_rgba8();
+        let mut colors = palette::distinct_colors(&reference);
+        if colors.len() > MAX_PALETTE_IMAGE_COLORS {
+            panic!(
+                "PALETTE has {} colors, more than {}! Reduce them with `palette --quantize` first.",
+                colors.len(),
+                MAX_PALETTE_IMAGE_COLORS
+            );
+        }
+        colors.sort_by_key(|e| e.color);
+        colors.into_iter().map(|e| e.color).collect()
+    } else {
+        let text = std::fs::read_to_string(&palette_file).expect("Failed to open PALETTE.");
+        palette::parse_list(&text).unwrap_or_else(|e| panic!("{}", e))
+    };
+    let matcher = palette::Matcher::new(colors, distance);
+
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+
+    warn_if_downconverting(&img, "INFILE", "apply-palette");
+
+    let mut img = img.to_rgba8();
+    let (width, height) = img.dimensions();
+    if !dither {
+        let mut cache = std::collections::HashMap::new();
+        for pixel in img.pixels_mut() {
+            let color = [pixel[0], pixel[1], pixel[2]];
+            let nearest = *cache
+                .entry(color)
+                .or_insert_with(|| matcher.nearest(color.map(|c| c as f32)));
+            pixel.0[..3].copy_from_slice(&nearest);
+        }
+    } else {
+        let mut values = img
+            .pixels()
+            .map(|p| [p[0] as f32, p[1] as f32, p[2] as f32])
+            .collect::<Vec<_>>();
+        let index = |x: u32, y: u32| (y * width + x) as usize;
+        for y in 0..height {
+            for x in 0..width {
+                let old = values[index(x, y)];
+                let new = matcher.nearest(old);
+                img.get_pixel_mut(x, y).0[..3].copy_from_slice(&new);
+                let error = [0, 1, 2].map(|c| old[c] - new[c] as f32);
+                let mut spread = |dx: i64, dy: u32, weight: f32| {
+                    let nx = x as i64 + dx;
+                    if nx < 0 || nx >= width as i64 || y + dy >= height {
+                        return;
+                    }
+                    let value = &mut values[index(nx as u32, y + dy)];
+                    for c in 0..3 {
+                        value[c] += error[c] * weight;
+                    }
+                };
+                spread(1, 0, 7.0 / 16.0);
+                spread(-1, 1, 3.0 / 16.0);
+                spread(0, 1, 5.0 / 16.0);
+                spread(1, 1, 1.0 / 16.0);
+            }
+        }
+    }
+
+    save_image(&image::DynamicImage::ImageRgba8(img), &outfile);
+}
+
+// Every pixel inside the circle is fetched from a point on the same ray from the center, scaled by
+// `1 - strength * (1 - d^2)^2`, where `d` is the pixel's distance from the center relative to
+// `radius`. The scale is 1 at the edge of the circle and changes smoothly towards the center, so
+// there is no visible seam. The center itself never moves.
+fn bulge(infile: String, outfile: String, cx: f32, cy: f32, radius: f32, strength: f32) {
+    if radius <= 0.0 {
+        panic!("{} is not a valid radius!", radius);
+    }
+    if !(-1.0..=1.0).contains(&strength) {
+        panic!("{} is not a valid strength!", strength);
+    }
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "bulge");
+    let img = img.to_rgba8();
+
+    let mut imgbuf = img.clone();
+    for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+        let dx = x as f32 - cx;
+        let dy = y as f32 - cy;
+        let d = (dx * dx + dy * dy).sqrt() / radius;
+        if d >= 1.0 {
+            continue;
+        }
+        let scale = 1.0 - strength * (1.0 - d * d).powi(2);
+        *pixel = sample_bilinear(&img, cx + dx * scale, cy + dy * scale);
+    }
+
+    save_image(&image::DynamicImage::ImageRgba8(imgbuf), &outfile);
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+// The position is written in decimal degrees, e.g. `48.85837 N, 2.29448 E`, in the built-in bitmap
+// font scaled to the photo's size.
+fn geo_stamp(infile: String, outfile: String, position: Corner, color: Color, skip_missing: bool) {
+    let bytes = std::fs::read(&infile).expect("Failed to open INFILE.");
+    let gps = exif::gps_position(&bytes).unwrap_or_else(|e| panic!("{}", e));
+    let img = limits::open(&infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "geo-stamp");
+    let mut img = img.to_rgb8();
+
+    match gps {
+        Some((latitude, longitude)) => {
+            let text = format!(
+                "{:.5} {}, {:.5} {}",
+                latitude.abs(),
+                if latitude < 0.0 { 'S' } else { 'N' },
+                longitude.abs(),
+                if longitude < 0.0 { 'W' } else { 'E' }
+            );
+            let (width, height) = img.dimensions();
+            let scale = (width.min(height) / 250).max(1);
+            let margin = 4 * scale;
+            let text_width = font::text_width(&text, scale);
+            let text_height = font::GLYPH_HEIGHT * scale;
+            let x = match position {
+                Corner::TopLeft | Corner::BottomLeft => margin,
+                Corner::TopRight | Corner::BottomRight => width.saturating_sub(text_width + margin),
+            };
+            let y = match position {
+                Corner::TopLeft | Corner::TopRight => margin,
+                Corner::BottomLeft | Corner::BottomRight => {
+                    height.saturating_sub(text_height + margin)
+                }
+            };
+            let ink = image::Rgb([color.red, color.green, color.blue]);
+            font::draw_text(&mut img, x, y, &text, scale, ink);
+        }
+        None if skip_missing => eprintln!("INFILE has no GPS position, copying it unchanged."),
+        None => panic!("INFILE has no GPS position!"),
+    }
+
+    save_image(&image::DynamicImage::ImageRgb8(img), &outfile);
+}
+
+// Shelf packing: sprites are placed left to right in rows ("shelves") as tall as their tallest
+// sprite, starting a new shelf when the next one doesn't fit. Sprites go in the order of their
+// names, which are the file names without the extension, so the same inputs always give the same
+// sheet. The sheet is made roughly square, and is cropped to what the sprites use.
+fn sprite_sheet(infiles: Vec<String>, outfile: String, padding: u32, max_size: u32) {
+    let mut sprites = infiles
+        .iter()
+        .map(|infile| {
+            let name = std::path::Path::new(infile)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_else(|| infile.clone());
+            let img = limits::open(infile).unwrap_or_else(|_| panic!("Failed to open {}.", infile));
+            warn_if_downconverting(&img, infile, "sprite-sheet");
+            let img = img.to_rgba8();
+            if img.width() + 2 * padding > max_size || img.height() + 2 * padding > max_size {
+                panic!(
+                    "{} is {}x{}, too big for a --max-size {} sheet!",
+                    infile,
+                    img.width(),
+                    img.height(),
+                    max_size
+                );
+            }
+            (name, img)
+        })
+        .collect::<Vec<_>>();
+    sprites.sort_by(|a, b| a.0.cmp(&b.0));
+    for pair in sprites.windows(2) {
+        if pair[0].0 == pair[1].0 {
+            panic!("Two inputs are named {}!", pair[0].0);
+        }
+    }
+
+    let padded = |img: &image::RgbaImage| (img.width() + 2 * padding, img.height() + 2 * padding);
+    let area = sprites
+        .iter()
+        .map(|(_, img)| {
+            let (w, h) = padded(img);
+            w as u64 * h as u64
+        })
+        .sum::<u64>();
+    let widest = sprites.iter().map(|(_, img)| padded(img).0).max().unwrap();
+    let sheet_width = ((area as f64).sqrt().ceil() as u32)
+        .max(widest)
+        .min(max_size);
+
+    let mut placements = Vec::with_capacity(sprites.len());
+    let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+    for (_, img) in &sprites {
+        let (w, h) = padded(img);
+        if x + w > sheet_width {
+            x = 0;
+            y += shelf_height;
+            shelf_height = 0;
+        }
+        placements.push((x + padding, y + padding));
+        x += w;
+        shelf_height = shelf_height.max(h);
+    }
+    let used_width = sprites
+        .iter()
+        .zip(&placements)
+        .map(|((_, img), &(x, _))| x + img.width() + padding)
+        .max()
+        .unwrap();
+    let used_height = y + shelf_height;
+    if used_height > max_size {
+        panic!(
+            "The sprites need a {}x{} sheet, more than --max-size {}!",
+            used_width, used_height, max_size
+        );
+    }
+
+    let mut sheet = image::RgbaImage::new(used_width, used_height);
+    let mut entries = Vec::with_capacity(sprites.len());
+    for ((name, img), &(x, y)) in sprites.iter().zip(&placements) {
+        image::imageops::replace(&mut sheet, img, x as i64, y as i64);
+        entries.push(format!(
+            "  {}: {{\"x\": {}, \"y\": {}, \"w\": {}, \"h\": {}}}",
+            json::string(name),
+            x,
+            y,
+            img.width(),
+            img.height()
+        ));
+    }
+    println!("{{\n{}\n}}", entries.join(",\n"));
+    save_image(&image::DynamicImage::ImageRgba8(sheet), &outfile);
+}
+
+// Characters from sparse to dense, for dark to light, which suits the usual dark terminal.
+const ASCII_RAMP: &[u8] = b" .:-=+*#%@";
+
+// Escape codes are noise in a file or a pipe, so `--color` only applies to a terminal, unless
+// `--force-color` insists.
+fn ascii_color(color: bool, force_color: bool, is_terminal: bool) -> bool {
+    force_color || (color && is_terminal)
+}
+
+// Every character stands for a cell of pixels twice as tall as it is wide, roughly the shape of a
+// character in a terminal, so the picture isn't stretched.
+fn ascii(infile: String, columns: u32, color: bool, out: &mut impl std::io::Write) {
+    if columns == 0 {
+        panic!("{} is not a valid width!", columns);
+    }
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "ascii");
+    let img = img.to_rgb8();
+    let (width, height) = img.dimensions();
+    let cell_width = width as f32 / columns as f32;
+    let rows = ((height as f32 / (cell_width * 2.0)).round() as u32).max(1);
+    let cell_height = height as f32 / rows as f32;
+
+    for row in 0..rows {
+        let mut line = String::new();
+        for column in 0..columns {
+            let x0 = (column as f32 * cell_width) as u32;
+            let x1 = (((column + 1) as f32 * cell_width) as u32).clamp(x0 + 1, width);
+            let y0 = (row as f32 * cell_height) as u32;
+            let y1 = (((row + 1) as f32 * cell_height) as u32).clamp(y0 + 1, height);
+            let mut sum = [0u64; 3];
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    let p = img.get_pixel(x, y);
+                    for c in 0..3 {
+                        sum[c] += p[c] as u64;
+                    }
+                }
+            }
+            let count = ((x1 - x0) * (y1 - y0)) as u64;
+            let [r, g, b] = sum.map(|total| (total / count) as u8);
+            let luma = (2126 * r as u32 + 7152 * g as u32 + 722 * b as u32) / 10000;
+            let c = ASCII_RAMP[luma as usize * ASCII_RAMP.len() / 256] as char;
+            if color {
+                line.push_str(&format!("\x1b[38;2;{};{};{}m{}", r, g, b, c));
+            } else {
+                line.push(c);
+            }
+        }
+        if color {
+            line.push_str("\x1b[0m");
+        }
+        writeln!(out, "{}", line).expect("Failed writing to stdout.");
+    }
+}
+
+// Row `j` of the reflection is row `height - 1 - j` of the image, so the bottom row of the image
+// and the top row of the reflection are the same pixels, meeting with no gap. The reflection's
+// alpha falls along the `--fade` curve from `opacity` there to 0 on its last row.
+fn reflect(
+    infile: String,
+    outfile: String,
+    reflection_height: Option<u32>,
+    fraction: f32,
+    opacity: f32,
+    curve: FadeCurve,
+    background: Option<Color>,
+) {
+    if !(0.0..=1.0).contains(&opacity) {
+        panic!("{} is not a valid opacity!", opacity);
+    }
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "reflect");
+    let img = img.to_rgba8();
+    let height = img.height();
+    let reflection_height = match reflection_height {
+        Some(pixels) => pixels,
+        None if (0.0..=1.0).contains(&fraction) => (height as f32 * fraction).round() as u32,
+        None => panic!("{} is not a valid fraction!", fraction),
+    };
+    if reflection_height > height {
+        panic!(
+            "The reflection can't be taller than INFILE, which is {} pixels!",
+            height
+        );
+    }
+
+    let mut imgbuf = with_reflection(&img, reflection_height, opacity, curve);
+    if let Some(color) = background {
+        let mut flat = image::RgbaImage::from_pixel(
+            imgbuf.width(),
+            imgbuf.height(),
+            image::Rgba([color.red, color.green, color.blue, 255]),
+        );
+        image::imageops::overlay(&mut flat, &imgbuf, 0, 0);
+        imgbuf = flat;
+    }
+
+    save_image(&image::DynamicImage::ImageRgba8(imgbuf), &outfile);
+}
+
+// `img` with the flipped bottom `reflection_height` rows appended below it. The first reflected row
+// mirrors the bottom row at `opacity` and the alpha falls along `curve` to 0 on the last one.
+fn with_reflection(
+    img: &image::RgbaImage,
+    reflection_height: u32,
+    opacity: f32,
+    curve: FadeCurve,
+) -> image::RgbaImage {
+    let (width, height) = img.dimensions();
+    let mut imgbuf = image::RgbaImage::new(width, height + reflection_height);
+    image::imageops::replace(&mut imgbuf, img, 0, 0);
+    let last = reflection_height.saturating_sub(1).max(1) as f32;
+    for j in 0..reflection_height {
+        let fade = opacity * curve.apply(1.0 - j as f32 / last);
+        for x in 0..width {
+            let mut pixel = *img.get_pixel(x, height - 1 - j);
+            pixel[3] = (pixel[3] as f32 * fade).round() as u8;
+            imgbuf.put_pixel(x, height + j, pixel);
+        }
+    }
+    imgbuf
+}
+
+// Pointy-topped hexagons in axial coordinates (see redblobgames.com/grids/hexagons): the hexagon
+// (q, r) is centered at `size * (sqrt(3) * (q + r / 2), 1.5 * r)`, so one is centered on the top
+// left corner of the image and its corners are at 30, 90, 150, ... degrees from its center.
+fn hex_center(q: f32, r: f32, size: f32) -> (f32, f32) {
+    (size * 3f32.sqrt() * (q + r / 2.0), size * 1.5 * r)
+}
+
+// The axial coordinates of the hexagon containing the point (x, y).
+fn hex_at(x: f32, y: f32, size: f32) -> (f32, f32) {
+    let q = (3f32.sqrt() / 3.0 * x - y / 3.0) / size;
+    let r = 2.0 / 3.0 * y / size;
+    // Round in cube coordinates, where q + r + s = 0, fixing whichever coordinate moved most.
+    let s = -q - r;
+    let (mut rq, mut rr, rs) = (q.round(), r.round(), s.round());
+    let (dq, dr, ds) = ((rq - q).abs(), (rr - r).abs(), (rs - s).abs());
+    if dq > dr && dq > ds {
+        rq = -rr - rs;
+    } else if dr > ds {
+        rr = -rq - rs;
+    }
+    (rq, rr)
+}
+
+// A pixel is part of an outline when it is within half the thickness of the edge of the hexagon
+// it lies in. How far a point is from the edge is the hexagon's inradius minus its distance from
+// the center along the nearest of the three edge normals.
+fn hex_grid(
+    outfile: String,
+    width: u32,
+    height: u32,
+    color: Color,
+    background: Color,
+    hex_size: f32,
+    thickness: f32,
+) {
+    if hex_size <= 0.0 {
+        panic!("{} is not a valid hexagon size!", hex_size);
+    }
+    let inradius = hex_size * 3f32.sqrt() / 2.0;
+    let normals = [0f32, 60.0, 120.0].map(|angle| {
+        let angle = angle.to_radians();
+        (angle.cos(), angle.sin())
+    });
+
+    let imgbuf = image::RgbImage::from_fn(width, height, |x, y| {
+        let (x, y) = (x as f32, y as f32);
+        let (q, r) = hex_at(x, y, hex_size);
+        let (cx, cy) = hex_center(q, r, hex_size);
+        let (dx, dy) = (x - cx, y - cy);
+        let reach = normals
+            .iter()
+            .map(|(nx, ny)| (dx * nx + dy * ny).abs())
+            .fold(0.0, f32::max);
+        if inradius - reach < thickness / 2.0 {
+            image::Rgb([color.red, color.green, color.blue])
+        } else {
+            image::Rgb([background.red, background.green, background.blue])
+        }
+    });
+
+    save_image(&image::DynamicImage::ImageRgb8(imgbuf), &outfile);
+}
+
+// Blurring at full size with a large sigma is slow and wastes effort, since the result has no
+// detail left anyway. Instead the background is shrunk until the blur only needs this sigma,
+// blurred, and scaled back up.
+const BLUR_PAD_WORKING_SIGMA: f32 = 4.0;
+
+// The background is INFILE resized to cover the whole canvas, cropped to it and blurred, all with
+// the chain operations; INFILE itself is then resized to fit inside the canvas and centered on top.
+fn blur_pad(infile: String, outfile: String, target: String, blur_sigma: f32, darken: f32) {
+    if !(0.0..=1.0).contains(&darken) {
+        panic!("{} is not a valid darkening amount!", darken);
+    }
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "blur-pad");
+    let img = img.to_rgba8();
+    let (width, height) = (img.width() as f64, img.height() as f64);
+    let invalid = || -> ! { panic!("{} is not a valid aspect ratio or size!", target) };
+    let (canvas_width, canvas_height) = if let Some((w, h)) = target.split_once('x') {
+        match (w.parse::<u32>(), h.parse::<u32>()) {
+            (Ok(w), Ok(h)) if w > 0 && h > 0 => (w, h),
+            _ => invalid(),
+        }
+    } else if let Some((w, h)) = target.split_once(':') {
+        let ratio = match (w.parse::<f64>(), h.parse::<f64>()) {
+            (Ok(w), Ok(h)) if w > 0.0 && h > 0.0 => w / h,
+            _ => invalid(),
+        };
+        if width / height < ratio {
+            ((height * ratio).round() as u32, height as u32)
+        } else {
+            (width as u32, (width / ratio).round() as u32)
+        }
+    } else {
+        invalid()
+    };
+
+    let shrink = (blur_sigma / BLUR_PAD_WORKING_SIGMA).max(1.0) as f64;
+    let (small_width, small_height) = (
+        ((canvas_width as f64 / shrink).round() as u32).max(1),
+        ((canvas_height as f64 / shrink).round() as u32).max(1),
+    );
+    let cover = (small_width as f64 / width).max(small_height as f64 / height);
+    let (cover_width, cover_height) = (
+        ((width * cover).ceil() as u32).max(small_width),
+        ((height * cover).ceil() as u32).max(small_height),
+    );
+    let background = format!(
+        "resize {} {} crop {} {} {} {} blur {} resize {} {}",
+        cover_width,
+        cover_height,
+        (cover_width - small_width) / 2,
+        (cover_height - small_height) / 2,
+        small_width,
+        small_height,
+        blur_sigma / shrink as f32,
+        canvas_width,
+        canvas_height
+    );
+    let operations =
+        pipeline::parse_operations(&background.split_whitespace().collect::<Vec<_>>()).unwrap();
+    let mut canvas = pipeline::apply_all(img.clone(), &operations);
+    for pixel in canvas.pixels_mut() {
+        for c in 0..3 {
+            pixel[c] = (pixel[c] as f32 * (1.0 - darken)).round() as u8;
+        }
+    }
+
+    let fit = (canvas_width as f64 / width).min(canvas_height as f64 / height);
+    let (fit_width, fit_height) = (
+        ((width * fit).round() as u32).clamp(1, canvas_width),
+        ((height * fit).round() as u32).clamp(1, canvas_height),
+    );
+    let foreground = if (fit_width, fit_height) == img.dimensions() {
+        img
+    } else {
+        let resize = format!("resize {} {}", fit_width, fit_height);
+        let resize =
+            pipeline::parse_operations(&resize.split_whitespace().collect::<Vec<_>>()).unwrap();
+        pipeline::apply_all(img, &resize)
+    };
+    image::imageops::overlay(
+        &mut canvas,
+        &foreground,
+        ((canvas_width - fit_width) / 2) as i64,
+        ((canvas_height - fit_height) / 2) as i64,
+    );
+
+    save_image(&image::DynamicImage::ImageRgba8(canvas), &outfile);
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+enum Edge {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+enum FadeCurve {
+    Linear,
+    /// Eases in and out (smoothstep), so neither end of the fade has a visible hard line
+    Smooth,
+}
+
+impl FadeCurve {
+    // Map `t`, from 0 where something is fully faded to 1 where it isn't faded at all, to an
+    // opacity factor.
+    fn apply(self, t: f32) -> f32 {
+        match self {
+            FadeCurve::Linear => t,
+            FadeCurve::Smooth => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+// Alpha is multiplied by a factor that rises from 0 at the edge to 1 at `distance` pixels from it,
+// and the factors of several edges multiply. Pixels further than `distance` from every chosen
+// edge are left exactly as they were. 16-bit images stay 16-bit.
+fn fade(infile: String, outfile: String, edges: Vec<Edge>, distance: u32, curve: FadeCurve) {
+    if distance == 0 {
+        panic!("{} is not a valid fade distance!", distance);
+    }
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    let (width, height) = (img.width(), img.height());
+    let factor = |x: u32, y: u32| {
+        edges
+            .iter()
+            .map(|edge| {
+                let from_edge = match edge {
+                    Edge::Left => x,
+                    Edge::Right => width - 1 - x,
+                    Edge::Top => y,
+                    Edge::Bottom => height - 1 - y,
+                };
+                if from_edge >= distance {
+                    return 1.0;
+                }
+                curve.apply((from_edge as f32 + 0.5) / distance as f32)
+            })
+            .product::<f32>()
+    };
+
+    let faded = if is_high_bit_depth(&img) {
+        let mut imgbuf = img.to_rgba16();
+        for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+            let factor = factor(x, y);
+            if factor < 1.0 {
+                pixel[3] = (pixel[3] as f32 * factor).round() as u16;
+            }
+        }
+        image::DynamicImage::ImageRgba16(imgbuf)
+    } else {
+        let mut imgbuf = img.to_rgba8();
+        for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+            let factor = factor(x, y);
+            if factor < 1.0 {
+                pixel[3] = (pixel[3] as f32 * factor).round() as u8;
+            }
+        }
+        image::DynamicImage::ImageRgba8(imgbuf)
+    };
+    save_image(&faded, &outfile);
+}
+
+// Barnsley's four affine maps `(a, b, c, d, e, f)`, taking (x, y) to
+// (a * x + b * y + e, c * x + d * y + f), each with the probability of picking it.
+const FERN_MAPS: [([f64; 6], f64); 4] = [
+    ([0.0, 0.0, 0.0, 0.16, 0.0, 0.0], 0.01),
+    ([0.85, 0.04, -0.04, 0.85, 0.0, 1.6], 0.85),
+    ([0.2, -0.26, 0.23, 0.22, 0.0, 1.6], 0.07),
+    ([-0.15, 0.28, 0.26, 0.24, 0.0, 0.44], 0.07),
+];
+
+// The area the fern's points fall into, as (min x, max x, max y); the minimum y is 0.
+const FERN_BOUNDS: (f64, f64, f64) = (-2.182, 2.6558, 9.9983);
+
+// Starting from the origin, apply a randomly picked map over and over and plot every point. The
+// fern is scaled to fit the image, keeping its proportions, with the stem at the bottom.
+fn fern(
+    outfile: String,
+    width: u32,
+    height: u32,
+    iterations: u64,
+    seed: u64,
+    cancel: cancel::Cancel,
+) {
+    let (min_x, max_x, max_y) = FERN_BOUNDS;
+    let margin = 0.05;
+    let scale = ((width as f64 * (1.0 - 2.0 * margin)) / (max_x - min_x))
+        .min(height as f64 * (1.0 - 2.0 * margin) / max_y);
+    let offset_x = (width as f64 - (max_x - min_x) * scale) / 2.0 - min_x * scale;
+    let offset_y = (height as f64 + max_y * scale) / 2.0;
+
+    let mut rng = random::Rng::new(seed);
+    let mut imgbuf = image::RgbImage::new(width, height);
+    let (mut x, mut y) = (0.0f64, 0.0f64);
+    for i in 0..iterations {
+        if i % 1_000_000 == 0 && cancel.requested() {
+            cancel::exit(i as usize, iterations as usize, "points");
+        }
+        let mut pick = rng.next_f64();
+        let (map, _) = FERN_MAPS
+            .iter()
+            .find(|(_, probability)| {
+                pick -= probability;
+                pick < 0.0
+            })
+            .unwrap_or(&FERN_MAPS[1]);
+        let [a, b, c, d, e, f] = *map;
+        (x, y) = (a * x + b * y + e, c * x + d * y + f);
+
+        let px = (offset_x + x * scale).floor();
+        let py = (offset_y - y * scale).floor();
+        if px >= 0.0 && py >= 0.0 && px < width as f64 && py < height as f64 {
+            imgbuf.put_pixel(px as u32, py as u32, image::Rgb([60, 200, 80]));
+        }
+    }
+
+    save_image(&image::DynamicImage::ImageRgb8(imgbuf), &outfile);
+}
+
+// The text is wrapped to the image's width, each line centered, and the bar is exactly as tall as
+// the lines need plus a margin of one letter height above and below.
+fn caption(
+    infile: String,
+    outfile: String,
+    text: String,
+    top: bool,
+    font_size: u32,
+    background: Color,
+    color: Color,
+) {
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "caption");
+    let img = img.to_rgba8();
+    let (width, height) = img.dimensions();
+    let scale = (font_size / font::GLYPH_HEIGHT).max(1);
+    let margin = font::GLYPH_HEIGHT * scale;
+    let advance = (font::GLYPH_WIDTH + 1) * scale;
+    let line_height = (font::GLYPH_HEIGHT + 2) * scale;
+    let max_chars = (width.saturating_sub(2 * margin) + scale) / advance;
+    let lines = font::wrap(&text, max_chars as usize);
+    if lines.is_empty() {
+        panic!("The caption text is empty!");
+    }
+    let bar_height = lines.len() as u32 * line_height - 2 * scale + 2 * margin;
+
+    let mut bar = image::RgbImage::from_pixel(
+        width,
+        bar_height,
+        image::Rgb([background.red, background.green, background.blue]),
+    );
+    for (i, line) in lines.iter().enumerate() {
+        let x = width.saturating_sub(font::text_width(line, scale)) / 2;
+        let y = margin + i as u32 * line_height;
+        let ink = image::Rgb([color.red, color.green, color.blue]);
+        font::draw_text(&mut bar, x, y, line, scale, ink);
+    }
+
+    let bar = image::DynamicImage::ImageRgb8(bar).to_rgba8();
+    let mut imgbuf = image::RgbaImage::new(width, height + bar_height);
+    let (image_y, bar_y) = if top { (bar_height, 0) } else { (0, height) };
+    image::imageops::replace(&mut imgbuf, &img, 0, image_y as i64);
+    image::imageops::replace(&mut imgbuf, &bar, 0, bar_y as i64);
+    save_image(&image::DynamicImage::ImageRgba8(imgbuf), &outfile);
+}
+
+// Every pixel is rotated into the screen's coordinate system, where the dots sit on a square grid,
+// to find the cell it falls into. The cell's dot has a radius proportional to how dark the image
+// is at the cell's center, reaching the cell's corners for black so that solid areas stay solid.
+// The image is blurred to about the size of a cell first, so each dot reflects its surroundings
+// rather than a single pixel.
+fn halftone(infile: String, outfile: String, dot_spacing: f32, angle: f32) {
+    if dot_spacing < 1.0 {
+        panic!("{} is not a valid dot spacing!", dot_spacing);
+    }
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    let (width, height) = (img.width(), img.height());
+    let smoothed = boxblur::gaussian_approx(&img.grayscale().to_rgba32f(), dot_spacing / 3.0);
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let max_radius = dot_spacing * std::f32::consts::FRAC_1_SQRT_2;
+
+    let imgbuf = image::GrayImage::from_fn(width, height, |x, y| {
+        let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+        let u = px * cos + py * sin;
+        let v = -px * sin + py * cos;
+        let center_u = ((u / dot_spacing).floor() + 0.5) * dot_spacing;
+        let center_v = ((v / dot_spacing).floor() + 0.5) * dot_spacing;
+        let center_x = center_u * cos - center_v * sin;
+        let center_y = center_u * sin + center_v * cos;
+        let sample = smoothed.get_pixel(
+            (center_x.max(0.0) as u32).min(width - 1),
+            (center_y.max(0.0) as u32).min(height - 1),
+        );
+        let darkness = 1.0 - sample[0].clamp(0.0, 1.0);
+        let distance = ((u - center_u).powi(2) + (v - center_v).powi(2)).sqrt();
+        if distance < darkness * max_radius {
+            image::Luma([0])
+        } else {
+            image::Luma([255])
+        }
+    });
+
+    save_image(&image::DynamicImage::ImageLuma8(imgbuf), &outfile);
+}
+
+const QR_MODULE_PIXELS: u32 = 8;
+
+// Every module is drawn as a solid square of whole pixels, so the edges stay sharp at any size.
+fn qr_code(
+    outfile: String,
+    data: String,
+    size: Option<u32>,
+    margin: u32,
+    ec_level: qr::EcLevel,
+    dark: Color,
+    light: Color,
+) {
+    use std::io::Read;
+
+    let data = if data == "-" {
+        let mut data = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut data)
+            .expect("Failed to read the data from standard input.");
+        data
+    } else {
+        data.into_bytes()
+    };
+    let code = qr::encode(&data, ec_level).unwrap_or_else(|err| panic!("{}", err));
+
+    let modules = code.size() as u32 + 2 * margin;
+    let scale = match size {
+        Some(size) if size < modules => panic!(
+            "{} pixels is too small for a QR code {} modules wide!",
+            size, modules
+        ),
+        Some(size) => size / modules,
+        None => QR_MODULE_PIXELS,
+    };
+    let dark = image::Rgb([dark.red, dark.green, dark.blue]);
+    let light = image::Rgb([light.red, light.green, light.blue]);
+    let imgbuf = image::RgbImage::from_fn(modules * scale, modules * scale, |x, y| {
+        let (x, y) = ((x / scale) as usize, (y / scale) as usize);
+        let (margin, code_size) = (margin as usize, code.size());
+        let inside =
+            (margin..margin + code_size).contains(&x) && (margin..margin + code_size).contains(&y);
+        if inside && code.is_dark(x - margin, y - margin) {
+            dark
+        } else {
+            light
+        }
+    });
+
+    save_image(&image::DynamicImage::ImageRgb8(imgbuf), &outfile);
+}
+
+// The gray world assumption: the average color of a typical scene is gray, so any tint in the
+// average comes from the light. Each channel is scaled so its mean matches the mean of all three,
+// which removes the tint without changing the overall brightness. Fully transparent pixels don't
+// count towards the means. Highlights in a boosted channel clip, so its mean may end up a little
+// short.
+fn auto_white_balance(infile: String, outfile: String) {
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    let mut balanced = img.to_rgba32f();
+
+    let mut sums = [0.0f64; 3];
+    let mut count = 0u64;
+    for pixel in balanced.pixels().filter(|p| p[3] > 0.0) {
+        for c in 0..3 {
+            sums[c] += pixel[c] as f64;
+        }
+        count += 1;
+    }
+    if count == 0 || sums.contains(&0.0) {
+        panic!("INFILE has no color to balance!");
+    }
+    let gray = sums.iter().sum::<f64>() / 3.0;
+    let gains = sums.map(|sum| (gray / sum) as f32);
+
+    for pixel in balanced.pixels_mut() {
+        for c in 0..3 {
+            pixel[c] = (pixel[c] * gains[c]).min(1.0);
+        }
+    }
+    save_image(&like_original(balanced, &img), &outfile);
+}
+
+// Viewers that honor the EXIF orientation tag show the pixels transformed, and viewers that don't
+// show them as stored, so the two disagree for any orientation but 1. Orientations 5 to 8 also swap
+// width and height, turning a landscape pixel buffer into a portrait photo or the other way around.
+fn check_orientation(infile: String, fix: Option<String>) {
+    let (orientation, report) = orientation_report(&infile);
+    println!("{}", report);
+
+    if let Some(outfile) = fix {
+        let img = limits::open(&infile).expect("Failed to open INFILE.");
+        let fixed = match orientation {
+            Some(2) => img.fliph(),
+            Some(3) => img.rotate180(),
+            Some(4) => img.flipv(),
+            Some(5) => img.rotate90().fliph(),
+            Some(6) => img.rotate90(),
+            Some(7) => img.rotate270().fliph(),
+            Some(8) => img.rotate270(),
+            _ => img,
+        };
+        save_image(&fixed, &outfile);
+    }
+}
+
+// The orientation tag of `infile` and the line check-orientation reports for it.
+fn orientation_report(infile: &str) -> (Option<u16>, String) {
+    let bytes = std::fs::read(infile).expect("Failed to open INFILE.");
+    let orientation = exif::orientation(&bytes).unwrap_or_else(|e| panic!("{}", e));
+    let (width, height) = image::image_dimensions(infile).expect("Failed to open INFILE.");
+
+    let report = match orientation {
+        None | Some(1) => format!(
+            "{}: OK (orientation {}, {}x{})",
+            infile,
+            orientation.map_or("not set".to_string(), |o| o.to_string()),
+            width,
+            height
+        ),
+        Some(o @ 2..=8) => {
+            let description = [
+                "mirrored horizontally",
+                "rotated 180 degrees",
+                "flipped vertically",
+                "transposed",
+                "rotated 90 degrees clockwise",
+                "transversed",
+                "rotated 90 degrees counterclockwise",
+            ][o as usize - 2];
+            let (shown_width, shown_height) = if o >= 5 {
+                (height, width)
+            } else {
+                (width, height)
+            };
+            format!(
+                "{}: MISMATCH (orientation {}, {}: stored {}x{}, displayed {}x{})",
+                infile, o, description, width, height, shown_width, shown_height
+            )
+        }
+        Some(o) => panic!("{} is not a valid EXIF orientation!", o),
+    };
+    (orientation, report)
+}
+
+fn from_matrix(infile: String, outfile: String, palette: colormap::Colormap) {
+    let imgbuf = render_matrix(&read_matrix(&infile), palette);
+    save_image(&image::DynamicImage::ImageRgb8(imgbuf), &outfile);
+}
+
+// Blank lines are skipped. Every other line must have as many numbers as the first, so that a
+// missing value can't shift the rest of its row over silently.
+fn read_matrix(infile: &str) -> Vec<Vec<f64>> {
+    let text = std::fs::read_to_string(infile).expect("Failed to open INFILE.");
+    let mut rows: Vec<Vec<f64>> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let row = line
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|value| !value.is_empty())
+            .map(|value| match value.parse::<f64>() {
+                Ok(value) if value.is_finite() => value,
+                _ => panic!("{} on line {} is not a valid number!", value, number + 1),
+            })
+            .collect::<Vec<_>>();
+        if row.is_empty() {
+            continue;
+        }
+        if let Some(first) = rows.first() {
+            if row.len() != first.len() {
+                panic!(
+                    "Line {} has {} values, but the first row has {}!",
+                    number + 1,
+                    row.len(),
+                    first.len()
+                );
+            }
+        }
+        rows.push(row);
+    }
+    if rows.is_empty() {
+        panic!("INFILE has no numbers!");
+    }
+    rows
+}
+
+// One pixel per number, `rows[y][x]`. The lowest number becomes the low end of `palette` and the
+// highest the high end.
+fn render_matrix(rows: &[Vec<f64>], palette: colormap::Colormap) -> image::RgbImage {
+    let values = rows.iter().flatten();
+    let min = values.clone().copied().fold(f64::INFINITY, f64::min);
+    let max = values.copied().fold(f64::NEG_INFINITY, f64::max);
+    let range = max - min;
+    image::RgbImage::from_fn(rows[0].len() as u32, rows.len() as u32, |x, y| {
+        let value = rows[y as usize][x as usize];
+        let t = if range > 0.0 {
+            (value - min) / range
+        } else {
+            0.0
+        };
+        image::Rgb(palette.sample(t as f32))
+    })
+}
+
+fn resize(infile: String, outfile: String, width: u32, height: u32, linear: bool) {
+    if width == 0 || height == 0 {
+        panic!("{}x{} is not a valid size!", width, height);
+    }
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    save_image(&resize_exact(&img, width, height, linear), &outfile);
+}
+
+// Lanczos resampling to exactly `width` x `height`, in linear light if `linear` is set.
+fn resize_exact(
+    img: &image::DynamicImage,
+    width: u32,
+    height: u32,
+    linear: bool,
+) -> image::DynamicImage {
+    let filter = image::imageops::FilterType::Lanczos3;
+    if linear {
+        let resized = image::imageops::resize(&colorspace::to_linear(img), width, height, filter);
+        colorspace::from_linear(resized, img)
+    } else {
+        img.resize_exact(width, height, filter)
+    }
+}
+
+// The longer side becomes exactly `max_edge` and the shorter one is rounded to keep the aspect
+// ratio. When there is nothing to do and OUTFILE would be written in INFILE's format anyway, INFILE
+// is copied instead of decoded and encoded again, which would only lose quality and time.
+fn resize_max_edge(infile: String, outfile: String, max_edge: u32, upscale: bool, linear: bool) {
+    if max_edge == 0 {
+        panic!("0 is not a valid maximum edge length!");
+    }
+    let (width, height) = image::image_dimensions(&infile).expect("Failed to open INFILE.");
+    let longest = width.max(height);
+
+    if longest == max_edge || (longest < max_edge && !upscale) {
+        let input_format = image::io::Reader::open(&infile)
+            .and_then(|reader| reader.with_guessed_format())
+            .expect("Failed to open INFILE.")
+            .format();
+        let output_format = match OUTPUT_FORMAT.get().copied().flatten() {
+            Some(format) => format.image_format(),
+            None => image::ImageFormat::from_path(&outfile).ok(),
+        };
+        let force_rgba = matches!(COLOR_TYPE_MODE.get(), Some(ColorTypeMode::ForceRgba));
+        if input_format.is_some() && input_format == output_format && !force_rgba {
+            write_atomically(&outfile, |path| {
+                std::fs::copy(&infile, path)
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            });
+        } else {
+            save_image(
+                &limits::open(infile).expect("Failed to open INFILE."),
+                &outfile,
+            );
+        }
+        return;
+    }
+
+    let scale = max_edge as f64 / longest as f64;
+    let fit = |side: u32| {
+        if side == longest {
+            max_edge
+        } else {
+            ((side as f64 * scale).round() as u32).max(1)
+        }
+    };
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    save_image(
+        &resize_exact(&img, fit(width), fit(height), linear),
+        &outfile,
+    );
+}
+
+const TIMELINE_PADDING: u32 = 8;
+const TIMELINE_LABEL_SCALE: u32 = 2;
+
+// The images in INDIR, and with `recursive` in its subdirectories, with their capture dates, oldest
+// first. Files without a date go last, and ties are broken by path so the order is the same on
+// every run.
+fn dated_image_files(indir: &str, recursive: bool) -> Vec<(Option<String>, std::path::PathBuf)> {
+    let mut files = image_files(indir, recursive)
+        .expect("Failed to read INDIR.")
+        .into_iter()
+        .map(|path| {
+            let bytes = std::fs::read(&path).expect("Failed to read a file in INDIR.");
+            let date = exif::capture_date(&bytes).unwrap_or_else(|err| {
+                eprintln!(
+                    "Warning: ignoring the EXIF data of {}: {}",
+                    path.display(),
+                    err
+                );
+                None
+            });
+            (date, path)
+        })
+        .collect::<Vec<_>>();
+    files.sort_by(|(date_a, path_a), (date_b, path_b)| {
+        (date_a.is_none(), date_a, path_a).cmp(&(date_b.is_none(), date_b, path_b))
+    });
+    files
+}
+
+// A contact sheet in capture order (see `dated_image_files`), each thumbnail labeled with its date.
+// Files in INDIR that aren't images are ignored, and images that fail to decode are skipped with a
+// warning.
+fn timeline(indir: String, outfile: String, cols: u32, thumb_size: u32) {
+    if cols == 0 {
+        panic!("{} is not a valid number of columns!", cols);
+    }
+    if thumb_size == 0 {
+        panic!("{} is not a valid thumbnail size!", thumb_size);
+    }
+
+    let thumbnails = dated_image_files(&indir, false)
+        .into_iter()
+        .filter_map(|(date, path)| match limits::open(&path) {
+            Ok(img) => {
+                warn_if_downconverting(&img, &path.to_string_lossy(), "timeline");
+                Some((date, img.thumbnail(thumb_size, thumb_size).to_rgba8()))
+            }
+            Err(err) => {
+                eprintln!("Warning: skipping {}: {}", path.display(), err);
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    if thumbnails.is_empty() {
+        panic!("INDIR does not contain any images!");
+    }
+
+    // `YYYY:MM:DD HH:MM:SS` reads better as `YYYY-MM-DD HH:MM`.
+    let labels = thumbnails
+        .iter()
+        .map(|(date, _)| match date {
+            Some(date) => format!("{} {}", date[..10].replace(':', "-"), &date[11..16]),
+            None => "no date".to_string(),
+        })
+        .collect::<Vec<_>>();
+    let label_width = labels
+        .iter()
+        .map(|label| font::text_width(label, TIMELINE_LABEL_SCALE))
+        .max()
+        .unwrap();
+
+    // Columns are widened for the labels when the thumbnails are narrower.
+    let column_width = thumb_size.max(label_width);
+    let label_height = (font::GLYPH_HEIGHT + 2) * TIMELINE_LABEL_SCALE;
+    let cell_width = column_width + TIMELINE_PADDING;
+    let cell_height = thumb_size + label_height + TIMELINE_PADDING;
+    let cols = cols.min(thumbnails.len() as u32);
+    let rows = (thumbnails.len() as u32).div_ceil(cols);
+    let mut sheet = image::RgbaImage::from_pixel(
+        cols * cell_width + TIMELINE_PADDING,
+        rows * cell_height + TIMELINE_PADDING,
+        image::Rgba([255, 255, 255, 255]),
+    );
+    let cell_origin = |i: usize| {
+        (
+            (i as u32 % cols) * cell_width + TIMELINE_PADDING,
+            (i as u32 / cols) * cell_height + TIMELINE_PADDING,
+        )
+    };
+    for (i, (_, thumbnail)) in thumbnails.iter().enumerate() {
+        let (left, top) = cell_origin(i);
+        let x = left + (column_width - thumbnail.width()) / 2;
+        let y = top + (thumb_size - thumbnail.height()) / 2;
+        image::imageops::overlay(&mut sheet, thumbnail, x as i64, y as i64);
+    }
+
+    // Transparent thumbnails have been blended onto the white background, so the sheet is opaque.
+    let mut sheet = image::DynamicImage::ImageRgba8(sheet).to_rgb8();
+    for (i, label) in labels.iter().enumerate() {
+        let (left, top) = cell_origin(i);
+        let label_x = left + (column_width - font::text_width(label, TIMELINE_LABEL_SCALE)) / 2;
+        font::draw_text(
+            &mut sheet,
+            label_x,
+            top + thumb_size + TIMELINE_LABEL_SCALE,
+            label,
+            TIMELINE_LABEL_SCALE,
+            image::Rgb([0, 0, 0]),
+        );
+    }
+    save_image(&image::DynamicImage::ImageRgb8(sheet), &outfile);
+}
+
+const EXPLAIN_LABEL_SCALE: u32 = 2;
+
+// Every frame shows one step's image centered on a dark canvas big enough for the largest of them,
+// since steps like crop and resize change the size, with the step's label in a bar underneath.
+fn write_explain_gif(explain: &Explain, steps: Vec<(String, image::RgbaImage)>) {
+    use image::codecs::gif::{GifEncoder, Repeat};
+
+    let width = steps.iter().map(|(_, img)| img.width()).max().unwrap();
+    let height = steps.iter().map(|(_, img)| img.height()).max().unwrap();
+    let max_chars = (width / ((font::GLYPH_WIDTH + 1) * EXPLAIN_LABEL_SCALE)) as usize;
+    let labels = steps
+        .iter()
+        .map(|(label, _)| font::wrap(label, max_chars))
+        .collect::<Vec<_>>();
+    let line_height = (font::GLYPH_HEIGHT + 2) * EXPLAIN_LABEL_SCALE;
+    let bar_height =
+        labels.iter().map(Vec::len).max().unwrap() as u32 * line_height + 2 * EXPLAIN_LABEL_SCALE;
+
+    let background = image::Rgba([32, 32, 32, 255]);
+    let frames = steps.iter().zip(&labels).map(|((_, img), lines)| {
+        let mut canvas = image::RgbaImage::from_pixel(width, height + bar_height, background);
+        let x = (width - img.width()) / 2;
+        let y = (height - img.height()) / 2;
+        image::imageops::overlay(&mut canvas, img, x as i64, y as i64);
+
+        let mut canvas = image::DynamicImage::ImageRgba8(canvas).to_rgb8();
+        for (i, line) in lines.iter().enumerate() {
+            font::draw_text(
+                &mut canvas,
+                EXPLAIN_LABEL_SCALE,
+                height + 2 * EXPLAIN_LABEL_SCALE + i as u32 * line_height,
+                line,
+                EXPLAIN_LABEL_SCALE,
+                image::Rgb([255, 255, 255]),
+            );
+        }
+        let delay = image::Delay::from_numer_denom_ms(explain.delay, 1);
+        image::Frame::from_parts(
+            image::DynamicImage::ImageRgb8(canvas).to_rgba8(),
+            0,
+            0,
+            delay,
+        )
+    });
+
+    write_atomically(&explain.outfile, |path| {
+        let file = std::fs::File::create(path).map_err(|e| e.to_string())?;
+        let mut encoder = GifEncoder::new(std::io::BufWriter::new(file));
+        encoder
+            .set_repeat(Repeat::Infinite)
+            .map_err(|e| e.to_string())?;
+        encoder.encode_frames(frames).map_err(|e| e.to_string())
+    });
+}
+
+// Blur radius of the unsharp mask, and of the copy that edges are detected in. Finding edges in a
+// slightly blurred image keeps pixel-level noise from registering as edges.
+const EDGE_SHARPEN_RADIUS: f32 = 1.0;
+
+// An unsharp mask applied only where the Sobel gradient magnitude of the luma reaches
+// `edge_threshold`. The magnitude is divided by 4, the sum of the Sobel weights on either side, so
+// a clean step from black to white measures 255.
+fn edge_sharpen(infile: String, outfile: String, amount: f32, edge_threshold: f32) {
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "edge-sharpen");
+    let img = img.to_rgba8();
+    let blurred = image::imageops::blur(&img, EDGE_SHARPEN_RADIUS);
+    let edges = image::imageops::grayscale(&blurred);
+
+    let mut imgbuf = img.clone();
+    for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+        let (gx, gy) = sobel_gradient(&edges, x, y);
+        if (gx * gx + gy * gy).sqrt() / 4.0 < edge_threshold {
+            continue;
+        }
+        let blurred_pixel = blurred.get_pixel(x, y);
+        for c in 0..3 {
+            let original = pixel[c] as f32;
+            let diff = original - blurred_pixel[c] as f32;
+            pixel[c] = (original + amount * diff).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+
+    save_image(&image::DynamicImage::ImageRgba8(imgbuf), &outfile);
+}
+
+#[derive(Copy, Clone)]
+struct ChannelStats {
+    min: f64,
+    max: f64,
+    sum: f64,
+    sum_of_squares: f64,
+}
+
+struct PixelStats {
+    channels: Vec<ChannelStats>,
+    black: u64,
+    white: u64,
+    transparent: u64,
+}
+
+// Everything in one pass over interleaved samples. A pixel is black or white when all of its color
+// channels are at 0 or at `max`, whatever its alpha, and transparent when its alpha is 0.
+fn pixel_stats<T: Copy + PartialEq + Into<f64>>(
+    samples: &[T],
+    channels: usize,
+    has_alpha: bool,
+    zero: T,
+    max: T,
+) -> PixelStats {
+    let color_channels = if has_alpha { channels - 1 } else { channels };
+    let mut stats = PixelStats {
+        channels: vec![
+            ChannelStats {
+                min: f64::INFINITY,
+                max: f64::NEG_INFINITY,
+                sum: 0.0,
+                sum_of_squares: 0.0,
+            };
+            channels
+        ],
+        black: 0,
+        white: 0,
+        transparent: 0,
+    };
+    for pixel in samples.chunks_exact(channels) {
+        for (channel, &sample) in stats.channels.iter_mut().zip(pixel) {
+            let value = sample.into();
+            channel.min = channel.min.min(value);
+            channel.max = channel.max.max(value);
+            channel.sum += value;
+            channel.sum_of_squares += value * value;
+        }
+        let color = &pixel[..color_channels];
+        if color.iter().all(|&sample| sample == zero) {
+            stats.black += 1;
+        }
+        if color.iter().all(|&sample| sample == max) {
+            stats.white += 1;
+        }
+        if has_alpha && pixel[channels - 1] == zero {
+            stats.transparent += 1;
+        }
+    }
+    stats
+}
+
+// Values are in the image's own units: 0 to 255 for 8-bit images and 0 to 65535 for 16-bit ones.
+// Floating point images are measured as 16-bit.
+fn stats(infile: String, json: bool) {
+    use image::DynamicImage::*;
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    let img = match img {
+        ImageRgb32F(_) => ImageRgb16(img.to_rgb16()),
+        ImageRgba32F(_) => ImageRgba16(img.to_rgba16()),
+        img => img,
+    };
+    let channels = img.color().channel_count() as usize;
+    let has_alpha = img.color().has_alpha();
+    let stats = match &img {
+        ImageLuma16(buf) => pixel_stats(buf.as_raw(), 1, false, 0, u16::MAX),
+        ImageLumaA16(buf) => pixel_stats(buf.as_raw(), 2, true, 0, u16::MAX),
+        ImageRgb16(buf) => pixel_stats(buf.as_raw(), 3, false, 0, u16::MAX),
+        ImageRgba16(buf) => pixel_stats(buf.as_raw(), 4, true, 0, u16::MAX),
+        _ => pixel_stats(img.as_bytes(), channels, has_alpha, 0, u8::MAX),
+    };
+
+    let names: &[&str] = match (channels, has_alpha) {
+        (1, _) => &["luma"],
+        (2, _) => &["luma", "alpha"],
+        (3, _) => &["red", "green", "blue"],
+        _ => &["red", "green", "blue", "alpha"],
+    };
+    let pixels = img.width() as u64 * img.height() as u64;
+    let summary = stats
+        .channels
+        .iter()
+        .map(|channel| {
+            let mean = channel.sum / pixels as f64;
+            let variance = (channel.sum_of_squares / pixels as f64 - mean * mean).max(0.0);
+            (channel.min, channel.max, mean, variance.sqrt())
+        })
+        .collect::<Vec<_>>();
+    let counts = [
+        ("black", stats.black),
+        ("white", stats.white),
+        ("transparent", stats.transparent),
+    ];
+    let percent = |count: u64| count as f64 * 100.0 / pixels.max(1) as f64;
+
+    if json {
+        let channel_entries = names
+            .iter()
+            .zip(&summary)
+            .map(|(name, (min, max, mean, stddev))| {
+                format!(
+                    "    \"{}\": {{\"min\": {}, \"max\": {}, \"mean\": {:.4}, \"stddev\": {:.4}}}",
+                    name, min, max, mean, stddev
+                )
+            })
+            .collect::<Vec<_>>();
+        let count_entries = counts
+            .iter()
+            .map(|(name, count)| {
+                format!(
+                    "  \"{}\": {{\"pixels\": {}, \"percent\": {:.4}}}",
+                    name,
+                    count,
+                    percent(*count)
+                )
+            })
+            .collect::<Vec<_>>();
+        println!(
+            "{{\n  \"width\": {},\n  \"height\": {},\n  \"channels\": {{\n{}\n  }},\n{}\n}}",
+            img.width(),
+            img.height(),
+            channel_entries.join(",\n"),
+            count_entries.join(",\n")
+        );
+        return;
+    }
+
+    println!(
+        "{:<8} {:>8} {:>8} {:>10} {:>10}",
+        "channel", "min", "max", "mean", "stddev"
+    );
+    for (name, (min, max, mean, stddev)) in names.iter().zip(&summary) {
+        println!(
+            "{:<8} {:>8} {:>8} {:>10.2} {:>10.2}",
+            name, min, max, mean, stddev
+        );
+    }
+    for (name, count) in counts {
+        println!("{:<12} {:>10} {:>9.2}%", name, count, percent(count));
+    }
+}
+
+// The text is drawn once as a white-on-black mask, and every pixel it covers is then colored by
+// its height within the letters, so the gradient runs from the top of the capitals to the bottom.
+fn gradient_text(
+    outfile: String,
+    text: String,
+    size: u32,
+    start_color: Color,
+    end_color: Color,
+    background: Color,
+) {
+    if text.trim().is_empty() {
+        panic!("The text is empty!");
+    }
+    let scale = (size / font::GLYPH_HEIGHT).max(1);
+    let margin = font::GLYPH_HEIGHT * scale;
+    let text_height = font::GLYPH_HEIGHT * scale;
+    let width = font::text_width(&text, scale) + 2 * margin;
+    let height = text_height + 2 * margin;
+
+    let mut mask = image::RgbImage::new(width, height);
+    font::draw_text(
+        &mut mask,
+        margin,
+        margin,
+        &text,
+        scale,
+        image::Rgb([255, 255, 255]),
+    );
+
+    let start = [start_color.red, start_color.green, start_color.blue];
+    let end = [end_color.red, end_color.green, end_color.blue];
+    let imgbuf = image::RgbImage::from_fn(width, height, |x, y| {
+        if mask.get_pixel(x, y)[0] == 0 {
+            return image::Rgb([background.red, background.green, background.blue]);
+        }
+        let t = (y - margin) as f32 / (text_height - 1).max(1) as f32;
+        image::Rgb(
+            [0, 1, 2]
+                .map(|c| (start[c] as f32 + (end[c] as f32 - start[c] as f32) * t).round() as u8),
+        )
+    });
+    save_image(&image::DynamicImage::ImageRgb8(imgbuf), &outfile);
+}
+
+// The files in DIR, and with `recursive` in its subdirectories, whose extension names an image
+// format, sorted by path. Symlinked directories aren't followed, so links can't make it loop.
+fn image_files(dir: &str, recursive: bool) -> std::io::Result<Vec<std::path::PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![std::path::PathBuf::from(dir)];
+    while let Some(dir) = pending.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                if recursive {
+                    pending.push(path);
+                }
+            } else if path.is_file() && image::ImageFormat::from_path(&path).is_ok() {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+struct DedupeImage {
+    path: std::path::PathBuf,
+    bytes: u64,
+    width: u32,
+    height: u32,
+    hash: u64,
+}
+
+// Groups of two or more images, each listed with the one to keep first. Groups are joined
+// transitively: if A is close to B and B to C, all three are one group even when A and C are further
+// apart than `threshold`.
+fn dedupe_groups(images: &[DedupeImage], threshold: u32) -> Vec<Vec<usize>> {
+    // Union-find over image indices, with path halving.
+    let mut parent = (0..images.len()).collect::<Vec<_>>();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    let mut tree = bktree::BkTree::new();
+    for (i, image) in images.iter().enumerate() {
+        for j in tree.find(image.hash, threshold) {
+            let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+            parent[a.max(b)] = a.min(b);
+        }
+        tree.insert(image.hash, i);
+    }
+
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut group_of = vec![usize::MAX; images.len()];
+    for i in 0..images.len() {
+        let r = root(&mut parent, i);
+        if group_of[r] == usize::MAX {
+            group_of[r] = groups.len();
+            groups.push(Vec::new());
+        }
+        groups[group_of[r]].push(i);
+    }
+    groups.retain(|group| group.len() > 1);
+    for group in &mut groups {
+        group.sort_by_key(|&i| {
+            let image = &images[i];
+            (
+                std::cmp::Reverse(image.width as u64 * image.height as u64),
+                std::cmp::Reverse(image.bytes),
+                i,
+            )
+        });
+    }
+    groups
+}
+
+// The images --delete-smaller deletes: those within `threshold` of the one kept in their group. A
+// member that only joined through others may not look like the kept image at all.
+fn dedupe_doomed(images: &[DedupeImage], groups: &[Vec<usize>], threshold: u32) -> Vec<usize> {
+    groups
+        .iter()
+        .flat_map(|group| {
+            let kept = images[group[0]].hash;
+            group[1..]
+                .iter()
+                .copied()
+                .filter(move |&i| (images[i].hash ^ kept).count_ones() <= threshold)
+        })
+        .collect()
+}
+
+// Hashing means decoding every image, which dominates the running time, so it's spread over all
+// cores. Images are then grouped with a BK-tree lookup per image instead of comparing every pair.
+//
+// Within a group the largest image by pixel count is kept, with ties going to the larger file and
+// then the earlier path. Groups are joined transitively (see `dedupe_groups`), so --delete-smaller
+// only deletes the images within THRESHOLD of the one kept.
+fn dedupe(
+    dir: String,
+    recursive: bool,
+    threshold: u32,
+    json: bool,
+    delete_smaller: bool,
+    dry_run: bool,
+    cancel: cancel::Cancel,
+) {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let paths = image_files(&dir, recursive).expect("Failed to read DIR.");
+    let next = AtomicUsize::new(0);
+    let done = AtomicUsize::new(0);
+    let workers = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut hashed = std::thread::scope(|scope| {
+        let handles = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut hashed = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        if i >= paths.len() || cancel.requested() {
+                            return hashed;
+                        }
+                        let path = &paths[i];
+                        match limits::open(path) {
+                            Ok(img) => hashed.push((
+                                i,
+                                DedupeImage {
+                                    path: path.clone(),
+                                    bytes: std::fs::metadata(path).map_or(0, |m| m.len()),
+                                    width: img.width(),
+                                    height: img.height(),
+                                    hash: perceptual_hash(&img),
+                                },
+                            )),
+                            Err(err) => {
+                                eprintln!("Warning: skipping {}: {}", path.display(), err)
+                            }
+                        }
+                        done.fetch_add(1, Ordering::Relaxed);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+    if cancel.requested() {
+        eprintln!(
+            "Interrupted after {} of {} files, nothing was deleted.",
+            done.load(Ordering::Relaxed),
+            paths.len()
+        );
+        std::process::exit(cancel::EXIT_CODE);
+    }
+    hashed.sort_by_key(|&(i, _)| i);
+    let images = hashed
+        .into_iter()
+        .map(|(_, image)| image)
+        .collect::<Vec<_>>();
+
+    let groups = dedupe_groups(&images, threshold);
+
+    if json {
+        let groups = groups
+            .iter()
+            .map(|group| {
+                let files = group
+                    .iter()
+                    .map(|&i| {
+                        let image = &images[i];
+                        format!(
+                            "{{\"path\": {}, \"bytes\": {}, \"width\": {}, \"height\": {}, \"phash\": \"{:016x}\"}}",
+                            json::string(&image.path.to_string_lossy()),
+                            image.bytes,
+                            image.width,
+                            image.height,
+                            image.hash
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                format!("  [\n    {}\n  ]", files.join(",\n    "))
+            })
+            .collect::<Vec<_>>();
+        if groups.is_empty() {
+            println!("[]");
+        } else {
+            println!("[\n{}\n]", groups.join(",\n"));
+        }
+    } else {
+        for (n, group) in groups.iter().enumerate() {
+            if n > 0 {
+                println!();
+            }
+            println!("Group {} ({} files):", n + 1, group.len());
+            for &i in group {
+                let image = &images[i];
+                println!(
+                    "  {:>12} bytes  {:>11}  {}",
+                    image.bytes,
+                    format!("{}x{}", image.width, image.height),
+                    image.path.display()
+                );
+            }
+        }
+    }
+
+    if !delete_smaller {
+        return;
+    }
+    let doomed = dedupe_doomed(&images, &groups, threshold)
+        .into_iter()
+        .map(|i| &images[i].path)
+        .collect::<Vec<_>>();
+    if doomed.is_empty() {
+        eprintln!("Nothing to delete.");
+        return;
+    }
+    // Stderr, so --json output on stdout stays parseable.
+    for path in &doomed {
+        eprintln!("Would delete {}", path.display());
+    }
+    if dry_run {
+        return;
+    }
+    eprint!("Delete {} files? [y/N] ", doomed.len());
+    let mut answer = String::new();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .expect("Failed to read the answer.");
+    if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+        eprintln!("Nothing was deleted.");
+        return;
+    }
+    for path in doomed {
+        if let Err(err) = std::fs::remove_file(path) {
+            eprintln!("Warning: could not delete {}: {}", path.display(), err);
+        }
+    }
+}
+
+// The share of pixels, least saturated first, that are taken to be neutral.
+const COLOR_CAST_NEUTRAL_FRACTION: f32 = 0.2;
+
+// Things that should be gray, like walls, paper, clouds and asphalt, are the least saturated parts of
+// most photos, and under a cast they all lean the same way. So the cast is the average a*b* of the
+// least saturated fifth of the pixels, leaving out near-black and near-white ones, which are often
+// clipped to neutral whatever the cast. An image with nothing gray in it, like a close-up of a red
+// flower, reports the color of its dullest parts instead.
+fn color_cast(infile: String) {
+    println!("{}", color_cast_report(&infile));
+}
+
+// The line color-cast prints for `infile`.
+fn color_cast_report(infile: &str) -> String {
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    let mut candidates = img
+        .to_rgba32f()
+        .pixels()
+        .filter(|p| p[3] > 0.0)
+        .map(|p| colorspace::srgb_to_lab([p[0], p[1], p[2]]))
+        .filter(|lab| (10.0..=95.0).contains(&lab[0]))
+        .map(|lab| (lab[1].hypot(lab[2]), lab[1], lab[2]))
+        .collect::<Vec<_>>();
+    if candidates.is_empty() {
+        panic!("INFILE has no pixels that aren't transparent, near black or near white!");
+    }
+
+    let count = ((candidates.len() as f32 * COLOR_CAST_NEUTRAL_FRACTION).ceil() as usize).max(1);
+    candidates.select_nth_unstable_by(count - 1, |a, b| a.0.partial_cmp(&b.0).unwrap());
+    let (a_sum, b_sum) = candidates[..count]
+        .iter()
+        .fold((0.0f64, 0.0f64), |(a_sum, b_sum), &(_, a, b)| {
+            (a_sum + a as f64, b_sum + b as f64)
+        });
+    let a = (a_sum / count as f64) as f32;
+    let b = (b_sum / count as f64) as f32;
+    let strength = a.hypot(b);
+
+    // The hue angles of the sRGB primaries and secondaries in Lab.
+    let hues = [
+        ("red", 40.0),
+        ("yellow", 103.0),
+        ("green", 136.0),
+        ("cyan", 197.0),
+        ("blue", 306.0),
+        ("magenta", 328.0),
+    ];
+    let angle = b.atan2(a).to_degrees().rem_euclid(360.0);
+    let (hue, _) = hues
+        .iter()
+        .min_by(|(_, x), (_, y)| {
+            let distance = |h: f32| (angle - h).abs().min(360.0 - (angle - h).abs());
+            distance(*x).partial_cmp(&distance(*y)).unwrap()
+        })
+        .unwrap();
+
+    // A difference of about 2 in a*b* is the smallest most people notice side by side.
+    let verdict = match strength {
+        s if s < 2.0 => "no noticeable cast".to_string(),
+        s if s < 6.0 => format!("slight {} cast", hue),
+        s if s < 12.0 => format!("moderate {} cast", hue),
+        _ => format!("strong {} cast", hue),
+    };
+    format!(
+        "{}: {} (a* {:.1}, b* {:.1}, strength {:.1})",
+        infile, verdict, a, b, strength
+    )
+}
+
+// Smoothing: how far a pixel's neighbors reach, in pixels, and how different in color they can be
+// and still count, out of 255. Each pass widens the flat areas.
+const CARTOON_SMOOTH_RADIUS: i32 = 3;
+const CARTOON_SMOOTH_SIGMA: f32 = 2.0;
+const CARTOON_COLOR_SIGMA: f32 = 24.0;
+const CARTOON_SMOOTH_PASSES: u32 = 2;
+// Levels per channel after posterizing.
+const CARTOON_LEVELS: u32 = 6;
+// Edge strength, as in edge-sharpen, from which a pixel is drawn as outline. It's measured after a
+// slight blur, so fine texture that survived smoothing doesn't count.
+const CARTOON_EDGE_THRESHOLD: f32 = 20.0;
+const CARTOON_EDGE_BLUR: f32 = 1.0;
+
+// Bilateral smoothing flattens texture while keeping edges sharp, since neighbors across an edge are
+// too different in color to be averaged in. Posterizing then turns the smooth areas into bands of
+// flat color, and the outlines come from the edges of the smoothed image, so texture doesn't turn
+// into speckles of ink.
+fn cartoon(infile: String, outfile: String) {
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "cartoon");
+    let mut imgbuf = img.to_rgba8();
+    let (width, height) = imgbuf.dimensions();
+
+    let spatial = (-CARTOON_SMOOTH_RADIUS..=CARTOON_SMOOTH_RADIUS)
+        .flat_map(|dy| (-CARTOON_SMOOTH_RADIUS..=CARTOON_SMOOTH_RADIUS).map(move |dx| (dx, dy)))
+        .map(|(dx, dy)| {
+            let distance = (dx * dx + dy * dy) as f32;
+            (
+                dx,
+                dy,
+                (-distance / (2.0 * CARTOON_SMOOTH_SIGMA.powi(2))).exp(),
+            )
+        })
+        .collect::<Vec<_>>();
+    for _ in 0..CARTOON_SMOOTH_PASSES {
+        let source = imgbuf.clone();
+        for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+            let center = source.get_pixel(x, y);
+            let mut sums = [0.0f32; 3];
+            let mut total = 0.0;
+            for &(dx, dy, spatial_weight) in &spatial {
+                let nx = (x as i32 + dx).clamp(0, width as i32 - 1) as u32;
+                let ny = (y as i32 + dy).clamp(0, height as i32 - 1) as u32;
+                let neighbor = source.get_pixel(nx, ny);
+                let difference = (0..3)
+                    .map(|c| (neighbor[c] as f32 - center[c] as f32).powi(2))
+                    .sum::<f32>();
+                let weight =
+                    spatial_weight * (-difference / (2.0 * CARTOON_COLOR_SIGMA.powi(2))).exp();
+                for c in 0..3 {
+                    sums[c] += weight * neighbor[c] as f32;
+                }
+                total += weight;
+            }
+            for c in 0..3 {
+                pixel[c] = (sums[c] / total).round() as u8;
+            }
+        }
+    }
+
+    let edges = image::imageops::grayscale(&image::imageops::blur(&imgbuf, CARTOON_EDGE_BLUR));
+    let step = 255.0 / (CARTOON_LEVELS - 1) as f32;
+    for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+        let (gx, gy) = sobel_gradient(&edges, x, y);
+        for c in 0..3 {
+            pixel[c] = if (gx * gx + gy * gy).sqrt() / 4.0 >= CARTOON_EDGE_THRESHOLD {
+                0
+            } else {
+                ((pixel[c] as f32 / step).round() * step).round() as u8
+            };
+        }
+    }
+
+    save_image(&image::DynamicImage::ImageRgba8(imgbuf), &outfile);
+}
+
+// How many blurred copies the blur strength of each row is interpolated between.
+const TILT_SHIFT_LEVELS: usize = 6;
+
+// The blur grows linearly from nothing at the edges of the sharp band to BLUR at the top and bottom
+// of the image, so each side reaches full strength at its own edge. Blurring every row by its own
+// amount would take a separate pass per row, so instead each row blends the two nearest of a few
+// copies blurred by evenly spaced amounts, which leaves no visible steps.
+fn tilt_shift(
+    infile: String,
+    outfile: String,
+    focus_y: u32,
+    focus_height: u32,
+    blur: f32,
+    saturation: f32,
+) {
+    if blur < 0.0 {
+        panic!("{} is not a valid blur amount!", blur);
+    }
+    if saturation < 0.0 {
+        panic!("{} is not a valid saturation!", saturation);
+    }
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    let height = img.height();
+    if focus_y >= height {
+        panic!(
+            "{} is not a valid focus row, INFILE is {} pixels tall!",
+            focus_y, height
+        );
+    }
+
+    let sharp = img.to_rgba32f();
+    let levels = (0..=TILT_SHIFT_LEVELS)
+        .map(|level| match level {
+            0 => sharp.clone(),
+            _ => boxblur::gaussian_approx(&sharp, blur * level as f32 / TILT_SHIFT_LEVELS as f32),
+        })
+        .collect::<Vec<_>>();
+
+    let band_top = focus_y as f32 - focus_height as f32 / 2.0;
+    let band_bottom = focus_y as f32 + focus_height as f32 / 2.0;
+    let mut imgbuf = sharp;
+    for (y, row) in imgbuf.enumerate_rows_mut() {
+        let center = y as f32 + 0.5;
+        let strength = if center < band_top {
+            (band_top - center) / band_top
+        } else if center > band_bottom {
+            (center - band_bottom) / (height as f32 - band_bottom)
+        } else {
+            0.0
+        };
+        let position = strength.clamp(0.0, 1.0) * TILT_SHIFT_LEVELS as f32;
+        let lower = (position.floor() as usize).min(TILT_SHIFT_LEVELS - 1);
+        let t = position - lower as f32;
+        for (x, _, pixel) in row {
+            let a = levels[lower].get_pixel(x, y);
+            let b = levels[lower + 1].get_pixel(x, y);
+            for c in 0..4 {
+                pixel[c] = a[c] + (b[c] - a[c]) * t;
+            }
+            let gray = 0.2126 * pixel[0] + 0.7152 * pixel[1] + 0.0722 * pixel[2];
+            for c in 0..3 {
+                pixel[c] = (gray + (pixel[c] - gray) * saturation).clamp(0.0, 1.0);
+            }
+        }
+    }
+
+    save_image(&like_original(imgbuf, &img), &outfile);
+}
+
+// The size of the square the image is shrunk to before finding salient regions, and how much the
+// map is smoothed there. The method picks out objects at roughly this scale, whatever the size of
+// the input.
+const SALIENCY_SIZE: usize = 64;
+const SALIENCY_SIGMA: f32 = 2.5;
+
+// The spectral residual method (Hou and Zhang, "Saliency Detection: A Spectral Residual Approach",
+// 2007). The log amplitude spectra of natural images all look much alike, so what's left after
+// subtracting a smoothed copy of it is what's unusual about this image. Transforming that residual
+// back, with the original phases, lights up the regions that caused it.
+fn saliency(infile: String, outfile: String) {
+    use num_complex::Complex;
+
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    let n = SALIENCY_SIZE;
+    let small = image::imageops::resize(
+        &img.to_luma32f(),
+        n as u32,
+        n as u32,
+        image::imageops::FilterType::Triangle,
+    );
+
+    // A plain O(n^3) DFT over rows, then columns. At 64x64 that's quick enough not to need an FFT.
+    let dft = |data: &mut Vec<Complex<f64>>, inverse: bool| {
+        let sign = if inverse { 1.0 } else { -1.0 };
+        let twiddles = (0..n)
+            .map(|k| {
+                let angle = sign * 2.0 * std::f64::consts::PI * k as f64 / n as f64;
+                Complex::new(angle.cos(), angle.sin())
+            })
+            .collect::<Vec<_>>();
+        for (stride, step) in [(n, 1), (1, n)] {
+            for line in 0..n {
+                let input = (0..n)
+                    .map(|i| data[line * stride + i * step])
+                    .collect::<Vec<_>>();
+                for k in 0..n {
+                    data[line * stride + k * step] = input
+                        .iter()
+                        .enumerate()
+                        .map(|(i, &value)| value * twiddles[(i * k) % n])
+                        .sum();
+                }
+            }
+        }
+    };
+
+    let mut spectrum = small
+        .pixels()
+        .map(|p| Complex::new(p[0] as f64, 0.0))
+        .collect::<Vec<_>>();
+    dft(&mut spectrum, false);
+
+    // A tiny floor keeps the log finite for frequencies that are exactly zero.
+    let log_amplitude = spectrum
+        .iter()
+        .map(|c| (c.norm() + 1e-9).ln())
+        .collect::<Vec<_>>();
+    for v in 0..n {
+        for u in 0..n {
+            // The 3x3 average, wrapping around since the spectrum is periodic.
+            let mut average = 0.0;
+            for dv in [n - 1, 0, 1] {
+                for du in [n - 1, 0, 1] {
+                    average += log_amplitude[(v + dv) % n * n + (u + du) % n];
+                }
+            }
+            let residual = log_amplitude[v * n + u] - average / 9.0;
+            let phase = spectrum[v * n + u].arg();
+            spectrum[v * n + u] = Complex::new(phase.cos(), phase.sin()) * residual.exp();
+        }
+    }
+    dft(&mut spectrum, true);
+
+    // Scaled to 0..1 before blurring too, since `blur` clamps float pixels to that range.
+    let normalize = |map: &image::ImageBuffer<image::Luma<f32>, Vec<f32>>| {
+        let max = map.pixels().map(|p| p[0]).fold(0.0, f32::max);
+        image::ImageBuffer::from_fn(n as u32, n as u32, |x, y| {
+            image::Luma([if max > 0.0 {
+                map.get_pixel(x, y)[0] / max
+            } else {
+                0.0
+            }])
+        })
+    };
+    let map = normalize(&image::ImageBuffer::from_fn(n as u32, n as u32, |x, y| {
+        image::Luma([spectrum[y as usize * n + x as usize].norm_sqr() as f32])
+    }));
+    let map = normalize(&image::imageops::blur(&map, SALIENCY_SIGMA));
+    let map = image::ImageBuffer::from_fn(n as u32, n as u32, |x, y| {
+        image::Luma([(map.get_pixel(x, y)[0] * 255.0).round() as u8])
+    });
+    let map = image::imageops::resize(
+        &map,
+        img.width(),
+        img.height(),
+        image::imageops::FilterType::Triangle,
+    );
+    save_image(&image::DynamicImage::ImageLuma8(map), &outfile);
+}
+
+const ORGANIZE_PLACEHOLDERS: [&str; 8] =
+    ["year", "month", "day", "date", "time", "seq", "name", "ext"];
+
+// Images are handled oldest first, so {seq} follows the order they were taken in. A file whose
+// target already exists is skipped with a warning rather than overwritten, which also makes running
+// it again over the same photos harmless. Copies are byte for byte, EXIF included, unless PATTERN
+// names a different format.
+fn organize(indir: String, outdir: String, pattern: String, recursive: bool) {
+    let mut rest = pattern.as_str();
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => panic!("{} is not a valid pattern!", pattern),
+        };
+        let placeholder = &rest[start + 1..end];
+        if !ORGANIZE_PLACEHOLDERS.contains(&placeholder) {
+            panic!("{{{}}} is not a valid pattern placeholder!", placeholder);
+        }
+        rest = &rest[end + 1..];
+    }
+
+    let files = dated_image_files(&indir, recursive);
+
+    let mut per_day = std::collections::HashMap::new();
+    let mut written = std::collections::HashSet::new();
+    for (date, path) in files {
+        let relative = match &date {
+            // `YYYY:MM:DD HH:MM:SS`
+            Some(date) => {
+                let seq = per_day.entry(date[..10].to_string()).or_insert(0);
+                *seq += 1;
+                let name = path.file_stem().unwrap_or_default().to_string_lossy();
+                let ext = path.extension().unwrap_or_default().to_string_lossy();
+                let time = format!("{}{}{}", &date[11..13], &date[14..16], &date[17..19]);
+                let values = [
+                    &date[..4],
+                    &date[5..7],
+                    &date[8..10],
+                    &date[..10].replace(':', "-"),
+                    &time,
+                    &format!("{:03}", seq),
+                    &name,
+                    &ext.to_lowercase(),
+                ];
+                let mut relative = pattern.clone();
+                for (placeholder, value) in ORGANIZE_PLACEHOLDERS.iter().zip(values) {
+                    relative = relative.replace(&format!("{{{}}}", placeholder), value);
+                }
+                std::path::PathBuf::from(relative)
+            }
+            None => std::path::Path::new("undated").join(path.file_name().unwrap()),
+        };
+        let target = std::path::Path::new(&outdir).join(relative);
+
+        if target.exists() || !written.insert(target.clone()) {
+            eprintln!(
+                "Warning: skipping {}: {} already exists",
+                path.display(),
+                target.display()
+            );
+            continue;
+        }
+        let target_format = match image::ImageFormat::from_path(&target) {
+            Ok(format) => format,
+            Err(_) => {
+                eprintln!(
+                    "Warning: skipping {}: {} doesn't name an image format",
+                    path.display(),
+                    target.display()
+                );
+                continue;
+            }
+        };
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent).expect("Failed to create a folder in OUTDIR.");
+        }
+        let target_name = target.to_string_lossy();
+        if image::ImageFormat::from_path(&path).ok() == Some(target_format) {
+            write_atomically(&target_name, |partial| {
+                std::fs::copy(&path, partial)
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            });
+        } else {
+            match limits::open(&path) {
+                Ok(img) => save_image(&img, &target_name),
+                Err(err) => {
+                    eprintln!("Warning: skipping {}: {}", path.display(), err);
+                    continue;
+                }
+            }
+        }
+        println!("{} -> {}", path.display(), target.display());
+    }
+}
+
+// The 8x8 ordered-dither (Bayer) matrix: each of the 64 thresholds once, arranged so that the pixels
+// that are on at any level are spread as evenly as possible. Its top-left 2x2 and 4x4 corners, divided
+// by 16 and 4, are the smaller Bayer matrices.
+const BAYER_8X8: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+// The threshold, between 0.0 and 1.0, at (x, y) of the Bayer matrix that is `size` pixels wide
+// (2, 4 or 8), repeated across the image.
+fn bayer_threshold(x: u32, y: u32, size: u32) -> f32 {
+    let cells = size * size;
+    let rank = BAYER_8X8[(y % size) as usize][(x % size) as usize] as u32 / (64 / cells);
+    (rank as f32 + 0.5) / cells as f32
+}
+
+// A pixel is kept, fully opaque, when OPACITY times its own opacity is above the threshold for its
+// position in the Bayer matrix, and is otherwise made fully transparent. Colors are left alone.
+fn screen_door(infile: String, outfile: String, opacity: f32) {
+    if !(0.0..=1.0).contains(&opacity) {
+        panic!("{} is not a valid opacity!", opacity);
+    }
+    let imgbuf = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&imgbuf, "INFILE", "screen-door");
+    let mut imgbuf = imgbuf.to_rgba8();
+    for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+        let threshold = bayer_threshold(x, y, 8);
+        let coverage = opacity * pixel[3] as f32 / 255.0;
+        pixel[3] = if coverage > threshold { 255 } else { 0 };
+    }
+    save_image(&image::DynamicImage::ImageRgba8(imgbuf), &outfile);
+}
+
+// Each channel is rounded down or up to one of LEVELS evenly spaced values depending on whether the
+// part of the way between the two is above the threshold for the pixel's position in the Bayer
+// matrix. A flat area between two levels turns into a regular pattern of both, in proportion to how
+// close it is to each. Alpha is kept as it is.
+fn ordered_dither(infile: String, outfile: String, levels: u32, matrix_size: u32) {
+    if levels < 2 {
+        panic!("{} is not a valid number of levels!", levels);
+    }
+    if ![2, 4, 8].contains(&matrix_size) {
+        panic!("{} is not a valid matrix size!", matrix_size);
+    }
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "ordered-dither");
+    let mut imgbuf = img.to_rgba8();
+    let step = 255.0 / (levels - 1) as f32;
+    for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+        let threshold = bayer_threshold(x, y, matrix_size);
+        for c in 0..3 {
+            let position = pixel[c] as f32 / step;
+            let lower = position.floor();
+            let level = if position - lower > threshold {
+                lower + 1.0
+            } else {
+                lower
+            };
+            pixel[c] = (level * step).round() as u8;
+        }
+    }
+    save_image(&image::DynamicImage::ImageRgba8(imgbuf), &outfile);
+}
+
+// The share of cells that start out alive.
+const LIFE_DENSITY: f64 = 0.3;
+
+// One step of the Game of Life on a grid that wraps around at the edges: a live cell with two or
+// three live neighbors stays alive, and a dead cell with exactly three comes to life.
+fn life_step(cells: &[bool], width: usize, height: usize) -> Vec<bool> {
+    let mut next = vec![false; cells.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let mut neighbors = 0;
+            for dy in [height - 1, 0, 1] {
+                for dx in [width - 1, 0, 1] {
+                    if (dx, dy) != (0, 0) && cells[(y + dy) % height * width + (x + dx) % width] {
+                        neighbors += 1;
+                    }
+                }
+            }
+            let alive = cells[y * width + x];
+            next[y * width + x] = neighbors == 3 || (alive && neighbors == 2);
+        }
+    }
+    next
+}
+
+// The first frame is the random starting grid, followed by one frame per generation.
+fn game_of_life(
+    outfile: String,
+    width: u32,
+    height: u32,
+    generations: u32,
+    seed: u64,
+    delay_ms: u32,
+    cancel: cancel::Cancel,
+) {
+    use image::codecs::gif::{GifEncoder, Repeat};
+
+    if width == 0 || height == 0 {
+        panic!("The grid must be at least one cell wide and high!");
+    }
+    let mut rng = random::Rng::new(seed);
+    let mut cells = (0..width * height)
+        .map(|_| rng.next_f64() < LIFE_DENSITY)
+        .collect::<Vec<_>>();
+    let mut frames = Vec::with_capacity(generations as usize + 1);
+    for generation in 0..=generations {
+        if cancel.requested() {
+            cancel::exit(generation as usize, generations as usize + 1, "generations");
+        }
+        if generation > 0 {
+            cells = life_step(&cells, width as usize, height as usize);
+        }
+        let imgbuf = image::RgbaImage::from_fn(width, height, |x, y| {
+            if cells[(y * width + x) as usize] {
+                image::Rgba([255, 255, 255, 255])
+            } else {
+                image::Rgba([32, 32, 32, 255])
+            }
+        });
+        let delay = image::Delay::from_numer_denom_ms(delay_ms, 1);
+        frames.push(image::Frame::from_parts(imgbuf, 0, 0, delay));
+    }
+
+    write_atomically(&outfile, |path| {
+        let file = std::fs::File::create(path).map_err(|e| e.to_string())?;
+        let mut encoder = GifEncoder::new(std::io::BufWriter::new(file));
+        encoder
+            .set_repeat(Repeat::Infinite)
+            .map_err(|e| e.to_string())?;
+        encoder.encode_frames(frames).map_err(|e| e.to_string())
+    });
+}
+
+// How many blurred copies the blur strength of each pixel is interpolated between.
+const DEPTH_BLUR_LEVELS: usize = 8;
+
+// Like tilt-shift, but per pixel: the blur grows linearly from nothing at FOCUS_DEPTH to MAX_BLUR at
+// the nearest and the farthest depths, so each side reaches full strength at its own end, and each
+// pixel blends the two nearest of a few copies blurred by evenly spaced amounts.
+fn depth_blur(
+    infile: String,
+    depth_map: String,
+    outfile: String,
+    focus_depth: f32,
+    max_blur: f32,
+    far_is_bright: bool,
+) {
+    if !(0.0..=1.0).contains(&focus_depth) {
+        panic!("{} is not a valid focus depth!", focus_depth);
+    }
+    if max_blur < 0.0 {
+        panic!("{} is not a valid blur amount!", max_blur);
+    }
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    let depth_map = limits::open(depth_map).expect("Failed to open DEPTH_MAP.");
+    warn_if_downconverting(&depth_map, "DEPTH_MAP", "depth-blur");
+    let depth_map = depth_map.to_luma8();
+    let (width, height) = (img.width(), img.height());
+    let depth_map = if depth_map.dimensions() == (width, height) {
+        depth_map
+    } else {
+        image::imageops::resize(
+            &depth_map,
+            width,
+            height,
+            image::imageops::FilterType::Triangle,
+        )
+    };
+
+    let sharp = img.to_rgba32f();
+    let levels = (0..=DEPTH_BLUR_LEVELS)
+        .map(|level| match level {
+            0 => sharp.clone(),
+            _ => {
+                boxblur::gaussian_approx(&sharp, max_blur * level as f32 / DEPTH_BLUR_LEVELS as f32)
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut imgbuf = sharp;
+    for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+        let value = depth_map.get_pixel(x, y)[0] as f32 / 255.0;
+        let depth = if far_is_bright { value } else { 1.0 - value };
+        let strength = if depth < focus_depth {
+            (focus_depth - depth) / focus_depth
+        } else if depth > focus_depth {
+            (depth - focus_depth) / (1.0 - focus_depth)
+        } else {
+            0.0
+        };
+        let position = strength.clamp(0.0, 1.0) * DEPTH_BLUR_LEVELS as f32;
+        let lower = (position.floor() as usize).min(DEPTH_BLUR_LEVELS - 1);
+        let t = position - lower as f32;
+        let a = levels[lower].get_pixel(x, y);
+        let b = levels[lower + 1].get_pixel(x, y);
+        for c in 0..4 {
+            pixel[c] = a[c] + (b[c] - a[c]) * t;
+        }
+    }
+
+    save_image(&like_original(imgbuf, &img), &outfile);
+}
+
+// Time runs left to right and frequency bottom to top, so the matrix is turned a quarter turn
+// counterclockwise before it is drawn.
+fn spectrum(infile: String, outfile: String, palette: colormap::Colormap) {
+    let frames = read_matrix(&infile);
+    let bins = frames[0].len();
+    let rows = (0..bins)
+        .rev()
+        .map(|bin| frames.iter().map(|frame| frame[bin]).collect())
+        .collect::<Vec<_>>();
+    let imgbuf = render_matrix(&rows, palette);
+    save_image(&image::DynamicImage::ImageRgb8(imgbuf), &outfile);
+}
+
+// Only pixels within this many pixels of an edge at least this strong, measured like edge-sharpen's
+// threshold, can be fringe.
+const DEFRINGE_RADIUS: i64 = 2;
+const DEFRINGE_EDGE_THRESHOLD: f32 = 48.0;
+// The hues, in degrees, that count as purple and as green fringe.
+const DEFRINGE_PURPLE_HUES: std::ops::RangeInclusive<f32> = 250.0..=340.0;
+const DEFRINGE_GREEN_HUES: std::ops::RangeInclusive<f32> = 75.0..=165.0;
+
+// A fringe pixel is one next to a strong edge in brightness whose hue is purple or green. Each is
+// moved towards its own luma by AMOUNT, so the brightness on either side of the edge, and so the
+// edge itself, is kept. Alpha is kept as it is.
+fn defringe_correct(infile: String, outfile: String, amount: f32) {
+    if !(0.0..=1.0).contains(&amount) {
+        panic!("{} is not a valid amount!", amount);
+    }
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "defringe-correct");
+    let mut imgbuf = img.to_rgba8();
+    let (width, height) = imgbuf.dimensions();
+    let luma = image::DynamicImage::ImageRgba8(imgbuf.clone()).to_luma8();
+    let strong = image::GrayImage::from_fn(width, height, |x, y| {
+        let (gx, gy) = sobel_gradient(&luma, x, y);
+        image::Luma([((gx * gx + gy * gy).sqrt() / 4.0 >= DEFRINGE_EDGE_THRESHOLD) as u8])
+    });
+
+    for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+        let near_edge = (-DEFRINGE_RADIUS..=DEFRINGE_RADIUS).any(|dy| {
+            (-DEFRINGE_RADIUS..=DEFRINGE_RADIUS).any(|dx| {
+                let nx = (x as i64 + dx).clamp(0, width as i64 - 1) as u32;
+                let ny = (y as i64 + dy).clamp(0, height as i64 - 1) as u32;
+                strong.get_pixel(nx, ny)[0] == 1
+            })
+        });
+        if !near_edge {
+            continue;
+        }
+        let [r, g, b] = [0, 1, 2].map(|c| pixel[c] as f32 / 255.0);
+        let max = r.max(g).max(b);
+        let chroma = max - r.min(g).min(b);
+        if chroma == 0.0 {
+            continue;
+        }
+        let hue = if max == r {
+            60.0 * ((g - b) / chroma).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / chroma + 2.0)
+        } else {
+            60.0 * ((r - g) / chroma + 4.0)
+        };
+        if !DEFRINGE_PURPLE_HUES.contains(&hue) && !DEFRINGE_GREEN_HUES.contains(&hue) {
+            continue;
+        }
+        let gray = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        for (c, &value) in [r, g, b].iter().enumerate() {
+            pixel[c] = ((value + (gray - value) * amount) * 255.0)
+                .round()
+                .clamp(0.0, 255.0) as u8;
+        }
+    }
+
+    save_image(&image::DynamicImage::ImageRgba8(imgbuf), &outfile);
+}
+
+// The document is taken to be the largest connected area brighter than Otsu's level, so it should
+// be lighter than what it lies on. Its corners are the points of that area furthest towards the
+// top left, top right, bottom right and bottom left, which holds as long as it is turned less than
+// 45 degrees.
+fn scan_crop(infile: String, outfile: String) {
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "scan-crop");
+    let luma = img.to_luma8();
+    let (width, height) = luma.dimensions();
+    let level = otsu_level(&luma_histogram(&luma));
+    let bright = luma.pixels().map(|p| p[0] > level).collect::<Vec<_>>();
+
+    let mut seen = vec![false; bright.len()];
+    let mut largest = Vec::new();
+    for start in 0..bright.len() {
+        if !bright[start] || seen[start] {
+            continue;
+        }
+        let mut area = Vec::new();
+        let mut stack = vec![start];
+        seen[start] = true;
+        while let Some(index) = stack.pop() {
+            area.push(index);
+            let (x, y) = (index as u32 % width, index as u32 / width);
+            let neighbors = [
+                (x > 0).then(|| index - 1),
+                (x + 1 < width).then(|| index + 1),
+                (y > 0).then(|| index - width as usize),
+                (y + 1 < height).then(|| index + width as usize),
+            ];
+            for &neighbor in neighbors.iter().flatten() {
+                if bright[neighbor] && !seen[neighbor] {
+                    seen[neighbor] = true;
+                    stack.push(neighbor);
+                }
+            }
+        }
+        if area.len() > largest.len() {
+            largest = area;
+        }
+    }
+    if largest.is_empty() {
+        panic!("Failed to find a document in INFILE!");
+    }
+
+    // Each pixel's outer corner in the direction being searched, so that an upright document is
+    // cropped to exactly its own pixels.
+    let points = largest
+        .iter()
+        .map(|&index| ((index as u32 % width) as f64, (index as u32 / width) as f64));
+    let extreme = |score: fn(f64, f64) -> f64, (dx, dy): (f64, f64)| {
+        let (x, y) = points
+            .clone()
+            .max_by(|a, b| score(a.0, a.1).total_cmp(&score(b.0, b.1)))
+            .unwrap();
+        (x + dx, y + dy)
+    };
+    let corners = [
+        extreme(|x, y| -x - y, (0.0, 0.0)),
+        extreme(|x, y| x - y, (1.0, 0.0)),
+        extreme(|x, y| x + y, (1.0, 1.0)),
+        extreme(|x, y| y - x, (0.0, 1.0)),
+    ];
+    let (out_width, out_height) = quad_size(corners);
+    if out_width == 0 || out_height == 0 {
+        panic!("Failed to find a document in INFILE!");
+    }
+    let imgbuf = warp_perspective(&img.to_rgba8(), corners, out_width, out_height)
+        .unwrap_or_else(|| panic!("Failed to find a document in INFILE!"));
+
+    save_image(&image::DynamicImage::ImageRgba8(imgbuf), &outfile);
+}
+
+// The size of the rectangle a quadrilateral given clockwise from its top left corner straightens
+// out to: as wide and tall as the longer of each pair of opposite sides.
+fn quad_size(corners: [(f64, f64); 4]) -> (u32, u32) {
+    let distance = |a: (f64, f64), b: (f64, f64)| (a.0 - b.0).hypot(a.1 - b.1);
+    let width = distance(corners[0], corners[1]).max(distance(corners[3], corners[2]));
+    let height = distance(corners[0], corners[3]).max(distance(corners[1], corners[2]));
+    (width.round() as u32, height.round() as u32)
+}
+
+// Map the quadrilateral `corners` of `source`, given clockwise from the top left in pixel edge
+// coordinates, onto a `width` x `height` image. Each output pixel is sampled bilinearly at the
+// point its center maps to. Returns None unless the corners make a convex quadrilateral, since any
+// other shape would fold the image over itself.
+fn warp_perspective(
+    source: &image::RgbaImage,
+    corners: [(f64, f64); 4],
+    width: u32,
+    height: u32,
+) -> Option<image::RgbaImage> {
+    let turns = (0..4).map(|i| {
+        let (a, b, c) = (corners[i], corners[(i + 1) % 4], corners[(i + 2) % 4]);
+        (b.0 - a.0) * (c.1 - b.1) - (b.1 - a.1) * (c.0 - b.0)
+    });
+    if !turns.clone().all(|turn| turn > 0.0) && !turns.clone().all(|turn| turn < 0.0) {
+        return None;
+    }
+    let to_source = homography(
+        [
+            (0.0, 0.0),
+            (width as f64, 0.0),
+            (width as f64, height as f64),
+            (0.0, height as f64),
+        ],
+        corners,
+    )?;
+    Some(image::RgbaImage::from_fn(width, height, |x, y| {
+        let (u, v) = (x as f64 + 0.5, y as f64 + 0.5);
+        let [a, b, c, d, e, f, g, h] = to_source;
+        let w = g * u + h * v + 1.0;
+        let sx = (a * u + b * v + c) / w;
+        let sy = (d * u + e * v + f) / w;
+        sample_bilinear(source, sx as f32 - 0.5, sy as f32 - 0.5)
+    }))
+}
+
+// The perspective transform taking each of `from` to the matching point of `to`, as the first eight
+// entries of its 3x3 matrix (the ninth is 1), or None if three of the points are on one line.
+fn homography(from: [(f64, f64); 4], to: [(f64, f64); 4]) -> Option<[f64; 8]> {
+    let mut rows = Vec::with_capacity(8);
+    for ((x, y), (u, v)) in from.iter().copied().zip(to.iter().copied()) {
+        rows.push([x, y, 1.0, 0.0, 0.0, 0.0, -u * x, -u * y, u]);
+        rows.push([0.0, 0.0, 0.0, x, y, 1.0, -v * x, -v * y, v]);
+    }
+    // Gaussian elimination with partial pivoting.
+    for column in 0..8 {
+        let pivot =
+            (column..8).max_by(|&a, &b| rows[a][column].abs().total_cmp(&rows[b][column].abs()))?;
+        if rows[pivot][column].abs() < 1e-12 {
+            return None;
+        }
+        rows.swap(column, pivot);
+        let pivot_row = rows[column];
+        for (index, row) in rows.iter_mut().enumerate() {
+            if index != column {
+                let factor = row[column] / pivot_row[column];
+                for (value, pivot_value) in row.iter_mut().zip(&pivot_row).skip(column) {
+                    *value -= factor * pivot_value;
+                }
+            }
+        }
+    }
+    let mut solution = [0.0; 8];
+    for (i, value) in solution.iter_mut().enumerate() {
+        *value = rows[i][8] / rows[i][i];
+    }
+    Some(solution)
+}
+
+fn perspective(
+    infile: String,
+    outfile: String,
+    corners: Vec<String>,
+    out_width: Option<u32>,
+    out_height: Option<u32>,
+) {
+    let points = corners
+        .iter()
+        .map(|corner| {
+            let parsed = corner
+                .split_once(',')
+                .and_then(|(x, y)| Some((x.trim().parse().ok()?, y.trim().parse().ok()?)))
+                .filter(|(x, y): &(f64, f64)| x.is_finite() && y.is_finite());
+            parsed.unwrap_or_else(|| panic!("{} is not a valid X,Y point!", corner))
+        })
+        .collect::<Vec<_>>();
+    let corners = [points[0], points[1], points[2], points[3]];
+    let (default_width, default_height) = quad_size(corners);
+    let width = out_width.unwrap_or(default_width);
+    let height = out_height.unwrap_or(default_height);
+    if width == 0 || height == 0 {
+        panic!("{}x{} is not a valid size!", width, height);
+    }
+
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "perspective");
+    let imgbuf = warp_perspective(&img.to_rgba8(), corners, width, height)
+        .unwrap_or_else(|| panic!("The corners don't make a convex quadrilateral!"));
+    save_image(&image::DynamicImage::ImageRgba8(imgbuf), &outfile);
+}
+
+// The maze is carved by a randomized depth-first search (recursive backtracking, with an explicit
+// stack): from the current cell, knock down the wall to a random unvisited neighbor and move there,
+// backing up when there is none. Every cell is reached exactly once, so the passages form a
+// spanning tree. It is drawn on a grid of blocks `cell_size` pixels wide, with cells on the odd rows
+// and columns and walls between them.
+fn maze(outfile: String, width: u32, height: u32, cell_size: u32, seed: u64) {
+    if width == 0 || height == 0 || cell_size == 0 {
+        panic!(
+            "{}x{} cells of {} pixels is not a valid maze!",
+            width, height, cell_size
+        );
+    }
+    let blocks_wide = 2 * width + 1;
+    let blocks_high = 2 * height + 1;
+    let mut open = vec![false; (blocks_wide * blocks_high) as usize];
+    let block = |x: u32, y: u32| (y * blocks_wide + x) as usize;
+
+    let mut rng = random::Rng::new(seed);
+    let mut visited = vec![false; (width * height) as usize];
+    let mut stack = vec![(0u32, 0u32)];
+    visited[0] = true;
+    open[block(1, 1)] = true;
+    while let Some(&(x, y)) = stack.last() {
+        let neighbors = [(0, -1), (1, 0), (0, 1), (-1, 0)]
+            .iter()
+            .map(|&(dx, dy)| (x as i64 + dx, y as i64 + dy))
+            .filter(|&(nx, ny)| nx >= 0 && ny >= 0 && nx < width as i64 && ny < height as i64)
+            .map(|(nx, ny)| (nx as u32, ny as u32))
+            .filter(|&(nx, ny)| !visited[(ny * width + nx) as usize])
+            .collect::<Vec<_>>();
+        if neighbors.is_empty() {
+            stack.pop();
+            continue;
+        }
+        let (nx, ny) = neighbors[rng.below(neighbors.len() as u64) as usize];
+        visited[(ny * width + nx) as usize] = true;
+        open[block(x + nx + 1, y + ny + 1)] = true;
+        open[block(2 * nx + 1, 2 * ny + 1)] = true;
+        stack.push((nx, ny));
+    }
+    open[block(1, 0)] = true;
+    open[block(blocks_wide - 2, blocks_high - 1)] = true;
+
+    let imgbuf =
+        image::GrayImage::from_fn(blocks_wide * cell_size, blocks_high * cell_size, |x, y| {
+            if open[block(x / cell_size, y / cell_size)] {
+                image::Luma([255])
+            } else {
+                image::Luma([0])
+            }
+        });
+    save_image(&image::DynamicImage::ImageLuma8(imgbuf), &outfile);
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+enum LocalStat {
+    Mean,
+    /// Scaled so that the largest possible variance, half black and half white, is white
+    Variance,
+    /// Scaled so that the largest possible deviation, half black and half white, is white
+    StdDev,
+}
+
+// Each statistic is computed from a summed-area table, so the window size doesn't affect the running
+// time. The largest possible standard deviation of values from 0 to 255 is 127.5.
+fn local_stats(infile: String, outfile: String, window: u32, stat: LocalStat) {
+    if window == 0 {
+        panic!("{} is not a valid window size!", window);
+    }
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "local-stats");
+    let img = img.to_luma8();
+    let integral = integral::Integral::new(&img);
+    let imgbuf = image::GrayImage::from_fn(img.width(), img.height(), |x, y| {
+        let (mean, variance) = integral.window(x, y, window);
+        let value = match stat {
+            LocalStat::Mean => mean,
+            LocalStat::Variance => variance / (127.5 * 127.5) * 255.0,
+            LocalStat::StdDev => variance.sqrt() / 127.5 * 255.0,
+        };
+        image::Luma([value.round().clamp(0.0, 255.0) as u8])
+    });
+    save_image(&image::DynamicImage::ImageLuma8(imgbuf), &outfile);
+}
+
+// A pixel moved when any of its color channels differs by more than THRESHOLD. Cleanup is a
+// morphological opening (erode, then dilate) followed by a closing (dilate, then erode) with a square
+// of CLEANUP pixels each way from the center: the opening drops areas of motion too small to hold
+// the square, and the closing fills gaps in the rest that are too narrow for it.
+fn motion_mask(frame_a: String, frame_b: String, outfile: String, threshold: u8, cleanup: u32) {
+    let a = limits::open(&frame_a).expect("Failed to open FRAME_A.");
+    warn_if_downconverting(&a, "FRAME_A", "motion-mask");
+    let a = a.to_rgb8();
+    let b = limits::open(&frame_b).expect("Failed to open FRAME_B.");
+    warn_if_downconverting(&b, "FRAME_B", "motion-mask");
+    let b = b.to_rgb8();
+    if a.dimensions() != b.dimensions() {
+        panic!("{} is not the same size as {}!", frame_b, frame_a);
+    }
+    let (width, height) = a.dimensions();
+    let mut mask = image::GrayImage::from_fn(width, height, |x, y| {
+        let (pa, pb) = (a.get_pixel(x, y), b.get_pixel(x, y));
+        let moved = (0..3).any(|c| pa[c].abs_diff(pb[c]) > threshold);
+        image::Luma([if moved { 255 } else { 0 }])
+    });
+    if cleanup > 0 {
+        for dilating in [false, true, true, false] {
+            mask = morph(&mask, cleanup, dilating);
+        }
+    }
+
+    let moved = mask.pixels().filter(|p| p[0] == 255).count();
+    println!(
+        "Motion: {:.2}% of the frame",
+        moved as f64 * 100.0 / (width as f64 * height as f64)
+    );
+    save_image(&image::DynamicImage::ImageLuma8(mask), &outfile);
+}
+
+// Erode or dilate a black and white mask with a square reaching `radius` pixels each way from the
+// center: a pixel becomes the minimum (eroding) or maximum (dilating) of the square around it. The
+// square is separable, so it's done as a pass along the rows and one along the columns. Pixels
+// outside the mask don't count.
+fn morph(mask: &image::GrayImage, radius: u32, dilating: bool) -> image::GrayImage {
+    let (width, height) = mask.dimensions();
+    let pick = |values: &mut dyn Iterator<Item = u8>| {
+        if dilating {
+            values.max().unwrap()
+        } else {
+            values.min().unwrap()
+        }
+    };
+    let rows = image::GrayImage::from_fn(width, height, |x, y| {
+        let x0 = x.saturating_sub(radius);
+        let x1 = (x + radius).min(width - 1);
+        image::Luma([pick(&mut (x0..=x1).map(|nx| mask.get_pixel(nx, y)[0]))])
+    });
+    image::GrayImage::from_fn(width, height, |x, y| {
+        let y0 = y.saturating_sub(radius);
+        let y1 = (y + radius).min(height - 1);
+        image::Luma([pick(&mut (y0..=y1).map(|ny| rows.get_pixel(x, ny)[0]))])
+    })
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+enum TestPatternType {
+    /// Black on the left to white on the right, in even steps
+    GrayRamp,
+    /// SMPTE-style color bars
+    ColorBars,
+    /// An 8 by 8 board of black and white squares
+    Checkerboard,
+    /// A grid of white lines on black, 8 cells across, with a line along every edge
+    Crosshatch,
+}
+
+// The 75% bars of the SMPTE pattern from left to right: gray, yellow, cyan, green, magenta, red and
+// blue. The strip below them repeats the blue, magenta, cyan and gray bars under their counterparts
+// with black in between, and the bottom row holds the -I, white and +Q patches.
+const SMPTE_BARS: [[u8; 3]; 7] = [
+    [191, 191, 191],
+    [191, 191, 0],
+    [0, 191, 191],
+    [0, 191, 0],
+    [191, 0, 191],
+    [191, 0, 0],
+    [0, 0, 191],
+];
+const SMPTE_BOTTOM: [[u8; 3]; 4] = [[0, 33, 76], [255, 255, 255], [50, 0, 106], [0, 0, 0]];
+
+// Checkerboard squares and crosshatch cells are square, sized so that 8 fit across the shorter side.
+fn test_pattern(outfile: String, width: u32, height: u32, pattern: TestPatternType) {
+    if width == 0 || height == 0 {
+        panic!("{}x{} is not a valid size!", width, height);
+    }
+    let cell = (width.min(height) / 8).max(1);
+    let imgbuf = image::RgbImage::from_fn(width, height, |x, y| {
+        let color = match pattern {
+            TestPatternType::GrayRamp => {
+                let value = if width > 1 {
+                    (x as f32 * 255.0 / (width - 1) as f32).round() as u8
+                } else {
+                    0
+                };
+                [value; 3]
+            }
+            TestPatternType::ColorBars => {
+                let bar = (x * 7 / width) as usize;
+                if y < height * 67 / 100 {
+                    SMPTE_BARS[bar]
+                } else if y < height * 75 / 100 {
+                    if bar.is_multiple_of(2) {
+                        SMPTE_BARS[6 - bar]
+                    } else {
+                        [0, 0, 0]
+                    }
+                } else {
+                    // The first three patches are as wide as five fourths of a bar.
+                    SMPTE_BOTTOM[((x * 28 / width) / 5).min(3) as usize]
+                }
+            }
+            TestPatternType::Checkerboard => {
+                if (x / cell + y / cell).is_multiple_of(2) {
+                    [255; 3]
+                } else {
+                    [0; 3]
+                }
+            }
+            TestPatternType::Crosshatch => {
+                if x % cell == 0 || y % cell == 0 || x == width - 1 || y == height - 1 {
+                    [255; 3]
+                } else {
+                    [0; 3]
+                }
+            }
+        };
+        image::Rgb(color)
+    });
+    save_image(&image::DynamicImage::ImageRgb8(imgbuf), &outfile);
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+enum ColorFamily {
+    Reds,
+    Yellows,
+    Greens,
+    Cyans,
+    Blues,
+    Magentas,
+    Whites,
+    Neutrals,
+    Blacks,
+}
+
+impl ColorFamily {
+    // How much a color, with channels from 0.0 to 1.0, belongs to the family, from 0.0 to 1.0. A
+    // primary (red, green, blue) counts by how far its channel stands above the middle one, and a
+    // secondary (cyan, magenta, yellow) by how far the middle one stands above the lowest, so pure
+    // colors count fully and grays not at all.
+    fn membership(self, [r, g, b]: [f32; 3]) -> f32 {
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let mid = r + g + b - max - min;
+        match self {
+            ColorFamily::Reds if r == max => max - mid,
+            ColorFamily::Greens if g == max => max - mid,
+            ColorFamily::Blues if b == max => max - mid,
+            ColorFamily::Cyans if r == min => mid - min,
+            ColorFamily::Magentas if g == min => mid - min,
+            ColorFamily::Yellows if b == min => mid - min,
+            ColorFamily::Whites => ((min - 0.5) * 2.0).max(0.0),
+            ColorFamily::Blacks => ((0.5 - max) * 2.0).max(0.0),
+            ColorFamily::Neutrals => (1.0 - (max - 0.5).abs() - (min - 0.5).abs()).max(0.0),
+            _ => 0.0,
+        }
+    }
+}
+
+// Cyan, magenta and yellow ink are the absence of red, green and blue. Each is changed by its
+// percentage plus BLACK's, both scaled by how much the pixel belongs to TARGET, so pixels outside
+// the family are untouched and colors at its edge change only a little. Alpha is kept as it is.
+fn selective_color(
+    infile: String,
+    outfile: String,
+    target: ColorFamily,
+    inks: [f32; 3],
+    black: f32,
+) {
+    for amount in inks.iter().chain(Some(&black)) {
+        if !(-100.0..=100.0).contains(amount) {
+            panic!("{} is not a valid percentage!", amount);
+        }
+    }
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    let mut imgbuf = img.to_rgba32f();
+    for pixel in imgbuf.pixels_mut() {
+        let color = [pixel[0], pixel[1], pixel[2]];
+        let weight = target.membership(color);
+        if weight == 0.0 {
+            continue;
+        }
+        for c in 0..3 {
+            let ink = (1.0 - color[c]) + weight * (inks[c] + black) / 100.0;
+            pixel[c] = 1.0 - ink.clamp(0.0, 1.0);
+        }
+    }
+    save_image(&like_original(imgbuf, &img), &outfile);
+}
+
+// Non-local means (Buades, Coll and Morel, 2005): every pixel becomes the average of the pixels in
+// its search window, each weighted by exp(-d / STRENGTH^2), where d is the mean squared difference
+// between the patches around the two. The pixel itself gets the largest weight any other pixel got,
+// as its own patch would otherwise always win.
+//
+// Comparing whole patches for every pair would cost PATCH_SIZE^2 per pair, so the work is turned
+// around (Darbon et al., 2008): for each offset in the search window, the squared differences
+// between the image and the image shifted by that offset go into a summed-area table, from which
+// every pixel's patch distance at that offset takes four lookups. Rayon shares the offsets out
+// among all cores, each job keeping its own running sums, which are added up at the end. Pixels past
+// the edges repeat the nearest edge pixel. Alpha is kept as it is.
+fn nl_means(
+    infile: String,
+    outfile: String,
+    strength: f32,
+    patch_size: u32,
+    window: u32,
+    cancel: cancel::Cancel,
+) {
+    use rayon::prelude::*;
+
+    if strength <= 0.0 {
+        panic!("{} is not a valid strength!", strength);
+    }
+    if patch_size.is_multiple_of(2) {
+        panic!("{} is not a valid patch size, it must be odd!", patch_size);
+    }
+    if window.is_multiple_of(2) {
+        panic!("{} is not a valid window, it must be odd!", window);
+    }
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    let mut imgbuf = img.to_rgba32f();
+    let (width, height) = (imgbuf.width() as usize, imgbuf.height() as usize);
+    let patch_radius = (patch_size / 2) as usize;
+    let window_radius = (window / 2) as i64;
+
+    // The image with a margin of `margin` repeated edge pixels all around, scaled to 0 to 255.
+    let margin = patch_radius + window_radius as usize;
+    let padded_width = width + 2 * margin;
+    let padded_height = height + 2 * margin;
+    let padded = (0..padded_height)
+        .flat_map(|py| (0..padded_width).map(move |px| (px, py)))
+        .map(|(px, py)| {
+            let x = (px as i64 - margin as i64).clamp(0, width as i64 - 1) as u32;
+            let y = (py as i64 - margin as i64).clamp(0, height as i64 - 1) as u32;
+            let pixel = imgbuf.get_pixel(x, y);
+            [pixel[0] * 255.0, pixel[1] * 255.0, pixel[2] * 255.0]
+        })
+        .collect::<Vec<_>>();
+
+    let offsets = (-window_radius..=window_radius)
+        .flat_map(|dy| (-window_radius..=window_radius).map(move |dx| (dx, dy)))
+        .filter(|&offset| offset != (0, 0))
+        .collect::<Vec<_>>();
+    // The patch distances are needed for the pixels of the image, so the differences are needed
+    // over the image plus a patch radius all around.
+    let area_width = width + 2 * patch_radius;
+    let area_height = height + 2 * patch_radius;
+    let area_origin = margin - patch_radius;
+    let patch_pixels = (patch_size * patch_size * 3) as f64;
+    let h2 = (strength * strength) as f64;
+
+    let stride = area_width + 1;
+    let total = offsets
+        .par_iter()
+        .fold(
+            || {
+                let table = vec![0.0f64; stride * (area_height + 1)];
+                (NlMeansSums::new(width * height), table)
+            },
+            |(mut partial, mut table), &(dx, dy)| {
+                if cancel.requested() {
+                    return (partial, table);
+                }
+                for ay in 0..area_height {
+                    let mut row_sum = 0.0;
+                    for ax in 0..area_width {
+                        let (px, py) = (area_origin + ax, area_origin + ay);
+                        let a = padded[py * padded_width + px];
+                        let b = padded
+                            [(py as i64 + dy) as usize * padded_width + (px as i64 + dx) as usize];
+                        row_sum += (0..3).map(|c| ((a[c] - b[c]) as f64).powi(2)).sum::<f64>();
+                        table[(ay + 1) * stride + ax + 1] = table[ay * stride + ax + 1] + row_sum;
+                    }
+                }
+                for y in 0..height {
+                    for x in 0..width {
+                        let (x1, y1) = (x + 2 * patch_radius + 1, y + 2 * patch_radius + 1);
+                        let distance = (table[y1 * stride + x1] + table[y * stride + x]
+                            - table[y * stride + x1]
+                            - table[y1 * stride + x])
+                            / patch_pixels;
+                        let weight = (-distance.max(0.0) / h2).exp();
+                        let neighbor = padded[(y as i64 + margin as i64 + dy) as usize
+                            * padded_width
+                            + (x as i64 + margin as i64 + dx) as usize];
+                        let index = y * width + x;
+                        for (sum, value) in partial.sums[index].iter_mut().zip(neighbor) {
+                            *sum += weight * value as f64;
+                        }
+                        partial.weights[index] += weight;
+                        if weight > partial.max_weights[index] {
+                            partial.max_weights[index] = weight;
+                        }
+                    }
+                }
+                partial.offsets += 1;
+                (partial, table)
+            },
+        )
+        .map(|(partial, _)| partial)
+        .reduce(|| NlMeansSums::new(width * height), NlMeansSums::add);
+    if cancel.requested() {
+        cancel::exit(total.offsets, offsets.len(), "offsets");
+    }
+
+    for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+        let index = y as usize * width + x as usize;
+        let own_weight = total.max_weights[index];
+        // A pixel with no look-alikes at all keeps its own value.
+        if own_weight == 0.0 {
+            continue;
+        }
+        for c in 0..3 {
+            let value = (total.sums[index][c] / 255.0 + own_weight * pixel[c] as f64)
+                / (total.weights[index] + own_weight);
+            pixel[c] = value as f32;
+        }
+    }
+    save_image(&like_original(imgbuf, &img), &outfile);
+}
+
+// The running sums of `nl_means` for each pixel, over the offsets one rayon job has handled.
+struct NlMeansSums {
+    sums: Vec<[f64; 3]>,
+    weights: Vec<f64>,
+    max_weights: Vec<f64>,
+    offsets: usize,
+}
+
+impl NlMeansSums {
+    fn new(pixels: usize) -> Self {
+        NlMeansSums {
+            sums: vec![[0.0; 3]; pixels],
+            weights: vec![0.0; pixels],
+            max_weights: vec![0.0; pixels],
+            offsets: 0,
+        }
+    }
+
+    fn add(mut self, other: Self) -> Self {
+        for (sum, other) in self.sums.iter_mut().zip(&other.sums) {
+            for c in 0..3 {
+                sum[c] += other[c];
+            }
+        }
+        for (weight, other) in self.weights.iter_mut().zip(&other.weights) {
+            *weight += other;
+        }
+        for (max_weight, other) in self.max_weights.iter_mut().zip(&other.max_weights) {
+            *max_weight = max_weight.max(*other);
+        }
+        self.offsets += other.offsets;
+        self
+    }
+}
+
+// How many colors the image is reduced to before picking the two most used. Photos have so many
+// shades that their single most used exact color means little; a few median cut boxes gather the
+// shades of each area together.
+const AUTO_BACKGROUND_COLORS: usize = 8;
+
+// The gradient runs from the most used color at the top left corner to the second at the bottom
+// right. An image of a single color gets a flat background of it.
+fn auto_background(infile: String, outfile: String, width: u32, height: u32) {
+    if width == 0 || height == 0 {
+        panic!("{}x{} is not a valid size!", width, height);
+    }
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "auto-background");
+    let img = img.to_rgba8();
+    let distinct = palette::distinct_colors(&img);
+    if distinct.is_empty() {
+        panic!("INFILE is fully transparent!");
+    }
+    let mut colors = palette::median_cut(&distinct, AUTO_BACKGROUND_COLORS);
+    colors.sort_by_key(|e| (std::cmp::Reverse(e.count), palette::luma(e.color), e.color));
+    let start = colors[0].color;
+    let end = colors.get(1).map_or(start, |e| e.color);
+    println!(
+        "Colors: {} {}",
+        palette::to_hex(start),
+        palette::to_hex(end)
+    );
+
+    let imgbuf = image::RgbImage::from_fn(width, height, |x, y| {
+        let t =
+            (x as f32 / (width - 1).max(1) as f32 + y as f32 / (height - 1).max(1) as f32) / 2.0;
+        image::Rgb(
+            [0, 1, 2]
+                .map(|c| (start[c] as f32 + (end[c] as f32 - start[c] as f32) * t).round() as u8),
+        )
+    });
+    save_image(&image::DynamicImage::ImageRgb8(imgbuf), &outfile);
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+enum BadgeKind {
+    /// A rating: VALUE filled stars out of five
+    Stars,
+    /// A progress bar VALUE percent full
+    Bar,
+    /// VALUE as text
+    Text,
+}
+
+const BADGE_STARS: u32 = 5;
+const BADGE_FILLED: image::Rgb<u8> = image::Rgb([255, 200, 40]);
+const BADGE_EMPTY: image::Rgb<u8> = image::Rgb([90, 90, 90]);
+const BADGE_BAR: image::Rgb<u8> = image::Rgb([80, 200, 120]);
+const BADGE_TEXT: image::Rgb<u8> = image::Rgb([255, 255, 255]);
+
+// Whether (x, y) is inside a five-pointed star pointing up, centered on (cx, cy) with its points
+// `radius` from the center. The inner corners are where the lines between the points cross, which
+// makes it the regular star. Even-odd test against its ten corners.
+fn in_star(x: f32, y: f32, cx: f32, cy: f32, radius: f32) -> bool {
+    let inner = radius * (0.4 * std::f32::consts::PI).cos() / (0.2 * std::f32::consts::PI).cos();
+    let corners = (0..10)
+        .map(|i| {
+            let angle = -std::f32::consts::FRAC_PI_2 + i as f32 * std::f32::consts::PI / 5.0;
+            let r = if i % 2 == 0 { radius } else { inner };
+            (cx + r * angle.cos(), cy + r * angle.sin())
+        })
+        .collect::<Vec<_>>();
+    let mut inside = false;
+    for i in 0..corners.len() {
+        let (x1, y1) = corners[i];
+        let (x2, y2) = corners[(i + 1) % corners.len()];
+        if (y1 > y) != (y2 > y) && x < x1 + (y - y1) / (y2 - y1) * (x2 - x1) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+// Everything is sized from the image like geo-stamp's text, and drawn on a plate darkened to 40%
+// brightness so it reads on any background. Stars and the bar are as tall as the text at twice
+// geo-stamp's scale.
+fn badge(infile: String, outfile: String, kind: BadgeKind, value: String, position: Corner) {
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "badge");
+    let mut img = img.to_rgb8();
+    let (width, height) = img.dimensions();
+    let scale = (width.min(height) / 250).max(1);
+    let margin = 4 * scale;
+    let padding = 2 * scale;
+    let size = font::GLYPH_HEIGHT * 2 * scale;
+    let gap = 2 * scale;
+
+    let content_width = match kind {
+        BadgeKind::Stars => BADGE_STARS * size + (BADGE_STARS - 1) * gap,
+        BadgeKind::Bar => 10 * size,
+        BadgeKind::Text => font::text_width(&value, 2 * scale),
+    };
+    let plate_width = content_width + 2 * padding;
+    let plate_height = size + 2 * padding;
+    let left = match position {
+        Corner::TopLeft | Corner::BottomLeft => margin,
+        Corner::TopRight | Corner::BottomRight => width.saturating_sub(plate_width + margin),
+    };
+    let top = match position {
+        Corner::TopLeft | Corner::TopRight => margin,
+        Corner::BottomLeft | Corner::BottomRight => height.saturating_sub(plate_height + margin),
+    };
+    let plate =
+        |x: u32, y: u32| x >= left && y >= top && x < left + plate_width && y < top + plate_height;
+    for (x, y, pixel) in img.enumerate_pixels_mut() {
+        if plate(x, y) {
+            for c in 0..3 {
+                pixel[c] = (pixel[c] as f32 * 0.4).round() as u8;
+            }
+        }
+    }
+
+    let (x0, y0) = (left + padding, top + padding);
+    match kind {
+        BadgeKind::Stars => {
+            let filled = value
+                .trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|&n| n <= BADGE_STARS)
+                .unwrap_or_else(|| panic!("{} is not a valid number of stars!", value));
+            let radius = size as f32 / 2.0;
+            for star in 0..BADGE_STARS {
+                let cx = (x0 + star * (size + gap)) as f32 + radius;
+                let cy = y0 as f32 + radius;
+                let color = if star < filled {
+                    BADGE_FILLED
+                } else {
+                    BADGE_EMPTY
+                };
+                for y in y0..(y0 + size).min(height) {
+                    for x in (cx - radius) as u32..((cx + radius) as u32).min(width) {
+                        if in_star(x as f32 + 0.5, y as f32 + 0.5, cx, cy, radius) {
+                            img.put_pixel(x, y, color);
+                        }
+                    }
+                }
+            }
+        }
+        BadgeKind::Bar => {
+            let percent = value
+                .trim()
+                .parse::<f32>()
+                .ok()
+                .filter(|p| (0.0..=100.0).contains(p))
+                .unwrap_or_else(|| panic!("{} is not a valid percentage!", value));
+            let full = (content_width as f32 * percent / 100.0).round() as u32;
+            for y in y0..(y0 + size).min(height) {
+                for x in x0..(x0 + content_width).min(width) {
+                    let color = if x - x0 < full {
+                        BADGE_BAR
+                    } else {
+                        BADGE_EMPTY
+                    };
+                    img.put_pixel(x, y, color);
+                }
+            }
+        }
+        BadgeKind::Text => {
+            if value.trim().is_empty() {
+                panic!("The text is empty!");
+            }
+            font::draw_text(&mut img, x0, y0, &value, 2 * scale, BADGE_TEXT);
+        }
+    }
+
+    save_image(&image::DynamicImage::ImageRgb8(img), &outfile);
+}
+
+// A robust auto-contrast. Per channel, the value with `clip_percent` of the pixels below it becomes
+// 0 and the value with `clip_percent` above it becomes 255, so a few stray black or white pixels
+// don't stop the rest of the range from being stretched. The clipped pixels saturate. Fully
+// transparent pixels don't count, and a channel with a single value is left alone.
+fn auto_levels(infile: String, outfile: String, clip_percent: f32) {
+    if !(0.0..50.0).contains(&clip_percent) {
+        panic!("{} is not a valid clip percentage!", clip_percent);
+    }
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    let mut stretched = img.to_rgba32f();
+
+    let mut histograms = [[0u64; 256]; 3];
+    let mut count = 0u64;
+    for pixel in stretched.pixels().filter(|p| p[3] > 0.0) {
+        for c in 0..3 {
+            histograms[c][(pixel[c].clamp(0.0, 1.0) * 255.0).round() as usize] += 1;
+        }
+        count += 1;
+    }
+    if count == 0 {
+        panic!("INFILE has no opaque pixels!");
+    }
+
+    let clipped = (count as f64 * clip_percent as f64 / 100.0) as u64;
+    // The first level whose running total passes `clipped`, counted from either end.
+    let passes = |histogram: &[u64; 256], levels: &mut dyn Iterator<Item = usize>| {
+        let mut total = 0;
+        for level in levels {
+            total += histogram[level];
+            if total > clipped {
+                return level;
+            }
+        }
+        unreachable!()
+    };
+    let ranges = histograms.map(|histogram| {
+        (
+            passes(&histogram, &mut (0..256)),
+            passes(&histogram, &mut (0..256).rev()),
+        )
+    });
+
+    for pixel in stretched.pixels_mut() {
+        for (c, &(low, high)) in ranges.iter().enumerate() {
+            if high > low {
+                let low = low as f32 / 255.0;
+                let high = high as f32 / 255.0;
+                pixel[c] = ((pixel[c] - low) / (high - low)).clamp(0.0, 1.0);
+            }
+        }
+    }
+    save_image(&like_original(stretched, &img), &outfile);
+}
+
+// Frequency separation as used in retouching: the low layer is the image blurred by `radius`, and the
+// high layer is what the blur took away, halved and offset to mid-gray so that differences of either
+// sign and any size fit. Halving costs the lowest bit, so recombining the two layers gives back the
+// original to within one level. Both layers keep the original alpha.
+fn frequency_separation(infile: String, low_out: String, high_out: String, radius: f32) {
+    if radius <= 0.0 {
+        panic!("{} is not a valid radius!", radius);
+    }
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    warn_if_downconverting(&img, "INFILE", "frequency-separation");
+    let original = img.to_rgba8();
+
+    let mut low =
+        image::DynamicImage::ImageRgba32F(boxblur::gaussian_approx(&img.to_rgba32f(), radius))
+            .to_rgba8();
+    let mut high = original.clone();
+    for ((o, l), h) in original
+        .pixels()
+        .zip(low.pixels_mut())
+        .zip(high.pixels_mut())
+    {
+        for c in 0..3 {
+            h[c] = ((o[c] as i32 - l[c] as i32 + 256) / 2).min(255) as u8;
+        }
+        l[3] = o[3];
+    }
+
+    save_image(&image::DynamicImage::ImageRgba8(low), &low_out);
+    save_image(&image::DynamicImage::ImageRgba8(high), &high_out);
+}
+
+fn recombine(low: String, high: String, outfile: String) {
+    let imgbuf = limits::open(&low).expect("Failed to open LOW.");
+    warn_if_downconverting(&imgbuf, "LOW", "recombine");
+    let mut imgbuf = imgbuf.to_rgba8();
+    let detail = limits::open(&high).expect("Failed to open HIGH.");
+    warn_if_downconverting(&detail, "HIGH", "recombine");
+    let detail = detail.to_rgba8();
+    if imgbuf.dimensions() != detail.dimensions() {
+        panic!("{} is not the same size as {}!", high, low);
+    }
+    for (pixel, d) in imgbuf.pixels_mut().zip(detail.pixels()) {
+        for c in 0..3 {
+            pixel[c] = (pixel[c] as i32 + 2 * (d[c] as i32 - 128)).clamp(0, 255) as u8;
+        }
+    }
+    save_image(&image::DynamicImage::ImageRgba8(imgbuf), &outfile);
+}
+
+// Each band ends where the running total of the weights so far, as a share of all the weights,
+// reaches along the axis, and a pixel belongs to the band its center falls in. Bands too thin for
+// that to hit a pixel center don't show up at all.
+fn weighted_stripes(
+    outfile: String,
+    width: u32,
+    height: u32,
+    orientation: StripeOrientation,
+    stripes: Vec<String>,
+) {
+    let stripes = stripes
+        .iter()
+        .map(|stripe| {
+            let weight = stripe
+                .rsplit_once(':')
+                .and_then(|(_, weight)| weight.parse::<f64>().ok())
+                .filter(|weight| weight.is_finite() && *weight > 0.0)
+                .unwrap_or_else(|| panic!("{} is not a valid stripe!", stripe));
+            (parse_color(stripe), weight)
+        })
+        .collect::<Vec<(Color, f64)>>();
+
+    let length = match orientation {
+        StripeOrientation::Vertical => width,
+        StripeOrientation::Horizontal => height,
+    } as f64;
+    let total: f64 = stripes.iter().map(|(_, weight)| weight).sum();
+    let mut ends = Vec::with_capacity(stripes.len());
+    let mut sum = 0.0;
+    for (_, weight) in &stripes {
+        sum += weight;
+        ends.push(sum / total * length);
+    }
+
+    let imgbuf = image::RgbImage::from_fn(width, height, |x, y| {
+        let position = match orientation {
+            StripeOrientation::Vertical => x,
+            StripeOrientation::Horizontal => y,
+        } as f64
+            + 0.5;
+        let index = ends
+            .iter()
+            .position(|&end| position < end)
+            .unwrap_or(stripes.len() - 1);
+        let color = &stripes[index].0;
+        image::Rgb([color.red, color.green, color.blue])
+    });
+
+    save_image(&image::DynamicImage::ImageRgb8(imgbuf), &outfile);
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+enum FilmStock {
+    /// Kodak Portra 400: soft contrast, muted warm color and fine grain
+    Portra400,
+    /// Fujichrome Velvia 50: punchy contrast, vivid color and almost no grain
+    Velvia50,
+    /// Kodak Tri-X 400: black and white with strong contrast and coarse grain
+    TriX400,
+}
+
+struct FilmLook {
+    // How much to multiply the saturation by, with 0.0 for black and white.
+    saturation: f32,
+    // How far the tone curve bends from a straight line towards an S-curve, from 0.0 to 1.0.
+    contrast: f32,
+    // What black and white turn into in each channel, which lifts the shadows and tints the image.
+    black: [f32; 3],
+    white: [f32; 3],
+    // Standard deviation of the grain in the midtones.
+    grain: f32,
+}
+
+impl FilmStock {
+    // Rough approximations, made by eye, of how each stock renders a scene.
+    fn look(self) -> FilmLook {
+        match self {
+            FilmStock::Portra400 => FilmLook {
+                saturation: 0.85,
+                contrast: 0.15,
+                black: [0.04, 0.03, 0.02],
+                white: [1.0, 0.98, 0.93],
+                grain: 0.025,
+            },
+            FilmStock::Velvia50 => FilmLook {
+                saturation: 1.4,
+                contrast: 0.6,
+                black: [0.0, 0.0, 0.01],
+                white: [1.0, 1.0, 0.98],
+                grain: 0.01,
+            },
+            FilmStock::TriX400 => FilmLook {
+                saturation: 0.0,
+                contrast: 0.5,
+                black: [0.03, 0.03, 0.03],
+                white: [0.97, 0.97, 0.97],
+                grain: 0.06,
+            },
+        }
+    }
+}
+
+// Grain is always the same for the same image size, so a stock gives the same result every time.
+const FILM_GRAIN_SEED: u64 = 400;
+
+// The saturation changes first, then each channel goes through the stock's tone curve, and last
+// grain is added. Like film grain it is the same in all three channels and strongest in the midtones,
+// fading out towards pure black and white. Alpha is kept as it is.
+fn film_emulate(infile: String, outfile: String, stock: FilmStock) {
+    let img = limits::open(infile).expect("Failed to open INFILE.");
+    let mut imgbuf = img.to_rgba32f();
+    let look = stock.look();
+
+    let mut rng = random::Rng::new(FILM_GRAIN_SEED);
+    for pixel in imgbuf.pixels_mut() {
+        let gray = 0.2126 * pixel[0] + 0.7152 * pixel[1] + 0.0722 * pixel[2];
+        for c in 0..3 {
+            let t = (gray + (pixel[c] - gray) * look.saturation).clamp(0.0, 1.0);
+            let t = t + (t * t * (3.0 - 2.0 * t) - t) * look.contrast;
+            pixel[c] = look.black[c] + (look.white[c] - look.black[c]) * t;
+        }
+
+        // A normally distributed number (Box-Muller).
+        let u = 1.0 - rng.next_f64();
+        let v = rng.next_f64();
+        let normal = ((-2.0 * u.ln()).sqrt() * (std::f64::consts::TAU * v).cos()) as f32;
+        let gray = 0.2126 * pixel[0] + 0.7152 * pixel[1] + 0.0722 * pixel[2];
+        let grain = normal * look.grain * 4.0 * gray * (1.0 - gray);
+        for c in 0..3 {
+            pixel[c] = (pixel[c] + grain).clamp(0.0, 1.0);
+        }
+    }
+
+    save_image(&like_original(imgbuf, &img), &outfile);
+}
+
+// Hue goes clockwise from red at the top through yellow, green, cyan at the bottom, blue and
+// magenta. Value is full throughout. Outside the circle the image is transparent, and the pixels
+// along the rim are partly transparent by how much of them the circle covers, so the edge stays
+// smooth.
+fn color_wheel(outfile: String, size: u32) {
+    if size == 0 {
+        panic!("{} is not a valid size!", size);
+    }
+    let radius = size as f32 / 2.0;
+    let imgbuf = image::RgbaImage::from_fn(size, size, |x, y| {
+        let dx = x as f32 + 0.5 - radius;
+        let dy = y as f32 + 0.5 - radius;
+        let distance = (dx * dx + dy * dy).sqrt();
+        let hue = dx.atan2(-dy).to_degrees();
+        let saturation = (distance / radius).min(1.0);
+        let [r, g, b] = colorspace::hsv_to_rgb([hue, saturation, 1.0]);
+        let coverage = (radius - distance + 0.5).clamp(0.0, 1.0);
+        image::Rgba([r, g, b, coverage].map(|c| (c * 255.0).round() as u8))
+    });
+    save_image(&image::DynamicImage::ImageRgba8(imgbuf), &outfile);
+}
+
+fn average(infiles: Vec<String>, outfile: String, linear: bool) {
+    let images = infiles
+        .iter()
+        .map(|infile| limits::open(infile).unwrap_or_else(|_| panic!("Failed to open {}.", infile)))
+        .collect::<Vec<_>>();
+    let dimensions = (images[0].width(), images[0].height());
+    for (infile, img) in infiles.iter().zip(&images) {
+        if (img.width(), img.height()) != dimensions {
+            panic!("{} is not the same size as {}!", infile, infiles[0]);
+        }
+    }
+    save_image(&average_images(&images, linear), &outfile);
+}
+
+// The mean of same-sized images, with 16 bits per channel if any of them had them.
+fn average_images(images: &[image::DynamicImage], linear: bool) -> image::DynamicImage {
+    let mut sum = colorspace::to_working(&images[0], linear);
+    for img in &images[1..] {
+        for (total, pixel) in sum
+            .pixels_mut()
+            .zip(colorspace::to_working(img, linear).pixels())
+        {
+            for c in 0..4 {
+                total[c] += pixel[c];
+            }
+        }
+    }
+    for pixel in sum.pixels_mut() {
+        for c in 0..4 {
+            pixel[c] /= images.len() as f32;
+        }
+    }
+
+    let template = if images.iter().any(is_high_bit_depth) {
+        image::DynamicImage::new_rgba16(0, 0)
+    } else {
+        image::DynamicImage::new_rgba8(0, 0)
+    };
+    colorspace::from_working(sum, &template, linear)
+}
+
+fn compose(
+    base: String,
+    overlay: String,
+    outfile: String,
+    x: i64,
+    y: i64,
+    opacity: f32,
+    linear: bool,
+) {
+    if !(0.0..=1.0).contains(&opacity) {
+        panic!("{} is not a valid opacity!", opacity);
+    }
+    let base = limits::open(base).expect("Failed to open BASE.");
+    let overlay = limits::open(overlay).expect("Failed to open OVERLAY.");
+    save_image(
+        &compose_images(&base, &overlay, x, y, opacity, linear),
+        &outfile,
+    );
+}
+
+// `overlay` blended over `base` at (`x`, `y`), cropped to `base`. The result has `base`'s bit depth,
+// and an alpha channel if either image has one.
+fn compose_images(
+    base: &image::DynamicImage,
+    overlay: &image::DynamicImage,
+    x: i64,
+    y: i64,
+    opacity: f32,
+    linear: bool,
+) -> image::DynamicImage {
+    let mut canvas = colorspace::to_working(base, linear);
+    let layer = colorspace::to_working(overlay, linear);
+    for (ox, oy, above) in layer.enumerate_pixels() {
+        let (cx, cy) = (x + ox as i64, y + oy as i64);
+        if cx >= 0 && cy >= 0 && cx < canvas.width() as i64 && cy < canvas.height() as i64 {
+            blend_over(canvas.get_pixel_mut(cx as u32, cy as u32), above, opacity);
+        }
+    }
+
+    let has_alpha = base.color().has_alpha() || overlay.color().has_alpha();
+    let template = match (is_high_bit_depth(base), has_alpha) {
+        (true, true) => image::DynamicImage::new_rgba16(0, 0),
+        (true, false) => image::DynamicImage::new_rgb16(0, 0),
+        (false, true) => image::DynamicImage::new_rgba8(0, 0),
+        (false, false) => image::DynamicImage::new_rgb8(0, 0),
+    };
+    colorspace::from_working(canvas, &template, linear)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A path in the temporary directory, unique to this test run.
+    fn temp_path(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("mirage-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join(name).to_string_lossy().into_owned()
+    }
+
+    // A small image with some structure, so encoders can't reduce it to a handful of bytes.
+    fn gradient(width: u32, height: u32) -> image::RgbImage {
+        image::RgbImage::from_fn(width, height, |x, y| {
+            image::Rgb([
+                (x * 255 / width) as u8,
+                (y * 255 / height) as u8,
+                ((x + y) % 256) as u8,
+            ])
+        })
+    }
+
+    // A fine checkerboard of black and white pixels, half the light of white on average.
+    fn checkerboard(size: u32) -> image::DynamicImage {
+        image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(size, size, |x, y| {
+            image::Rgb([if (x + y) % 2 == 0 { 0 } else { 255 }; 3])
+        }))
+    }
+
+    fn center_value(img: &image::DynamicImage) -> u8 {
+        img.to_rgb8().get_pixel(img.width() / 2, img.height() / 2).0[0]
+    }
+
+    // Half of white's light is 0.5 in linear light, which sRGB encodes as 188, not 128.
+    #[test]
+    fn linear_downscaling_of_a_checkerboard_keeps_its_brightness() {
+        let board = checkerboard(64);
+        let linear = center_value(&resize_exact(&board, 8, 8, true));
+        let srgb = center_value(&resize_exact(&board, 8, 8, false));
+        assert!((186..=190).contains(&linear), "{}", linear);
+        assert!((126..=130).contains(&srgb), "{}", srgb);
+    }
+
+    #[test]
+    fn averaging_black_and_white_gives_mid_gray_in_linear_light() {
+        let black = image::DynamicImage::ImageRgb8(image::RgbImage::new(4, 4));
+        let white =
+            image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(4, 4, image::Rgb([255; 3])));
+        let images = [black, white];
+        assert_eq!(center_value(&average_images(&images, true)), 188);
+        assert_eq!(center_value(&average_images(&images, false)), 128);
+    }
+
+    #[test]
+    fn compose_blends_inside_the_overlay_and_leaves_the_rest() {
+        let base = image::DynamicImage::ImageRgb8(image::RgbImage::new(8, 8));
+        let overlay =
+            image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(4, 4, image::Rgb([255; 3])));
+        let linear = compose_images(&base, &overlay, 6, -2, 0.5, true).to_rgb8();
+        assert_eq!(linear.dimensions(), (8, 8));
+        assert_eq!(linear.get_pixel(7, 1).0, [188; 3]);
+        assert_eq!(linear.get_pixel(5, 1).0, [0; 3]);
+        assert_eq!(linear.get_pixel(7, 2).0, [0; 3]);
+        let srgb = compose_images(&base, &overlay, 6, -2, 0.5, false).to_rgb8();
+        assert_eq!(srgb.get_pixel(6, 0).0, [128; 3]);
+    }
+
+    #[test]
+    fn reflection_mirrors_the_bottom_row_and_fades_out() {
+        let img = image::DynamicImage::ImageRgb8(gradient(8, 8)).to_rgba8();
+        for curve in [FadeCurve::Linear, FadeCurve::Smooth] {
+            let reflected = with_reflection(&img, 4, 1.0, curve);
+            assert_eq!(reflected.dimensions(), (8, 12));
+            for x in 0..8 {
+                assert_eq!(reflected.get_pixel(x, 7), img.get_pixel(x, 7));
+                assert_eq!(reflected.get_pixel(x, 8), img.get_pixel(x, 7));
+                assert_eq!(
+                    reflected.get_pixel(x, 10).0[..3],
+                    img.get_pixel(x, 5).0[..3]
+                );
+                assert_eq!(reflected.get_pixel(x, 11)[3], 0);
+            }
+            assert!(reflected.get_pixel(0, 9)[3] < 255 && reflected.get_pixel(0, 9)[3] > 0);
+        }
+    }
+
+    #[test]
+    fn verify_accepts_a_valid_image_and_rejects_a_truncated_one() {
+        let valid = temp_path("verify-valid.png");
+        gradient(64, 64).save(&valid).unwrap();
+        assert_eq!(verify_file(&valid), Ok((64, 64)));
+
+        let truncated = temp_path("verify-truncated.png");
+        let bytes = std::fs::read(&valid).unwrap();
+        std::fs::write(&truncated, &bytes[..bytes.len() / 2]).unwrap();
+        assert!(verify_file(&truncated).is_err());
+    }
+
+    #[test]
+    fn verify_reports_a_max_pixels_refusal_as_a_failure() {
+        // A header claiming 400 megapixels, over the default limit, with no pixel data after it.
+        let huge = temp_path("verify-huge.pgm");
+        std::fs::write(&huge, "P5\n20000 20000\n255\n").unwrap();
+        let refusal = verify_file(&huge).unwrap_err();
+        assert!(refusal.contains("--max-pixels"), "{}", refusal);
+    }
+
+    #[test]
+    fn filmstrip_has_one_cell_per_frame() {
+        use image::codecs::gif::GifEncoder;
+
+        let colors = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]];
+        let infile = temp_path("filmstrip.gif");
+        let mut encoder = GifEncoder::new(std::fs::File::create(&infile).unwrap());
+        encoder
+            .encode_frames(colors.iter().map(|&color| {
+                image::Frame::new(image::RgbaImage::from_pixel(8, 6, image::Rgba(color)))
+            }))
+            .unwrap();
+        drop(encoder);
+
+        let outfile = temp_path("filmstrip.png");
+        filmstrip(infile, outfile.clone(), 2);
+        let strip = image::open(&outfile).unwrap().to_rgba8();
+        assert_eq!(strip.dimensions(), (16, 12));
+        assert_eq!(strip.get_pixel(4, 3).0, colors[0]);
+        assert_eq!(strip.get_pixel(12, 3).0, colors[1]);
+        assert_eq!(strip.get_pixel(4, 9).0, colors[2]);
+        // Three frames in two columns leave the last cell empty.
+        assert_eq!(strip.get_pixel(12, 9)[3], 0);
+    }
+
+    #[test]
+    fn unsharp_mask_sharpens_edges_and_leaves_faint_noise_alone() {
+        // A dark and a light half with a little noise of +-2 on top.
+        let img = image::GrayImage::from_fn(32, 16, |x, y| {
+            let noise = [0, 2, 254, 1][((x * 7 + y * 3) % 4) as usize];
+            image::Luma([if x < 16 { 60u8 } else { 190u8 }.wrapping_add(noise)])
+        });
+        let infile = temp_path("unsharp-in.png");
+        img.save(&infile).unwrap();
+        let outfile = temp_path("unsharp-out.png");
+        unsharp_mask(infile, outfile.clone(), 2.0, 1.0, 8);
+        let sharpened = image::open(&outfile).unwrap().to_luma8();
+
+        // Next to the edge the dark side gets darker and the light side lighter.
+        assert!(sharpened.get_pixel(15, 8)[0] < img.get_pixel(15, 8)[0] - 10);
+        assert!(sharpened.get_pixel(16, 8)[0] > img.get_pixel(16, 8)[0] + 10);
+        // Far from it, the noise is under the threshold and nothing changes.
+        for x in (0..6).chain(26..32) {
+            for y in 0..16 {
+                assert_eq!(
+                    sharpened.get_pixel(x, y),
+                    img.get_pixel(x, y),
+                    "({}, {})",
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn clipping_check_reports_the_share_of_pure_black_and_white() {
+        // A quarter white, a quarter black, and the rest mid-gray except for a red stripe.
+        let img = image::RgbImage::from_fn(20, 20, |x, y| match (x < 10, y < 10) {
+            (true, true) => image::Rgb([255; 3]),
+            (false, true) => image::Rgb([0; 3]),
+            _ if y == 19 => image::Rgb([255, 128, 128]),
+            _ => image::Rgb([128; 3]),
+        });
+        let (shadows, highlights) = clipped_percentages(&img);
+        assert_eq!(shadows, [25.0; 3]);
+        assert_eq!(highlights, [30.0, 25.0, 25.0]);
+    }
+
+    // The share of an image's variance that is local: the variance of the pixels in each 4x4 block,
+    // averaged over the image, relative to the variance of the whole image.
+    fn local_detail(img: &image::GrayImage) -> f64 {
+        let variance = |values: &[f64]| {
+            let mean = values.iter().sum::<f64>() / values.len() as f64;
+            values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64
+        };
+        let mut local = 0.0;
+        let mut blocks = 0;
+        for by in (0..img.height()).step_by(4) {
+            for bx in (0..img.width()).step_by(4) {
+                let values = (0..16)
+                    .map(|i| img.get_pixel(bx + i % 4, by + i / 4)[0] as f64)
+                    .collect::<Vec<_>>();
+                local += variance(&values);
+                blocks += 1;
+            }
+        }
+        let values = img.pixels().map(|p| p[0] as f64).collect::<Vec<_>>();
+        local / blocks as f64 / variance(&values)
+    }
+
+    #[test]
+    fn more_cloud_octaves_add_local_detail() {
+        let render = |octaves: u32| {
+            let outfile = temp_path(&format!("clouds-{}.png", octaves));
+            clouds(outfile.clone(), 128, 128, octaves, 7, cancel::install());
+            image::open(&outfile).unwrap().to_luma8()
+        };
+        let single = local_detail(&render(1));
+        let detailed = local_detail(&render(5));
+        assert!(detailed > 2.0 * single, "{} vs {}", detailed, single);
+    }
+
+    #[test]
+    fn a_mid_gray_map_displaces_nothing() {
+        let infile = temp_path("displace-in.png");
+        gradient(24, 16).save(&infile).unwrap();
+        let map = temp_path("displace-map.png");
+        image::GrayImage::from_pixel(24, 16, image::Luma([128]))
+            .save(&map)
+            .unwrap();
+        let outfile = temp_path("displace-out.png");
+        displace(infile, map, outfile.clone(), 10.0);
+        assert_eq!(image::open(&outfile).unwrap().to_rgb8(), gradient(24, 16));
+    }
+
+    #[test]
+    fn a_flat_heightmap_gives_the_neutral_normal() {
+        let infile = temp_path("normal-map-in.png");
+        image::GrayImage::from_pixel(8, 8, image::Luma([90]))
+            .save(&infile)
+            .unwrap();
+        let outfile = temp_path("normal-map-out.png");
+        normal_map(infile, outfile.clone(), 2.0);
+        let normals = image::open(&outfile).unwrap().to_rgb8();
+        assert!(normals.pixels().all(|pixel| pixel.0 == [128, 128, 255]));
+    }
+
+    #[test]
+    fn reinhard_compresses_bright_values_more_than_mid_tones() {
+        let gain = |x: f32| tone_map_value(x, ToneMapOperator::Reinhard) / x;
+        assert!(gain(4.0) < gain(0.18));
+        assert!(gain(0.18) < gain(0.01));
+        assert!(tone_map_value(100.0, ToneMapOperator::Reinhard) < 1.0);
+    }
+
+    #[test]
+    fn devignetting_brightens_dark_corners_to_the_center_level() {
+        // Flat gray, darkened towards the corners the way a lens does: by 1 / (1 + r^2) in linear light.
+        let size = 33;
+        let center = (size - 1) as f32 / 2.0;
+        let gray = colorspace::srgb_to_linear(150.0 / 255.0);
+        let img = image::GrayImage::from_fn(size, size, |x, y| {
+            let r2 = ((x as f32 - center).powi(2) + (y as f32 - center).powi(2))
+                / (2.0 * center * center);
+            image::Luma([(colorspace::linear_to_srgb(gray / (1.0 + r2)) * 255.0).round() as u8])
+        });
+        let infile = temp_path("devignette-in.png");
+        img.save(&infile).unwrap();
+        let outfile = temp_path("devignette-out.png");
+        devignette_correct(infile, outfile.clone(), 1.0);
+        let corrected = image::open(&outfile).unwrap().to_luma8();
+
+        assert!(img.get_pixel(0, 0)[0] < 130);
+        for (x, y) in [(0, 0), (32, 0), (0, 32), (32, 32), (16, 16), (5, 20)] {
+            let value = corrected.get_pixel(x, y)[0];
+            assert!((149..=151).contains(&value), "({}, {}): {}", x, y, value);
+        }
+    }
+
+    #[test]
+    fn polar_transform_round_trips() {
+        // Smooth, and periodic across the width so that the seam where the circle closes doesn't show.
+        let img = image::RgbImage::from_fn(96, 96, |x, y| {
+            let angle = x as f32 / 96.0 * std::f32::consts::TAU;
+            image::Rgb([
+                (128.0 + 100.0 * angle.sin()) as u8,
+                (y * 255 / 95) as u8,
+                (128.0 + 100.0 * angle.cos()) as u8,
+            ])
+        });
+        let infile = temp_path("polar-in.png");
+        img.save(&infile).unwrap();
+        let polar = temp_path("polar-polar.png");
+        polar_transform(infile, polar.clone(), PolarDirection::ToPolar);
+        let outfile = temp_path("polar-out.png");
+        polar_transform(polar, outfile.clone(), PolarDirection::FromPolar);
+        let round_trip = image::open(&outfile).unwrap().to_rgb8();
+
+        // The bottom rows end up squeezed into the middle of the circle, so only the top half is
+        // expected to survive.
+        let mut total_error = 0u32;
+        let mut count = 0u32;
+        for y in 0..48 {
+            for x in 0..96 {
+                for c in 0..3 {
+                    let error = (round_trip.get_pixel(x, y)[c] as i32
+                        - img.get_pixel(x, y)[c] as i32)
+                        .unsigned_abs();
+                    assert!(error <= 24, "({}, {}): {}", x, y, error);
+                    total_error += error;
+                    count += 1;
+                }
+            }
+        }
+        assert!(total_error as f32 / (count as f32) < 3.0);
+    }
+
+    #[test]
+    fn stacked_layers_match_the_over_operator() {
+        let layer = |name: &str, color: [u8; 4]| {
+            let path = temp_path(name);
+            image::RgbaImage::from_pixel(4, 4, image::Rgba(color))
+                .save(&path)
+                .unwrap();
+            path
+        };
+        let infiles = vec![
+            layer("stack-0.png", [0, 0, 255, 255]),
+            layer("stack-1.png", [255, 0, 0, 128]),
+            layer("stack-2.png", [0, 255, 0, 64]),
+        ];
+        let outfile = temp_path("stack-out.png");
+        stack_layers(infiles, outfile.clone());
+        let stacked = image::open(&outfile).unwrap().to_rgba8();
+
+        // Every channel is 0 or 1 in linear light, so "over" reduces to products of the alphas.
+        let (a1, a2) = (128.0 / 255.0, 64.0 / 255.0);
+        let expected = [(1.0 - a2) * a1, a2, (1.0 - a2) * (1.0 - a1)]
+            .map(|linear: f32| (colorspace::linear_to_srgb(linear) * 255.0).round() as i32);
+        let pixel = stacked.get_pixel(2, 2);
+        for c in 0..3 {
+            assert!(
+                (pixel[c] as i32 - expected[c]).abs() <= 1,
+                "{:?} vs {:?}",
+                pixel,
+                expected
+            );
+        }
+        assert_eq!(pixel[3], 255);
+    }
+
+    #[test]
+    fn dimensions_come_from_the_header_without_decoding_the_pixels() {
+        let infile = temp_path("dimensions.png");
+        gradient(1200, 900).save(&infile).unwrap();
+        assert_eq!(header_dimensions(&infile).unwrap(), (1200, 900));
+
+        // A header promising 20000x20000 pixels with none after it, which can't be decoded.
+        let infile = temp_path("dimensions.pgm");
+        std::fs::write(&infile, "P5\n20000 20000\n255\n").unwrap();
+        assert!(image::open(&infile).is_err());
+        assert_eq!(header_dimensions(&infile).unwrap(), (20000, 20000));
+    }
+
+    #[test]
+    fn otsu_splits_a_bimodal_histogram_between_its_peaks() {
+        // Two bumps, around 50 and 180, with nothing in between.
+        let img = image::GrayImage::from_fn(40, 40, |x, y| {
+            let spread = ((x * 3 + y * 5) % 9) as u8;
+            image::Luma([if (x + y) % 3 == 0 {
+                46 + spread
+            } else {
+                176 + spread
+            }])
+        });
+        let level = otsu_level(&luma_histogram(&img));
+        assert!((54..176).contains(&level), "{}", level);
+    }
+
+    fn phash_fixture(name: &str) -> u64 {
+        let path = format!(
+            "{}/tests/fixtures/phash/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        perceptual_hash(&image::open(path).unwrap())
+    }
+
+    // Scripts compare hashes across versions, so the exact values are pinned. scene.jpg and
+    // scene-small.png are a recompressed and a half-size copy of scene.png, other.png is unrelated.
+    #[test]
+    fn phash_is_stable_and_close_for_near_duplicates() {
+        let scene = phash_fixture("scene.png");
+        let recompressed = phash_fixture("scene.jpg");
+        let small = phash_fixture("scene-small.png");
+        let other = phash_fixture("other.png");
+        assert_eq!(scene, 0x9534_69c3_16bc_61db);
+        assert_eq!(recompressed, 0x9134_69cb_163c_71db);
+        assert_eq!(small, 0x9534_69c3_16bc_61db);
+        assert_eq!(other, 0x8cd0_ffa5_3661_4cc5);
+
+        let distance = |a: u64, b: u64| (a ^ b).count_ones();
+        assert!(distance(scene, recompressed) <= 6);
+        assert!(distance(scene, small) <= 6);
+        assert!(distance(scene, other) >= 20);
+        assert!(distance(recompressed, other) >= 20);
+    }
+
+    #[test]
+    fn adaptive_threshold_handles_a_brightness_gradient_that_defeats_a_global_one() {
+        // Pairs of dark "strokes" on a background that brightens from left to right.
+        let is_stroke = |x: u32, y: u32| x % 8 >= 3 && x % 8 <= 4 && (4..28).contains(&y);
+        let img = image::GrayImage::from_fn(128, 32, |x, y| {
+            let background = 40 + x * 180 / 127;
+            image::Luma([if is_stroke(x, y) {
+                background - 40
+            } else {
+                background
+            } as u8])
+        });
+        let infile = temp_path("adaptive-in.png");
+        img.save(&infile).unwrap();
+        let outfile = temp_path("adaptive-out.png");
+        adaptive_threshold(infile, outfile.clone(), 15, 10.0);
+        let binary = image::open(&outfile).unwrap().to_luma8();
+        for (x, y, pixel) in binary.enumerate_pixels() {
+            let expected = if is_stroke(x, y) { 0 } else { 255 };
+            assert_eq!(pixel[0], expected, "({}, {})", x, y);
+        }
+
+        // A global threshold puts the dark end of the background with the strokes.
+        let level = otsu_level(&luma_histogram(&img));
+        assert!(img.get_pixel(0, 0)[0] <= level);
+    }
+
+    #[test]
+    fn count_objects_measures_blobs_and_drops_small_noise() {
+        let mut img = image::GrayImage::new(40, 30);
+        for (x, y, w, h) in [(2, 2, 10, 8), (20, 4, 6, 5), (30, 20, 3, 3)] {
+            for dy in 0..h {
+                for dx in 0..w {
+                    img.put_pixel(x + dx, y + dy, image::Luma([220]));
+                }
+            }
+        }
+        for (x, y) in [(5, 25), (38, 1), (15, 15)] {
+            img.put_pixel(x, y, image::Luma([220]));
+        }
+        let (_, objects) = find_objects(&img, 4, false);
+        assert_eq!(
+            objects,
+            vec![(80, 2, 2, 10, 8), (30, 20, 4, 6, 5), (9, 30, 20, 3, 3)]
+        );
+        assert_eq!(find_objects(&img, 1, false).1.len(), 6);
+    }
+
+    #[test]
+    fn rle_preview_finds_long_runs_in_flat_images_and_short_ones_in_noise() {
+        let flat = image::RgbImage::from_pixel(32, 8, image::Rgb([10, 20, 30]));
+        assert_eq!(scanline_runs(flat.as_raw(), 32, 3), vec![8; 3]);
+
+        let mut rng = random::Rng::new(1);
+        let noise = image::RgbImage::from_fn(32, 8, |_, _| {
+            image::Rgb([0; 3].map(|_: u8| rng.below(256) as u8))
+        });
+        for runs in scanline_runs(noise.as_raw(), 32, 3) {
+            // Average runs of at most 1.1 pixels.
+            assert!(runs * 11 >= 32 * 8 * 10, "{}", runs);
+        }
+    }
+
+    #[test]
+    fn raw_bytes_round_trip_through_an_image_unchanged() {
+        let mut rng = random::Rng::new(2);
+        for (format, channels, name) in [
+            (RawPixelFormat::Rgb8, 3, "rgb8"),
+            (RawPixelFormat::Rgba8, 4, "rgba8"),
+            (RawPixelFormat::Luma8, 1, "luma8"),
+            (RawPixelFormat::Bgr8, 3, "bgr8"),
+        ] {
+            let bytes = (0..7 * 5 * channels)
+                .map(|_| rng.below(256) as u8)
+                .collect::<Vec<_>>();
+            let raw = temp_path(&format!("raw-{}.raw", name));
+            std::fs::write(&raw, &bytes).unwrap();
+            let png = temp_path(&format!("raw-{}.png", name));
+            from_raw(raw, png.clone(), 7, 5, format);
+            let dumped = temp_path(&format!("raw-{}-dumped.raw", name));
+            raw_dump(png, dumped.clone(), format);
+            assert_eq!(std::fs::read(&dumped).unwrap(), bytes, "{}", name);
+        }
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "INFILE is 100 bytes, but 7x5 pixels at 3 bytes per pixel needs 105 bytes!"
+    )]
+    fn from_raw_rejects_a_size_mismatch() {
+        let raw = temp_path("raw-short.raw");
+        std::fs::write(&raw, [0u8; 100]).unwrap();
+        from_raw(raw, temp_path("raw-short.png"), 7, 5, RawPixelFormat::Rgb8);
+    }
+
+    #[test]
+    fn neighboring_diamonds_have_different_colors() {
+        let outfile = temp_path("diamond.png");
+        let color = |red, green, blue| Color { red, green, blue };
+        diamond(
+            outfile.clone(),
+            32,
+            32,
+            color(255, 0, 0),
+            color(0, 0, 255),
+            8,
+        );
+        let img = image::open(&outfile).unwrap().to_rgb8();
+        // Diamonds are centered where x + y and x - y are both 4 more than a multiple of 8, so
+        // (8, 4) is the center of one and (12, 8) and (12, 0) the centers of two of its neighbors.
+        assert_eq!(img.get_pixel(8, 4).0, [0, 0, 255]);
+        assert_eq!(img.get_pixel(12, 8).0, [255, 0, 0]);
+        assert_eq!(img.get_pixel(4, 8).0, [255, 0, 0]);
+        // Diagonal neighbors across a corner share the color.
+        assert_eq!(img.get_pixel(16, 4).0, [0, 0, 255]);
+    }
+
+    #[test]
+    fn pixel_sort_orders_runs_and_leaves_pixels_outside_the_band() {
+        let mut rng = random::Rng::new(3);
+        let img = image::GrayImage::from_fn(32, 8, |_, _| image::Luma([rng.below(256) as u8]));
+        let infile = temp_path("pixel-sort-in.png");
+        img.save(&infile).unwrap();
+        let outfile = temp_path("pixel-sort-out.png");
+        pixel_sort(
+            infile,
+            outfile.clone(),
+            PixelSortDirection::Rows,
+            60..=200,
+            PixelSortKey::Luminance,
+            None,
+        );
+        let sorted = image::open(&outfile).unwrap().to_luma8();
+
+        let in_band = |value: u8| value > 60 && value <= 200;
+        for y in 0..8 {
+            let before = (0..32).map(|x| img.get_pixel(x, y)[0]).collect::<Vec<_>>();
+            let after = (0..32)
+                .map(|x| sorted.get_pixel(x, y)[0])
+                .collect::<Vec<_>>();
+            let mut start = 0;
+            while start < 32 {
+                if !in_band(before[start]) {
+                    assert_eq!(after[start], before[start]);
+                    start += 1;
+                    continue;
+                }
+                let end = (start..32).find(|&i| !in_band(before[i])).unwrap_or(32);
+                let run = &after[start..end];
+                assert!(run.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", run);
+                let mut expected = before[start..end].to_vec();
+                expected.sort();
+                assert_eq!(run, expected);
+                start = end;
+            }
+        }
+    }
+
+    #[test]
+    fn glitch_is_reproducible_and_a_no_op_at_zero_intensity() {
+        let infile = temp_path("glitch-in.png");
+        gradient(48, 32).save(&infile).unwrap();
+        let run = |intensity, name: &str| {
+            let outfile = temp_path(name);
+            glitch(infile.clone(), outfile.clone(), intensity, 5);
+            image::open(&outfile).unwrap().to_rgb8()
+        };
+
+        let first = run(0.6, "glitch-first.png");
+        assert_eq!(first, run(0.6, "glitch-second.png"));
+        assert_ne!(first, gradient(48, 32));
+        assert_eq!(run(0.0, "glitch-none.png"), gradient(48, 32));
+    }
+
+    #[test]
+    fn each_fractal_type_renders_a_different_image() {
+        let renders = [
+            FractalType::Julia,
+            FractalType::Mandelbrot,
+            FractalType::BurningShip,
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, &fractal_type)| {
+            let outfile = temp_path(&format!("fractal-art-{}.png", i));
+            fractal_art(
+                outfile.clone(),
+                48,
+                32,
+                fractal_type,
+                colormap::Colormap::Viridis,
+                64,
+                cancel::install(),
+            );
+            image::open(&outfile).unwrap().to_rgb8()
+        })
+        .collect::<Vec<_>>();
+        assert_ne!(renders[0], renders[1]);
+        assert_ne!(renders[0], renders[2]);
+        assert_ne!(renders[1], renders[2]);
+    }
+
+    #[test]
+    fn an_image_round_trips_through_raw_dump_and_from_raw() {
+        let infile = temp_path("raw-dump-in.png");
+        gradient(24, 16).save(&infile).unwrap();
+        for (format, name) in [
+            (RawPixelFormat::Rgb8, "rgb8"),
+            (RawPixelFormat::Rgba8, "rgba8"),
+            (RawPixelFormat::Bgr8, "bgr8"),
+        ] {
+            let raw = temp_path(&format!("raw-dump-{}.raw", name));
+            raw_dump(infile.clone(), raw.clone(), format);
+            let outfile = temp_path(&format!("raw-dump-{}.png", name));
+            from_raw(raw, outfile.clone(), 24, 16, format);
+            assert_eq!(image::open(&outfile).unwrap().to_rgb8(), gradient(24, 16));
+        }
+    }
+
+    #[test]
+    fn bulge_keeps_the_center_and_displaces_pixels_inside_the_radius() {
+        let ramp = image::GrayImage::from_fn(64, 64, |x, _| image::Luma([(x * 4) as u8]));
+        let infile = temp_path("bulge-in.png");
+        ramp.save(&infile).unwrap();
+        let outfile = temp_path("bulge-out.png");
+        bulge(infile, outfile.clone(), 32.0, 32.0, 20.0, 0.5);
+        let bulged = image::open(&outfile).unwrap().to_luma8();
+
+        assert_eq!(bulged.get_pixel(32, 32), ramp.get_pixel(32, 32));
+        // Right of the center, pixels are fetched from closer to it, so the ramp reads darker, out to
+        // three quarters of the radius and beyond.
+        for x in 33..=47 {
+            assert!(
+                bulged.get_pixel(x, 32)[0] < ramp.get_pixel(x, 32)[0],
+                "{}",
+                x
+            );
+        }
+        for x in 52..64 {
+            assert_eq!(bulged.get_pixel(x, 32), ramp.get_pixel(x, 32));
+        }
+    }
+
+    #[test]
+    fn pixel_sort_copes_with_lines_that_have_nothing_to_sort() {
+        let infile = temp_path("pixel-sort-dark.png");
+        let img = image::GrayImage::from_fn(16, 16, |x, y| image::Luma([((x + y) * 2) as u8]));
+        img.save(&infile).unwrap();
+        for (direction, seed) in [
+            (PixelSortDirection::Rows, None),
+            (PixelSortDirection::Columns, Some(9)),
+        ] {
+            let outfile = temp_path("pixel-sort-dark-out.png");
+            pixel_sort(
+                infile.clone(),
+                outfile.clone(),
+                direction,
+                100..=200,
+                PixelSortKey::Hue,
+                seed,
+            );
+            assert_eq!(image::open(&outfile).unwrap().to_luma8(), img);
+        }
+    }
+
+    // The metadata to give a test photo.
+    #[derive(Default)]
+    struct Exif {
+        orientation: Option<u16>,
+        date: Option<&'static str>,
+        gps: Option<(f64, f64)>,
+    }
+
+    // `img` as a JPEG with `exif` in an APP1 segment: a little-endian TIFF whose IFD0 holds the
+    // orientation, the date and a pointer to a GPS IFD, with the values too long for an entry after
+    // each directory.
+    fn jpeg_with_exif(img: &image::RgbImage, exif: &Exif) -> Vec<u8> {
+        let entry = |tag: u16, kind: u16, count: u32, value: u32| {
+            [
+                &tag.to_le_bytes()[..],
+                &kind.to_le_bytes(),
+                &count.to_le_bytes(),
+                &value.to_le_bytes(),
+            ]
+            .concat()
+        };
+        let (short, ascii, long, rational) = (3, 2, 4, 5);
+        let ifd_len = |entries: usize| 2 + 12 * entries + 4;
+
+        let count = [
+            exif.orientation.is_some(),
+            exif.date.is_some(),
+            exif.gps.is_some(),
+        ]
+        .iter()
+        .filter(|&&present| present)
+        .count();
+        let mut ifd0 = (count as u16).to_le_bytes().to_vec();
+        let mut data = Vec::new();
+        let data_start = 8 + ifd_len(count);
+        if let Some(orientation) = exif.orientation {
+            ifd0.extend(entry(0x0112, short, 1, orientation as u32));
+        }
+        if let Some(date) = exif.date {
+            let offset = data_start + data.len();
+            ifd0.extend(entry(0x0132, ascii, 20, offset as u32));
+            data.extend(date.as_bytes());
+            data.push(0);
+        }
+        if let Some((latitude, longitude)) = exif.gps {
+            let gps_ifd = data_start + data.len();
+            ifd0.extend(entry(0x8825, long, 1, gps_ifd as u32));
+            // Each coordinate is whole degrees in millionths, with zero minutes and seconds.
+            let values = gps_ifd + ifd_len(4);
+            data.extend(4u16.to_le_bytes());
+            let references = [
+                if latitude < 0.0 { b'S' } else { b'N' },
+                if longitude < 0.0 { b'W' } else { b'E' },
+            ];
+            for (i, reference) in references.iter().enumerate() {
+                let tag = 1 + 2 * i as u16;
+                data.extend(entry(tag, ascii, 2, *reference as u32));
+                data.extend(entry(tag + 1, rational, 3, (values + 24 * i) as u32));
+            }
+            data.extend(0u32.to_le_bytes());
+            for value in [latitude, longitude] {
+                let millionths = (value.abs() * 1e6).round() as u32;
+                for (numerator, denominator) in [(millionths, 1_000_000u32), (0, 1), (0, 1)] {
+                    data.extend(numerator.to_le_bytes());
+                    data.extend(denominator.to_le_bytes());
+                }
+            }
+        }
+        ifd0.extend(0u32.to_le_bytes());
+        let tiff = [&b"II*\0"[..], &8u32.to_le_bytes(), &ifd0, &data].concat();
+
+        let mut jpeg = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 95)
+            .encode_image(img)
+            .unwrap();
+        let app1_len = (2 + 6 + tiff.len()) as u16;
+        [
+            &jpeg[..2],
+            &[0xFF, 0xE1],
+            &app1_len.to_be_bytes(),
+            b"Exif\0\0",
+            &tiff,
+            &jpeg[2..],
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn geo_stamp_writes_the_position_in_the_chosen_corner() {
+        let infile = temp_path("geo-stamp.jpg");
+        let exif = Exif {
+            gps: Some((48.85837, -2.29448)),
+            ..Exif::default()
+        };
+        std::fs::write(&infile, jpeg_with_exif(&gradient(160, 60), &exif)).unwrap();
+        let outfile = temp_path("geo-stamp.png");
+        let yellow = Color {
+            red: 255,
+            green: 255,
+            blue: 0,
+        };
+        geo_stamp(
+            infile.clone(),
+            outfile.clone(),
+            Corner::TopLeft,
+            yellow,
+            false,
+        );
+
+        let mut expected = image::open(&infile).unwrap().to_rgb8();
+        let text = "48.85837 N, 2.29448 W";
+        font::draw_text(&mut expected, 4, 4, text, 1, image::Rgb([255, 255, 0]));
+        assert_eq!(image::open(&outfile).unwrap().to_rgb8(), expected);
+    }
+
+    #[test]
+    fn sprite_sheets_do_not_depend_on_the_input_order() {
+        let sprites = ["c", "a", "b", "d"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let path = temp_path(&format!("sprite-{}.png", name));
+                let size = 6 + 4 * i as u32;
+                image::RgbaImage::from_pixel(size, size, image::Rgba([40 * i as u8, 0, 0, 255]))
+                    .save(&path)
+                    .unwrap();
+                path
+            })
+            .collect::<Vec<_>>();
+        let pack = |infiles: Vec<String>, name: &str| {
+            let outfile = temp_path(name);
+            sprite_sheet(infiles, outfile.clone(), 1, 256);
+            std::fs::read(outfile).unwrap()
+        };
+
+        let mut reversed = sprites.clone();
+        reversed.reverse();
+        assert_eq!(
+            pack(sprites, "sprites-forward.png"),
+            pack(reversed, "sprites-reversed.png")
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "sprite-huge.png is 40x40, too big for a --max-size 32 sheet!")]
+    fn sprite_sheet_names_a_sprite_too_big_for_it() {
+        let path = temp_path("sprite-huge.png");
+        image::RgbaImage::new(40, 40).save(&path).unwrap();
+        sprite_sheet(vec![path], temp_path("sprites-huge.png"), 0, 32);
+    }
+
+    #[test]
+    fn ascii_colors_only_terminals_unless_forced() {
+        let infile = temp_path("ascii.png");
+        gradient(40, 20).save(&infile).unwrap();
+        let render = |color, force_color, is_terminal| {
+            let mut out = Vec::new();
+            ascii(
+                infile.clone(),
+                20,
+                ascii_color(color, force_color, is_terminal),
+                &mut out,
+            );
+            String::from_utf8(out).unwrap()
+        };
+
+        let forced = render(false, true, false);
+        assert!(forced.contains("\x1b[38;2;"));
+        assert!(forced.lines().all(|line| line.ends_with("\x1b[0m")));
+        assert!(render(true, false, true).contains("\x1b["));
+        let piped = render(true, false, false);
+        assert!(!piped.contains('\x1b'));
+        assert_eq!(piped.lines().count(), 5);
+        assert!(piped.lines().all(|line| line.len() == 20));
+    }
+
+    #[test]
+    fn hexagon_corners_land_where_the_geometry_puts_them() {
+        let (cx, cy) = hex_center(1.0, 1.0, 20.0);
+        assert!((cx - 30.0 * 3f32.sqrt()).abs() < 1e-4 && (cy - 30.0).abs() < 1e-4);
+
+        let outfile = temp_path("hex-grid.png");
+        let color = |red, green, blue| Color { red, green, blue };
+        hex_grid(
+            outfile.clone(),
+            100,
+            80,
+            color(255, 255, 255),
+            color(0, 0, 0),
+            20.0,
+            2.0,
+        );
+        let img = image::open(&outfile).unwrap().to_rgb8();
+        // The corners of hexagon (1, 1), at 30, 90, 150, ... degrees and 20 pixels from its center,
+        // rounded to pixels.
+        for (x, y) in [(69, 40), (52, 50), (35, 40), (35, 20), (52, 10), (69, 20)] {
+            assert_eq!(img.get_pixel(x, y).0, [255; 3], "({}, {})", x, y);
+        }
+        assert_eq!(img.get_pixel(52, 30).0, [0; 3]);
+        assert_eq!(img.get_pixel(52, 20).0, [0; 3]);
+    }
+
+    #[test]
+    fn fading_two_edges_multiplies_and_leaves_the_rest_bit_identical() {
+        let img = image::RgbaImage::from_fn(40, 30, |x, y| {
+            let [r, g, b] = gradient(40, 30).get_pixel(x, y).0;
+            image::Rgba([r, g, b, 240])
+        });
+        let infile = temp_path("fade-in.png");
+        img.save(&infile).unwrap();
+        let outfile = temp_path("fade-out.png");
+        fade(
+            infile,
+            outfile.clone(),
+            vec![Edge::Left, Edge::Top],
+            10,
+            FadeCurve::Linear,
+        );
+        let faded = image::open(&outfile).unwrap().to_rgba8();
+
+        for (x, y, pixel) in faded.enumerate_pixels() {
+            let original = img.get_pixel(x, y);
+            assert_eq!(pixel.0[..3], original.0[..3]);
+            if x >= 10 && y >= 10 {
+                assert_eq!(pixel, original);
+            }
+        }
+        // Factors of 0.05 and 0.55 at the first and sixth pixel from an edge, and their product where
+        // both edges fade.
+        assert_eq!(faded.get_pixel(0, 15)[3], 12);
+        assert_eq!(faded.get_pixel(5, 15)[3], 132);
+        assert_eq!(faded.get_pixel(15, 5)[3], 132);
+        assert_eq!(faded.get_pixel(5, 5)[3], 73);
+    }
+
+    #[test]
+    fn fern_points_stay_within_its_bounds() {
+        let outfile = temp_path("fern.png");
+        fern(outfile.clone(), 200, 200, 50_000, 1, cancel::install());
+        let img = image::open(&outfile).unwrap().to_rgb8();
+
+        // The fern is twice as tall as it is wide, so it spans the height less the 5% margins, 18
+        // pixels per unit, and is centered horizontally.
+        let (min_x, max_x, max_y) = FERN_BOUNDS;
+        let scale = 180.0 / max_y;
+        let left = 100.0 - (max_x - min_x) * scale / 2.0;
+        let right = 100.0 + (max_x - min_x) * scale / 2.0;
+        let mut plotted = 0;
+        for (x, y, pixel) in img.enumerate_pixels() {
+            if pixel.0 != [0; 3] {
+                plotted += 1;
+                assert!((left.floor()..=right).contains(&(x as f64)), "x {}", x);
+                assert!((10..=190).contains(&y), "y {}", y);
+            }
+        }
+        assert!(plotted > 2000, "{} pixels", plotted);
+    }
+
+    #[test]
+    fn halftone_dots_grow_with_darkness_on_the_screen_grid() {
+        let img =
+            image::GrayImage::from_fn(96, 48, |x, _| image::Luma([if x < 48 { 40 } else { 215 }]));
+        let infile = temp_path("halftone-in.png");
+        img.save(&infile).unwrap();
+        let outfile = temp_path("halftone-out.png");
+        halftone(infile, outfile.clone(), 8.0, 0.0);
+        let dots = image::open(&outfile).unwrap().to_luma8();
+
+        // Ink in each 8x8 cell, away from where the blur mixes the two halves.
+        let ink = |cx: u32, cy: u32| {
+            (0..64)
+                .filter(|i| dots.get_pixel(cx * 8 + i % 8, cy * 8 + i / 8)[0] == 0)
+                .count()
+        };
+        for cy in 0..6 {
+            for cx in 0..4 {
+                assert!(ink(cx, cy) > 2 * ink(cx + 8, cy), "cell ({}, {})", cx, cy);
+            }
+        }
+        // The light half's small dots sit on the cell centers, 8 pixels apart.
+        for (x, y, pixel) in dots.enumerate_pixels() {
+            if x >= 64 && pixel[0] == 0 {
+                assert!(x % 8 == 3 || x % 8 == 4, "({}, {})", x, y);
+                assert!(y % 8 == 3 || y % 8 == 4, "({}, {})", x, y);
+            }
+        }
+        assert!(ink(10, 2) > 0);
+    }
+
+    #[test]
+    fn gray_world_white_balance_equalizes_the_channel_means() {
+        // A neutral scene under bluish light.
+        let img = image::RgbImage::from_fn(40, 30, |x, y| {
+            let v = (40 + x * 3 + y * 2) as f32;
+            image::Rgb([(v * 0.7) as u8, (v * 0.8) as u8, v as u8])
+        });
+        let infile = temp_path("awb-in.png");
+        img.save(&infile).unwrap();
+        let outfile = temp_path("awb-out.png");
+        auto_white_balance(infile, outfile.clone());
+
+        let balanced = image::open(&outfile).unwrap().to_rgb8();
+        let mut means = [0.0f64; 3];
+        for pixel in balanced.pixels() {
+            for c in 0..3 {
+                means[c] += pixel[c] as f64 / (40.0 * 30.0);
+            }
+        }
+        assert!((means[0] - means[2]).abs() < 1.0, "{:?}", means);
+        assert!((means[1] - means[2]).abs() < 1.0, "{:?}", means);
+    }
+
+    #[test]
+    fn invert_and_grayscale_keep_semi_transparent_alpha() {
+        let img = image::RgbaImage::from_fn(8, 4, |x, y| {
+            image::Rgba([x as u8 * 30, y as u8 * 60, 200, (x * 32 + y) as u8])
+        });
+        let infile = temp_path("alpha-in.png");
+        img.save(&infile).unwrap();
+
+        let inverted = temp_path("alpha-inverted.png");
+        invert(infile.clone(), inverted.clone());
+        let inverted = image::open(&inverted).unwrap().to_rgba8();
+        for (p, q) in img.pixels().zip(inverted.pixels()) {
+            assert_eq!(q.0, [255 - p[0], 255 - p[1], 255 - p[2], p[3]]);
+        }
 
-fn main() {
-    let args = Cli::parse();
+        let deep = temp_path("alpha-in-16.png");
+        image::DynamicImage::ImageRgba8(img.clone())
+            .to_rgba16()
+            .save(&deep)
+            .unwrap();
+        let inverted = temp_path("alpha-inverted-16.png");
+        invert(deep, inverted.clone());
+        let inverted = image::open(&inverted).unwrap();
+        assert_eq!(inverted.color(), image::ColorType::Rgba16);
+        for (p, q) in img.pixels().zip(inverted.to_rgba8().pixels()) {
+            assert_eq!(q[3], p[3]);
+        }
 
-    match args.command {
-        Commands::Blur {
+        let gray = temp_path("alpha-gray.png");
+        grayscale(infile.clone(), gray.clone(), false);
+        let gray = image::open(&gray).unwrap();
+        assert_eq!(gray.color(), image::ColorType::La8);
+        for (p, q) in img.pixels().zip(gray.to_luma_alpha8().pixels()) {
+            assert_eq!(q[1], p[3]);
+        }
+
+        let opaque = temp_path("alpha-gray-opaque.png");
+        grayscale(infile, opaque.clone(), true);
+        assert_eq!(image::open(&opaque).unwrap().color(), image::ColorType::L8);
+    }
+
+    #[test]
+    fn check_orientation_reports_a_rotated_photo_and_fixes_it() {
+        let infile = temp_path("orientation-6.jpg");
+        let exif = Exif {
+            orientation: Some(6),
+            ..Exif::default()
+        };
+        std::fs::write(&infile, jpeg_with_exif(&gradient(16, 8), &exif)).unwrap();
+        let (orientation, report) = orientation_report(&infile);
+        assert_eq!(orientation, Some(6));
+        assert_eq!(
+            report,
+            format!(
+                "{}: MISMATCH (orientation 6, rotated 90 degrees clockwise: stored 16x8, displayed 8x16)",
+                infile
+            )
+        );
+
+        let fixed = temp_path("orientation-fixed.png");
+        check_orientation(infile.clone(), Some(fixed.clone()));
+        let stored = image::open(&infile).unwrap().to_rgb8();
+        assert_eq!(
+            image::open(&fixed).unwrap().to_rgb8(),
+            image::imageops::rotate90(&stored)
+        );
+
+        let upright = temp_path("orientation-1.jpg");
+        let exif = Exif {
+            orientation: Some(1),
+            ..Exif::default()
+        };
+        std::fs::write(&upright, jpeg_with_exif(&gradient(16, 8), &exif)).unwrap();
+        assert!(orientation_report(&upright)
+            .1
+            .contains(": OK (orientation 1, 16x8)"));
+    }
+
+    #[test]
+    fn percentage_crops_stay_inside_the_image_and_are_never_empty() {
+        assert_eq!(percent_span(200, 10.0, 80.0), (20, 160));
+        assert_eq!(percent_span(3, 90.0, 10.0), (2, 1));
+        assert_eq!(percent_span(5, 0.0, 1.0), (0, 1));
+        for total in 1..=20 {
+            for start in (0..=100).step_by(5) {
+                for length in (0..=100 - start).step_by(5) {
+                    let (first, len) = percent_span(total, start as f32, length as f32);
+                    assert!(
+                        len >= 1 && first + len <= total,
+                        "{} {} {}",
+                        total,
+                        start,
+                        length
+                    );
+                }
+            }
+        }
+
+        let infile = temp_path("crop-percent.png");
+        gradient(50, 20).save(&infile).unwrap();
+        let outfile = temp_path("crop-percent-out.png");
+        crop(
             infile,
-            outfile,
-            blur_amount,
-        } => {
-            let blur_amount = if let Some(blur_amount) = blur_amount {
-                blur_amount
+            outfile.clone(),
+            CropRect::Percent([10.0, 10.0, 80.0, 80.0]),
+        );
+        let cropped = image::open(&outfile).unwrap().to_rgb8();
+        assert_eq!(cropped.dimensions(), (40, 16));
+        assert_eq!(cropped.get_pixel(0, 0), gradient(50, 20).get_pixel(5, 2));
+    }
+
+    #[test]
+    fn a_matrix_maps_its_lowest_and_highest_values_to_the_palette_ends() {
+        let infile = temp_path("matrix.csv");
+        std::fs::write(&infile, "0, 2\n\n1 4\n").unwrap();
+        let outfile = temp_path("matrix.png");
+        from_matrix(infile, outfile.clone(), colormap::Colormap::Grayscale);
+        let img = image::open(&outfile).unwrap().to_rgb8();
+        assert_eq!(img.dimensions(), (2, 2));
+        assert_eq!(img.get_pixel(0, 0).0, [0; 3]);
+        assert_eq!(img.get_pixel(1, 1).0, [255; 3]);
+        assert!(img.get_pixel(0, 1)[0] < img.get_pixel(1, 0)[0]);
+
+        let viridis = render_matrix(
+            &[vec![0.0, 2.0], vec![1.0, 4.0]],
+            colormap::Colormap::Viridis,
+        );
+        assert_eq!(
+            viridis.get_pixel(0, 0).0,
+            colormap::Colormap::Viridis.sample(0.0)
+        );
+        assert_eq!(
+            viridis.get_pixel(1, 1).0,
+            colormap::Colormap::Viridis.sample(1.0)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Line 2 has 3 values, but the first row has 2!")]
+    fn a_matrix_with_a_ragged_row_is_rejected() {
+        let infile = temp_path("matrix-ragged.csv");
+        std::fs::write(&infile, "0, 2\n1, 4, 5\n").unwrap();
+        read_matrix(&infile);
+    }
+
+    #[test]
+    fn max_edge_shrinks_the_longer_side_and_copies_no_ops() {
+        let resized = |infile: &str, name: &str, max_edge, upscale| {
+            let outfile = temp_path(name);
+            resize_max_edge(
+                infile.to_string(),
+                outfile.clone(),
+                max_edge,
+                upscale,
+                false,
+            );
+            outfile
+        };
+        let portrait = temp_path("max-edge-portrait.png");
+        gradient(30, 60).save(&portrait).unwrap();
+        let landscape = temp_path("max-edge-landscape.png");
+        gradient(60, 30).save(&landscape).unwrap();
+        let dimensions = |path: String| image::image_dimensions(path).unwrap();
+        assert_eq!(
+            dimensions(resized(&portrait, "max-edge-portrait-out.png", 20, false)),
+            (10, 20)
+        );
+        assert_eq!(
+            dimensions(resized(&landscape, "max-edge-landscape-out.png", 20, false)),
+            (20, 10)
+        );
+        assert_eq!(
+            dimensions(resized(&landscape, "max-edge-upscaled.png", 90, true)),
+            (90, 45)
+        );
+
+        let square = temp_path("max-edge-square.jpg");
+        gradient(48, 48).save(&square).unwrap();
+        for (max_edge, name) in [(48, "max-edge-same.jpg"), (64, "max-edge-smaller.jpg")] {
+            let outfile = resized(&square, name, max_edge, false);
+            assert_eq!(
+                std::fs::read(outfile).unwrap(),
+                std::fs::read(&square).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn timeline_lays_photos_out_in_capture_order() {
+        let indir = temp_path("timeline");
+        std::fs::create_dir_all(&indir).unwrap();
+        let photos = [
+            ("a.jpg", [200, 30, 30], Some("2021:03:04 09:00:00")),
+            ("b.jpg", [30, 200, 30], Some("2019:12:31 23:59:59")),
+            ("c.jpg", [30, 30, 200], None),
+            ("d.jpg", [200, 200, 30], Some("2020:06:15 12:30:00")),
+        ];
+        for (name, color, date) in photos {
+            let img = image::RgbImage::from_pixel(16, 16, image::Rgb(color));
+            let exif = Exif {
+                date,
+                ..Exif::default()
+            };
+            std::fs::write(format!("{}/{}", indir, name), jpeg_with_exif(&img, &exif)).unwrap();
+        }
+        let order = dated_image_files(&indir, false)
+            .into_iter()
+            .map(|(_, path)| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(order, ["b.jpg", "d.jpg", "a.jpg", "c.jpg"]);
+
+        let outfile = temp_path("timeline.png");
+        timeline(indir, outfile.clone(), 4, 16);
+        let sheet = image::open(&outfile).unwrap().to_rgb8();
+        // The centers of the thumbnails, in columns as wide as the dated labels.
+        let column_width = font::text_width("2019-12-31 23:59", TIMELINE_LABEL_SCALE).max(16);
+        for (i, (name, color, _)) in [photos[1], photos[3], photos[0], photos[2]]
+            .iter()
+            .enumerate()
+        {
+            let x =
+                i as u32 * (column_width + TIMELINE_PADDING) + TIMELINE_PADDING + column_width / 2;
+            let pixel = sheet.get_pixel(x, TIMELINE_PADDING + 8);
+            for c in 0..3 {
+                let difference = (pixel[c] as i32 - color[c] as i32).abs();
+                assert!(difference <= 8, "{} at {}: {:?}", name, i, pixel);
+            }
+        }
+    }
+
+    #[test]
+    fn edge_sharpen_enhances_strong_edges_and_leaves_flat_noise() {
+        let mut rng = random::Rng::new(4);
+        let img = image::GrayImage::from_fn(40, 20, |x, _| {
+            let level = if x < 20 { 60 } else { 190 };
+            image::Luma([level + rng.below(7) as u8 - 3])
+        });
+        let infile = temp_path("edge-sharpen-in.png");
+        img.save(&infile).unwrap();
+        let outfile = temp_path("edge-sharpen-out.png");
+        edge_sharpen(infile, outfile.clone(), 1.5, 20.0);
+        let sharpened = image::open(&outfile).unwrap().to_luma8();
+
+        for (x, y, pixel) in sharpened.enumerate_pixels() {
+            if !(15..25).contains(&x) {
+                assert_eq!(pixel, img.get_pixel(x, y), "({}, {})", x, y);
+            }
+        }
+        for y in 2..18 {
+            assert!(sharpened.get_pixel(19, y)[0] < img.get_pixel(19, y)[0]);
+            assert!(sharpened.get_pixel(20, y)[0] > img.get_pixel(20, y)[0]);
+        }
+    }
+
+    #[test]
+    fn stats_of_a_ramp_are_exact() {
+        // 0 to 255 once, with every other pixel transparent.
+        let samples = (0..=255u8)
+            .flat_map(|v| [v, v, v, if v % 2 == 0 { 0 } else { 255 }])
+            .collect::<Vec<_>>();
+        let stats = pixel_stats(&samples, 4, true, 0, 255);
+        for channel in &stats.channels[..3] {
+            assert_eq!((channel.min, channel.max), (0.0, 255.0));
+            assert_eq!(channel.sum, 32640.0);
+            assert_eq!(channel.sum_of_squares, 5559680.0);
+        }
+        assert_eq!(stats.channels[3].sum, 128.0 * 255.0);
+        assert_eq!((stats.black, stats.white, stats.transparent), (1, 1, 128));
+
+        let luma = (0..=65535u16).step_by(257).collect::<Vec<_>>();
+        let stats = pixel_stats(&luma, 1, false, 0, u16::MAX);
+        assert_eq!(stats.channels[0].sum, 32640.0 * 257.0);
+        assert_eq!((stats.black, stats.white, stats.transparent), (1, 1, 0));
+    }
+
+    #[test]
+    fn gradient_text_runs_from_the_start_color_at_the_top_to_the_end_color_at_the_bottom() {
+        let outfile = temp_path("gradient-text.png");
+        let color = |red, green, blue| Color { red, green, blue };
+        gradient_text(
+            outfile.clone(),
+            "HI".to_string(),
+            20,
+            color(255, 0, 0),
+            color(0, 0, 255),
+            color(0, 64, 0),
+        );
+        let img = image::open(&outfile).unwrap().to_rgb8();
+        let ink_rows = (0..img.height())
+            .filter(|&y| (0..img.width()).any(|x| img.get_pixel(x, y).0 != [0, 64, 0]))
+            .collect::<Vec<_>>();
+        let (top, bottom) = (ink_rows[0], *ink_rows.last().unwrap());
+        assert!(bottom - top >= 15, "{}..{}", top, bottom);
+
+        let ink = |y: u32| {
+            (0..img.width())
+                .map(|x| img.get_pixel(x, y).0)
+                .find(|&pixel| pixel != [0, 64, 0])
+                .unwrap()
+        };
+        let [red, _, blue] = ink(top);
+        assert!(red > 230 && blue < 25, "{:?}", ink(top));
+        let [red, _, blue] = ink(bottom);
+        assert!(blue > 230 && red < 25, "{:?}", ink(bottom));
+    }
+
+    #[test]
+    fn color_cast_identifies_a_red_cast() {
+        let scene = |cast: [f32; 3], name: &str| {
+            let img = image::RgbImage::from_fn(32, 32, |x, y| {
+                let v = (60 + x * 3 + y * 2) as f32;
+                image::Rgb(cast.map(|c| (v * c).min(255.0) as u8))
+            });
+            let path = temp_path(name);
+            img.save(&path).unwrap();
+            color_cast_report(&path)
+        };
+        let report = scene([1.0, 0.8, 0.8], "cast-red.png");
+        assert!(report.contains(": moderate red cast"), "{}", report);
+        let report = scene([1.0, 1.0, 1.0], "cast-none.png");
+        assert!(report.contains(": no noticeable cast"), "{}", report);
+    }
+
+    #[test]
+    fn lossless_rotation_gives_the_rotated_pixels_and_composes() {
+        let infile = temp_path("lossless.jpg");
+        image::DynamicImage::ImageRgb8(gradient(48, 32))
+            .save(&infile)
+            .unwrap();
+        let rotated = |infile: &str, degrees, name: &str| {
+            let outfile = temp_path(name);
+            rotate(infile.to_string(), outfile.clone(), degrees, true, true);
+            outfile
+        };
+        let decoded = |path: &str| image::open(path).unwrap().to_rgb8();
+        // The decoder rounds a little differently once the blocks are turned, so a level of difference
+        // here and there is expected.
+        let close = |a: &image::RgbImage, b: &image::RgbImage| {
+            a.dimensions() == b.dimensions()
+                && a.pixels()
+                    .zip(b.pixels())
+                    .all(|(p, q)| (0..3).all(|c| p[c].abs_diff(q[c]) <= 1))
+        };
+
+        let original = decoded(&infile);
+        let quarter = rotated(&infile, 90, "lossless-90.jpg");
+        assert!(close(
+            &decoded(&quarter),
+            &image::imageops::rotate90(&original)
+        ));
+        let half = rotated(&infile, 180, "lossless-180.jpg");
+        assert!(close(
+            &decoded(&half),
+            &image::imageops::rotate180(&original)
+        ));
+        let twice = rotated(&quarter, 90, "lossless-90-90.jpg");
+        assert_eq!(std::fs::read(twice).unwrap(), std::fs::read(half).unwrap());
+    }
+
+    #[test]
+    fn cartoon_posterizes_and_outlines_strong_boundaries() {
+        // Two smoothly shaded regions meeting at a hard edge down the middle.
+        let img = image::RgbImage::from_fn(64, 32, |x, y| {
+            if x < 32 {
+                image::Rgb([40 + x as u8 * 2, 60 + y as u8 * 2, 120])
+            } else {
+                image::Rgb([230, 200 - y as u8, 120 + x as u8])
+            }
+        });
+        let infile = temp_path("cartoon-in.png");
+        img.save(&infile).unwrap();
+        let outfile = temp_path("cartoon-out.png");
+        cartoon(infile, outfile.clone());
+        let toon = image::open(&outfile).unwrap().to_rgb8();
+
+        let distinct = |img: &image::RgbImage| {
+            img.pixels()
+                .map(|p| p.0)
+                .collect::<std::collections::HashSet<_>>()
+                .len()
+        };
+        assert!(distinct(&toon) * 20 < distinct(&img), "{}", distinct(&toon));
+        let levels = (0..CARTOON_LEVELS)
+            .map(|i| (i as f32 * 255.0 / (CARTOON_LEVELS - 1) as f32).round() as u8)
+            .collect::<Vec<_>>();
+        assert!(toon
+            .pixels()
+            .all(|p| p.0.iter().all(|v| levels.contains(v))));
+
+        for y in 0..32 {
+            assert!(
+                (29..35).any(|x| toon.get_pixel(x, y).0 == [0; 3]),
+                "row {}",
+                y
+            );
+            for x in (0..24).chain(40..64) {
+                assert_ne!(toon.get_pixel(x, y).0, [0; 3], "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn tilt_shift_keeps_the_band_sharp_and_blurs_gradually_away_from_it() {
+        let stripes = image::GrayImage::from_fn(64, 96, |x, _| {
+            image::Luma([if x % 4 < 2 { 0 } else { 255 }])
+        });
+        let infile = temp_path("tilt-shift-in.png");
+        stripes.save(&infile).unwrap();
+        let outfile = temp_path("tilt-shift-out.png");
+        tilt_shift(infile, outfile.clone(), 48, 16, 4.0, 1.0);
+        let img = image::open(&outfile).unwrap().to_luma8();
+
+        // The average difference between neighboring pixels of each row.
+        let contrast = (0..96)
+            .map(|y| {
+                (1..64)
+                    .map(|x| {
+                        (img.get_pixel(x, y)[0] as f32 - img.get_pixel(x - 1, y)[0] as f32).abs()
+                    })
+                    .sum::<f32>()
+                    / 63.0
+            })
+            .collect::<Vec<_>>();
+        let sharp = 255.0 * 31.0 / 63.0;
+        for (y, row) in contrast.iter().enumerate().take(56).skip(40) {
+            assert!((row - sharp).abs() < 0.5, "row {}: {}", y, row);
+        }
+        assert!(contrast[0] < sharp / 10.0 && contrast[95] < sharp / 10.0);
+        // No jumps from one row to the next.
+        for y in 0..95 {
+            assert!(
+                (contrast[y] - contrast[y + 1]).abs() < sharp / 4.0,
+                "row {}",
+                y
+            );
+        }
+    }
+
+    #[test]
+    fn saliency_is_highest_on_a_lone_object() {
+        let inside = |x: u32, y: u32| (80..100).contains(&x) && (20..40).contains(&y);
+        // The method expects the spectrum of a natural scene, which a plain background doesn't have,
+        // so the square sits on dim clouds.
+        let background = temp_path("saliency-clouds.png");
+        clouds(background.clone(), 128, 96, 5, 7, cancel::install());
+        let background = image::open(&background).unwrap().to_luma8();
+        let img = image::GrayImage::from_fn(128, 96, |x, y| {
+            image::Luma([if inside(x, y) {
+                250
             } else {
-                2.0
+                background.get_pixel(x, y)[0] / 2
+            }])
+        });
+        let infile = temp_path("saliency-in.png");
+        img.save(&infile).unwrap();
+        let outfile = temp_path("saliency-out.png");
+        saliency(infile, outfile.clone());
+        let map = image::open(&outfile).unwrap().to_luma8();
+        assert_eq!(map.dimensions(), (128, 96));
+
+        let (mut object, mut background) = ((0.0, 0), (0.0, 0));
+        for (x, y, pixel) in map.enumerate_pixels() {
+            let sums = if inside(x, y) {
+                &mut object
+            } else {
+                &mut background
             };
-            blur(infile, outfile, blur_amount);
+            sums.0 += pixel[0] as f64;
+            sums.1 += 1;
         }
+        let object = object.0 / object.1 as f64;
+        let background = background.0 / background.1 as f64;
+        assert!(object > 3.0 * background, "{} vs {}", object, background);
 
-        Commands::Brighten {
-            infile,
-            outfile,
-            brightness_amount,
-        } => {
-            let brightness_amount = if let Some(brightness_amount) = brightness_amount {
-                brightness_amount
+        let (x, y, _) = map.enumerate_pixels().max_by_key(|(_, _, p)| p[0]).unwrap();
+        assert!(
+            (72..108).contains(&x) && (12..48).contains(&y),
+            "({}, {})",
+            x,
+            y
+        );
+    }
+
+    #[test]
+    fn organize_files_photos_into_dated_folders() {
+        let indir = temp_path("organize-in");
+        std::fs::create_dir_all(&indir).unwrap();
+        for (name, date) in [
+            ("x.jpg", Some("2020:06:15 18:00:00")),
+            ("y.jpg", Some("2020:06:15 09:30:00")),
+            ("z.jpg", Some("2021:01:02 12:00:00")),
+            ("n.jpg", None),
+        ] {
+            let exif = Exif {
+                date,
+                ..Exif::default()
+            };
+            let bytes = jpeg_with_exif(&gradient(16, 16), &exif);
+            std::fs::write(format!("{}/{}", indir, name), bytes).unwrap();
+        }
+        let outdir = temp_path("organize-out");
+        organize(
+            indir.clone(),
+            outdir.clone(),
+            "{year}/{month}/{date}_{seq}.jpg".to_string(),
+            false,
+        );
+
+        for (source, target) in [
+            ("y.jpg", "2020/06/2020-06-15_001.jpg"),
+            ("x.jpg", "2020/06/2020-06-15_002.jpg"),
+            ("z.jpg", "2021/01/2021-01-02_001.jpg"),
+            ("n.jpg", "undated/n.jpg"),
+        ] {
+            let copied = std::fs::read(format!("{}/{}", outdir, target)).unwrap();
+            assert_eq!(
+                copied,
+                std::fs::read(format!("{}/{}", indir, source)).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn screen_door_at_half_opacity_keeps_half_the_pixels_in_the_bayer_pattern() {
+        let infile = temp_path("screen-door-in.png");
+        gradient(16, 16).save(&infile).unwrap();
+        let outfile = temp_path("screen-door-out.png");
+        screen_door(infile, outfile.clone(), 0.5);
+        let img = image::open(&outfile).unwrap().to_rgba8();
+
+        let opaque = img.pixels().filter(|p| p[3] == 255).count();
+        assert_eq!(opaque, 16 * 16 / 2);
+        for (x, y, pixel) in img.enumerate_pixels() {
+            let expected = if BAYER_8X8[y as usize % 8][x as usize % 8] < 32 {
+                255
             } else {
-                10
+                0
             };
-            brighten(infile, outfile, brightness_amount);
+            assert_eq!(pixel[3], expected, "({}, {})", x, y);
+            assert_eq!(pixel.0[..3], gradient(16, 16).get_pixel(x, y).0);
         }
+        // Every 2x2 block is half covered, so there are no clumps.
+        for y in (0..16).step_by(2) {
+            for x in (0..16).step_by(2) {
+                let covered = (0..4)
+                    .filter(|i| img.get_pixel(x + i % 2, y + i / 2)[3] == 255)
+                    .count();
+                assert_eq!(covered, 2, "({}, {})", x, y);
+            }
+        }
+    }
 
-        Commands::Rotate {
-            infile,
-            outfile,
-            rotation_amount,
-        } => {
-            rotate(infile, outfile, rotation_amount);
+    #[test]
+    fn ordered_dither_turns_flat_gray_into_the_bayer_pattern_of_its_neighboring_levels() {
+        let infile = temp_path("ordered-dither-in.png");
+        image::GrayImage::from_pixel(16, 16, image::Luma([64]))
+            .save(&infile)
+            .unwrap();
+        for matrix_size in [2, 4, 8] {
+            let outfile = temp_path(&format!("ordered-dither-{}.png", matrix_size));
+            // Three levels, 0, 128 and 255, so 64 is about halfway between the lower two.
+            ordered_dither(infile.clone(), outfile.clone(), 3, matrix_size);
+            let img = image::open(&outfile).unwrap().to_luma8();
+            for (x, y, pixel) in img.enumerate_pixels() {
+                let expected = if bayer_threshold(x, y, matrix_size) < 0.5 {
+                    128
+                } else {
+                    0
+                };
+                assert_eq!(pixel[0], expected, "({}, {}) of {}", x, y, matrix_size);
+            }
+            let light = img.pixels().filter(|p| p[0] == 128).count();
+            assert_eq!(light, 16 * 16 / 2);
         }
+    }
 
-        Commands::Grayscale { infile, outfile } => {
-            grayscale(infile, outfile);
+    #[test]
+    fn a_block_is_a_still_life() {
+        let (width, height) = (6, 5);
+        let mut block = vec![false; width * height];
+        for (x, y) in [(2, 1), (3, 1), (2, 2), (3, 2)] {
+            block[y * width + x] = true;
+        }
+        let mut cells = block.clone();
+        for _ in 0..10 {
+            cells = life_step(&cells, width, height);
+            assert_eq!(cells, block);
         }
 
-        Commands::Crop {
-            infile,
-            outfile,
-            x,
-            y,
-            width,
-            height,
-        } => {
-            crop(infile, outfile, x, y, width, height);
+        // A blinker, on the other hand, flips between a row and a column.
+        let mut blinker = vec![false; width * height];
+        for x in 1..4 {
+            blinker[2 * width + x] = true;
         }
+        let flipped = life_step(&blinker, width, height);
+        assert_ne!(flipped, blinker);
+        assert_eq!(life_step(&flipped, width, height), blinker);
+    }
 
-        Commands::Generate {
-            outfile,
-            width,
-            height,
-            stripe_orientation,
-            colors,
-        } => {
-            let colors = colors
-                .iter()
-                .map(|color_string| {
-                    let split_vals = color_string.split(":");
-                    let vec_vals = split_vals.collect::<Vec<&str>>();
-
-                    Color {
-                        red: vec_vals[0].parse::<u8>().unwrap(),
-                        green: vec_vals[1].parse::<u8>().unwrap(),
-                        blue: vec_vals[2].parse::<u8>().unwrap(),
-                    }
-                })
-                .collect::<Vec<Color>>();
+    #[test]
+    fn depth_blur_keeps_the_focus_depth_sharp_and_blurs_the_rest() {
+        let stripes = image::GrayImage::from_fn(64, 48, |x, _| {
+            image::Luma([if x % 4 < 2 { 0 } else { 255 }])
+        });
+        let infile = temp_path("depth-blur-in.png");
+        stripes.save(&infile).unwrap();
+        // Near at the top, far at the bottom, and the middle third at the focus depth.
+        let depth =
+            image::GrayImage::from_fn(64, 48, |_, y| image::Luma([[0, 128, 255][y as usize / 16]]));
+        let depth_map = temp_path("depth-blur-map.png");
+        depth.save(&depth_map).unwrap();
+        let outfile = temp_path("depth-blur-out.png");
+        depth_blur(infile, depth_map, outfile.clone(), 128.0 / 255.0, 4.0, true);
+        let img = image::open(&outfile).unwrap().to_luma8();
 
-            generate(outfile, width, height, colors, stripe_orientation);
+        let contrast = |y: u32| {
+            (1..64)
+                .map(|x| (img.get_pixel(x, y)[0] as i32 - img.get_pixel(x - 1, y)[0] as i32).abs())
+                .sum::<i32>()
+        };
+        for y in 16..32 {
+            for x in 0..64 {
+                assert_eq!(img.get_pixel(x, y), stripes.get_pixel(x, y));
+            }
         }
+        let sharp = 255 * 32;
+        for y in (0..8).chain(40..48) {
+            assert!(contrast(y) < sharp / 10, "row {}: {}", y, contrast(y));
+        }
+    }
 
-        Commands::Invert { infile, outfile } => {
-            invert(infile, outfile);
+    #[test]
+    fn spectrum_puts_time_across_frequency_up_and_loud_bins_at_the_hot_end() {
+        // Two frames of three frequency bins, the last bin of the second frame the loudest.
+        let infile = temp_path("spectrum.csv");
+        std::fs::write(&infile, "0, 1, 2\n3, 4, 9\n").unwrap();
+        let outfile = temp_path("spectrum.png");
+        spectrum(infile, outfile.clone(), colormap::Colormap::Inferno);
+        let img = image::open(&outfile).unwrap().to_rgb8();
+
+        assert_eq!(img.dimensions(), (2, 3));
+        let sample = |value: f32| colormap::Colormap::Inferno.sample(value / 9.0);
+        assert_eq!(img.get_pixel(1, 0).0, sample(9.0));
+        assert_eq!(img.get_pixel(0, 2).0, sample(0.0));
+        assert_eq!(img.get_pixel(1, 2).0, sample(3.0));
+        assert_eq!(img.get_pixel(0, 0).0, sample(2.0));
+    }
+
+    #[test]
+    fn defringing_desaturates_a_purple_fringe_and_keeps_the_edge() {
+        // Dark on the left, bright on the right, with a purple fringe on the bright side of the edge.
+        let img = image::RgbImage::from_fn(32, 16, |x, _| match x {
+            0..=15 => image::Rgb([20, 20, 20]),
+            16 => image::Rgb([180, 60, 200]),
+            _ => image::Rgb([230, 230, 230]),
+        });
+        let infile = temp_path("defringe-in.png");
+        img.save(&infile).unwrap();
+        let outfile = temp_path("defringe-out.png");
+        defringe_correct(infile, outfile.clone(), 1.0);
+        let corrected = image::open(&outfile).unwrap().to_rgb8();
+
+        let chroma = |p: &image::Rgb<u8>| p.0.iter().max().unwrap() - p.0.iter().min().unwrap();
+        let luma =
+            |p: &image::Rgb<u8>| 0.2126 * p[0] as f32 + 0.7152 * p[1] as f32 + 0.0722 * p[2] as f32;
+        for y in 0..16 {
+            let fringe = corrected.get_pixel(16, y);
+            assert!(chroma(fringe) <= 2, "{:?}", fringe);
+            assert!((luma(fringe) - luma(img.get_pixel(16, y))).abs() < 2.0);
+            for x in (0..16).chain(17..32) {
+                assert_eq!(corrected.get_pixel(x, y), img.get_pixel(x, y));
+            }
         }
+    }
 
-        Commands::Fractal { outfile } => {
-            fractal(outfile);
+    #[test]
+    fn scan_crop_straightens_a_skewed_page() {
+        // A 60x40 page turned by 10 degrees about the middle of a dark 120x90 photo.
+        let (sin, cos) = 10f64.to_radians().sin_cos();
+        let img = image::GrayImage::from_fn(120, 90, |x, y| {
+            let (dx, dy) = (x as f64 + 0.5 - 60.0, y as f64 + 0.5 - 45.0);
+            let (u, v) = (dx * cos + dy * sin, -dx * sin + dy * cos);
+            image::Luma([if u.abs() < 30.0 && v.abs() < 20.0 {
+                235
+            } else {
+                25
+            }])
+        });
+        let infile = temp_path("scan-crop-in.png");
+        img.save(&infile).unwrap();
+        let outfile = temp_path("scan-crop-out.png");
+        scan_crop(infile, outfile.clone());
+        let page = image::open(&outfile).unwrap().to_luma8();
+
+        let (width, height) = page.dimensions();
+        assert!(
+            (58..=62).contains(&width) && (38..=42).contains(&height),
+            "{}x{}",
+            width,
+            height
+        );
+        // Everything but a pixel or two at the edges, where the page's own edge is sampled, is page.
+        for (x, y, pixel) in page.enumerate_pixels() {
+            if (2..width - 2).contains(&x) && (2..height - 2).contains(&y) {
+                assert!(pixel[0] > 200, "({}, {}): {}", x, y, pixel[0]);
+            }
         }
     }
-}
 
-fn blur(infile: String, outfile: String, blur_amount: f32) {
-    let img = image::open(infile).expect("Failed to open INFILE.");
-    img.blur(blur_amount)
-        .save(outfile)
-        .expect("Failed writing OUTFILE.");
-}
+    #[test]
+    fn perspective_on_the_image_corners_is_a_plain_resize() {
+        let infile = temp_path("perspective-in.png");
+        gradient(32, 24).save(&infile).unwrap();
+        let corners = ["0,0", "32,0", "32,24", "0,24"]
+            .iter()
+            .map(|corner| corner.to_string())
+            .collect::<Vec<_>>();
+        let warp = |width, height, name: &str| {
+            let outfile = temp_path(name);
+            perspective(
+                infile.clone(),
+                outfile.clone(),
+                corners.clone(),
+                width,
+                height,
+            );
+            image::open(&outfile).unwrap().to_rgb8()
+        };
 
-fn brighten(infile: String, outfile: String, brightness_amount: i32) {
-    let img = image::open(infile).expect("Failed to open INFILE.");
-    img.brighten(brightness_amount)
-        .save(outfile)
-        .expect("Failed writing OUTFILE.");
-}
+        assert_eq!(warp(None, None, "perspective-same.png"), gradient(32, 24));
+        // At half size every output pixel center lands where four input pixels meet.
+        let half = warp(Some(16), Some(12), "perspective-half.png");
+        let original = gradient(32, 24);
+        for (x, y, pixel) in half.enumerate_pixels() {
+            for c in 0..3 {
+                let average = (0..4)
+                    .map(|i| original.get_pixel(2 * x + i % 2, 2 * y + i / 2)[c] as f32)
+                    .sum::<f32>()
+                    / 4.0;
+                assert!((pixel[c] as f32 - average).abs() <= 1.0, "({}, {})", x, y);
+            }
+        }
+    }
 
-fn crop(infile: String, outfile: String, x: u32, y: u32, width: u32, height: u32) {
-    let mut img = image::open(infile).expect("Failed to open INFILE.");
-    img.crop(x, y, width, height)
-        .save(outfile)
-        .expect("Failed writing OUTFILE.");
-}
+    #[test]
+    fn a_maze_has_exactly_one_path_between_any_two_cells() {
+        let (width, height) = (8u32, 6u32);
+        let outfile = temp_path("maze.png");
+        maze(outfile.clone(), width, height, 3, 11);
+        let img = image::open(&outfile).unwrap().to_luma8();
+        assert_eq!(
+            img.dimensions(),
+            ((2 * width + 1) * 3, (2 * height + 1) * 3)
+        );
+        let open = |bx: u32, by: u32| img.get_pixel(bx * 3 + 1, by * 3 + 1)[0] == 255;
 
-fn rotate(infile: String, outfile: String, rotation_amount: u32) {
-    let img = image::open(infile).expect("Failed to open INFILE.");
+        // Every cell is open, and a spanning tree of them has one passage fewer than cells.
+        let mut passages = 0;
+        for by in 1..2 * height {
+            for bx in 1..2 * width {
+                match (bx % 2, by % 2) {
+                    (1, 1) => assert!(open(bx, by)),
+                    (0, 0) => assert!(!open(bx, by)),
+                    _ if open(bx, by) => passages += 1,
+                    _ => {}
+                }
+            }
+        }
+        assert_eq!(passages, width * height - 1);
 
-    match rotation_amount {
-        90 => img.rotate90(),
-        180 => img.rotate180(),
-        270 => img.rotate270(),
-        _ => panic!("{} is not a valid rotation amount!", rotation_amount),
+        // And all cells are connected, so there are no loops either.
+        let mut seen = vec![false; (width * height) as usize];
+        let mut stack = vec![(0, 0)];
+        seen[0] = true;
+        while let Some((x, y)) = stack.pop() {
+            for (dx, dy) in [(0i64, -1i64), (1, 0), (0, 1), (-1, 0)] {
+                let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                if nx < 0 || ny < 0 || nx >= width as i64 || ny >= height as i64 {
+                    continue;
+                }
+                let wall = ((2 * x + 1) as i64 + dx, (2 * y + 1) as i64 + dy);
+                let (nx, ny) = (nx as u32, ny as u32);
+                if open(wall.0 as u32, wall.1 as u32) && !seen[(ny * width + nx) as usize] {
+                    seen[(ny * width + nx) as usize] = true;
+                    stack.push((nx, ny));
+                }
+            }
+        }
+        assert!(seen.iter().all(|&cell| cell));
     }
-    .save(outfile)
-    .expect("Failed writing OUTFILE.");
-}
 
-fn invert(infile: String, outfile: String) {
-    let mut img = image::open(infile).expect("Failed to open INFILE.");
-    img.invert();
-    img.save(outfile).expect("Failed writing OUTFILE.");
-}
+    #[test]
+    fn local_stats_of_a_flat_area_are_its_value_and_no_spread() {
+        let infile = temp_path("local-stats-in.png");
+        // Flat 90 on the left, a 0/200 checkerboard on the right.
+        image::GrayImage::from_fn(40, 20, |x, y| {
+            image::Luma([if x < 20 {
+                90
+            } else {
+                ((x + y) % 2 * 200) as u8
+            }])
+        })
+        .save(&infile)
+        .unwrap();
+        let stat = |stat, name: &str| {
+            let outfile = temp_path(name);
+            local_stats(infile.clone(), outfile.clone(), 5, stat);
+            image::open(&outfile).unwrap().to_luma8()
+        };
 
-fn grayscale(infile: String, outfile: String) {
-    let img = image::open(infile).expect("Failed to open INFILE.");
-    img.grayscale()
-        .save(outfile)
-        .expect("Failed writing OUTFILE.");
-}
+        let (mean, variance, std_dev) = (
+            stat(LocalStat::Mean, "local-mean.png"),
+            stat(LocalStat::Variance, "local-variance.png"),
+            stat(LocalStat::StdDev, "local-stddev.png"),
+        );
+        for y in 0..20 {
+            for x in 0..17 {
+                assert_eq!(mean.get_pixel(x, y)[0], 90);
+                assert_eq!(variance.get_pixel(x, y)[0], 0);
+                assert_eq!(std_dev.get_pixel(x, y)[0], 0);
+            }
+            // A 5x5 window over the checkerboard holds 12 or 13 of each value, so its standard
+            // deviation is within a hair of 100.
+            for x in 25..38 {
+                assert!((std_dev.get_pixel(x, y)[0] as f64 - 100.0 / 127.5 * 255.0).abs() <= 2.0);
+            }
+        }
+    }
 
-struct Color {
-    red: u8,
-    green: u8,
-    blue: u8,
-}
+    #[test]
+    fn motion_mask_marks_only_what_moved() {
+        let (frame_a, frame_b) = (temp_path("motion-a.png"), temp_path("motion-b.png"));
+        let before = gradient(48, 32);
+        let mut after = before.clone();
+        // A 10x8 object appears, along with a single speck of sensor noise.
+        for y in 12..20 {
+            for x in 20..30 {
+                after.put_pixel(x, y, image::Rgb([255, 0, 255]));
+            }
+        }
+        after.put_pixel(5, 5, image::Rgb([0, 255, 0]));
+        before.save(&frame_a).unwrap();
+        after.save(&frame_b).unwrap();
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
-enum StripeOrientation {
-    Vertical,
-    Horizontal,
-}
+        let mask = |cleanup, name: &str| {
+            let outfile = temp_path(name);
+            motion_mask(
+                frame_a.clone(),
+                frame_b.clone(),
+                outfile.clone(),
+                30,
+                cleanup,
+            );
+            image::open(&outfile).unwrap().to_luma8()
+        };
+        let object = |x, y| (20..30).contains(&x) && (12..20).contains(&y);
+        for (x, y, pixel) in mask(1, "motion-clean.png").enumerate_pixels() {
+            assert_eq!(pixel[0] == 255, object(x, y), "({}, {})", x, y);
+        }
+        let raw = mask(0, "motion-raw.png");
+        assert_eq!(raw.get_pixel(5, 5)[0], 255);
+        assert_eq!(raw.pixels().filter(|p| p[0] == 255).count(), 81);
+    }
 
-fn generate(
-    outfile: String,
-    width: u32,
-    height: u32,
-    colors: Vec<Color>,
-    stripe_orientation: StripeOrientation,
-) {
-    let mut imgbuf = image::ImageBuffer::new(width, height);
-    for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
-        let color_index = match stripe_orientation {
-            StripeOrientation::Vertical => (x as usize) / ((width as usize) / colors.len()),
-            StripeOrientation::Horizontal => (y as usize) / ((height as usize) / colors.len()),
+    #[test]
+    fn test_patterns_have_their_landmarks() {
+        let pattern = |kind, name: &str| {
+            let outfile = temp_path(name);
+            test_pattern(outfile.clone(), 64, 48, kind);
+            image::open(&outfile).unwrap().to_rgb8()
         };
-        let curr_color = &colors[color_index];
-        *pixel = image::Rgb([curr_color.red, curr_color.green, curr_color.blue]);
+
+        let ramp = pattern(TestPatternType::GrayRamp, "pattern-ramp.png");
+        for y in 0..48 {
+            assert_eq!(ramp.get_pixel(0, y), &image::Rgb([0; 3]));
+            assert_eq!(ramp.get_pixel(63, y), &image::Rgb([255; 3]));
+            for x in 1..64 {
+                assert!(ramp.get_pixel(x, y)[0] > ramp.get_pixel(x - 1, y)[0]);
+            }
+        }
+
+        let bars = pattern(TestPatternType::ColorBars, "pattern-bars.png");
+        for (bar, color) in SMPTE_BARS.iter().enumerate() {
+            assert_eq!(bars.get_pixel(bar as u32 * 64 / 7 + 4, 10).0, *color);
+        }
+
+        // Squares are 6 pixels, an eighth of the shorter side.
+        let board = pattern(TestPatternType::Checkerboard, "pattern-board.png");
+        assert_eq!(board.get_pixel(0, 0), &image::Rgb([255; 3]));
+        assert_eq!(board.get_pixel(6, 0), &image::Rgb([0; 3]));
+        assert_eq!(board.get_pixel(6, 6), &image::Rgb([255; 3]));
     }
 
-    imgbuf.save(outfile).unwrap();
-}
+    #[test]
+    fn selective_color_only_touches_the_target_family() {
+        let infile = temp_path("selective-in.png");
+        let colors = [[200, 30, 30], [30, 30, 200], [128, 128, 128]];
+        image::RgbImage::from_fn(3, 1, |x, _| image::Rgb(colors[x as usize]))
+            .save(&infile)
+            .unwrap();
+        let outfile = temp_path("selective-out.png");
+        selective_color(
+            infile,
+            outfile.clone(),
+            ColorFamily::Reds,
+            [50.0, 0.0, 0.0],
+            0.0,
+        );
+        let result = image::open(&outfile).unwrap().to_rgb8();
 
-// This code was adapted from https://github.com/PistonDevelopers/image
-fn fractal(outfile: String) {
-    let width = 800;
-    let height = 800;
+        // The red counts as (200 - 30) / 255 red, so it gets two thirds of the 50% cyan.
+        let red = result.get_pixel(0, 0);
+        assert!((red[0] as f32 - (200.0 - 255.0 * 0.5 * 170.0 / 255.0)).abs() <= 1.0);
+        assert_eq!((red[1], red[2]), (30, 30));
+        assert_eq!(result.get_pixel(1, 0).0, colors[1]);
+        assert_eq!(result.get_pixel(2, 0).0, colors[2]);
+    }
 
-    let mut imgbuf = image::ImageBuffer::new(width, height);
+    #[test]
+    fn nl_means_smooths_noise_and_keeps_repeating_texture() {
+        let denoise = |img: &image::GrayImage, name: &str| {
+            let infile = temp_path(&format!("{}-in.png", name));
+            let outfile = temp_path(&format!("{}-out.png", name));
+            img.save(&infile).unwrap();
+            nl_means(infile, outfile.clone(), 20.0, 3, 7, cancel::install());
+            image::open(&outfile).unwrap().to_luma8()
+        };
+        let spread = |img: &image::GrayImage| {
+            let values = img.pixels().map(|p| p[0] as f64).collect::<Vec<_>>();
+            let mean = values.iter().sum::<f64>() / values.len() as f64;
+            (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64).sqrt()
+        };
 
-    let scale_x = 3.0 / width as f32;
-    let scale_y = 3.0 / height as f32;
+        let mut rng = random::Rng::new(3);
+        let noisy =
+            image::GrayImage::from_fn(32, 32, |_, _| image::Luma([108 + rng.below(41) as u8]));
+        assert!(spread(&denoise(&noisy, "nl-means-noise")) < spread(&noisy) / 2.0);
 
-    // Iterate over the coordinates and pixels of the image
-    for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
-        // Use red and blue to be a pretty gradient background
-        let red = (0.3 * x as f32) as u8;
-        let blue = (0.3 * y as f32) as u8;
+        // Every patch of the stripes has exact copies in the window, and nothing else looks like it.
+        let stripes = image::GrayImage::from_fn(32, 32, |x, _| {
+            image::Luma([if x / 2 % 2 == 0 { 40 } else { 215 }])
+        });
+        let result = denoise(&stripes, "nl-means-stripes");
+        for y in 4..28 {
+            for x in 4..28 {
+                let (before, after) = (stripes.get_pixel(x, y)[0], result.get_pixel(x, y)[0]);
+                assert!(before.abs_diff(after) <= 1, "({}, {})", x, y);
+            }
+        }
+    }
 
-        // Use green as the fractal foreground (here is the fractal math part)
-        let cx = y as f32 * scale_x - 1.5;
-        let cy = x as f32 * scale_y - 1.5;
+    #[test]
+    fn auto_background_runs_between_the_two_main_colors() {
+        let infile = temp_path("auto-background-in.png");
+        // Three quarters teal, one quarter orange.
+        image::RgbImage::from_fn(40, 40, |x, _| {
+            image::Rgb(if x < 30 {
+                [20, 120, 130]
+            } else {
+                [240, 150, 30]
+            })
+        })
+        .save(&infile)
+        .unwrap();
+        let outfile = temp_path("auto-background-out.png");
+        auto_background(infile, outfile.clone(), 30, 20);
+        let result = image::open(&outfile).unwrap().to_rgb8();
+        assert_eq!(result.dimensions(), (30, 20));
+        assert_eq!(result.get_pixel(0, 0).0, [20, 120, 130]);
+        assert_eq!(result.get_pixel(29, 19).0, [240, 150, 30]);
+        assert_eq!(result.get_pixel(29, 0), result.get_pixel(0, 19));
+    }
 
-        let c = num_complex::Complex::new(-0.4, 0.6);
-        let mut z = num_complex::Complex::new(cx, cy);
+    #[test]
+    fn a_three_star_badge_fills_three_stars() {
+        let infile = temp_path("badge-in.png");
+        image::RgbImage::from_pixel(100, 60, image::Rgb([255; 3]))
+            .save(&infile)
+            .unwrap();
+        let outfile = temp_path("badge-out.png");
+        badge(
+            infile,
+            outfile.clone(),
+            BadgeKind::Stars,
+            "3".to_string(),
+            Corner::TopLeft,
+        );
+        let result = image::open(&outfile).unwrap().to_rgb8();
 
-        let mut green = 0;
-        while green < 255 && z.norm() <= 2.0 {
-            z = z * z + c;
-            green += 1;
+        // At this size stars are 10 pixels wide with 2 between them, on a plate 4 pixels in with 2 of
+        // padding. The plate darkens the white underneath it to 40%.
+        assert_eq!(result.get_pixel(0, 0).0, [255; 3]);
+        assert_eq!(result.get_pixel(4, 4).0, [102; 3]);
+        let (filled, empty) = (BADGE_FILLED, BADGE_EMPTY);
+        for star in 0..5 {
+            let left = 6 + star * 12;
+            let count = |color| {
+                (left..left + 10)
+                    .flat_map(|x| (6..16).map(move |y| (x, y)))
+                    .filter(|&(x, y)| result.get_pixel(x, y) == &color)
+                    .count()
+            };
+            let (expected, other) = if star < 3 {
+                (filled, empty)
+            } else {
+                (empty, filled)
+            };
+            assert_eq!(result.get_pixel(left + 5, 11), &expected);
+            // A regular star covers a bit over a quarter of its bounding square.
+            assert!((15..45).contains(&count(expected)), "star {}", star);
+            assert_eq!(count(other), 0);
+            assert_eq!(result.get_pixel(left, 15).0, [102; 3]);
         }
+    }
+
+    #[test]
+    fn auto_levels_stretches_past_stray_pixels() {
+        let infile = temp_path("auto-levels-in.png");
+        let mut img = image::GrayImage::from_fn(64, 32, |x, _| image::Luma([60 + 2 * x as u8]));
+        img.put_pixel(0, 0, image::Luma([0]));
+        img.put_pixel(63, 31, image::Luma([255]));
+        img.save(&infile).unwrap();
+        let levels = |clip, name: &str| {
+            let outfile = temp_path(name);
+            auto_levels(infile.clone(), outfile.clone(), clip);
+            image::open(&outfile).unwrap().to_luma8()
+        };
 
-        // Actually set the pixel. red, green, and blue are u8 values!
-        *pixel = image::Rgb([red, green, blue]);
+        // Without clipping the two stray pixels already span the whole range.
+        assert_eq!(levels(0.0, "auto-levels-none.png"), img);
+        let result = levels(1.0, "auto-levels-out.png");
+        assert_eq!(result.get_pixel(0, 5)[0], 0);
+        assert_eq!(result.get_pixel(63, 5)[0], 255);
+        let middle = (124.0 - 60.0) / (186.0 - 60.0) * 255.0;
+        assert!((result.get_pixel(32, 5)[0] as f32 - middle).abs() <= 1.0);
+        assert_eq!(result.get_pixel(0, 0)[0], 0);
+        assert_eq!(result.get_pixel(63, 31)[0], 255);
     }
 
-    imgbuf.save(outfile).unwrap();
-}
+    #[test]
+    fn frequency_separation_recombines_to_within_one_level() {
+        let infile = temp_path("separation-in.png");
+        let mut rng = random::Rng::new(8);
+        let mut img = gradient(40, 30);
+        for pixel in img.pixels_mut() {
+            for c in 0..3 {
+                pixel[c] = pixel[c].saturating_add(rng.below(60) as u8);
+            }
+        }
+        img.save(&infile).unwrap();
+        let (low, high) = (
+            temp_path("separation-low.png"),
+            temp_path("separation-high.png"),
+        );
+        frequency_separation(infile, low.clone(), high.clone(), 3.0);
+        let outfile = temp_path("separation-recombined.png");
+        recombine(low, high, outfile.clone());
 
-// **SUPER CHALLENGE FOR LATER** - Let's face it, you don't have time for this during class.
-//
-// Make all of the subcommands stackable!
-//
-// For example, if you run:
-//
-//   cargo run infile.png outfile.png blur 2.5 invert rotate 180 brighten 10
-//
-// ...then your program would:
-// - read infile.png
-// - apply a blur of 2.5
-// - invert the colors
-// - rotate the image 180 degrees clockwise
-// - brighten the image by 10
-// - and write the result to outfile.png
-//
-// Good luck!
+        let result = image::open(&outfile).unwrap().to_rgb8();
+        for (original, pixel) in img.pixels().zip(result.pixels()) {
+            for c in 0..3 {
+                assert!(original[c].abs_diff(pixel[c]) <= 1);
+            }
+        }
+    }
+
+    #[test]
+    fn weighted_stripes_split_the_axis_by_weight() {
+        let outfile = temp_path("weighted-stripes.png");
+        weighted_stripes(
+            outfile.clone(),
+            40,
+            8,
+            StripeOrientation::Vertical,
+            vec!["255:0:0:3".to_string(), "0:0:255:1".to_string()],
+        );
+        let result = image::open(&outfile).unwrap().to_rgb8();
+        for (x, _, pixel) in result.enumerate_pixels() {
+            let expected = if x < 30 { [255, 0, 0] } else { [0, 0, 255] };
+            assert_eq!(pixel.0, expected, "x = {}", x);
+        }
+
+        weighted_stripes(
+            outfile.clone(),
+            8,
+            40,
+            StripeOrientation::Horizontal,
+            vec!["255:0:0:3".to_string(), "0:0:255:1".to_string()],
+        );
+        let result = image::open(&outfile).unwrap().to_rgb8();
+        assert_eq!(result.get_pixel(0, 29).0, [255, 0, 0]);
+        assert_eq!(result.get_pixel(0, 30).0, [0, 0, 255]);
+    }
+
+    #[test]
+    fn film_stocks_have_their_character() {
+        let emulate = |img: &image::RgbImage, stock, name: &str| {
+            let infile = temp_path(&format!("{}-in.png", name));
+            let outfile = temp_path(&format!("{}-out.png", name));
+            img.save(&infile).unwrap();
+            film_emulate(infile, outfile.clone(), stock);
+            image::open(&outfile).unwrap().to_rgb8()
+        };
+        let flat = image::RgbImage::from_pixel(64, 64, image::Rgb([150, 110, 100]));
+
+        let tri_x = emulate(&flat, FilmStock::TriX400, "film-tri-x");
+        assert!(tri_x.pixels().all(|p| p[0] == p[1] && p[1] == p[2]));
+        let values = tri_x.pixels().map(|p| p[0] as f64).collect::<Vec<_>>();
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        let deviation =
+            (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64).sqrt();
+        assert!(deviation > 5.0, "{}", deviation);
+
+        // Velvia's grain is faint enough that every pixel ends up more colorful than before.
+        let velvia = emulate(&flat, FilmStock::Velvia50, "film-velvia");
+        for pixel in velvia.pixels() {
+            assert!(pixel[0] as i32 - pixel[2] as i32 > 50);
+        }
+    }
+
+    #[test]
+    fn the_color_wheel_has_red_at_the_top_and_white_in_the_middle() {
+        let outfile = temp_path("color-wheel.png");
+        color_wheel(outfile.clone(), 101);
+        let wheel = image::open(&outfile).unwrap().to_rgba8();
+        let hsv = |x, y| {
+            let pixel = wheel.get_pixel(x, y);
+            colorspace::rgb_to_hsv([0, 1, 2].map(|c| pixel[c] as f32 / 255.0))
+        };
+
+        let top = hsv(50, 0);
+        assert!(top[0] < 1.0 || top[0] > 359.0, "{:?}", top);
+        assert!(top[1] > 0.95);
+        assert_eq!(wheel.get_pixel(50, 50).0, [255; 4]);
+        assert!((hsv(100, 50)[0] - 90.0).abs() < 2.0);
+        assert!((hsv(50, 100)[0] - 180.0).abs() < 2.0);
+        assert_eq!(wheel.get_pixel(0, 0)[3], 0);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "OUTFILE must be written in a lossless format such as PNG, or the payload would be lost!"
+    )]
+    fn embed_refuses_a_lossy_outfile() {
+        let infile = temp_path("embed-in.png");
+        gradient(16, 16).save(&infile).unwrap();
+        embed(
+            infile,
+            temp_path("embed-out.jpg"),
+            None,
+            Some("secret".to_string()),
+            1,
+        );
+    }
+
+    #[test]
+    fn dedupe_only_deletes_images_close_to_the_one_kept() {
+        // A is 4 bits from B and B 4 from C, so with a threshold of 5 they form one group, but A and
+        // C are 8 apart.
+        let image = |name: &str, width, hash| DedupeImage {
+            path: std::path::PathBuf::from(name),
+            bytes: 1000,
+            width,
+            height: 100,
+            hash,
+        };
+        let chain = |widths: [u32; 3]| {
+            vec![
+                image("a.png", widths[0], 0x00),
+                image("b.png", widths[1], 0x0f),
+                image("c.png", widths[2], 0xff),
+            ]
+        };
+
+        // Keeping A, B is a near-duplicate of it but C isn't.
+        let images = chain([300, 200, 100]);
+        let groups = dedupe_groups(&images, 5);
+        assert_eq!(groups, vec![vec![0, 1, 2]]);
+        assert_eq!(dedupe_doomed(&images, &groups, 5), vec![1]);
+
+        // Keeping B, both ends are close to it.
+        let images = chain([200, 300, 100]);
+        let groups = dedupe_groups(&images, 5);
+        assert_eq!(groups, vec![vec![1, 0, 2]]);
+        assert_eq!(dedupe_doomed(&images, &groups, 5), vec![0, 2]);
+    }
+
+    #[test]
+    fn grayscale_input_stays_grayscale_and_small() {
+        let infile = temp_path("color-type-in.png");
+        let gray = image::GrayImage::from_fn(64, 64, |x, y| image::Luma([(x * 4 + y) as u8]));
+        gray.save(&infile).unwrap();
+        // Tilt-shift works in RGBA and hands back RGB.
+        let outfile = temp_path("color-type-out.png");
+        tilt_shift(infile, outfile.clone(), 32, 64, 0.0, 1.0);
+        let result = image::open(&outfile).unwrap();
+        assert_eq!(result.color(), image::ColorType::L8);
+        assert_eq!(result.to_luma8(), gray);
+        let rgb = temp_path("color-type-rgb.png");
+        image::DynamicImage::ImageLuma8(gray.clone())
+            .to_rgb8()
+            .save(&rgb)
+            .unwrap();
+        let size = |path: &str| std::fs::metadata(path).unwrap().len();
+        assert!(
+            size(&outfile) * 2 < size(&rgb),
+            "{} vs {}",
+            size(&outfile),
+            size(&rgb)
+        );
+
+        let rgba =
+            image::DynamicImage::ImageRgba8(image::DynamicImage::ImageLuma8(gray).to_rgba8());
+        let color = |mode, input| with_output_color_type(&rgba, mode, input).color();
+        use image::ColorType;
+        assert_eq!(color(ColorTypeMode::Narrowest, None), ColorType::L8);
+        assert_eq!(
+            color(ColorTypeMode::Keep, Some(ColorType::L8)),
+            ColorType::L8
+        );
+        assert_eq!(
+            color(ColorTypeMode::Keep, Some(ColorType::Rgb16)),
+            ColorType::Rgb16
+        );
+        assert_eq!(color(ColorTypeMode::Keep, None), ColorType::Rgba8);
+        assert_eq!(
+            color(ColorTypeMode::ForceRgba, Some(ColorType::L8)),
+            ColorType::Rgba8
+        );
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn every_command_logs_loading_decoding_and_saving() {
+        use std::io::Write;
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Clone)]
+        struct Log(Arc<Mutex<Vec<u8>>>);
+        impl Write for Log {
+            fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(bytes)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let infile = temp_path("traced_in.png");
+        let outfile = temp_path("traced_out.png");
+        gradient(8, 8).save(&infile).unwrap();
+        let log = Log(Arc::new(Mutex::new(Vec::new())));
+        let writer = log.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+            .json()
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            invert(infile.clone(), outfile.clone());
+        });
+
+        let log = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+        let spans = log
+            .lines()
+            .map(|line| {
+                line.split("\"name\":\"")
+                    .nth(1)
+                    .unwrap()
+                    .split('"')
+                    .next()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(spans, ["decode", "load", "save"]);
+        assert!(log.contains(&format!("\"path\":{}", mirage::json::string(&infile))));
+        assert!(log.contains(&format!("\"path\":{}", mirage::json::string(&outfile))));
+    }
+}
@@ -0,0 +1,78 @@
+// Seeded gradient (Perlin) noise, the building block for natural looking procedural textures.
+
+use crate::random::Rng;
+
+pub struct Perlin {
+    permutation: [u8; 512],
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut values = (0..=255u8).collect::<Vec<u8>>();
+        Rng::new(seed).shuffle(&mut values);
+        let mut permutation = [0u8; 512];
+        for (i, entry) in permutation.iter_mut().enumerate() {
+            *entry = values[i % 256];
+        }
+        Perlin { permutation }
+    }
+
+    // Classic 2D Perlin noise, roughly in `-1.0..=1.0`. The lattice has one cell per unit, so
+    // callers scale their coordinates to choose the feature size.
+    pub fn noise(&self, x: f32, y: f32) -> f32 {
+        let xi = x.floor() as i64 as usize & 255;
+        let yi = y.floor() as i64 as usize & 255;
+        let xf = x - x.floor();
+        let yf = y - y.floor();
+        let u = fade(xf);
+        let v = fade(yf);
+
+        let p = &self.permutation;
+        let aa = p[p[xi] as usize + yi];
+        let ab = p[p[xi] as usize + yi + 1];
+        let ba = p[p[xi + 1] as usize + yi];
+        let bb = p[p[xi + 1] as usize + yi + 1];
+
+        let x1 = lerp(grad(aa, xf, yf), grad(ba, xf - 1.0, yf), u);
+        let x2 = lerp(grad(ab, xf, yf - 1.0), grad(bb, xf - 1.0, yf - 1.0), u);
+        lerp(x1, x2, v)
+    }
+
+    // Fractional Brownian motion: `octaves` layers of noise, each at twice the frequency and half
+    // the amplitude of the one before, normalized back into `-1.0..=1.0`.
+    pub fn fbm(&self, x: f32, y: f32, octaves: u32) -> f32 {
+        let mut total = 0.0;
+        let mut max_amplitude = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = 1.0;
+        for _ in 0..octaves {
+            total += self.noise(x * frequency, y * frequency) * amplitude;
+            max_amplitude += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+        total / max_amplitude
+    }
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + t * (b - a)
+}
+
+// Dot product of (x, y) with one of eight gradient directions picked by `hash`.
+fn grad(hash: u8, x: f32, y: f32) -> f32 {
+    match hash & 7 {
+        0 => x + y,
+        1 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x,
+        5 => -x,
+        6 => y,
+        _ => -y,
+    }
+}
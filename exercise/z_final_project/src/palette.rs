@@ -0,0 +1,246 @@
+// Extracting an image's palette: its distinct colors and how often each one is used, optionally
+// reduced to a smaller number of representative colors by median cut (Heckbert, 1982). Also
+// reading palettes back from files and finding the nearest palette color to any other color.
+
+use crate::{colorspace, font};
+use image::{Rgb, RgbImage, RgbaImage};
+use mirage::json;
+use std::collections::HashMap;
+
+#[derive(Copy, Clone)]
+pub struct Entry {
+    pub color: [u8; 3],
+    // How many pixels have this color.
+    pub count: u64,
+}
+
+// Every color used by an opaque or partially transparent pixel, in no particular order. Fully
+// transparent pixels don't have a meaningful color and are skipped.
+pub fn distinct_colors(img: &RgbaImage) -> Vec<Entry> {
+    let mut counts = HashMap::new();
+    for p in img.pixels().filter(|p| p[3] > 0) {
+        *counts.entry([p[0], p[1], p[2]]).or_insert(0u64) += 1;
+    }
+    counts
+        .into_iter()
+        .map(|(color, count)| Entry { color, count })
+        .collect()
+}
+
+// Reduce `colors` to at most `n`. The box of colors with the widest spread along any one channel is
+// split at the pixel-weighted median of that channel until there are `n` boxes, and every box is
+// then replaced by its pixel-weighted mean color.
+pub fn median_cut(colors: &[Entry], n: usize) -> Vec<Entry> {
+    let mut boxes = vec![colors.to_vec()];
+    while boxes.len() < n {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .map(|(i, colors)| {
+                let (channel, range) = (0..3)
+                    .map(|c| {
+                        let values = colors.iter().map(|e| e.color[c]);
+                        let range = values.clone().max().unwrap() - values.min().unwrap();
+                        (c, range)
+                    })
+                    .max_by_key(|&(_, range)| range)
+                    .unwrap();
+                (i, channel, range)
+            })
+            .max_by_key(|&(_, _, range)| range);
+        let (i, channel) = match widest {
+            Some((i, channel, range)) if range > 0 => (i, channel),
+            _ => break,
+        };
+
+        let mut colors = boxes.swap_remove(i);
+        colors.sort_by_key(|e| e.color[channel]);
+        let half = colors.iter().map(|e| e.count).sum::<u64>() / 2;
+        let mut seen = 0;
+        let split = colors
+            .iter()
+            .position(|e| {
+                seen += e.count;
+                seen > half
+            })
+            .unwrap()
+            .clamp(1, colors.len() - 1);
+        let upper = colors.split_off(split);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    boxes
+        .iter()
+        .map(|colors| {
+            let count = colors.iter().map(|e| e.count).sum::<u64>();
+            let color = [0, 1, 2].map(|c| {
+                let sum = colors
+                    .iter()
+                    .map(|e| e.color[c] as u64 * e.count)
+                    .sum::<u64>();
+                ((sum + count / 2) / count) as u8
+            });
+            Entry { color, count }
+        })
+        .collect()
+}
+
+// Rec. 709 luma, the same weights as everywhere else in the tool.
+pub fn luma(color: [u8; 3]) -> u32 {
+    (2126 * color[0] as u32 + 7152 * color[1] as u32 + 722 * color[2] as u32) / 10000
+}
+
+pub fn to_hex(color: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+const SWATCH_SIZE: u32 = 96;
+const SWATCHES_PER_ROW: u32 = 16;
+const LABEL_SCALE: u32 = 2;
+
+// A grid of square swatches, up to 16 to a row, each labeled with its hex code in black or white,
+// whichever stands out more.
+pub fn swatches(entries: &[Entry]) -> RgbImage {
+    let count = entries.len() as u32;
+    let columns = count.clamp(1, SWATCHES_PER_ROW);
+    let rows = count.div_ceil(SWATCHES_PER_ROW).max(1);
+    let mut img = RgbImage::from_pixel(
+        columns * SWATCH_SIZE,
+        rows * SWATCH_SIZE,
+        Rgb([255, 255, 255]),
+    );
+    for (i, entry) in entries.iter().enumerate() {
+        let left = (i as u32 % SWATCHES_PER_ROW) * SWATCH_SIZE;
+        let top = (i as u32 / SWATCHES_PER_ROW) * SWATCH_SIZE;
+        for y in top..top + SWATCH_SIZE {
+            for x in left..left + SWATCH_SIZE {
+                img.put_pixel(x, y, Rgb(entry.color));
+            }
+        }
+
+        let label = to_hex(entry.color);
+        let ink = if luma(entry.color) > 127 {
+            Rgb([0, 0, 0])
+        } else {
+            Rgb([255, 255, 255])
+        };
+        let label_x = left + (SWATCH_SIZE - font::text_width(&label, LABEL_SCALE)) / 2;
+        let label_y = top + SWATCH_SIZE - (font::GLYPH_HEIGHT + 3) * LABEL_SCALE;
+        font::draw_text(&mut img, label_x, label_y, &label, LABEL_SCALE, ink);
+    }
+    img
+}
+
+// Read a palette written as a JSON array of hex strings, or of objects with a "color" field like
+// the palette command's --json output, or as plain hex codes separated by whitespace or commas.
+// The `#` in front of a hex code is optional.
+pub fn parse_list(text: &str) -> Result<Vec<[u8; 3]>, String> {
+    let codes = if text.trim_start().starts_with('[') {
+        let value = json::parse(text)?;
+        let entries = value
+            .as_array()
+            .ok_or_else(|| "A JSON palette must be an array!".to_string())?;
+        entries
+            .iter()
+            .map(|entry| match entry {
+                json::Value::Object(fields) => fields
+                    .iter()
+                    .find(|(key, _)| key == "color")
+                    .and_then(|(_, value)| value.as_str()),
+                value => value.as_str(),
+            })
+            .map(|code| {
+                code.map(str::to_string)
+                    .ok_or_else(|| "Palette entries must be hex color strings!".to_string())
+            })
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        text.split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|code| !code.is_empty())
+            .map(str::to_string)
+            .collect()
+    };
+
+    let colors = codes
+        .into_iter()
+        .map(|code| {
+            let hex = code.strip_prefix('#').unwrap_or(&code);
+            let channel = |i: usize| {
+                hex.get(i..i + 2)
+                    .and_then(|h| u8::from_str_radix(h, 16).ok())
+            };
+            match (hex.len(), channel(0), channel(2), channel(4)) {
+                (6, Some(r), Some(g), Some(b)) => Ok([r, g, b]),
+                _ => Err(format!("{} is not a valid hex color!", code)),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if colors.is_empty() {
+        return Err("The palette has no colors!".to_string());
+    }
+    Ok(colors)
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum Distance {
+    /// Plain Euclidean distance between RGB values
+    Rgb,
+    /// RGB distance weighted by how sensitive the eye is to each channel ("redmean")
+    Weighted,
+    /// Euclidean distance in CIE L*a*b*, the most faithful and the slowest
+    Lab,
+}
+
+// Finds the palette color closest to a given color.
+pub struct Matcher {
+    colors: Vec<[u8; 3]>,
+    lab: Vec<[f32; 3]>,
+    distance: Distance,
+}
+
+impl Matcher {
+    pub fn new(colors: Vec<[u8; 3]>, distance: Distance) -> Self {
+        let lab = match distance {
+            Distance::Lab => colors
+                .iter()
+                .map(|color| colorspace::srgb_to_lab(color.map(|c| c as f32 / 255.0)))
+                .collect(),
+            _ => Vec::new(),
+        };
+        Matcher {
+            colors,
+            lab,
+            distance,
+        }
+    }
+
+    // `color` has channels in `0.0..=255.0`, but may stray outside that range while dithering.
+    pub fn nearest(&self, color: [f32; 3]) -> [u8; 3] {
+        let index = match self.distance {
+            Distance::Rgb => self.closest(|i| {
+                let p = self.colors[i].map(|c| c as f32);
+                (0..3).map(|c| (p[c] - color[c]).powi(2)).sum()
+            }),
+            Distance::Weighted => self.closest(|i| {
+                let p = self.colors[i].map(|c| c as f32);
+                let mean_red = (p[0] + color[0]) / 2.0;
+                (2.0 + mean_red / 256.0) * (p[0] - color[0]).powi(2)
+                    + 4.0 * (p[1] - color[1]).powi(2)
+                    + (2.0 + (255.0 - mean_red) / 256.0) * (p[2] - color[2]).powi(2)
+            }),
+            Distance::Lab => {
+                let lab = colorspace::srgb_to_lab(color.map(|c| c.clamp(0.0, 255.0) / 255.0));
+                self.closest(|i| (0..3).map(|c| (self.lab[i][c] - lab[c]).powi(2)).sum())
+            }
+        };
+        self.colors[index]
+    }
+
+    fn closest(&self, distance: impl Fn(usize) -> f32) -> usize {
+        (0..self.colors.len())
+            .min_by(|&a, &b| distance(a).total_cmp(&distance(b)))
+            .unwrap()
+    }
+}
@@ -0,0 +1,380 @@
+// Stackable operations for the `chain` subcommand.
+//
+// A chain decodes its input once, runs every operation against the same working buffer, and only
+// encodes at the very end.  Operations that can work in place (invert, brighten, grayscale, crop,
+// rotate 180) mutate the working buffer directly.  The ones that can't (blur, rotate 90/270) render
+// into a single spare buffer that is ping-ponged with the working buffer, so a chain needs at most
+// two image-sized allocations no matter how many steps it has.  Resizing is the exception, since
+// its output doesn't fit the existing buffers.
+
+use image::RgbaImage;
+
+pub trait Operation {
+    // A short human readable description, including parameters, used in `--timing` output.
+    fn name(&self) -> String;
+    // The operation's parameters as key/value pairs, attached to its span in log output.
+    fn parameters(&self) -> Vec<(&'static str, String)> {
+        Vec::new()
+    }
+    fn apply(&self, buffers: &mut Buffers);
+}
+
+// The working image of a chain plus the spare allocation that out-of-place operations render into.
+pub struct Buffers {
+    image: RgbaImage,
+    spare: Vec<u8>,
+    allocations: usize,
+}
+
+impl Buffers {
+    pub fn new(image: RgbaImage) -> Self {
+        Buffers {
+            image,
+            spare: Vec::new(),
+            allocations: 1,
+        }
+    }
+
+    pub fn image(&self) -> &RgbaImage {
+        &self.image
+    }
+
+    pub fn image_mut(&mut self) -> &mut RgbaImage {
+        &mut self.image
+    }
+
+    pub fn into_image(self) -> RgbaImage {
+        self.image
+    }
+
+    // How many image-sized buffers have been allocated so far, including the decoded input.
+    pub fn allocations(&self) -> usize {
+        self.allocations
+    }
+
+    // Hand out the spare allocation as an image of the given size, only growing it when it is too
+    // small.  The contents are unspecified and must be completely overwritten by the caller.
+    pub fn take_spare(&mut self, width: u32, height: u32) -> RgbaImage {
+        let len = width as usize * height as usize * 4;
+        let mut spare = std::mem::take(&mut self.spare);
+        if spare.capacity() < len {
+            self.allocations += 1;
+        }
+        spare.resize(len, 0);
+        RgbaImage::from_raw(width, height, spare).unwrap()
+    }
+
+    // Make `output` the working image, keeping the previous working image's allocation as the
+    // spare for the next out-of-place operation.
+    pub fn replace(&mut self, output: RgbaImage) {
+        let previous = std::mem::replace(&mut self.image, output);
+        self.spare = previous.into_raw();
+    }
+
+    // Make a freshly allocated `output` the working image, for operations that can't render into
+    // the spare buffer. The allocation is counted so `--timing` stays honest.
+    pub fn replace_allocated(&mut self, output: RgbaImage) {
+        self.allocations += 1;
+        self.replace(output);
+    }
+
+    // Hand a buffer obtained from `take_spare` back without making it the working image.
+    pub fn give_back(&mut self, spare: RgbaImage) {
+        self.spare = spare.into_raw();
+    }
+}
+
+struct Blur {
+    sigma: f32,
+}
+
+impl Operation for Blur {
+    fn name(&self) -> String {
+        format!("blur {}", self.sigma)
+    }
+
+    fn parameters(&self) -> Vec<(&'static str, String)> {
+        vec![("sigma", self.sigma.to_string())]
+    }
+
+    // A separable gaussian: the horizontal pass renders into the spare buffer and the vertical pass
+    // renders back into the working buffer, so the result ends up where it started.
+    fn apply(&self, buffers: &mut Buffers) {
+        let kernel = gaussian_kernel(self.sigma);
+        let (width, height) = buffers.image().dimensions();
+        let mut spare = buffers.take_spare(width, height);
+        convolve_rows(buffers.image(), &mut spare, &kernel);
+        convolve_columns(&spare, buffers.image_mut(), &kernel);
+        buffers.give_back(spare);
+    }
+}
+
+pub fn gaussian_kernel(sigma: f32) -> Vec<f32> {
+    let sigma = sigma.max(0.01);
+    let radius = (sigma * 3.0).ceil() as i32;
+    let mut kernel = (-radius..=radius)
+        .map(|i| (-((i * i) as f32) / (2.0 * sigma * sigma)).exp())
+        .collect::<Vec<f32>>();
+    let sum: f32 = kernel.iter().sum();
+    for weight in kernel.iter_mut() {
+        *weight /= sum;
+    }
+    kernel
+}
+
+fn convolve_rows(src: &RgbaImage, dst: &mut RgbaImage, kernel: &[f32]) {
+    let (width, height) = src.dimensions();
+    let radius = (kernel.len() / 2) as i64;
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0.0f32; 4];
+            for (k, weight) in kernel.iter().enumerate() {
+                let sx = (x as i64 + k as i64 - radius).clamp(0, width as i64 - 1) as u32;
+                let pixel = src.get_pixel(sx, y);
+                for c in 0..4 {
+                    sum[c] += weight * pixel[c] as f32;
+                }
+            }
+            let out = dst.get_pixel_mut(x, y);
+            for c in 0..4 {
+                out[c] = sum[c].round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+}
+
+fn convolve_columns(src: &RgbaImage, dst: &mut RgbaImage, kernel: &[f32]) {
+    let (width, height) = src.dimensions();
+    let radius = (kernel.len() / 2) as i64;
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0.0f32; 4];
+            for (k, weight) in kernel.iter().enumerate() {
+                let sy = (y as i64 + k as i64 - radius).clamp(0, height as i64 - 1) as u32;
+                let pixel = src.get_pixel(x, sy);
+                for c in 0..4 {
+                    sum[c] += weight * pixel[c] as f32;
+                }
+            }
+            let out = dst.get_pixel_mut(x, y);
+            for c in 0..4 {
+                out[c] = sum[c].round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+}
+
+struct Brighten {
+    amount: i32,
+}
+
+impl Operation for Brighten {
+    fn name(&self) -> String {
+        format!("brighten {}", self.amount)
+    }
+
+    fn parameters(&self) -> Vec<(&'static str, String)> {
+        vec![("amount", self.amount.to_string())]
+    }
+
+    fn apply(&self, buffers: &mut Buffers) {
+        crate::raw::brighten_bytes(buffers.image_mut(), 4, true, self.amount);
+    }
+}
+
+struct Invert;
+
+impl Operation for Invert {
+    fn name(&self) -> String {
+        "invert".to_string()
+    }
+
+    fn apply(&self, buffers: &mut Buffers) {
+        crate::raw::invert_bytes(buffers.image_mut(), 4, true);
+    }
+}
+
+struct Grayscale;
+
+impl Operation for Grayscale {
+    fn name(&self) -> String {
+        "grayscale".to_string()
+    }
+
+    // Uses the same Rec. 709 luma weights as the `image` crate, but keeps the buffer RGBA.
+    fn apply(&self, buffers: &mut Buffers) {
+        for pixel in buffers.image_mut().pixels_mut() {
+            let luma =
+                (2126 * pixel[0] as u32 + 7152 * pixel[1] as u32 + 722 * pixel[2] as u32) / 10000;
+            pixel[0] = luma as u8;
+            pixel[1] = luma as u8;
+            pixel[2] = luma as u8;
+        }
+    }
+}
+
+struct Rotate {
+    degrees: u32,
+}
+
+impl Operation for Rotate {
+    fn name(&self) -> String {
+        format!("rotate {}", self.degrees)
+    }
+
+    fn parameters(&self) -> Vec<(&'static str, String)> {
+        vec![("degrees", self.degrees.to_string())]
+    }
+
+    fn apply(&self, buffers: &mut Buffers) {
+        if self.degrees == 180 {
+            // Rotating by 180 degrees is the same as reversing the order of the pixels.
+            let bytes: &mut [u8] = buffers.image_mut();
+            let pixel_count = bytes.len() / 4;
+            for i in 0..pixel_count / 2 {
+                let j = pixel_count - 1 - i;
+                for c in 0..4 {
+                    bytes.swap(i * 4 + c, j * 4 + c);
+                }
+            }
+            return;
+        }
+
+        let (width, height) = buffers.image().dimensions();
+        let mut rotated = buffers.take_spare(height, width);
+        for (x, y, pixel) in buffers.image().enumerate_pixels() {
+            if self.degrees == 90 {
+                rotated.put_pixel(height - 1 - y, x, *pixel);
+            } else {
+                rotated.put_pixel(y, width - 1 - x, *pixel);
+            }
+        }
+        buffers.replace(rotated);
+    }
+}
+
+struct Resize {
+    width: u32,
+    height: u32,
+}
+
+impl Operation for Resize {
+    fn name(&self) -> String {
+        format!("resize {} {}", self.width, self.height)
+    }
+
+    fn parameters(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("width", self.width.to_string()),
+            ("height", self.height.to_string()),
+        ]
+    }
+
+    fn apply(&self, buffers: &mut Buffers) {
+        let resized = image::imageops::resize(
+            buffers.image(),
+            self.width,
+            self.height,
+            image::imageops::FilterType::Triangle,
+        );
+        buffers.replace_allocated(resized);
+    }
+}
+
+struct Crop {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Operation for Crop {
+    fn name(&self) -> String {
+        format!("crop {} {} {} {}", self.x, self.y, self.width, self.height)
+    }
+
+    fn parameters(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("x", self.x.to_string()),
+            ("y", self.y.to_string()),
+            ("width", self.width.to_string()),
+            ("height", self.height.to_string()),
+        ]
+    }
+
+    // Every row of the cropped area starts at or after the position it is moved to, so the rows can
+    // be shifted to the front of the existing allocation without a second buffer.
+    fn apply(&self, buffers: &mut Buffers) {
+        let (img_width, img_height) = buffers.image().dimensions();
+        let x = self.x.min(img_width);
+        let y = self.y.min(img_height);
+        let width = self.width.min(img_width - x);
+        let height = self.height.min(img_height - y);
+
+        let image = std::mem::replace(buffers.image_mut(), RgbaImage::new(0, 0));
+        let mut bytes = image.into_raw();
+        let row_len = width as usize * 4;
+        for row in 0..height as usize {
+            let src = ((y as usize + row) * img_width as usize + x as usize) * 4;
+            bytes.copy_within(src..src + row_len, row * row_len);
+        }
+        bytes.truncate(row_len * height as usize);
+        *buffers.image_mut() = RgbaImage::from_raw(width, height, bytes).unwrap();
+    }
+}
+
+// Turn a list of words into operations, e.g. `blur 2.5 invert rotate 180 brighten 10`.
+pub fn parse_operations<S: AsRef<str>>(args: &[S]) -> Result<Vec<Box<dyn Operation>>, String> {
+    let mut operations: Vec<Box<dyn Operation>> = Vec::new();
+    let mut args = args.iter().map(|arg| arg.as_ref());
+    while let Some(name) = args.next() {
+        let mut next_arg = |what: &str| {
+            args.next()
+                .ok_or_else(|| format!("{} needs a {} argument!", name, what))
+        };
+        let operation: Box<dyn Operation> = match name {
+            "blur" => Box::new(Blur {
+                sigma: parse_arg(next_arg("blur amount")?, name)?,
+            }),
+            "brighten" => Box::new(Brighten {
+                amount: parse_arg(next_arg("brightness amount")?, name)?,
+            }),
+            "invert" => Box::new(Invert),
+            "grayscale" => Box::new(Grayscale),
+            "rotate" => {
+                let degrees = parse_arg(next_arg("rotation amount")?, name)?;
+                match degrees {
+                    90 | 180 | 270 => Box::new(Rotate { degrees }),
+                    _ => return Err(format!("{} is not a valid rotation amount!", degrees)),
+                }
+            }
+            "crop" => Box::new(Crop {
+                x: parse_arg(next_arg("x")?, name)?,
+                y: parse_arg(next_arg("y")?, name)?,
+                width: parse_arg(next_arg("width")?, name)?,
+                height: parse_arg(next_arg("height")?, name)?,
+            }),
+            "resize" => Box::new(Resize {
+                width: parse_arg(next_arg("width")?, name)?,
+                height: parse_arg(next_arg("height")?, name)?,
+            }),
+            _ => return Err(format!("{} is not a chainable operation!", name)),
+        };
+        operations.push(operation);
+    }
+    Ok(operations)
+}
+
+fn parse_arg<T: std::str::FromStr>(arg: &str, operation: &str) -> Result<T, String> {
+    arg.parse()
+        .map_err(|_| format!("{} is not a valid argument for {}!", arg, operation))
+}
+
+// Run `operations` in order against `image` without any timing or file access, for embedders.
+pub fn apply_all(image: RgbaImage, operations: &[Box<dyn Operation>]) -> RgbaImage {
+    let mut buffers = Buffers::new(image);
+    for operation in operations {
+        operation.apply(&mut buffers);
+    }
+    buffers.into_image()
+}
@@ -0,0 +1,462 @@
+// QR code encoding following ISO/IEC 18004. Data is always stored in byte mode, which handles any
+// input, in the smallest version (size) that holds it at the requested error correction level. The
+// mask is picked by the standard's penalty rules.
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum EcLevel {
+    /// Recovers about 7% of the code
+    L,
+    /// Recovers about 15% of the code
+    M,
+    /// Recovers about 25% of the code
+    Q,
+    /// Recovers about 30% of the code
+    H,
+}
+
+impl EcLevel {
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    // The two bits identifying the level in the format information. They are not in order.
+    fn format_bits(self) -> u32 {
+        match self {
+            EcLevel::L => 1,
+            EcLevel::M => 0,
+            EcLevel::Q => 3,
+            EcLevel::H => 2,
+        }
+    }
+}
+
+// Error correction codewords in every block, by level and version. Index 0 is unused.
+#[rustfmt::skip]
+const ECC_CODEWORDS_PER_BLOCK: [[u8; 41]; 4] = [
+    [0, 7, 10, 15, 20, 26, 18, 20, 24, 30, 18, 20, 24, 26, 30, 22, 24, 28, 30, 28, 28, 28, 28, 30, 30, 26, 28, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30],
+    [0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26, 30, 22, 22, 24, 24, 28, 28, 26, 26, 26, 26, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28],
+    [0, 13, 22, 18, 26, 18, 24, 18, 22, 20, 24, 28, 26, 24, 20, 30, 24, 28, 28, 26, 30, 28, 30, 30, 30, 30, 28, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30],
+    [0, 17, 28, 22, 16, 22, 28, 26, 26, 24, 28, 24, 28, 22, 24, 24, 30, 28, 28, 26, 28, 30, 24, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30],
+];
+
+// Number of error correction blocks the codewords are split into, by level and version.
+#[rustfmt::skip]
+const ECC_BLOCKS: [[u8; 41]; 4] = [
+    [0, 1, 1, 1, 1, 1, 2, 2, 2, 2, 4, 4, 4, 4, 4, 6, 6, 6, 6, 7, 8, 8, 9, 9, 10, 12, 12, 12, 13, 14, 15, 16, 17, 18, 19, 19, 20, 21, 22, 24, 25],
+    [0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5, 5, 8, 9, 9, 10, 10, 11, 13, 14, 16, 17, 17, 18, 20, 21, 23, 25, 26, 28, 29, 31, 33, 35, 37, 38, 40, 43, 45, 47, 49],
+    [0, 1, 1, 2, 2, 4, 4, 6, 6, 8, 8, 8, 10, 12, 16, 12, 17, 16, 18, 21, 20, 23, 23, 25, 27, 29, 34, 34, 35, 38, 40, 43, 45, 48, 51, 53, 56, 59, 62, 65, 68],
+    [0, 1, 1, 2, 4, 4, 4, 5, 6, 8, 8, 11, 11, 16, 16, 18, 16, 19, 21, 25, 25, 25, 34, 30, 32, 35, 37, 40, 42, 45, 48, 51, 54, 57, 60, 63, 66, 70, 74, 77, 81],
+];
+
+pub struct Code {
+    size: usize,
+    modules: Vec<bool>,
+}
+
+impl Code {
+    // Width and height in modules, without the quiet zone.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.size + x]
+    }
+}
+
+pub fn encode(data: &[u8], level: EcLevel) -> Result<Code, String> {
+    let version = (1..=40)
+        .find(|&version| {
+            let count_bits = if version <= 9 { 8 } else { 16 };
+            4 + count_bits + data.len() * 8 <= data_codewords(version, level) * 8
+        })
+        .ok_or_else(|| {
+            format!(
+                "{} bytes don't fit in a QR code at this error correction level!",
+                data.len()
+            )
+        })?;
+
+    // Mode indicator, character count, the data, then a terminator of up to four zero bits and
+    // alternating pad bytes to fill the capacity.
+    let capacity = data_codewords(version, level);
+    let mut bits = Bits::default();
+    bits.push(0b0100, 4);
+    bits.push(data.len() as u32, if version <= 9 { 8 } else { 16 });
+    for &byte in data {
+        bits.push(byte as u32, 8);
+    }
+    let terminator = (capacity * 8 - bits.len).min(4);
+    bits.push(0, terminator);
+    bits.push(0, (8 - bits.len % 8) % 8);
+    for pad in [0xec, 0x11]
+        .iter()
+        .cycle()
+        .take(capacity - bits.bytes.len())
+    {
+        bits.push(*pad, 8);
+    }
+
+    let codewords = add_error_correction(&bits.bytes, version, level);
+    let mut grid = Grid::new(version);
+    grid.draw_function_patterns(version);
+    grid.draw_codewords(&codewords);
+
+    let mask = (0..8)
+        .min_by_key(|&mask| {
+            grid.apply_mask(mask);
+            grid.draw_format_bits(level, mask);
+            let penalty = grid.penalty();
+            grid.apply_mask(mask);
+            penalty
+        })
+        .unwrap();
+    grid.apply_mask(mask);
+    grid.draw_format_bits(level, mask);
+
+    Ok(Code {
+        size: grid.size,
+        modules: grid.modules,
+    })
+}
+
+#[derive(Default)]
+struct Bits {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl Bits {
+    // Append the lowest `count` bits of `value`, most significant first.
+    fn push(&mut self, value: u32, count: usize) {
+        for i in (0..count).rev() {
+            if self.len.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            let bit = ((value >> i) & 1) as u8;
+            *self.bytes.last_mut().unwrap() |= bit << (7 - self.len % 8);
+            self.len += 1;
+        }
+    }
+}
+
+// Modules left for data and error correction after the function patterns, format and version
+// information are drawn.
+fn raw_data_modules(version: usize) -> usize {
+    let mut modules = (16 * version + 128) * version + 64;
+    if version >= 2 {
+        let alignment = version / 7 + 2;
+        modules -= (25 * alignment - 10) * alignment - 55;
+        if version >= 7 {
+            modules -= 36;
+        }
+    }
+    modules
+}
+
+fn data_codewords(version: usize, level: EcLevel) -> usize {
+    let ecc = ECC_CODEWORDS_PER_BLOCK[level.index()][version] as usize;
+    let blocks = ECC_BLOCKS[level.index()][version] as usize;
+    raw_data_modules(version) / 8 - ecc * blocks
+}
+
+// Split the data into blocks, compute each block's Reed-Solomon codewords and interleave it all.
+// Later blocks are one codeword longer when the data doesn't divide evenly.
+fn add_error_correction(data: &[u8], version: usize, level: EcLevel) -> Vec<u8> {
+    let ecc_len = ECC_CODEWORDS_PER_BLOCK[level.index()][version] as usize;
+    let block_count = ECC_BLOCKS[level.index()][version] as usize;
+    let raw_codewords = raw_data_modules(version) / 8;
+    let short_blocks = block_count - raw_codewords % block_count;
+    let short_len = raw_codewords / block_count - ecc_len;
+
+    let divisor = reed_solomon_divisor(ecc_len);
+    let mut blocks = Vec::with_capacity(block_count);
+    let mut start = 0;
+    for i in 0..block_count {
+        let len = short_len + usize::from(i >= short_blocks);
+        let block = &data[start..start + len];
+        blocks.push((block, reed_solomon_remainder(block, &divisor)));
+        start += len;
+    }
+
+    let mut result = Vec::with_capacity(raw_codewords);
+    for i in 0..=short_len {
+        for (block, _) in &blocks {
+            if let Some(&codeword) = block.get(i) {
+                result.push(codeword);
+            }
+        }
+    }
+    for i in 0..ecc_len {
+        for (_, ecc) in &blocks {
+            result.push(ecc[i]);
+        }
+    }
+    result
+}
+
+// Multiplication in GF(2^8) modulo x^8 + x^4 + x^3 + x^2 + 1.
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut product: u32 = 0;
+    for i in (0..8).rev() {
+        product = (product << 1) ^ ((product >> 7) * 0x11d);
+        product ^= ((y as u32 >> i) & 1) * x as u32;
+    }
+    product as u8
+}
+
+// The generator polynomial (x - 1)(x - 2)(x - 4)...(x - 2^(degree - 1)), highest coefficient
+// first, leaving out the leading 1.
+fn reed_solomon_divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0; degree];
+    result[degree - 1] = 1;
+    let mut root = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_multiply(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = gf_multiply(root, 2);
+    }
+    result
+}
+
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut result = vec![0; divisor.len()];
+    for &byte in data {
+        let factor = byte ^ result.remove(0);
+        result.push(0);
+        for (r, &d) in result.iter_mut().zip(divisor) {
+            *r ^= gf_multiply(d, factor);
+        }
+    }
+    result
+}
+
+struct Grid {
+    size: usize,
+    modules: Vec<bool>,
+    // Whether a module belongs to a function pattern, which masks and data leave alone.
+    function: Vec<bool>,
+}
+
+impl Grid {
+    fn new(version: usize) -> Self {
+        let size = version * 4 + 17;
+        Grid {
+            size,
+            modules: vec![false; size * size],
+            function: vec![false; size * size],
+        }
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+        self.function[y * self.size + x] = true;
+    }
+
+    fn draw_function_patterns(&mut self, version: usize) {
+        let size = self.size;
+        for i in 0..size {
+            self.set_function(6, i, i % 2 == 0);
+            self.set_function(i, 6, i % 2 == 0);
+        }
+
+        // Finder patterns with their light separators, in three corners.
+        for &(cx, cy) in &[(3, 3), (size - 4, 3), (3, size - 4)] {
+            for dy in -4..=4_isize {
+                for dx in -4..=4_isize {
+                    let (x, y) = (cx as isize + dx, cy as isize + dy);
+                    if (0..size as isize).contains(&x) && (0..size as isize).contains(&y) {
+                        let distance = dx.abs().max(dy.abs());
+                        self.set_function(x as usize, y as usize, distance != 2 && distance != 4);
+                    }
+                }
+            }
+        }
+
+        let positions = alignment_positions(version);
+        let last = positions.len().saturating_sub(1);
+        for (i, &cx) in positions.iter().enumerate() {
+            for (j, &cy) in positions.iter().enumerate() {
+                // These would overlap the finder patterns.
+                let corner = (i == 0 || i == last) && (j == 0 || j == last);
+                if corner && !(i == last && j == last) {
+                    continue;
+                }
+                for dy in -2..=2_isize {
+                    for dx in -2..=2_isize {
+                        let (x, y) = ((cx as isize + dx) as usize, (cy as isize + dy) as usize);
+                        self.set_function(x, y, dx.abs().max(dy.abs()) != 1);
+                    }
+                }
+            }
+        }
+
+        // Reserve the format information; the real bits go in once the mask is known.
+        self.draw_format_bits(EcLevel::L, 0);
+
+        if version >= 7 {
+            let mut remainder = version as u32;
+            for _ in 0..12 {
+                remainder = (remainder << 1) ^ ((remainder >> 11) * 0x1f25);
+            }
+            let bits = (version as u32) << 12 | remainder;
+            for i in 0..18 {
+                let dark = (bits >> i) & 1 == 1;
+                let (a, b) = (size - 11 + i % 3, i / 3);
+                self.set_function(a, b, dark);
+                self.set_function(b, a, dark);
+            }
+        }
+    }
+
+    // The 15 bits of format information, BCH coded and XORed with 0x5412, drawn twice: around the
+    // top left finder, and split between the other two.
+    fn draw_format_bits(&mut self, level: EcLevel, mask: u32) {
+        let data = level.format_bits() << 3 | mask;
+        let mut remainder = data;
+        for _ in 0..10 {
+            remainder = (remainder << 1) ^ ((remainder >> 9) * 0x537);
+        }
+        let bits = (data << 10 | remainder) ^ 0x5412;
+        let bit = |i: usize| (bits >> i) & 1 == 1;
+
+        let size = self.size;
+        for i in 0..6 {
+            self.set_function(8, i, bit(i));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i));
+        }
+
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i));
+        }
+        // Always dark.
+        self.set_function(8, size - 8, true);
+    }
+
+    // Fill the remaining modules in two-column strips from the right, zigzagging up and down and
+    // skipping the vertical timing pattern.
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let size = self.size;
+        let total_bits = codewords.len() * 8;
+        let mut i = 0;
+        let mut right = size as isize - 1;
+        while right >= 1 {
+            if right == 6 {
+                right = 5;
+            }
+            let upward = (right + 1) & 2 == 0;
+            for vertical in 0..size {
+                let y = if upward {
+                    size - 1 - vertical
+                } else {
+                    vertical
+                };
+                for x in [right as usize, right as usize - 1] {
+                    if !self.function[y * size + x] && i < total_bits {
+                        self.modules[y * size + x] = (codewords[i / 8] >> (7 - i % 8)) & 1 == 1;
+                        i += 1;
+                    }
+                }
+            }
+            right -= 2;
+        }
+    }
+
+    // XOR a mask pattern over the data modules. Applying the same mask twice undoes it.
+    fn apply_mask(&mut self, mask: u32) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                let index = y * self.size + x;
+                if invert && !self.function[index] {
+                    self.modules[index] = !self.modules[index];
+                }
+            }
+        }
+    }
+
+    // How hard the code is to scan, by the four rules of the standard: long runs of one color,
+    // 2x2 blocks of one color, patterns that look like finders, and an uneven share of dark modules.
+    fn penalty(&self) -> usize {
+        let size = self.size;
+        let at = |x: usize, y: usize| self.modules[y * size + x];
+        let mut penalty = 0;
+
+        let lines = (0..size)
+            .map(|y| (0..size).map(|x| at(x, y)).collect::<Vec<_>>())
+            .chain((0..size).map(|x| (0..size).map(|y| at(x, y)).collect::<Vec<_>>()));
+        const FINDER_BEFORE: [bool; 11] = [
+            false, false, false, false, true, false, true, true, true, false, true,
+        ];
+        const FINDER_AFTER: [bool; 11] = [
+            true, false, true, true, true, false, true, false, false, false, false,
+        ];
+        for line in lines {
+            let mut run = 1;
+            for i in 1..=size {
+                if i < size && line[i] == line[i - 1] {
+                    run += 1;
+                    continue;
+                }
+                if run >= 5 {
+                    penalty += run - 2;
+                }
+                run = 1;
+            }
+            penalty += line
+                .windows(11)
+                .filter(|window| *window == FINDER_BEFORE || *window == FINDER_AFTER)
+                .count()
+                * 40;
+        }
+
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let color = at(x, y);
+                if at(x + 1, y) == color && at(x, y + 1) == color && at(x + 1, y + 1) == color {
+                    penalty += 3;
+                }
+            }
+        }
+
+        let total = size * size;
+        let dark = self.modules.iter().filter(|&&dark| dark).count();
+        let steps = (dark * 20).abs_diff(total * 10).div_ceil(total);
+        penalty + steps.saturating_sub(1) * 10
+    }
+}
+
+// Centers of the alignment patterns along either axis; every combination gets one, except where
+// the finder patterns are.
+fn alignment_positions(version: usize) -> Vec<usize> {
+    if version == 1 {
+        return Vec::new();
+    }
+    let count = version / 7 + 2;
+    let step = (version * 8 + count * 3 + 5) / (count * 4 - 4) * 2;
+    let size = version * 4 + 17;
+    let mut positions = vec![6];
+    positions.extend((0..count - 1).rev().map(|i| size - 7 - i * step));
+    positions
+}
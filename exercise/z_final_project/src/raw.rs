@@ -0,0 +1,62 @@
+// Operations on raw interleaved subpixel buffers. Working on the bare slice avoids the per-pixel
+// get/put overhead and bounds checks of the generic `image` code paths.
+
+// Brighten interleaved 8-bit pixel data with `channels` bytes per pixel, skipping the trailing
+// alpha byte of each pixel when there is one.
+pub fn brighten_bytes(bytes: &mut [u8], channels: usize, has_alpha: bool, brightness_amount: i32) {
+    let amount = brightness_amount.clamp(-255, 255);
+    let delta = amount.unsigned_abs() as u8;
+    let color_channels = if has_alpha { channels - 1 } else { channels };
+    for pixel in bytes.chunks_exact_mut(channels) {
+        for byte in &mut pixel[..color_channels] {
+            *byte = if amount >= 0 {
+                byte.saturating_add(delta)
+            } else {
+                byte.saturating_sub(delta)
+            };
+        }
+    }
+}
+
+// The 16-bit counterpart of `brighten_bytes`. The amount is scaled by 257 so that it means the
+// same thing as it does for 8-bit images.
+pub fn brighten_words(words: &mut [u16], channels: usize, has_alpha: bool, brightness_amount: i32) {
+    let amount = brightness_amount.clamp(-255, 255);
+    let delta = amount.unsigned_abs() as u16 * 257;
+    let color_channels = if has_alpha { channels - 1 } else { channels };
+    for pixel in words.chunks_exact_mut(channels) {
+        for word in &mut pixel[..color_channels] {
+            *word = if amount >= 0 {
+                word.saturating_add(delta)
+            } else {
+                word.saturating_sub(delta)
+            };
+        }
+    }
+}
+
+// Invert interleaved 8-bit pixel data with `channels` bytes per pixel, skipping the trailing alpha
+// byte of each pixel when there is one.
+pub fn invert_bytes(bytes: &mut [u8], channels: usize, has_alpha: bool) {
+    if !has_alpha {
+        for byte in bytes.iter_mut() {
+            *byte = 255 - *byte;
+        }
+        return;
+    }
+    for pixel in bytes.chunks_exact_mut(channels) {
+        for byte in &mut pixel[..channels - 1] {
+            *byte = 255 - *byte;
+        }
+    }
+}
+
+// The 16-bit counterpart of `invert_bytes`.
+pub fn invert_words(words: &mut [u16], channels: usize, has_alpha: bool) {
+    let color_channels = if has_alpha { channels - 1 } else { channels };
+    for pixel in words.chunks_exact_mut(channels) {
+        for word in &mut pixel[..color_channels] {
+            *word = u16::MAX - *word;
+        }
+    }
+}
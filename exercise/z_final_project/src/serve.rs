@@ -0,0 +1,437 @@
+// A small HTTP server that processes images on the fly, e.g.
+//
+//     GET /photos/cat.jpg?width=400&grayscale=1&quality=80
+//
+// serves `ROOT/photos/cat.jpg` resized to 400 pixels wide, in grayscale, as an 80% quality JPEG.
+// The query parameters are turned into `chain` operations, and responses are kept in an in-memory
+// LRU cache keyed by path and query. Only built with the `serve` feature.
+
+use mirage::pipeline;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn error(status: u16, message: &str) -> Self {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: format!("{}\n", message).into_bytes(),
+        }
+    }
+}
+
+type Entry = Arc<(&'static str, Vec<u8>)>;
+
+// A least-recently-used cache holding up to `capacity` successful responses. The entries form a
+// doubly linked list through indices into `nodes`, most recently used first, so looking up,
+// refreshing and evicting an entry all take constant time.
+struct Cache {
+    capacity: usize,
+    index: HashMap<String, usize>,
+    nodes: Vec<Node>,
+    newest: Option<usize>,
+    oldest: Option<usize>,
+}
+
+struct Node {
+    key: String,
+    entry: Entry,
+    newer: Option<usize>,
+    older: Option<usize>,
+}
+
+impl Cache {
+    fn new(capacity: usize) -> Self {
+        Cache {
+            capacity,
+            index: HashMap::new(),
+            nodes: Vec::new(),
+            newest: None,
+            oldest: None,
+        }
+    }
+
+    fn get(&mut self, key: &str) -> Option<Entry> {
+        let i = *self.index.get(key)?;
+        self.unlink(i);
+        self.push_newest(i);
+        Some(self.nodes[i].entry.clone())
+    }
+
+    fn insert(&mut self, key: String, entry: Entry) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some(&i) = self.index.get(&key) {
+            self.nodes[i].entry = entry;
+            self.unlink(i);
+            self.push_newest(i);
+            return;
+        }
+        let node = Node {
+            key: key.clone(),
+            entry,
+            newer: None,
+            older: None,
+        };
+        // Once full, the least recently used entry's slot is reused for the new one.
+        let i = match self.oldest {
+            Some(oldest) if self.nodes.len() == self.capacity => {
+                self.unlink(oldest);
+                self.index.remove(&self.nodes[oldest].key);
+                self.nodes[oldest] = node;
+                oldest
+            }
+            _ => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+        self.index.insert(key, i);
+        self.push_newest(i);
+    }
+
+    fn unlink(&mut self, i: usize) {
+        let (newer, older) = (self.nodes[i].newer, self.nodes[i].older);
+        match newer {
+            Some(newer) => self.nodes[newer].older = older,
+            None => self.newest = older,
+        }
+        match older {
+            Some(older) => self.nodes[older].newer = newer,
+            None => self.oldest = newer,
+        }
+    }
+
+    fn push_newest(&mut self, i: usize) {
+        self.nodes[i].newer = None;
+        self.nodes[i].older = self.newest;
+        match self.newest {
+            Some(newest) => self.nodes[newest].newer = Some(i),
+            None => self.oldest = Some(i),
+        }
+        self.newest = Some(i);
+    }
+}
+
+pub fn serve(root: String, addr: String, cache_entries: usize, max_pixels: u64) {
+    let root = std::fs::canonicalize(&root).expect("Failed to open ROOT.");
+    let server = tiny_http::Server::http(&addr).expect("Failed to listen on ADDR.");
+    println!("Serving {} on http://{}", root.display(), addr);
+
+    let root = Arc::new(root);
+    let cache = Arc::new(Mutex::new(Cache::new(cache_entries)));
+    for request in server.incoming_requests() {
+        let root = Arc::clone(&root);
+        let cache = Arc::clone(&cache);
+        std::thread::spawn(move || handle_request(request, &root, &cache, max_pixels));
+    }
+}
+
+fn handle_request(request: tiny_http::Request, root: &Path, cache: &Mutex<Cache>, max_pixels: u64) {
+    let response = if *request.method() == tiny_http::Method::Get {
+        let key = request.url().to_string();
+        let cached = cache.lock().unwrap().get(&key);
+        match cached {
+            Some(entry) => Response {
+                status: 200,
+                content_type: entry.0,
+                body: entry.1.clone(),
+            },
+            None => {
+                let response = handle_get(request.url(), root, max_pixels);
+                if response.status == 200 {
+                    let entry = Arc::new((response.content_type, response.body.clone()));
+                    cache.lock().unwrap().insert(key, entry);
+                }
+                response
+            }
+        }
+    } else {
+        Response::error(405, "Only GET is supported.")
+    };
+
+    println!("{} {} {}", response.status, request.method(), request.url());
+    let content_type = tiny_http::Header::from_bytes("Content-Type", response.content_type)
+        .expect("Content types are valid header values.");
+    let _ = request.respond(
+        tiny_http::Response::from_data(response.body)
+            .with_status_code(response.status)
+            .with_header(content_type),
+    );
+}
+
+fn handle_get(target: &str, root: &Path, max_pixels: u64) -> Response {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let path = match resolve_path(root, &percent_decode(path, false)) {
+        Some(path) => path,
+        None => return Response::error(403, "Forbidden."),
+    };
+    if !path.is_file() {
+        return Response::error(404, "Not found.");
+    }
+
+    let params = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key, true), percent_decode(value, true))
+        })
+        .collect::<HashMap<String, String>>();
+
+    process(&path, &params, max_pixels).unwrap_or_else(|response| response)
+}
+
+// Join the request path onto `root`, refusing anything that would escape it.
+fn resolve_path(root: &Path, request_path: &str) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+    for component in request_path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => return None,
+            c if c.contains('\\') || c.contains('\0') => return None,
+            c => path.push(c),
+        }
+    }
+    // Symlinks could still point outside of the root.
+    match std::fs::canonicalize(&path) {
+        Ok(canonical) if canonical.starts_with(root) => Some(canonical),
+        Ok(_) => None,
+        Err(_) => Some(path),
+    }
+}
+
+fn process(
+    path: &Path,
+    params: &HashMap<String, String>,
+    max_pixels: u64,
+) -> Result<Response, Response> {
+    let bad_request = |message: String| Response::error(400, &message);
+
+    // Check the dimensions from the header before decoding anything.
+    let header = crate::limits::header_info(path)
+        .map_err(|_| Response::error(400, "Not a supported image."))?;
+    if max_pixels > 0 && header.pixels() > max_pixels {
+        return Err(Response::error(
+            413,
+            &format!(
+                "The image is {}x{} pixels in {} frame(s), more than the server's --max-pixels \
+                 limit of {} pixels.",
+                header.width, header.height, header.frames, max_pixels
+            ),
+        ));
+    }
+
+    let span = crate::trace::span!("load", path = path.display());
+    let img = image::open(path).map_err(|_| Response::error(400, "Failed to decode the image."))?;
+    span.finish();
+
+    let (width, height) = (header.width, header.height);
+    let mut words = Vec::new();
+    let requested_width = params.get("width").map(|w| w.parse::<u32>());
+    let requested_height = params.get("height").map(|h| h.parse::<u32>());
+    let size = match (requested_width, requested_height) {
+        (None, None) => None,
+        (Some(Ok(w)), None) => Some((w, (w as u64 * height as u64 / width as u64).max(1) as u32)),
+        (None, Some(Ok(h))) => Some(((h as u64 * width as u64 / height as u64).max(1) as u32, h)),
+        (Some(Ok(w)), Some(Ok(h))) => Some((w, h)),
+        _ => {
+            return Err(bad_request(
+                "width and height must be whole numbers.".to_string(),
+            ))
+        }
+    };
+    if let Some((w, h)) = size {
+        if w == 0 || h == 0 || (max_pixels > 0 && w as u64 * h as u64 > max_pixels) {
+            return Err(bad_request(
+                "The requested size is not allowed.".to_string(),
+            ));
+        }
+        words.extend(["resize".to_string(), w.to_string(), h.to_string()]);
+    }
+    for (param, operation) in [
+        ("rotate", "rotate"),
+        ("blur", "blur"),
+        ("brighten", "brighten"),
+    ] {
+        if let Some(value) = params.get(param) {
+            words.extend([operation.to_string(), value.clone()]);
+        }
+    }
+    for flag in ["grayscale", "invert"] {
+        if params
+            .get(flag)
+            .is_some_and(|value| value == "1" || value == "true")
+        {
+            words.push(flag.to_string());
+        }
+    }
+    let operations = pipeline::parse_operations(&words).map_err(bad_request)?;
+    let span = crate::trace::span!("operations", operations = words.join(" "));
+    let result = pipeline::apply_all(img.to_rgba8(), &operations);
+    span.finish();
+
+    let quality = match params.get("quality") {
+        Some(q) => match q.parse::<u8>() {
+            Ok(q) if (1..=100).contains(&q) => q,
+            _ => {
+                return Err(bad_request(
+                    "quality must be between 1 and 100.".to_string(),
+                ))
+            }
+        },
+        None => 85,
+    };
+    let is_jpeg = matches!(
+        image::ImageFormat::from_path(path),
+        Ok(image::ImageFormat::Jpeg)
+    );
+
+    let span = crate::trace::span!("save", format = if is_jpeg { "jpeg" } else { "png" });
+    let mut body = Vec::new();
+    let encoded = if is_jpeg {
+        let rgb = image::DynamicImage::ImageRgba8(result).to_rgb8();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut body, quality).encode_image(&rgb)
+    } else {
+        image::DynamicImage::ImageRgba8(result).write_to(
+            &mut std::io::Cursor::new(&mut body),
+            image::ImageOutputFormat::Png,
+        )
+    };
+    encoded.map_err(|_| Response::error(500, "Failed to encode the image."))?;
+    span.finish();
+
+    Ok(Response {
+        status: 200,
+        content_type: if is_jpeg { "image/jpeg" } else { "image/png" },
+        body,
+    })
+}
+
+// Decode %XX escapes. A '+' stands for a space only in the query string, where HTML forms put it,
+// and is a literal '+' in the path.
+fn percent_decode(s: &str, plus_is_space: bool) -> String {
+    let hex = |byte: u8| (byte as char).to_digit(16).map(|digit| digit as u8);
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => match (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                (Some(high), Some(low)) => {
+                    decoded.push(high * 16 + low);
+                    i += 2;
+                }
+                _ => decoded.push(b'%'),
+            },
+            b'+' if plus_is_space => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(body: &str) -> Entry {
+        Arc::new(("text/plain", body.as_bytes().to_vec()))
+    }
+
+    fn body(cache: &mut Cache, key: &str) -> Option<String> {
+        cache
+            .get(key)
+            .map(|entry| String::from_utf8(entry.1.clone()).unwrap())
+    }
+
+    #[test]
+    fn the_least_recently_used_entry_is_evicted() {
+        let mut cache = Cache::new(2);
+        cache.insert("a".to_string(), entry("1"));
+        cache.insert("b".to_string(), entry("2"));
+        // Reading `a` makes `b` the least recently used.
+        assert_eq!(body(&mut cache, "a").as_deref(), Some("1"));
+        cache.insert("c".to_string(), entry("3"));
+        assert_eq!(body(&mut cache, "b"), None);
+        assert_eq!(body(&mut cache, "a").as_deref(), Some("1"));
+        assert_eq!(body(&mut cache, "c").as_deref(), Some("3"));
+
+        // Replacing an entry refreshes it too.
+        cache.insert("a".to_string(), entry("4"));
+        cache.insert("d".to_string(), entry("5"));
+        assert_eq!(body(&mut cache, "c"), None);
+        assert_eq!(body(&mut cache, "a").as_deref(), Some("4"));
+        assert_eq!(body(&mut cache, "d").as_deref(), Some("5"));
+        assert_eq!(cache.nodes.len(), 2);
+    }
+
+    #[test]
+    fn a_cache_of_one_or_none_holds_at_most_that() {
+        let mut cache = Cache::new(1);
+        cache.insert("a".to_string(), entry("1"));
+        cache.insert("b".to_string(), entry("2"));
+        assert_eq!(body(&mut cache, "a"), None);
+        assert_eq!(body(&mut cache, "b").as_deref(), Some("2"));
+
+        let mut cache = Cache::new(0);
+        cache.insert("a".to_string(), entry("1"));
+        assert_eq!(body(&mut cache, "a"), None);
+    }
+
+    #[test]
+    fn plus_is_a_space_only_in_the_query() {
+        assert_eq!(percent_decode("/c++/a%20b+c.png", false), "/c++/a b+c.png");
+        assert_eq!(percent_decode("a+b%2Bc", true), "a b+c");
+        assert_eq!(percent_decode("100%", false), "100%");
+    }
+
+    // The path of a request, resolved the way `handle_get` does.
+    fn resolve(root: &Path, target: &str) -> Option<PathBuf> {
+        resolve_path(root, &percent_decode(target, false))
+    }
+
+    #[test]
+    fn request_paths_cannot_escape_the_root() {
+        let dir = std::env::temp_dir().join(format!("mirage-serve-test-{}", std::process::id()));
+        let root = dir.join("root");
+        std::fs::create_dir_all(root.join("photos")).unwrap();
+        std::fs::write(root.join("photos").join("cat.png"), b"").unwrap();
+        std::fs::write(dir.join("secret.png"), b"").unwrap();
+        let root = std::fs::canonicalize(root).unwrap();
+
+        assert_eq!(
+            resolve(&root, "/photos/cat.png"),
+            Some(root.join("photos").join("cat.png"))
+        );
+        for target in [
+            "/../secret.png",
+            "/photos/../../secret.png",
+            "/%2e%2e/secret.png",
+            "/%2E%2E/secret.png",
+            "/photos%2f..%2f..%2fsecret.png",
+            "/photos%2F%2e%2e%2F%2e%2e%2Fsecret.png",
+            "/..%5csecret.png",
+        ] {
+            assert_eq!(resolve(&root, target), None, "{}", target);
+        }
+        // An absolute path in the request is still taken from the root.
+        for target in ["//etc/passwd", "/%2fetc%2fpasswd", "/%2Fetc/passwd"] {
+            assert_eq!(
+                resolve(&root, target),
+                Some(root.join("etc").join("passwd")),
+                "{}",
+                target
+            );
+        }
+    }
+}
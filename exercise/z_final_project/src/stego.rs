@@ -0,0 +1,128 @@
+// Hiding data in the least significant bits of an image's pixels.
+//
+// The payload is preceded by a header of a magic marker and the payload's length (big endian), and
+// the whole message is written `bits` bits at a time, most significant first, into the red, green
+// and blue channels in pixel order. Alpha is left alone. Changing the lowest one or two bits of a
+// channel is invisible to the eye, but lossy compression destroys it, so the result must be saved
+// losslessly.
+
+use image::RgbaImage;
+use std::convert::TryInto;
+
+const MAGIC: &[u8; 4] = b"MIRG";
+const HEADER_LEN: usize = MAGIC.len() + 4;
+
+// How many payload bytes `img` can hold.
+pub fn capacity(img: &RgbaImage, bits: u8) -> usize {
+    let slots = img.pixels().len() * 3 * bits as usize / 8;
+    slots.saturating_sub(HEADER_LEN)
+}
+
+pub fn embed(img: &mut RgbaImage, payload: &[u8], bits: u8) -> Result<(), String> {
+    let capacity = capacity(img, bits);
+    if payload.len() > capacity {
+        return Err(format!(
+            "The payload is {} bytes, but the image can only hold {} bytes with --bits {}!",
+            payload.len(),
+            capacity,
+            bits
+        ));
+    }
+
+    let mut message = MAGIC.to_vec();
+    message.extend((payload.len() as u32).to_be_bytes());
+    message.extend(payload);
+
+    let mask = (1u8 << bits) - 1;
+    let mut channels = color_channels(img);
+    for byte in message {
+        for shift in (0..8 / bits).rev() {
+            let chunk = (byte >> (shift * bits)) & mask;
+            let channel = channels.next().unwrap();
+            *channel = (*channel & !mask) | chunk;
+        }
+    }
+    Ok(())
+}
+
+pub fn extract(img: &RgbaImage, bits: u8) -> Result<Vec<u8>, String> {
+    let mask = (1u8 << bits) - 1;
+    let mut channels = img
+        .as_raw()
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| i % 4 != 3)
+        .map(|(_, channel)| channel);
+    let mut next_byte = || {
+        let mut byte = 0u8;
+        for _ in 0..8 / bits {
+            byte = (byte << bits) | (channels.next()? & mask);
+        }
+        Some(byte)
+    };
+
+    let no_payload = || format!("INFILE has no embedded payload with --bits {}!", bits);
+    let header = (0..HEADER_LEN)
+        .map(|_| next_byte())
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(no_payload)?;
+    if &header[..MAGIC.len()] != MAGIC {
+        return Err(no_payload());
+    }
+    let length = u32::from_be_bytes(header[MAGIC.len()..].try_into().unwrap()) as usize;
+    (0..length)
+        .map(|_| next_byte())
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(|| "INFILE's payload is truncated!".to_string())
+}
+
+fn color_channels(img: &mut RgbaImage) -> impl Iterator<Item = &mut u8> {
+    img.iter_mut()
+        .enumerate()
+        .filter(|(i, _)| i % 4 != 3)
+        .map(|(_, channel)| channel)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn photo() -> RgbaImage {
+        RgbaImage::from_fn(32, 32, |x, y| {
+            image::Rgba([(x * 8) as u8, (y * 8) as u8, ((x * y) % 256) as u8, 200])
+        })
+    }
+
+    #[test]
+    fn a_message_round_trips_and_barely_changes_the_pixels() {
+        let message = "meet at the old mill, 9pm".as_bytes();
+        for bits in [1, 2] {
+            let original = photo();
+            let mut encoded = original.clone();
+            embed(&mut encoded, message, bits).unwrap();
+            assert_eq!(extract(&encoded, bits).unwrap(), message);
+
+            let largest_change = (1 << bits) - 1;
+            for (a, b) in original.pixels().zip(encoded.pixels()) {
+                for c in 0..3 {
+                    assert!((a[c] as i32 - b[c] as i32).abs() <= largest_change);
+                }
+                assert_eq!(a[3], b[3]);
+            }
+        }
+    }
+
+    #[test]
+    fn a_payload_over_capacity_is_refused_with_both_sizes() {
+        let mut img = photo();
+        let capacity = capacity(&img, 1);
+        let err = embed(&mut img, &vec![0; capacity + 1], 1).unwrap_err();
+        assert!(err.contains(&(capacity + 1).to_string()) && err.contains(&capacity.to_string()));
+        assert_eq!(img, photo());
+    }
+
+    #[test]
+    fn an_image_without_a_payload_is_reported() {
+        assert!(extract(&photo(), 1).is_err());
+    }
+}
@@ -0,0 +1,85 @@
+// Spans around the stages of a command: loading and decoding its input, each operation of `chain`,
+// and saving its output.
+//
+// With the `tracing` feature they are `tracing` spans, which `init` logs to stderr with
+// `tracing-subscriber`: nothing by default, every span as it closes with `-v`, and as it opens too
+// with `-vv`. Logs are human readable unless `--log-format json` asks for one JSON object per line.
+// Either way a span measures how long it was open, which `chain --timing` reports for each
+// operation.
+
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "tracing")]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum LogFormat {
+    Human,
+    Json,
+}
+
+// Install the subscriber for the verbosity and log format. Called once from `main` before any
+// command runs.
+#[cfg(feature = "tracing")]
+pub fn init(verbosity: u8, format: LogFormat) {
+    use tracing_subscriber::fmt::format::FmtSpan;
+
+    let events = match verbosity {
+        0 => return,
+        1 => FmtSpan::CLOSE,
+        _ => FmtSpan::NEW | FmtSpan::CLOSE,
+    };
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_span_events(events)
+        .with_target(false);
+    match format {
+        LogFormat::Human => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+}
+
+// Enter a span named `$name` with `key = value` fields, each formatted with `Display`. It stays open
+// until the returned `Span` is finished or dropped.
+#[cfg(feature = "tracing")]
+macro_rules! span {
+    ($name:literal $(, $key:ident = $value:expr)* $(,)?) => {
+        crate::trace::Span::enter(tracing::info_span!($name $(, $key = %$value)*))
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! span {
+    ($name:literal $(, $key:ident = $value:expr)* $(,)?) => {{
+        $(let _ = &$value;)*
+        crate::trace::Span::enter()
+    }};
+}
+
+pub(crate) use span;
+
+pub struct Span {
+    #[cfg(feature = "tracing")]
+    _entered: tracing::span::EnteredSpan,
+    start: Instant,
+}
+
+impl Span {
+    #[cfg(feature = "tracing")]
+    pub fn enter(span: tracing::Span) -> Self {
+        Span {
+            _entered: span.entered(),
+            start: Instant::now(),
+        }
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub fn enter() -> Self {
+        Span {
+            start: Instant::now(),
+        }
+    }
+
+    // Close the span and return how long it was open.
+    pub fn finish(self) -> Duration {
+        self.start.elapsed()
+    }
+}
@@ -0,0 +1,12 @@
+// Browser entry points. wasm-bindgen generates the JavaScript glue that copies the arguments into
+// WebAssembly memory and the result back out, and turns an error into a thrown exception.
+// See examples/wasm/index.html.
+
+use wasm_bindgen::prelude::*;
+
+// `crate::process` for JavaScript: takes the encoded image as a `Uint8Array` and the operations as
+// a JSON string, and returns the processed image, in the same format, as a `Uint8Array`.
+#[wasm_bindgen]
+pub fn process(bytes: &[u8], ops_json: &str) -> Result<Vec<u8>, JsError> {
+    crate::process(bytes, ops_json).map_err(|err| JsError::new(&err))
+}
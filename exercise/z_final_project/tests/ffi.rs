@@ -0,0 +1,87 @@
+// Checks on the C interface: include/mirage.h has to match what cbindgen generates from src/ffi.rs,
+// and examples/ffi/blur.c has to compile, link against the cdylib and run.
+#![cfg(feature = "ffi")]
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn manifest_dir() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+fn generated_header() -> String {
+    let config = cbindgen::Config::from_file(manifest_dir().join("cbindgen.toml"))
+        .expect("Failed to read cbindgen.toml.");
+    let bindings = cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(manifest_dir().join("src/ffi.rs"))
+        .generate()
+        .expect("cbindgen failed to generate the header.");
+    let mut header = Vec::new();
+    bindings.write(&mut header);
+    String::from_utf8(header).unwrap()
+}
+
+#[test]
+fn header_is_up_to_date() {
+    let path = manifest_dir().join("include/mirage.h");
+    let generated = generated_header();
+    if std::env::var_os("MIRAGE_BLESS").is_some() {
+        std::fs::write(&path, &generated).expect("Failed writing include/mirage.h.");
+        return;
+    }
+    let current = std::fs::read_to_string(&path).expect("Failed to read include/mirage.h.");
+    assert!(
+        current == generated,
+        "include/mirage.h doesn't match src/ffi.rs. Regenerate it with\n\n    \
+         MIRAGE_BLESS=1 cargo test --features ffi --test ffi\n"
+    );
+}
+
+// A directory for this test run's files.
+fn temp_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mirage-ffi-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[cfg(unix)]
+#[test]
+fn blur_example_links_and_runs() {
+    // Integration tests run from target/<profile>/deps, where cargo also builds the cdylib they
+    // depend on. The copy one level up is only refreshed by `cargo build`, so it may be stale.
+    let exe = std::env::current_exe().unwrap();
+    let lib_dir = exe.parent().unwrap();
+    let dir = temp_dir();
+    let blur = dir.join("blur");
+    let compiled = Command::new("cc")
+        .arg("-I")
+        .arg(manifest_dir().join("include"))
+        .arg(manifest_dir().join("examples/ffi/blur.c"))
+        .arg("-L")
+        .arg(lib_dir)
+        .arg("-lmirage")
+        .arg("-o")
+        .arg(&blur)
+        .status()
+        .expect("Failed to run cc.");
+    assert!(compiled.success(), "examples/ffi/blur.c failed to compile");
+
+    let input = dir.join("input.png");
+    let output = dir.join("output.png");
+    image::RgbImage::from_fn(32, 16, |x, y| {
+        image::Rgb([(x * 8) as u8, (y * 16) as u8, 100])
+    })
+    .save(&input)
+    .unwrap();
+    let ran = Command::new(&blur)
+        .arg(&input)
+        .arg(&output)
+        .env("LD_LIBRARY_PATH", lib_dir)
+        .env("DYLD_LIBRARY_PATH", lib_dir)
+        .status()
+        .expect("Failed to run the blur example.");
+    assert!(ran.success(), "the blur example failed");
+    // The example rotates by 90 degrees after blurring.
+    assert_eq!(image::image_dimensions(&output).unwrap(), (16, 32));
+}